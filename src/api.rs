@@ -0,0 +1,729 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sodiumoxide::crypto::secretbox::Key;
+use crate::database::{add_password, delete_password, get_entry, list_passwords, update_password, Password};
+use crate::encryption::{decrypt_metadata, decrypt_password, encrypt_metadata, encrypt_password, normalize_title, title_index, unix_now, EntryMetadata};
+use crate::header::state_dir;
+use crate::items::{decode_fields, entry_secret, ItemFields, ItemType};
+use crate::labels::{folder_label, is_in_folder, normalize_folder, tag_label, EntryLabels};
+use crate::sharing::refresh_shared_entry;
+use crate::tokens::{authenticate, granted_entries, sync_token_grants, ApiToken, GrantedEntry};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// Largest request line plus headers accepted, in bytes.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An entry as listed by the API, without its secret.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntrySummary {
+    pub title: String,
+    #[serde(rename = "type")]
+    pub item_type: ItemType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// An entry as read through the API: logins with their password, other
+/// items with their fields.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntryDetail {
+    #[serde(flatten)]
+    pub summary: EntrySummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<ItemFields>,
+}
+
+/// The body of `POST /v1/entries`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NewEntry {
+    pub title: String,
+    #[serde(rename = "type", default)]
+    pub item_type: ItemType,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub fields: ItemFields,
+    pub username: Option<String>,
+    pub url: Option<String>,
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// The body of `PUT /v1/entries/<title>`. Missing members are left as they
+/// are; an empty `username` or `url` removes it.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EntryUpdate {
+    pub password: Option<String>,
+    #[serde(default)]
+    pub fields: ItemFields,
+    pub username: Option<String>,
+    pub url: Option<String>,
+}
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path without its query string, e.g. `/v1/entries`.
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// A JSON response and its status code.
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+/// Called after an entry was read or changed, with the action and the title.
+pub type AccessHook = Box<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Serves the entries of the selected vault as JSON to clients holding an API
+/// token. Entries are read and written through the same functions as the
+/// command line, under the key the server was started with.
+pub struct Api {
+    key: Key,
+    /// Refuses every change whatever the token, e.g. for team readers.
+    pub read_only: bool,
+    /// Told about each entry read or changed, e.g. to record it in the audit log.
+    pub on_access: Option<AccessHook>,
+}
+
+impl Api {
+    pub fn new(key: Key) -> Api {
+        Api {
+            key,
+            read_only: false,
+            on_access: None,
+        }
+    }
+
+    /// Answers one request. Every route needs a token; changes need one
+    /// that is not read-only. Scoped tokens only reach the entries they
+    /// can decrypt themselves. HEAD is answered like GET; the body is left
+    /// out when the response is written.
+    pub fn handle(&self, request: &Request) -> Response {
+        let secret = request.header("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        let token = match secret.and_then(|secret| authenticate(secret, unix_now() as i64)) {
+            Some(token) => token,
+            None => return Response::error(401, "A valid, unexpired API token is required: Authorization: Bearer <token>"),
+        };
+
+        let title = match request.path.strip_prefix("/v1/entries") {
+            Some("") | Some("/") => None,
+            Some(rest) => match rest.strip_prefix('/').map(percent_decode) {
+                Some(Ok(title)) => Some(title),
+                Some(Err(err)) => return Response::error(400, err),
+                None => return Response::error(404, "Not found"),
+            },
+            None => return Response::error(404, "Not found"),
+        };
+        let method = match request.method.as_str() {
+            "HEAD" => "GET",
+            method => method,
+        };
+        let writes = method != "GET";
+        if writes && (self.read_only || token.read_only) {
+            return Response::error(403, format!("The token '{}' can only read", token.name));
+        }
+
+        let granted = if token.is_scoped() {
+            match granted_entries(&token, secret.unwrap_or_default()) {
+                Ok(entries) => Some(entries),
+                Err(err) => return Response::error(500, err),
+            }
+        } else {
+            None
+        };
+        let grant = Grant { token, entries: granted };
+
+        let result = match (method, title) {
+            ("GET", None) => self.list_entries(request, &grant),
+            ("POST", None) => parse_body(request).and_then(|entry| self.create_entry(entry, &grant)),
+            ("GET", Some(title)) => self.read_entry(&title, &grant),
+            ("PUT", Some(title)) => parse_body(request).and_then(|update| self.update_entry(&title, update, &grant)),
+            ("DELETE", Some(title)) => self.delete_entry(&title, &grant),
+            _ => Err(Response::error(405, format!("{} is not supported here", request.method))),
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    fn record(&self, action: &str, title: &str) {
+        if let Some(on_access) = &self.on_access {
+            on_access(action, title);
+        }
+    }
+
+    fn labels(&self) -> Result<EntryLabels, Response> {
+        EntryLabels::load(&self.key).map_err(|err| Response::error(500, format!("Failed to decrypt folders and tags: {}", err)))
+    }
+
+    fn summary(title_index: &str, metadata: &EntryMetadata, labels: &EntryLabels) -> EntrySummary {
+        EntrySummary {
+            title: metadata.title.clone(),
+            item_type: metadata.item_type,
+            username: metadata.username.clone(),
+            url: metadata.url.clone(),
+            folder: labels.folder(title_index).map(str::to_string),
+            tags: labels.tags(title_index).to_vec(),
+        }
+    }
+
+    fn decrypt_metadata(&self, entry: &Password) -> Result<EntryMetadata, Response> {
+        decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &self.key)
+            .map_err(|err| Response::error(500, format!("Failed to decrypt entry {}: {}", entry.id, err)))
+    }
+
+    /// `GET /v1/entries`, optionally `?folder=<path>` and `?tag=<tag>`.
+    fn list_entries(&self, request: &Request, grant: &Grant) -> Result<Response, Response> {
+        let folder = normalize_folder(request.query("folder").unwrap_or("")).map_err(|err| Response::error(400, err))?;
+        let labels = self.labels()?;
+        let visible = match &grant.entries {
+            Some(granted) => granted.iter().map(|entry| (entry.title_index.clone(), entry.item.metadata.clone())).collect(),
+            None => list_passwords()
+                .into_iter()
+                .map(|entry| Ok((entry.title_index.clone(), self.decrypt_metadata(&entry)?)))
+                .collect::<Result<Vec<_>, Response>>()?,
+        };
+        let mut entries = Vec::new();
+        for (title_index, metadata) in visible {
+            let in_folder = folder
+                .as_deref()
+                .is_none_or(|filter| labels.folder(&title_index).is_some_and(|path| is_in_folder(path, filter)));
+            let tagged = request.query("tag").is_none_or(|tag| labels.has_tag(&title_index, tag));
+            if in_folder && tagged {
+                entries.push(Api::summary(&title_index, &metadata, &labels));
+            }
+        }
+        entries.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(Response::ok(json!({ "entries": entries })))
+    }
+
+    /// `GET /v1/entries/<title>`. Scoped tokens read the copy sealed for them.
+    fn read_entry(&self, title: &str, grant: &Grant) -> Result<Response, Response> {
+        let (index, metadata, secret) = match grant.find(title)? {
+            Some(entry) => (entry.title_index.clone(), entry.item.metadata.clone(), entry.item.secret.clone()),
+            None => {
+                let index = title_index(title, &self.key);
+                let entry = get_entry(&index).ok_or_else(|| not_found(title))?;
+                let secret = decrypt_password(&entry.encrypted_password, &index, None, &self.key)
+                    .map_err(|err| Response::error(500, format!("Failed to decrypt '{}': {}", title, err)))?;
+                (index, self.decrypt_metadata(&entry)?, secret)
+            }
+        };
+        let summary = Api::summary(&index, &metadata, &self.labels()?);
+        let (password, fields) = if metadata.item_type.is_login() {
+            (Some(secret), None)
+        } else {
+            (None, Some(decode_fields(&secret).map_err(|err| Response::error(500, err))?))
+        };
+        self.record("get", title);
+        Ok(Response::ok(json!(EntryDetail { summary, password, fields })))
+    }
+
+    /// `POST /v1/entries`. Scoped tokens can only add entries inside their scope.
+    fn create_entry(&self, entry: NewEntry, grant: &Grant) -> Result<Response, Response> {
+        let title = entry.title.trim();
+        if title.is_empty() {
+            return Err(Response::error(400, "Title cannot be empty."));
+        }
+        let secret = entry_secret(entry.item_type, entry.password, entry.fields, ItemFields::new()).map_err(|err| Response::error(400, err))?;
+        let folder_path = normalize_folder(entry.folder.as_deref().unwrap_or("")).map_err(|err| Response::error(400, err))?;
+        if grant.entries.is_some()
+            && !grant.token.scopes().iter().any(|scope| scope.covers(title, folder_path.as_deref(), &entry.tags))
+        {
+            return Err(Response::error(403, format!("'{}' is outside the scope of the token '{}'", title, grant.token.name)));
+        }
+        let folder = folder_path.map(|path| folder_label(&path, &self.key));
+        let tags = entry
+            .tags
+            .iter()
+            .map(|tag| tag_label(tag, &self.key))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Response::error(400, err))?;
+
+        let index = title_index(title, &self.key);
+        let metadata = EntryMetadata {
+            title: title.to_string(),
+            item_type: entry.item_type,
+            username: entry.username.filter(|username| !username.is_empty()),
+            url: entry.url.filter(|url| !url.is_empty()),
+            urls: Vec::new(),
+            created: Some(unix_now()),
+            modified: Some(unix_now()),
+            rotate_every_days: None,
+        };
+        let encrypted_metadata = encrypt_metadata(&metadata, &index, &self.key);
+        match add_password(&index, &encrypted_metadata, &encrypt_password(&secret, &index, &self.key), folder.as_ref(), &tags) {
+            Ok(_) => {
+                self.record("add", title);
+                let _ = sync_token_grants(&self.key);
+                Ok(Response {
+                    status: 201,
+                    body: json!({ "title": title }),
+                })
+            }
+            Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(Response::error(409, format!("A password with the title '{}' already exists.", title)))
+            }
+            Err(e) => Err(Response::error(500, format!("Failed to add '{}': {}", title, e))),
+        }
+    }
+
+    /// `PUT /v1/entries/<title>`.
+    fn update_entry(&self, title: &str, update: EntryUpdate, grant: &Grant) -> Result<Response, Response> {
+        grant.check(title)?;
+        let index = title_index(title, &self.key);
+        let entry = get_entry(&index).ok_or_else(|| not_found(title))?;
+        let decrypt_error = |err: String| Response::error(500, format!("Failed to decrypt '{}': {}", title, err));
+        let old_secret = decrypt_password(&entry.encrypted_password, &index, None, &self.key).map_err(decrypt_error)?;
+        let mut metadata = decrypt_metadata(&entry.encrypted_metadata, &index, &self.key).map_err(decrypt_error)?;
+
+        let item_type = metadata.item_type;
+        let new_secret = if item_type.is_login() && update.password.is_none() && update.fields.is_empty() {
+            old_secret
+        } else {
+            let current = if item_type.is_login() { ItemFields::new() } else { decode_fields(&old_secret).map_err(decrypt_error)? };
+            entry_secret(item_type, update.password, update.fields, current).map_err(|err| Response::error(400, err))?
+        };
+        if let Some(username) = update.username {
+            metadata.username = Some(username).filter(|username| !username.is_empty());
+        }
+        if let Some(url) = update.url {
+            metadata.url = Some(url).filter(|url| !url.is_empty());
+        }
+        metadata.touch();
+
+        update_password(&index, &encrypt_password(&new_secret, &index, &self.key), Some(&encrypt_metadata(&metadata, &index, &self.key)))
+            .map_err(|e| Response::error(500, format!("Failed to update '{}': {}", title, e)))?;
+        self.record("modify", title);
+        let _ = refresh_shared_entry(&index, &self.key);
+        Ok(Response::ok(json!({ "title": title })))
+    }
+
+    /// `DELETE /v1/entries/<title>`.
+    fn delete_entry(&self, title: &str, grant: &Grant) -> Result<Response, Response> {
+        grant.check(title)?;
+        match delete_password(&title_index(title, &self.key)) {
+            Ok(_) => {
+                self.record("delete", title);
+                Ok(Response::ok(json!({ "deleted": title })))
+            }
+            Err(diesel::result::Error::NotFound) => Err(not_found(title)),
+            Err(e) => Err(Response::error(500, format!("Failed to delete '{}': {}", title, e))),
+        }
+    }
+}
+
+/// The token of a request and, for a scoped token, the entries it decrypted.
+struct Grant {
+    token: ApiToken,
+    entries: Option<Vec<GrantedEntry>>,
+}
+
+impl Grant {
+    /// The granted entry called `title`. Scoped tokens get a 404 for any
+    /// other entry, the same as for a missing one.
+    fn find(&self, title: &str) -> Result<Option<&GrantedEntry>, Response> {
+        match &self.entries {
+            Some(entries) => entries
+                .iter()
+                .find(|entry| normalize_title(&entry.item.metadata.title) == normalize_title(title))
+                .map(Some)
+                .ok_or_else(|| not_found(title)),
+            None => Ok(None),
+        }
+    }
+
+    fn check(&self, title: &str) -> Result<(), Response> {
+        self.find(title).map(|_| ())
+    }
+}
+
+fn not_found(title: &str) -> Response {
+    Response::error(404, format!("No password found for '{}'.", title))
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(request: &Request) -> Result<T, Response> {
+    serde_json::from_slice(&request.body).map_err(|e| Response::error(400, format!("Invalid JSON body: {}", e)))
+}
+
+/// Decodes `%XX` escapes, as in a path segment or query value.
+fn percent_decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let escape = text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            decoded.push(escape.ok_or_else(|| format!("Invalid escape in '{}'", text))?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("'{}' is not UTF-8", text))
+}
+
+/// Reads one HTTP/1.1 request.
+fn read_request(stream: &mut impl Read) -> Result<Request, Response> {
+    let mut reader = BufReader::new(stream);
+    let mut head_len = 0;
+    let mut read_line = |reader: &mut BufReader<_>| -> Result<String, Response> {
+        let mut line = String::new();
+        let read = reader
+            .by_ref()
+            .take((MAX_HEAD_LEN - head_len) as u64)
+            .read_line(&mut line)
+            .map_err(|_| Response::error(400, "Malformed request"))?;
+        head_len += read;
+        if !line.ends_with('\n') {
+            return Err(Response::error(431, "The request head is too large"));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method.to_string(), target.to_string()),
+        _ => return Err(Response::error(400, "Malformed request line")),
+    };
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| Response::error(400, "Malformed header"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let length = match headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Content-Length")) {
+        Some((_, value)) => value.parse::<usize>().map_err(|_| Response::error(400, "Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_LEN {
+        return Err(Response::error(413, "The request body is too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|_| Response::error(400, "The request body is shorter than its Content-Length"))?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(&key.replace('+', " "))?, percent_decode(&value.replace('+', " "))?))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|err| Response::error(400, err))?;
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body,
+    })
+}
+
+/// Writes `response`, without its body when answering a HEAD request.
+fn write_response(stream: &mut impl Write, response: &Response, head_only: bool) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_vec(&response.body).expect("Failed to serialize a response");
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason,
+        body.len()
+    );
+    if response.status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        stream.write_all(&body)?;
+    }
+    stream.flush()
+}
+
+/// Answers one request on a connection, then closes it.
+fn serve_client(mut stream: impl Read + Write, api: &Api) {
+    let (response, head_only) = match read_request(&mut stream) {
+        Ok(request) => (api.handle(&request), request.method == "HEAD"),
+        Err(response) => (response, false),
+    };
+    let _ = write_response(&mut stream, &response, head_only);
+}
+
+/// Where the API listens unless `--listen` or `--socket` is given.
+pub fn default_socket_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("api.sock"))
+}
+
+/// Binds a TCP address for the API. Only loopback addresses are accepted,
+/// since the API speaks plain HTTP.
+pub fn bind_tcp(address: &str) -> Result<TcpListener, String> {
+    let addresses: Vec<SocketAddr> = address
+        .to_socket_addrs()
+        .map_err(|e| format!("Invalid address '{}': {}", address, e))?
+        .collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !address.ip().is_loopback()) {
+        return Err(format!("Refusing to listen on '{}': the API has no TLS, so it only listens on localhost", address));
+    }
+    TcpListener::bind(&addresses[..]).map_err(|e| format!("Failed to listen on {}: {}", address, e))
+}
+
+/// Accepts TCP clients until the process is stopped, each on its own thread.
+pub fn serve_tcp(listener: TcpListener, api: Arc<Api>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                let api = Arc::clone(&api);
+                thread::spawn(move || serve_client(stream, &api));
+            }
+            Err(e) => eprintln!("Failed to accept an API client: {}", e),
+        }
+    }
+}
+
+/// Accepts clients on a Unix socket until the process is stopped, each on
+/// its own thread.
+pub fn serve_unix(listener: UnixListener, api: Arc<Api>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                let api = Arc::clone(&api);
+                thread::spawn(move || serve_client(stream, &api));
+            }
+            Err(e) => eprintln!("Failed to accept an API client: {}", e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::generate_key;
+    use crate::testing::test_vault;
+    use crate::tokens::{create_token, Scope, TokenOptions};
+    use std::net::TcpStream;
+
+    /// Sends one request to the server and returns the status and JSON body.
+    fn send(address: SocketAddr, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, serde_json::Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n", method, path, body.len());
+        if let Some(token) = token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_api_serves_entries_to_token_holders() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        let writer = create_token("writer", &TokenOptions::default(), 1_700_000_000, &key).unwrap();
+        let read_only = TokenOptions {
+            read_only: true,
+            ..Default::default()
+        };
+        let reader = create_token("reader", &read_only, 1_700_000_000, &key).unwrap();
+        let listener = bind_tcp("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_tcp(listener, Arc::new(Api::new(generate_key()))));
+        let new_entry = r#"{"title":"Mail / Work","password":"hunter2","username":"me","folder":"work","tags":["email"]}"#;
+
+        // Act
+        let created = send(address, "POST", "/v1/entries", Some(&writer), new_entry);
+        let duplicate = send(address, "POST", "/v1/entries", Some(&writer), new_entry);
+        let read = send(address, "GET", "/v1/entries/Mail%20%2F%20Work", Some(&reader), "");
+        let listed = send(address, "GET", "/v1/entries?folder=work", Some(&reader), "");
+        let updated = send(address, "PUT", "/v1/entries/Mail%20%2F%20Work", Some(&writer), r#"{"password":"correct horse","url":"https://mail.example.com"}"#);
+        let reread = send(address, "GET", "/v1/entries/Mail%20%2F%20Work", Some(&writer), "");
+        let forbidden = send(address, "DELETE", "/v1/entries/Mail%20%2F%20Work", Some(&reader), "");
+        let unauthorized = send(address, "GET", "/v1/entries", None, "");
+        let invalid = send(address, "POST", "/v1/entries", Some(&writer), r#"{"title":"x","pasword":"typo"}"#);
+        let deleted = send(address, "DELETE", "/v1/entries/Mail%20%2F%20Work", Some(&writer), "");
+        let missing = send(address, "GET", "/v1/entries/Mail%20%2F%20Work", Some(&writer), "");
+
+        // Assert
+        assert_eq!(created.0, 201);
+        assert_eq!(duplicate.0, 409);
+        assert_eq!(read.0, 200);
+        assert_eq!(read.1["password"], "hunter2");
+        assert_eq!(read.1["username"], "me");
+        assert_eq!(read.1["tags"], json!(["email"]));
+        assert_eq!(listed.1["entries"][0]["title"], "Mail / Work");
+        assert!(listed.1["entries"][0].get("password").is_none());
+        assert_eq!(updated.0, 200);
+        assert_eq!(reread.1["password"], "correct horse");
+        assert_eq!(reread.1["url"], "https://mail.example.com");
+        assert_eq!(reread.1["username"], "me");
+        assert_eq!(forbidden.0, 403);
+        assert_eq!(unauthorized.0, 401);
+        assert_eq!(invalid.0, 400);
+        assert_eq!(deleted.0, 200);
+        assert_eq!(missing.0, 404);
+    }
+
+    #[test]
+    fn test_api_scoped_tokens_cannot_reach_other_entries() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        let admin = create_token("admin", &TokenOptions::default(), 1_700_000_000, &key).unwrap();
+        let listener = bind_tcp("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_tcp(listener, Arc::new(Api::new(generate_key()))));
+        send(address, "POST", "/v1/entries", Some(&admin), r#"{"title":"prod-db","password":"p1","folder":"prod/db"}"#);
+        send(address, "POST", "/v1/entries", Some(&admin), r#"{"title":"dev-db","password":"p2","folder":"dev"}"#);
+        let options = TokenOptions {
+            scopes: vec![Scope::parse("folder:prod/*").unwrap()],
+            ..Default::default()
+        };
+        let deploy = create_token("deploy", &options, 1_700_000_000, &key).unwrap();
+
+        // Act
+        let listed = send(address, "GET", "/v1/entries", Some(&deploy), "");
+        let in_scope = send(address, "GET", "/v1/entries/prod-db", Some(&deploy), "");
+        let out_of_scope = send(address, "GET", "/v1/entries/dev-db", Some(&deploy), "");
+        let change_out_of_scope = send(address, "PUT", "/v1/entries/dev-db", Some(&deploy), r#"{"password":"x"}"#);
+        let delete_out_of_scope = send(address, "DELETE", "/v1/entries/dev-db", Some(&deploy), "");
+        let add_out_of_scope = send(address, "POST", "/v1/entries", Some(&deploy), r#"{"title":"dev-web","password":"p3","folder":"dev"}"#);
+        let add_in_scope = send(address, "POST", "/v1/entries", Some(&deploy), r#"{"title":"prod-web","password":"p4","folder":"prod"}"#);
+        let read_added = send(address, "GET", "/v1/entries/prod-web", Some(&deploy), "");
+
+        // Assert
+        assert_eq!(listed.1["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(listed.1["entries"][0]["title"], "prod-db");
+        assert_eq!(in_scope.1["password"], "p1");
+        assert_eq!(in_scope.1["folder"], "prod/db");
+        assert_eq!(out_of_scope.0, 404);
+        assert_eq!(change_out_of_scope.0, 404);
+        assert_eq!(delete_out_of_scope.0, 404);
+        assert_eq!(add_out_of_scope.0, 403);
+        assert_eq!(add_in_scope.0, 201);
+        assert_eq!(read_added.1["password"], "p4");
+        assert_eq!(send(address, "GET", "/v1/entries/dev-db", Some(&admin), "").1["password"], "p2");
+    }
+
+    #[test]
+    fn test_api_answers_head_like_get_without_a_body() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        let writer = create_token("writer", &TokenOptions::default(), 1_700_000_000, &key).unwrap();
+        let read_only = TokenOptions {
+            read_only: true,
+            ..Default::default()
+        };
+        let reader = create_token("reader", &read_only, 1_700_000_000, &key).unwrap();
+        let listener = bind_tcp("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_tcp(listener, Arc::new(Api::new(generate_key()))));
+        send(address, "POST", "/v1/entries", Some(&writer), r#"{"title":"mail","password":"hunter2"}"#);
+        let head = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let request = format!("HEAD {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n", path, reader);
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        // Act
+        let found = head("/v1/entries/mail");
+        let missing = head("/v1/entries/other");
+
+        // Assert
+        assert!(found.starts_with("HTTP/1.1 200 OK\r\n"), "Unexpected response: {}", found);
+        assert!(found.ends_with("\r\n\r\n"), "A HEAD response must have no body: {}", found);
+        assert!(!found.contains("Content-Length: 0\r\n"));
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"), "Unexpected response: {}", missing);
+        assert!(missing.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_api_only_listens_on_localhost() {
+        // Act
+        let result = bind_tcp("0.0.0.0:0");
+
+        // Assert
+        assert!(result.unwrap_err().contains("only listens on localhost"));
+    }
+}
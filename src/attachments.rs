@@ -0,0 +1,450 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::secretbox::Key;
+use sodiumoxide::crypto::secretstream::xchacha20poly1305 as stream;
+use sodiumoxide::crypto::secretstream::{Header, Stream, Tag};
+use crate::container::create_private_file;
+use crate::database::{establish_connection, write_transaction};
+use crate::encryption::{decrypt_label, encrypt_label, label_index, unwrap_attachment_key, wrap_attachment_key};
+use crate::schema::passwords::dsl as passwords_dsl;
+use crate::schema::{attachment_chunks, attachments};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Largest file that can be attached.
+pub const MAX_ATTACHMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Size of the plaintext chunks attachments are split into.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What is known about an attachment besides its contents. Stored encrypted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttachmentInfo {
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the contents, checked again on extract.
+    pub sha256: String,
+}
+
+#[derive(Queryable)]
+struct Attachment {
+    id: i32,
+    title_index: String,
+    name_index: String,
+    encrypted_info: String,
+    wrapped_key: String,
+    stream_header: String,
+}
+
+impl Attachment {
+    /// Binds the key, info and chunks of the attachment to its entry and name.
+    fn binding(&self) -> String {
+        binding(&self.title_index, &self.name_index)
+    }
+}
+
+fn binding(title_index: &str, name_index: &str) -> String {
+    format!("{}:{}", title_index, name_index)
+}
+
+/// Encrypts the file at `path` and attaches it to an entry under `name`.
+/// The file is read and stored one chunk at a time, each chunk encrypted
+/// with a fresh random key that is itself wrapped by the vault key.
+pub fn attach(title_index: &str, name: &str, path: &Path, key: &Key) -> Result<AttachmentInfo, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Attachment names cannot be empty".to_string());
+    }
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let size = file.metadata().map_err(|e| format!("Failed to read {}: {}", path.display(), e))?.len();
+    if size > MAX_ATTACHMENT_SIZE {
+        return Err(too_large(size));
+    }
+    let mut input = BufReader::new(file);
+
+    let connection = establish_connection();
+    let name_index = label_index("attachment", name, key);
+    let binding = binding(title_index, &name_index);
+    let attachment_key = stream::gen_key();
+    let (mut encryptor, header) =
+        Stream::init_push(&attachment_key).map_err(|_| "Failed to initialise encryption stream".to_string())?;
+
+    // Errors other than database errors are carried out of the transaction as strings
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+
+    write_transaction(&connection, || {
+        let entry = passwords_dsl::passwords
+            .filter(passwords_dsl::title_index.eq(title_index))
+            .select(passwords_dsl::id)
+            .first::<i32>(&connection)
+            .optional()?;
+        if entry.is_none() {
+            return Err(diesel::result::Error::NotFound);
+        }
+
+        let id = match diesel::insert_or_ignore_into(attachments::table)
+            .values((
+                attachments::title_index.eq(title_index),
+                attachments::name_index.eq(&name_index),
+                attachments::encrypted_info.eq(""),
+                attachments::wrapped_key.eq(wrap_attachment_key(attachment_key.as_ref(), &binding, key)),
+                attachments::stream_header.eq(base64::encode(header.as_ref())),
+            ))
+            .execute(&connection)?
+        {
+            0 => return Err(fail(format!("The entry already has an attachment named '{}'", name))),
+            _ => attachments::table
+                .filter(attachments::title_index.eq(title_index))
+                .filter(attachments::name_index.eq(&name_index))
+                .select(attachments::id)
+                .first::<i32>(&connection)?,
+        };
+
+        // Read one chunk ahead so the last chunk can be tagged as final
+        let mut digest = sha256::State::new();
+        let mut total = 0u64;
+        let mut current = read_chunk(&mut input).map_err(fail)?;
+        for seq in 0.. {
+            total += current.len() as u64;
+            if total > MAX_ATTACHMENT_SIZE {
+                return Err(fail(too_large(total)));
+            }
+            digest.update(&current);
+
+            let next = if current.len() == CHUNK_SIZE { read_chunk(&mut input).map_err(fail)? } else { Vec::new() };
+            let tag = if next.is_empty() { Tag::Final } else { Tag::Message };
+            let ciphertext = encryptor
+                .push(&current, Some(binding.as_bytes()), tag)
+                .map_err(|_| fail("Failed to encrypt attachment chunk".to_string()))?;
+            diesel::insert_into(attachment_chunks::table)
+                .values((
+                    attachment_chunks::attachment_id.eq(id),
+                    attachment_chunks::seq.eq(seq),
+                    attachment_chunks::data.eq(ciphertext),
+                ))
+                .execute(&connection)?;
+            if next.is_empty() {
+                break;
+            }
+            current = next;
+        }
+
+        let info = AttachmentInfo {
+            name: name.to_string(),
+            size: total,
+            sha256: base64::encode(digest.finalize().as_ref()),
+        };
+        let json = serde_json::to_string(&info).expect("Failed to serialize attachment info");
+        diesel::update(attachments::table.filter(attachments::id.eq(id)))
+            .set(attachments::encrypted_info.eq(encrypt_label("attachment", &json, &binding, key)))
+            .execute(&connection)?;
+        Ok(info)
+    })
+    .map_err(|e| match e {
+        diesel::result::Error::NotFound => "No password found for this title".to_string(),
+        diesel::result::Error::DeserializationError(message) => message.to_string(),
+        e => format!("Failed to store attachment: {}", e),
+    })
+}
+
+/// Decrypts an attachment into `output`. The chunks are authenticated one by
+/// one, and their order, count and overall digest are checked; the output only
+/// appears once everything has been verified.
+pub fn extract(title_index: &str, name: &str, output: &Path, key: &Key) -> Result<AttachmentInfo, String> {
+    let connection = establish_connection();
+    let attachment = find_attachment(&connection, title_index, name, key)?;
+    let binding = attachment.binding();
+    let info = decrypt_info(&attachment, key)?;
+
+    let attachment_key = unwrap_attachment_key(&attachment.wrapped_key, &binding, key)
+        .ok()
+        .and_then(|bytes| stream::Key::from_slice(&bytes))
+        .ok_or("Failed to unwrap the attachment key")?;
+    let header = base64::decode(&attachment.stream_header)
+        .ok()
+        .and_then(|bytes| Header::from_slice(&bytes))
+        .ok_or("Invalid attachment header")?;
+    let mut decryptor = Stream::init_pull(&header, &attachment_key)
+        .map_err(|_| "Failed to initialise decryption stream".to_string())?;
+
+    let part_path = output.with_file_name(format!(
+        ".{}.part",
+        output.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+    ));
+    let result = (|| -> Result<(), String> {
+        let mut writer = BufWriter::new(
+            create_private_file(&part_path).map_err(|e| format!("Failed to create {}: {}", part_path.display(), e))?,
+        );
+        let mut digest = sha256::State::new();
+        let mut total = 0u64;
+
+        let mut seq = 0;
+        loop {
+            let ciphertext = read_stored_chunk(&connection, attachment.id, seq)?
+                .ok_or("The attachment is truncated")?;
+            let (plaintext, tag) = decryptor
+                .pull(&ciphertext, Some(binding.as_bytes()))
+                .map_err(|_| format!("Chunk {} of the attachment was tampered with", seq))?;
+            digest.update(&plaintext);
+            total += plaintext.len() as u64;
+            writer.write_all(&plaintext).map_err(|e| format!("Failed to write {}: {}", part_path.display(), e))?;
+            seq += 1;
+            if tag == Tag::Final {
+                break;
+            }
+        }
+
+        // Nothing may follow the final chunk
+        if read_stored_chunk(&connection, attachment.id, seq)?.is_some() {
+            return Err("The attachment has unexpected trailing chunks".to_string());
+        }
+        if total != info.size || base64::encode(digest.finalize().as_ref()) != info.sha256 {
+            return Err("The attachment does not match its recorded size and digest".to_string());
+        }
+        writer.flush().map_err(|e| format!("Failed to write {}: {}", part_path.display(), e))
+    })();
+
+    match result {
+        Ok(()) => {
+            fs::rename(&part_path, output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+            Ok(info)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part_path);
+            Err(e)
+        }
+    }
+}
+
+/// Removes an attachment and its chunks.
+pub fn detach(title_index: &str, name: &str, key: &Key) -> Result<(), String> {
+    let connection = establish_connection();
+    let attachment = find_attachment(&connection, title_index, name, key)?;
+
+    write_transaction(&connection, || {
+        diesel::delete(attachment_chunks::table.filter(attachment_chunks::attachment_id.eq(attachment.id)))
+            .execute(&connection)?;
+        diesel::delete(attachments::table.filter(attachments::id.eq(attachment.id))).execute(&connection)
+    })
+    .map(|_| ())
+    .map_err(|e| format!("Failed to remove attachment: {}", e))
+}
+
+/// The attachments of an entry, sorted by name.
+pub fn list_attachments(title_index: &str, key: &Key) -> Result<Vec<AttachmentInfo>, String> {
+    let connection = establish_connection();
+    let rows = attachments::table
+        .filter(attachments::title_index.eq(title_index))
+        .load::<Attachment>(&connection)
+        .map_err(|e| format!("Failed to list attachments: {}", e))?;
+
+    let mut infos = rows.iter().map(|row| decrypt_info(row, key)).collect::<Result<Vec<_>, _>>()?;
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(infos)
+}
+
+/// Re-encrypts every attachment from `old_key` to `new_key`, inside the
+/// caller's transaction, moving it to its entry's new title index. Each
+/// attachment gets a fresh key, and its chunks are re-encrypted under it.
+pub fn rekey_attachments(
+    connection: &SqliteConnection,
+    title_indexes: &HashMap<String, String>,
+    old_key: &Key,
+    new_key: &Key,
+) -> Result<(), diesel::result::Error> {
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+
+    for attachment in attachments::table.load::<Attachment>(connection)? {
+        let info = decrypt_info(&attachment, old_key).map_err(fail)?;
+        let old_binding = attachment.binding();
+        let attachment_key = unwrap_attachment_key(&attachment.wrapped_key, &old_binding, old_key)
+            .ok()
+            .and_then(|bytes| stream::Key::from_slice(&bytes))
+            .ok_or_else(|| fail("Failed to unwrap the attachment key".to_string()))?;
+        let header = base64::decode(&attachment.stream_header)
+            .ok()
+            .and_then(|bytes| Header::from_slice(&bytes))
+            .ok_or_else(|| fail("Invalid attachment header".to_string()))?;
+        let mut decryptor = Stream::init_pull(&header, &attachment_key)
+            .map_err(|_| fail("Failed to initialise decryption stream".to_string()))?;
+
+        let title_index = title_indexes.get(&attachment.title_index).unwrap_or(&attachment.title_index);
+        let name_index = label_index("attachment", &info.name, new_key);
+        let new_binding = binding(title_index, &name_index);
+        let new_attachment_key = stream::gen_key();
+        let (mut encryptor, new_header) = Stream::init_push(&new_attachment_key)
+            .map_err(|_| fail("Failed to initialise encryption stream".to_string()))?;
+
+        let chunks = attachment_chunks::table
+            .filter(attachment_chunks::attachment_id.eq(attachment.id))
+            .order(attachment_chunks::seq.asc())
+            .select((attachment_chunks::seq, attachment_chunks::data))
+            .load::<(i32, Vec<u8>)>(connection)?;
+        for (seq, ciphertext) in chunks {
+            let (plaintext, tag) = decryptor
+                .pull(&ciphertext, Some(old_binding.as_bytes()))
+                .map_err(|_| fail(format!("Chunk {} of attachment '{}' was tampered with", seq, info.name)))?;
+            let ciphertext = encryptor
+                .push(&plaintext, Some(new_binding.as_bytes()), tag)
+                .map_err(|_| fail("Failed to encrypt attachment chunk".to_string()))?;
+            diesel::update(
+                attachment_chunks::table
+                    .filter(attachment_chunks::attachment_id.eq(attachment.id))
+                    .filter(attachment_chunks::seq.eq(seq)),
+            )
+            .set(attachment_chunks::data.eq(ciphertext))
+            .execute(connection)?;
+        }
+
+        let json = serde_json::to_string(&info).expect("Failed to serialize attachment info");
+        diesel::update(attachments::table.filter(attachments::id.eq(attachment.id)))
+            .set((
+                attachments::title_index.eq(title_index),
+                attachments::name_index.eq(&name_index),
+                attachments::encrypted_info.eq(encrypt_label("attachment", &json, &new_binding, new_key)),
+                attachments::wrapped_key.eq(wrap_attachment_key(new_attachment_key.as_ref(), &new_binding, new_key)),
+                attachments::stream_header.eq(base64::encode(new_header.as_ref())),
+            ))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Formats a size in bytes for display, e.g. `2.5 KiB`.
+pub fn format_size(size: u64) -> String {
+    match size {
+        0..=1023 => format!("{} B", size),
+        1024..=1_048_575 => format!("{:.1} KiB", size as f64 / 1024.0),
+        _ => format!("{:.1} MiB", size as f64 / 1_048_576.0),
+    }
+}
+
+fn find_attachment(connection: &SqliteConnection, title_index: &str, name: &str, key: &Key) -> Result<Attachment, String> {
+    attachments::table
+        .filter(attachments::title_index.eq(title_index))
+        .filter(attachments::name_index.eq(label_index("attachment", name.trim(), key)))
+        .first::<Attachment>(connection)
+        .optional()
+        .map_err(|e| format!("Failed to read attachment: {}", e))?
+        .ok_or_else(|| format!("No attachment named '{}'", name))
+}
+
+fn decrypt_info(attachment: &Attachment, key: &Key) -> Result<AttachmentInfo, String> {
+    let json = decrypt_label("attachment", &attachment.encrypted_info, &attachment.binding(), key)?;
+    serde_json::from_str(&json).map_err(|e| format!("Failed to parse attachment info: {}", e))
+}
+
+fn read_stored_chunk(connection: &SqliteConnection, attachment_id: i32, seq: i32) -> Result<Option<Vec<u8>>, String> {
+    attachment_chunks::table
+        .filter(attachment_chunks::attachment_id.eq(attachment_id))
+        .filter(attachment_chunks::seq.eq(seq))
+        .select(attachment_chunks::data)
+        .first::<Vec<u8>>(connection)
+        .optional()
+        .map_err(|e| format!("Failed to read attachment: {}", e))
+}
+
+fn read_chunk(input: &mut impl Read) -> Result<Vec<u8>, String> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    input
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| format!("Failed to read attachment: {}", e))?;
+    Ok(chunk)
+}
+
+fn too_large(size: u64) -> String {
+    format!(
+        "The file is {}; attachments are limited to {}",
+        format_size(size),
+        format_size(MAX_ATTACHMENT_SIZE)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{add_password, delete_password};
+    use crate::testing::{test_vault, TestVault};
+    use sodiumoxide::crypto::secretbox;
+
+    /// Setup: An isolated test vault holding one entry; its directory holds the files.
+    fn setup() -> TestVault {
+        let vault = test_vault();
+        add_password("index_a", "metadata_a", "password_a", None, &[]).unwrap();
+        vault
+    }
+
+    #[test]
+    fn test_attach_and_extract_round_trip() {
+        // Arrange: A file spanning several chunks
+        let vault = setup();
+        let dir = &vault.dir;
+        let key = secretbox::gen_key();
+        let contents = sodiumoxide::randombytes::randombytes(CHUNK_SIZE * 2 + 123);
+        fs::write(dir.join("kubeconfig"), &contents).unwrap();
+
+        // Act
+        let info = attach("index_a", "kubeconfig", &dir.join("kubeconfig"), &key).expect("Attach should succeed");
+        let duplicate = attach("index_a", "kubeconfig", &dir.join("kubeconfig"), &key);
+        extract("index_a", "kubeconfig", &dir.join("out"), &key).expect("Extract should succeed");
+
+        // Assert
+        assert_eq!(info.size, contents.len() as u64);
+        assert_eq!(fs::read(dir.join("out")).unwrap(), contents);
+        assert!(duplicate.is_err());
+        assert_eq!(list_attachments("index_a", &key).unwrap(), vec![info]);
+
+        // Act & Assert: Detaching removes it
+        detach("index_a", "kubeconfig", &key).unwrap();
+        assert!(list_attachments("index_a", &key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_extract_detects_tampering_and_truncation() {
+        // Arrange
+        let vault = setup();
+        let dir = &vault.dir;
+        let key = secretbox::gen_key();
+        fs::write(dir.join("codes.pdf"), vec![7u8; CHUNK_SIZE * 3]).unwrap();
+        attach("index_a", "codes.pdf", &dir.join("codes.pdf"), &key).unwrap();
+        let connection = establish_connection();
+
+        // Act: Drop the final chunk
+        diesel::sql_query("DELETE FROM attachment_chunks WHERE seq = 2;").execute(&connection).unwrap();
+        let truncated = extract("index_a", "codes.pdf", &dir.join("out"), &key);
+
+        // Act: Flip a byte in the first chunk
+        diesel::sql_query("UPDATE attachment_chunks SET data = X'00' || substr(data, 2) WHERE seq = 0;")
+            .execute(&connection)
+            .unwrap();
+        let tampered = extract("index_a", "codes.pdf", &dir.join("out"), &key);
+
+        // Assert: Nothing is written when verification fails
+        assert_eq!(truncated, Err("The attachment is truncated".to_string()));
+        assert_eq!(tampered, Err("Chunk 0 of the attachment was tampered with".to_string()));
+        assert!(!dir.join("out").exists());
+
+        // Act & Assert: Deleting the entry deletes its attachments
+        delete_password("index_a").unwrap();
+        assert!(list_attachments("index_a", &key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_attach_rejects_large_files() {
+        // Arrange
+        let vault = setup();
+        let dir = &vault.dir;
+        let file = File::create(dir.join("large")).unwrap();
+        file.set_len(MAX_ATTACHMENT_SIZE + 1).unwrap();
+
+        // Act
+        let result = attach("index_a", "large", &dir.join("large"), &secretbox::gen_key());
+
+        // Assert
+        assert_eq!(result, Err("The file is 16.0 MiB; attachments are limited to 16.0 MiB".to_string()));
+    }
+}
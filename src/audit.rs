@@ -0,0 +1,353 @@
+use serde::Serialize;
+use crate::strength::estimate_strength;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Passwords scoring below this (on the 0-4 scale) are reported as weak.
+const MIN_SCORE: u8 = 3;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A decrypted entry to audit.
+pub struct AuditInput {
+    pub title: String,
+    /// The decrypted password, or why it could not be decrypted.
+    pub password: Result<String, String>,
+    /// Unix time the password was last set, if known.
+    pub modified: Option<u64>,
+}
+
+/// What to check besides strength and reuse.
+pub struct AuditOptions {
+    /// Passwords last set longer ago than this are reported as old.
+    pub max_age_days: u64,
+    pub breaches: Option<BreachList>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntryAudit {
+    pub title: String,
+    /// Strength on the 0-4 scale, if the password could be decrypted.
+    pub score: Option<u8>,
+    pub age_days: Option<u64>,
+    /// Other titles sharing the same password.
+    pub reused_with: Vec<String>,
+    /// How often the password appears in the breach list, if one was given.
+    pub breach_count: Option<u64>,
+    pub issues: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct AuditSummary {
+    pub entries: usize,
+    pub weak: usize,
+    pub reused: usize,
+    pub old: usize,
+    pub breached: usize,
+    pub unreadable: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AuditReport {
+    pub entries: Vec<EntryAudit>,
+    pub summary: AuditSummary,
+}
+
+/// A local copy of a breached password hash list in the Have I Been Pwned
+/// format. Either a directory of range files named after the first five hex
+/// digits of the SHA-1 hash (each line `SUFFIX:COUNT`), as served by the
+/// k-anonymity range API, or a single file of full `HASH:COUNT` lines.
+pub struct BreachList {
+    path: PathBuf,
+}
+
+impl BreachList {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Err(format!("Breach list {} does not exist", path.display()));
+        }
+        Ok(BreachList {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// How often `password` appears in the list; 0 if it does not.
+    pub fn count(&self, password: &str) -> Result<u64, String> {
+        let hash = sha1_smol::Sha1::from(password).digest().to_string().to_uppercase();
+
+        if self.path.is_dir() {
+            // Only the range file of the hash prefix is read, as with the online API
+            let (prefix, suffix) = hash.split_at(5);
+            for name in [prefix.to_string(), format!("{}.txt", prefix)] {
+                match File::open(self.path.join(&name)) {
+                    Ok(file) => return find_hash(file, suffix),
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(format!("Failed to read breach range file {}: {}", name, e)),
+                }
+            }
+            Ok(0)
+        } else {
+            let file = File::open(&self.path)
+                .map_err(|e| format!("Failed to read breach list {}: {}", self.path.display(), e))?;
+            find_hash(file, &hash)
+        }
+    }
+}
+
+fn find_hash(file: File, hash: &str) -> Result<u64, String> {
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read breach list: {}", e))?;
+        if let Some((candidate, count)) = line.trim().split_once(':') {
+            if candidate.eq_ignore_ascii_case(hash) {
+                return Ok(count.trim().parse().unwrap_or(1));
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Parses a duration such as `90d`, `12w`, `6m` or `1y` into days. A bare
+/// number is taken as days.
+pub fn parse_days(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => value.split_at(position),
+        None => (value, "d"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}': expected something like 90d", value))?;
+    let days_per_unit = match unit {
+        "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        "y" => 365,
+        _ => return Err(format!("Invalid duration '{}': use d, w, m or y", value)),
+    };
+    number
+        .checked_mul(days_per_unit)
+        .ok_or_else(|| format!("Invalid duration '{}': it is too long", value))
+}
+
+/// Audits every entry for weak, reused, old and breached passwords.
+pub fn audit(inputs: &[AuditInput], options: &AuditOptions, now: u64) -> Result<AuditReport, String> {
+    let mut titles_by_password: HashMap<&str, Vec<&str>> = HashMap::new();
+    for input in inputs {
+        if let Ok(password) = &input.password {
+            titles_by_password.entry(password).or_default().push(&input.title);
+        }
+    }
+
+    let mut summary = AuditSummary {
+        entries: inputs.len(),
+        ..Default::default()
+    };
+    let mut entries = Vec::new();
+    for input in inputs {
+        let mut issues = Vec::new();
+        let age_days = input.modified.map(|modified| now.saturating_sub(modified) / SECONDS_PER_DAY);
+        match age_days {
+            Some(age) if age > options.max_age_days => {
+                issues.push(format!("not changed for {} days", age));
+                summary.old += 1;
+            }
+            None => issues.push("age unknown".to_string()),
+            _ => {}
+        }
+
+        let password = match &input.password {
+            Ok(password) => password,
+            Err(err) => {
+                issues.push(format!("cannot be checked: {}", err));
+                summary.unreadable += 1;
+                entries.push(EntryAudit {
+                    title: input.title.clone(),
+                    score: None,
+                    age_days,
+                    reused_with: Vec::new(),
+                    breach_count: None,
+                    issues,
+                });
+                continue;
+            }
+        };
+
+        let strength = estimate_strength(password);
+        if strength.score < MIN_SCORE {
+            issues.push(format!("weak: {}", strength.warnings.join(", ")));
+            summary.weak += 1;
+        }
+
+        let reused_with: Vec<String> = titles_by_password[password.as_str()]
+            .iter()
+            .filter(|title| **title != input.title)
+            .map(|title| title.to_string())
+            .collect();
+        if !reused_with.is_empty() {
+            issues.push(format!("reused with {}", reused_with.join(", ")));
+            summary.reused += 1;
+        }
+
+        let breach_count = match &options.breaches {
+            Some(breaches) => Some(breaches.count(password)?),
+            None => None,
+        };
+        if let Some(count) = breach_count.filter(|count| *count > 0) {
+            issues.push(format!("found in {} breaches", count));
+            summary.breached += 1;
+        }
+
+        entries.push(EntryAudit {
+            title: input.title.clone(),
+            score: Some(strength.score),
+            age_days,
+            reused_with,
+            breach_count,
+            issues,
+        });
+    }
+
+    Ok(AuditReport { entries, summary })
+}
+
+/// Renders the report as a plain-text table followed by a summary line.
+pub fn render_table(report: &AuditReport) -> String {
+    let rows: Vec<[String; 4]> = report
+        .entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.title.clone(),
+                entry.score.map_or("-".to_string(), |score| format!("{}/4", score)),
+                entry.age_days.map_or("-".to_string(), |age| format!("{}d", age)),
+                if entry.issues.is_empty() { "ok".to_string() } else { entry.issues.join("; ") },
+            ]
+        })
+        .collect();
+
+    let header = ["TITLE", "STRENGTH", "AGE", "ISSUES"].map(str::to_string);
+    let widths: Vec<usize> = (0..3)
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        output.push_str(&format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}\n",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        ));
+    }
+
+    let summary = &report.summary;
+    output.push_str(&format!(
+        "\n{} entries audited: {} weak, {} reused, {} old, {} breached, {} unreadable.\n",
+        summary.entries, summary.weak, summary.reused, summary.old, summary.breached, summary.unreadable
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn input(title: &str, password: &str, age_days: u64) -> AuditInput {
+        AuditInput {
+            title: title.to_string(),
+            password: Ok(password.to_string()),
+            modified: Some(NOW - age_days * SECONDS_PER_DAY),
+        }
+    }
+
+    #[test]
+    fn test_audit_reports_weak_reused_and_old_passwords() {
+        // Arrange
+        let inputs = [
+            input("bank", "x7#Kq9!vLm2$Rt8w", 10),
+            input("mail", "password1", 10),
+            input("forum", "password1", 500),
+            AuditInput {
+                title: "legacy".to_string(),
+                password: Err("needs its nonce".to_string()),
+                modified: None,
+            },
+        ];
+        let options = AuditOptions {
+            max_age_days: 365,
+            breaches: None,
+        };
+
+        // Act
+        let report = audit(&inputs, &options, NOW).expect("Audit should succeed");
+
+        // Assert
+        assert!(report.entries[0].issues.is_empty());
+        assert_eq!(report.entries[1].reused_with, vec!["forum".to_string()]);
+        assert_eq!(report.entries[2].age_days, Some(500));
+        assert_eq!(
+            report.summary,
+            AuditSummary {
+                entries: 4,
+                weak: 2,
+                reused: 2,
+                old: 1,
+                breached: 0,
+                unreadable: 1,
+            }
+        );
+        assert!(render_table(&report).contains("reused with forum"));
+    }
+
+    #[test]
+    fn test_breach_list_range_files_and_full_list() {
+        // Setup: SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let dir = env::temp_dir().join(format!("pwvault_breach_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("5BAA6"), "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n").unwrap();
+        let full_list = dir.join("full.txt");
+        fs::write(&full_list, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:42\n").unwrap();
+
+        // Act
+        let range = BreachList::open(&dir).unwrap();
+        let full = BreachList::open(&full_list).unwrap();
+
+        // Assert
+        assert_eq!(range.count("password"), Ok(9545824));
+        assert_eq!(range.count("x7#Kq9!vLm2$Rt8w"), Ok(0));
+        assert_eq!(full.count("password"), Ok(42));
+        assert!(BreachList::open(&dir.join("missing")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_days() {
+        // Act & Assert
+        assert_eq!(parse_days("14d"), Ok(14));
+        assert_eq!(parse_days("2w"), Ok(14));
+        assert_eq!(parse_days("90"), Ok(90));
+        assert_eq!(parse_days("1y"), Ok(365));
+        assert!(parse_days("soon").is_err());
+        assert!(parse_days("3h").is_err());
+        assert_eq!(
+            parse_days("100000000000000000y"),
+            Err("Invalid duration '100000000000000000y': it is too long".to_string())
+        );
+    }
+}
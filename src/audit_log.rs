@@ -0,0 +1,309 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+use crate::container::create_private_file;
+use crate::header::state_dir;
+use crate::schema::audit_log;
+use crate::schema::audit_log::dsl as audit_log_dsl;
+use std::fs;
+use std::io::Write;
+
+/// The previous hash of the first record.
+const GENESIS_HASH: &str = "genesis";
+
+/// A record of the audit log. Each record carries the hash of the one before
+/// it and is signed with the key of the device that wrote it, so edited,
+/// reordered or removed records break the chain.
+#[derive(Queryable, Insertable, Debug, Clone, PartialEq)]
+#[table_name = "audit_log"]
+pub struct LogRecord {
+    pub seq: i64,
+    pub timestamp: i64,
+    /// What was done, e.g. `add`, `get`, `extract` or `clean`.
+    pub action: String,
+    /// Blind index of the entry's title; empty for vault-wide actions.
+    pub title_index: String,
+    /// The entry's title, encrypted like folder and tag names.
+    pub encrypted_title: String,
+    /// Public signing key of the device that wrote the record.
+    pub device_key: String,
+    pub prev_hash: String,
+    pub hash: String,
+    pub signature: String,
+}
+
+impl LogRecord {
+    /// The bytes covered by the record hash and signature.
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!(
+            "pwvault-audit-v1\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.seq, self.timestamp, self.action, self.title_index, self.encrypted_title, self.device_key, self.prev_hash
+        )
+        .into_bytes()
+    }
+
+    /// A short fingerprint of the device that wrote the record.
+    pub fn device_id(&self) -> String {
+        base64::decode(&self.device_key)
+            .unwrap_or_default()
+            .iter()
+            .take(4)
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Creates the `audit_log` table if it does not already exist. The log is
+/// never dropped, not even by `clean`.
+pub fn create_audit_log_table(connection: &SqliteConnection) -> QueryResult<()> {
+    diesel::sql_query(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            seq BIGINT PRIMARY KEY,
+            timestamp BIGINT NOT NULL,
+            action TEXT NOT NULL,
+            title_index TEXT NOT NULL,
+            encrypted_title TEXT NOT NULL,
+            device_key TEXT NOT NULL,
+            prev_hash TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            signature TEXT NOT NULL
+        );",
+    )
+    .execute(connection)
+    .map(|_| ())
+}
+
+/// Loads every record, oldest first.
+pub fn load_records(connection: &SqliteConnection) -> QueryResult<Vec<LogRecord>> {
+    audit_log_dsl::audit_log
+        .order(audit_log_dsl::seq.asc())
+        .load::<LogRecord>(connection)
+}
+
+/// Loads the newest record, which the vault header covers so the log cannot
+/// be truncated without the vault key.
+pub fn last_record(connection: &SqliteConnection) -> QueryResult<Option<LogRecord>> {
+    audit_log_dsl::audit_log
+        .order(audit_log_dsl::seq.desc())
+        .first::<LogRecord>(connection)
+        .optional()
+}
+
+/// The time of the newest correctly signed record written by the device with
+/// the public key `device_key`, if there is one.
+pub fn last_activity(connection: &SqliteConnection, device_key: &str) -> QueryResult<Option<i64>> {
+    let records = audit_log_dsl::audit_log
+        .filter(audit_log_dsl::device_key.eq(device_key))
+        .order(audit_log_dsl::seq.desc())
+        .load::<LogRecord>(connection)?;
+    Ok(records
+        .iter()
+        .find(|record| verify_signature(&record.signed_bytes(), record))
+        .map(|record| record.timestamp))
+}
+
+/// Appends a record after the newest one. Must run inside a write transaction.
+pub fn append_record(
+    connection: &SqliteConnection,
+    action: &str,
+    title_index: &str,
+    encrypted_title: &str,
+    device: &sign::SecretKey,
+    timestamp: i64,
+) -> QueryResult<LogRecord> {
+    let previous = last_record(connection)?;
+    let record = new_record(previous.as_ref(), action, title_index, encrypted_title, device, timestamp);
+    diesel::insert_into(audit_log::table)
+        .values(&record)
+        .execute(connection)?;
+    Ok(record)
+}
+
+/// Builds and signs the record following `previous`.
+fn new_record(
+    previous: Option<&LogRecord>,
+    action: &str,
+    title_index: &str,
+    encrypted_title: &str,
+    device: &sign::SecretKey,
+    timestamp: i64,
+) -> LogRecord {
+    let mut record = LogRecord {
+        seq: previous.map_or(1, |previous| previous.seq + 1),
+        timestamp,
+        action: action.to_string(),
+        title_index: title_index.to_string(),
+        encrypted_title: encrypted_title.to_string(),
+        device_key: base64::encode(device.public_key().as_ref()),
+        prev_hash: previous.map_or(GENESIS_HASH.to_string(), |previous| previous.hash.clone()),
+        hash: String::new(),
+        signature: String::new(),
+    };
+    let signed_bytes = record.signed_bytes();
+    record.hash = base64::encode(sha256::hash(&signed_bytes).as_ref());
+    record.signature = base64::encode(sign::sign_detached(&signed_bytes, device).as_ref());
+    record
+}
+
+/// Checks that the records form an unbroken chain from the first one: no
+/// record is missing, each one follows the one before it, and every hash and
+/// signature matches. Returns a description of every problem found.
+pub fn verify_chain(records: &[LogRecord]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut previous: Option<&LogRecord> = None;
+
+    for record in records {
+        let expected_seq = previous.map_or(1, |previous| previous.seq + 1);
+        if record.seq == expected_seq + 1 {
+            problems.push(format!("record {} is missing", expected_seq));
+        } else if record.seq != expected_seq {
+            problems.push(format!("records {} to {} are missing", expected_seq, record.seq - 1));
+        }
+
+        let expected_prev_hash = previous.map_or(GENESIS_HASH, |previous| previous.hash.as_str());
+        if record.prev_hash != expected_prev_hash {
+            problems.push(format!("record {} does not follow the record before it", record.seq));
+        }
+
+        let signed_bytes = record.signed_bytes();
+        if record.hash != base64::encode(sha256::hash(&signed_bytes).as_ref()) {
+            problems.push(format!("record {} was modified", record.seq));
+        }
+        if !verify_signature(&signed_bytes, record) {
+            problems.push(format!("record {} has an invalid signature", record.seq));
+        }
+
+        previous = Some(record);
+    }
+    problems
+}
+
+fn verify_signature(signed_bytes: &[u8], record: &LogRecord) -> bool {
+    let public_key = base64::decode(&record.device_key)
+        .ok()
+        .and_then(|key| sign::PublicKey::from_slice(&key));
+    let signature = base64::decode(&record.signature)
+        .ok()
+        .and_then(|signature| sign::Signature::from_bytes(&signature).ok());
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => sign::verify_detached(&signature, signed_bytes, &public_key),
+        _ => false,
+    }
+}
+
+/// Loads the signing key of this device from the state directory, creating
+/// it on first use. It stays on this machine and is never stored in a vault.
+pub fn device_key() -> Result<sign::SecretKey, String> {
+    let path = state_dir()
+        .ok_or("Cannot locate the state directory; set XDG_STATE_HOME or HOME")?
+        .join("device_key");
+
+    if let Ok(encoded) = fs::read_to_string(&path) {
+        return base64::decode(encoded.trim())
+            .ok()
+            .and_then(|key| sign::SecretKey::from_slice(&key))
+            .ok_or_else(|| format!("Device key {} is corrupted", path.display()));
+    }
+
+    let (_, secret_key) = sign::gen_keypair();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    create_private_file(&path)
+        .and_then(|mut file| file.write_all(base64::encode(secret_key.as_ref()).as_bytes()))
+        .map_err(|e| format!("Failed to store device key {}: {}", path.display(), e))?;
+    Ok(secret_key)
+}
+
+/// Formats a Unix time as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86_400);
+    let seconds = timestamp.rem_euclid(86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(device: &sign::SecretKey, actions: &[&str]) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = Vec::new();
+        for (i, action) in actions.iter().enumerate() {
+            let record = new_record(records.last(), action, "index", "title", device, 1_700_000_000 + i as i64);
+            records.push(record);
+        }
+        records
+    }
+
+    #[test]
+    fn test_verify_chain_accepts_intact_log() {
+        // Arrange
+        let (_, device) = sign::gen_keypair();
+        let records = chain(&device, &["add", "get", "modify", "delete"]);
+
+        // Act & Assert
+        assert_eq!(records[3].seq, 4);
+        assert!(verify_chain(&records).is_empty());
+        assert!(verify_chain(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_verify_chain_detects_edits_and_removals() {
+        // Arrange
+        let (_, device) = sign::gen_keypair();
+        let (_, other_device) = sign::gen_keypair();
+        let records = chain(&device, &["add", "get", "modify", "delete"]);
+
+        // Act: Change an action, keeping the old hash
+        let mut edited = records.clone();
+        edited[1].action = "add".to_string();
+
+        // Act: Remove a record from the middle
+        let mut removed = records.clone();
+        removed.remove(1);
+
+        // Act: Rewrite the last record with another device's key
+        let mut forged = records.clone();
+        forged[3] = new_record(Some(&records[2]), "clean", "", "", &other_device, 1_700_000_100);
+        forged[3].device_key = records[3].device_key.clone();
+
+        // Assert
+        assert_eq!(
+            verify_chain(&edited),
+            vec!["record 2 was modified".to_string(), "record 2 has an invalid signature".to_string()]
+        );
+        assert_eq!(
+            verify_chain(&removed),
+            vec!["record 2 is missing".to_string(), "record 3 does not follow the record before it".to_string()]
+        );
+        assert!(verify_chain(&forged).contains(&"record 4 has an invalid signature".to_string()));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        // Act & Assert
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34:56 UTC");
+    }
+}
@@ -0,0 +1,443 @@
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox::Key;
+use crate::config::GeneratorPolicy;
+use crate::database::{apply_entry_writes, get_entry, EntryWrite};
+use crate::encryption::{decrypt_metadata, decrypt_password, encrypt_metadata, encrypt_password, title_index, unix_now, EntryMetadata};
+use crate::expiry::parse_rotation;
+use crate::items::{decode_fields, entry_secret, ItemFields, ItemType};
+use crate::labels::{folder_label, normalize_folder, tag_label, EntryLabels};
+use crate::url_match::{url_rules, UrlRule};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// A manifest of `batch apply`: changes to entries, applied in order.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub operations: Vec<Operation>,
+}
+
+/// One change of a manifest, named by its `action`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Operation {
+    /// Adds an entry, as `add` does.
+    Add(EntrySpec),
+    /// Adds a login whose password is generated under the generator policy.
+    Generate(EntrySpec),
+    /// Changes the members given, leaving the others as they are.
+    Modify(EntrySpec),
+    Delete(DeletedEntry),
+}
+
+/// An entry as a manifest describes it. Which members each action takes is
+/// checked when the manifest is planned. For `modify`, an empty `username`,
+/// `folder` or `rotate_every` removes it, and `urls` and `tags` replace the
+/// ones the entry has.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EntrySpec {
+    pub title: String,
+    #[serde(rename = "type")]
+    pub item_type: Option<ItemType>,
+    pub password: Option<String>,
+    /// The length of a generated password, instead of that of the configuration.
+    pub length: Option<usize>,
+    #[serde(default)]
+    pub fields: ItemFields,
+    pub username: Option<String>,
+    /// URLs as `[mode:]url`, as given to `add --url`.
+    pub urls: Option<Vec<String>>,
+    pub folder: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// How often the password must be changed, e.g. `90d`.
+    pub rotate_every: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeletedEntry {
+    pub title: String,
+}
+
+/// What one operation of a manifest changes. Secrets are never part of the diff.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Change {
+    /// `add`, `generate`, `modify` or `delete`.
+    pub action: &'static str,
+    pub title: String,
+    /// One line per member set or changed, e.g. `username: ops -> deploy`.
+    pub diff: Vec<String>,
+}
+
+/// A validated manifest: what it changes, and the writes that make the changes.
+pub struct Plan {
+    pub changes: Vec<Change>,
+    writes: Vec<EntryWrite>,
+}
+
+impl Plan {
+    /// Writes every change in one transaction; if any write fails, none is kept.
+    pub fn apply(&self) -> Result<(), String> {
+        apply_entry_writes(&self.writes).map_err(|e| format!("Failed to write the batch, so nothing was written: {}", e))
+    }
+}
+
+/// An entry as the operations planned so far leave it.
+#[derive(Clone)]
+struct PlannedEntry {
+    metadata: EntryMetadata,
+    secret: String,
+    folder: Option<String>,
+    tags: Vec<String>,
+}
+
+/// The entries of the vault as the operations planned so far leave them.
+/// Entries are read from the vault when first needed; `None` is a deleted one.
+struct Entries<'a> {
+    key: &'a Key,
+    labels: EntryLabels,
+    planned: HashMap<String, Option<PlannedEntry>>,
+}
+
+impl Entries<'_> {
+    fn get(&mut self, index: &str) -> Result<Option<PlannedEntry>, String> {
+        if let Some(entry) = self.planned.get(index) {
+            return Ok(entry.clone());
+        }
+        let entry = match get_entry(index) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let secret = decrypt_password(&entry.encrypted_password, index, None, self.key)?;
+        let metadata = decrypt_metadata(&entry.encrypted_metadata, index, self.key)?;
+        let planned = PlannedEntry {
+            metadata,
+            secret,
+            folder: self.labels.folder(index).map(str::to_string),
+            tags: self.labels.tags(index).to_vec(),
+        };
+        self.planned.insert(index.to_string(), Some(planned.clone()));
+        Ok(Some(planned))
+    }
+}
+
+/// Reads a manifest, as JSON or YAML by the extension of its file.
+pub fn load_manifest(path: &Path) -> Result<Manifest, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+    let manifest = match extension.as_deref() {
+        Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+        _ => return Err(format!("Unknown manifest format of {}: use a .json, .yaml or .yml file", path.display())),
+    };
+    manifest.map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))
+}
+
+/// Validates every operation of a manifest against the vault as the
+/// operations before it leave it, without writing anything. Fails with one
+/// message per invalid operation.
+pub fn plan(manifest: &Manifest, generator: &GeneratorPolicy, key: &Key) -> Result<Plan, Vec<String>> {
+    let labels = EntryLabels::load(key).map_err(|e| vec![format!("Failed to decrypt folders and tags: {}", e)])?;
+    let mut entries = Entries {
+        key,
+        labels,
+        planned: HashMap::new(),
+    };
+    let mut plan = Plan {
+        changes: Vec::new(),
+        writes: Vec::new(),
+    };
+    let mut errors = Vec::new();
+
+    for (number, operation) in manifest.operations.iter().enumerate() {
+        let (action, title) = match operation {
+            Operation::Add(spec) => ("add", &spec.title),
+            Operation::Generate(spec) => ("generate", &spec.title),
+            Operation::Modify(spec) => ("modify", &spec.title),
+            Operation::Delete(entry) => ("delete", &entry.title),
+        };
+        let title = title.trim();
+        let planned = if title.is_empty() {
+            Err("Title cannot be empty.".to_string())
+        } else {
+            let index = title_index(title, key);
+            match operation {
+                Operation::Add(spec) | Operation::Generate(spec) => plan_add(action, title, &index, spec, generator, &mut entries),
+                Operation::Modify(spec) => plan_modify(title, &index, spec, &mut entries),
+                Operation::Delete(_) => plan_delete(title, &index, &mut entries),
+            }
+        };
+        match planned {
+            Ok((diff, write)) => {
+                plan.changes.push(Change {
+                    action,
+                    title: title.to_string(),
+                    diff,
+                });
+                plan.writes.extend(write);
+            }
+            Err(err) => errors.push(format!("Operation {} ({} '{}'): {}", number + 1, action, title, err)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(plan)
+    } else {
+        Err(errors)
+    }
+}
+
+type Planned = Result<(Vec<String>, Option<EntryWrite>), String>;
+
+fn plan_add(action: &str, title: &str, index: &str, spec: &EntrySpec, generator: &GeneratorPolicy, entries: &mut Entries) -> Planned {
+    if entries.get(index)?.is_some() {
+        return Err(format!("A password with the title '{}' already exists.", title));
+    }
+    let item_type = spec.item_type.unwrap_or_default();
+    let mut diff = Vec::new();
+    if !item_type.is_login() {
+        diff.push(format!("type: {}", item_type.name()));
+    }
+
+    let secret = if action == "generate" {
+        if !item_type.is_login() || spec.password.is_some() || !spec.fields.is_empty() {
+            return Err("Only the passwords of logins are generated; use add for this entry.".to_string());
+        }
+        let policy = GeneratorPolicy {
+            length: spec.length.unwrap_or(generator.length),
+            ..generator.clone()
+        };
+        if !(8..=1024).contains(&policy.length) {
+            return Err("The length of a generated password must be between 8 and 1024.".to_string());
+        }
+        diff.push(format!("password: generated, {} characters", policy.length));
+        policy.generate()
+    } else {
+        if spec.length.is_some() {
+            return Err("Only generate takes a length.".to_string());
+        }
+        let secret = entry_secret(item_type, spec.password.clone(), spec.fields.clone(), ItemFields::new())?;
+        if item_type.is_login() {
+            diff.push("password: set".to_string());
+        } else {
+            diff.push(format!("fields: {}", decode_fields(&secret)?.keys().cloned().collect::<Vec<_>>().join(", ")));
+        }
+        secret
+    };
+
+    let (url, urls) = parse_urls(spec.urls.as_deref().unwrap_or_default())?;
+    let folder = spec.folder.as_deref().map(normalize_folder).transpose()?.flatten();
+    let tags = normalize_tags(spec.tags.as_deref().unwrap_or_default())?;
+    let rotate_every_days = spec.rotate_every.as_deref().map(parse_rotation).transpose()?;
+    let metadata = EntryMetadata {
+        title: title.to_string(),
+        item_type,
+        username: spec.username.clone().filter(|username| !username.is_empty()),
+        url,
+        urls,
+        created: Some(unix_now()),
+        modified: Some(unix_now()),
+        rotate_every_days,
+    };
+
+    let added = PlannedEntry {
+        metadata,
+        secret,
+        folder,
+        tags,
+    };
+    diff.extend(describe(&added));
+    let write = EntryWrite::Insert {
+        title_index: index.to_string(),
+        encrypted_metadata: encrypt_metadata(&added.metadata, index, entries.key),
+        encrypted_password: encrypt_password(&added.secret, index, entries.key),
+        folder: added.folder.as_deref().map(|path| folder_label(path, entries.key)),
+        tags: added.tags.iter().map(|tag| tag_label(tag, entries.key)).collect::<Result<_, _>>()?,
+    };
+    entries.planned.insert(index.to_string(), Some(added));
+    Ok((diff, Some(write)))
+}
+
+fn plan_modify(title: &str, index: &str, spec: &EntrySpec, entries: &mut Entries) -> Planned {
+    let old = entries.get(index)?.ok_or_else(|| format!("No password found for '{}'.", title))?;
+    let item_type = old.metadata.item_type;
+    if spec.item_type.is_some_and(|new_type| new_type != item_type) {
+        return Err(format!("The type of an entry cannot be changed; it is a {}.", item_type.name()));
+    }
+    if spec.length.is_some() {
+        return Err("Only generate takes a length.".to_string());
+    }
+
+    let mut new = old.clone();
+    if spec.password.is_some() || !spec.fields.is_empty() {
+        let fields = if item_type.is_login() { ItemFields::new() } else { decode_fields(&old.secret)? };
+        new.secret = entry_secret(item_type, spec.password.clone(), spec.fields.clone(), fields)?;
+    }
+    if let Some(username) = &spec.username {
+        new.metadata.username = Some(username.clone()).filter(|username| !username.is_empty());
+    }
+    if let Some(urls) = &spec.urls {
+        (new.metadata.url, new.metadata.urls) = parse_urls(urls)?;
+    }
+    if let Some(folder) = &spec.folder {
+        new.folder = normalize_folder(folder)?;
+    }
+    if let Some(tags) = &spec.tags {
+        new.tags = normalize_tags(tags)?;
+    }
+    if let Some(rotate_every) = &spec.rotate_every {
+        new.metadata.rotate_every_days = Some(rotate_every.as_str()).filter(|every| !every.trim().is_empty()).map(parse_rotation).transpose()?;
+    }
+
+    let mut diff = Vec::new();
+    if new.secret != old.secret {
+        new.metadata.touch();
+        if item_type.is_login() {
+            diff.push("password: changed".to_string());
+        } else {
+            let (old_fields, new_fields) = (decode_fields(&old.secret)?, decode_fields(&new.secret)?);
+            let changed: BTreeSet<&str> = new_fields
+                .keys()
+                .chain(old_fields.keys())
+                .filter(|name| old_fields.get(*name) != new_fields.get(*name))
+                .map(String::as_str)
+                .collect();
+            diff.push(format!("fields changed: {}", changed.into_iter().collect::<Vec<_>>().join(", ")));
+        }
+    }
+    let (old_lines, new_lines) = (describe_all(&old), describe_all(&new));
+    for ((name, old_value), (_, new_value)) in old_lines.iter().zip(&new_lines) {
+        if old_value != new_value {
+            diff.push(format!("{}: {} -> {}", name, old_value.as_deref().unwrap_or("(none)"), new_value.as_deref().unwrap_or("(none)")));
+        }
+    }
+    if diff.is_empty() {
+        return Ok((diff, None));
+    }
+
+    let write = EntryWrite::Update {
+        title_index: index.to_string(),
+        encrypted_metadata: encrypt_metadata(&new.metadata, index, entries.key),
+        encrypted_password: encrypt_password(&new.secret, index, entries.key),
+        folder: (new.folder != old.folder).then(|| new.folder.as_deref().map(|path| folder_label(path, entries.key))),
+        tags: if new.tags != old.tags {
+            Some(new.tags.iter().map(|tag| tag_label(tag, entries.key)).collect::<Result<_, _>>()?)
+        } else {
+            None
+        },
+    };
+    entries.planned.insert(index.to_string(), Some(new));
+    Ok((diff, Some(write)))
+}
+
+fn plan_delete(title: &str, index: &str, entries: &mut Entries) -> Planned {
+    if entries.get(index)?.is_none() {
+        return Err(format!("No password found for '{}'.", title));
+    }
+    entries.planned.insert(index.to_string(), None);
+    Ok((Vec::new(), Some(EntryWrite::Delete { title_index: index.to_string() })))
+}
+
+/// Parses `[mode:]url` rules. As with `add`, the first URL matched by host is
+/// kept as the URL of the entry.
+fn parse_urls(specs: &[String]) -> Result<(Option<String>, Vec<UrlRule>), String> {
+    let mut urls = specs.iter().map(|spec| UrlRule::parse(spec)).collect::<Result<Vec<_>, _>>()?;
+    let url = urls.iter().position(|rule| rule.mode.is_default()).map(|i| urls.remove(i).url);
+    Ok((url, urls))
+}
+
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut tags = tags
+        .iter()
+        .map(|tag| match tag.trim() {
+            "" => Err("Tags cannot be empty".to_string()),
+            tag => Ok(tag.to_string()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// The members of an entry shown in a diff, by name, with their values.
+fn describe_all(entry: &PlannedEntry) -> Vec<(&'static str, Option<String>)> {
+    let join = |values: Vec<String>| Some(values.join(", ")).filter(|joined| !joined.is_empty());
+    vec![
+        ("username", entry.metadata.username.clone()),
+        ("urls", join(url_rules(&entry.metadata).iter().map(UrlRule::to_string).collect())),
+        ("folder", entry.folder.clone()),
+        ("tags", join(entry.tags.clone())),
+        ("rotate every", entry.metadata.rotate_every_days.map(|days| format!("{}d", days))),
+    ]
+}
+
+/// The members an added entry sets.
+fn describe(entry: &PlannedEntry) -> Vec<String> {
+    describe_all(entry)
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{}: {}", name, value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests_parse_from_json_and_yaml() {
+        // Arrange
+        let json = r#"{"operations": [
+            {"action": "generate", "title": "prod-db", "username": "app", "length": 32, "tags": ["db"]},
+            {"action": "delete", "title": "legacy-db"}
+        ]}"#;
+        let yaml = "operations:\n  - action: generate\n    title: prod-db\n    username: app\n    length: 32\n    tags: [db]\n  - action: delete\n    title: legacy-db\n";
+
+        // Act
+        let from_json: Manifest = serde_json::from_str(json).unwrap();
+        let from_yaml: Manifest = serde_yaml::from_str(yaml).unwrap();
+
+        // Assert
+        assert_eq!(from_json, from_yaml);
+        assert_eq!(
+            from_json.operations[0],
+            Operation::Generate(EntrySpec {
+                title: "prod-db".to_string(),
+                username: Some("app".to_string()),
+                length: Some(32),
+                tags: Some(vec!["db".to_string()]),
+                ..EntrySpec::default()
+            })
+        );
+        assert!(serde_json::from_str::<Manifest>(r#"{"operations": [{"action": "add", "title": "x", "pasword": "typo"}]}"#).is_err());
+        assert!(serde_json::from_str::<Manifest>(r#"{"operations": [{"action": "rename", "title": "x"}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_diffs_describe_members_without_secrets() {
+        // Arrange
+        let entry = PlannedEntry {
+            metadata: EntryMetadata {
+                title: "prod-db".to_string(),
+                username: Some("app".to_string()),
+                url: Some("db.example.com".to_string()),
+                rotate_every_days: Some(90),
+                ..EntryMetadata::default()
+            },
+            secret: "hunter2".to_string(),
+            folder: Some("prod".to_string()),
+            tags: normalize_tags(&["db".to_string(), " ops ".to_string(), "db".to_string()]).unwrap(),
+        };
+
+        // Act
+        let diff = describe(&entry);
+
+        // Assert
+        assert_eq!(
+            diff,
+            ["username: app", "urls: host:db.example.com", "folder: prod", "tags: db, ops", "rotate every: 90d"]
+        );
+        assert!(normalize_tags(&[" ".to_string()]).is_err());
+    }
+}
@@ -1,6 +1,45 @@
-use clap::{App, Arg, SubCommand};
-use crate::database::{add_password, get_password, clean_database};
-use crate::encryption::{encrypt_password, decrypt_password, generate_key};
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+use crate::api::{bind_tcp, default_socket_path as default_api_socket_path, serve_tcp, serve_unix, Api};
+use crate::attachments::{attach, detach, extract, format_size, list_attachments, AttachmentInfo};
+use crate::audit::{audit, parse_days, render_table, AuditInput, AuditOptions, BreachList};
+use crate::audit_log::{device_key, format_timestamp, verify_chain};
+use crate::batch::{load_manifest, plan as plan_batch};
+use crate::config::{config_path, load_config, render_config, set_config_value, GeneratorPolicy, OutputFormat};
+use crate::container::{close_vault, convert_vault, create_private_file, is_container, VaultFormat};
+use crate::database::{
+    add_password, clean_database, create_table_if_not_exists, get_entry, get_password, list_passwords, migrate_plaintext_titles,
+    clear_folder_policy, load_audit_log, log_event, move_entries, select_vault, set_folder_policy, update_metadata, update_password, upgrade_legacy_metadata, vault_path, verify_vault_integrity,
+};
+use crate::emergency::{add_contact, deny_request, emergency_access, list_contacts, remove_contact, request_access, AccessState};
+use crate::encryption::{
+    decrypt_label, decrypt_metadata, decrypt_password, encrypt_label, encrypt_metadata, encrypt_password, generate_key, is_legacy_ciphertext,
+    label_index, normalize_title, title_index, unix_now, EntryMetadata,
+};
+use crate::expiry::{due, effective_policy, load_folder_policies, parse_rotation, policy_for_entry, policy_label, Due, FolderPolicy};
+use crate::recovery::{combine_shares, split_secret, Share, ShareFormat};
+use crate::references::{locate_secret, parse_env_mapping, read_secret, resolve_secret};
+use crate::search::{search, suggest, Candidate};
+use crate::sharing::{
+    encode_public_key, list_shares, load_or_create_identity, parse_public_key, received_shares, refresh_shared_entry, revoke_share,
+    share_entry, SharedItem,
+};
+use crate::ssh_agent::{bind_socket, confirm_on_terminal, default_socket_path, load_agent_keys, serve, Agent};
+use crate::team::{add_member, create_team, is_team_vault, member_key, parse_member_key, recover_team, remove_member, set_role, unlock_team, Role, Team};
+use crate::templates::{parse_template, render_template};
+use crate::tokens::{
+    authenticate, create_token, granted_entries, list_tokens, parse_ttl, revoke_token, sync_token_grants, ApiToken, Scope, TokenOptions,
+};
+use crate::items::{apply_fields, decode_fields, encode_fields, parse_field, render_fields, ItemFields, ItemType};
+use crate::labels::{folder_label, is_in_folder, normalize_folder, tag_label, EntryLabels};
+use crate::native_host::{serve as serve_native_host, NativeHost};
+use crate::output::{emit, finish_report, is_json, start_report, Attachment, AuditLog, BatchResult, ConfigValue, DetachedFile, EmergencyContact, EmergencyRequest, EntryList, EntryRef, ExpiringEntry, ExtractedFile, IncomingShares, Injection, ListedEntry, Listening, LogEntry, FolderRotation, OutgoingShare, PublicKey, RecoveryShare, RecoveryShares, RemovedKey, RotationPolicy, ServedKey, ShareChange, TeamMember, TeamMembers, TokenInfo, VaultInfo, MatchedEntry, SearchResult, SecretEntry};
+use crate::url_match::{match_entries, page_url, url_rules, UrlRule};
+use crate::vaults::{load_registry, register_vault, resolve_vault, set_default_vault, unregister_vault};
+use sodiumoxide::crypto::secretbox::Key;
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 // main function for processing and handling CLI commands.
 pub fn run_cli() {
@@ -8,6 +47,28 @@ pub fn run_cli() {
         .version("0.1")
         .author("Siting Li & Zifan Meng")
         .about("Secure decentralized password manager")
+        .arg(
+            Arg::with_name("vault")
+                .long("vault")
+                .global(true)
+                .takes_value(true)
+                .help("The vault to use, by registered name or by path"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .global(true)
+                .takes_value(true)
+                .help("The configuration file to use instead of ~/.config/pwvault/config.toml"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .help("Print text, or a single JSON report for scripts; defaults to the output setting of the configuration"),
+        )
         .subcommand(
             // Takes the title and password, checks if they are valid, encrypts the password, and attempts to add it to the database.
             SubCommand::with_name("add")
@@ -25,14 +86,62 @@ pub fn run_cli() {
                         .long("password")
                         .short('p')
                         .takes_value(true)
-                        .required(true)
-                        .help("The password to store"),
+                        .help("The password to store; required for logins"),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .possible_values(ItemType::ALL.map(ItemType::name))
+                        .default_value("login")
+                        .help("The kind of item to store"),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .long("field")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("A field of a non-login item as name=value, or name=@file to read it from a file"),
+                )
+                .arg(
+                    Arg::with_name("username")
+                        .long("username")
+                        .short('u')
+                        .takes_value(true)
+                        .help("The username of the account"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .long("url")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("A URL of the site or service as [mode:]url, where mode is exact, host (the default), domain, starts-with or regex; can be given several times"),
+                )
+                .arg(
+                    Arg::with_name("folder")
+                        .long("folder")
+                        .short('f')
+                        .takes_value(true)
+                        .help("The folder to file the password under, e.g. prod/db/postgres"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("A tag for the password; can be given several times"),
+                )
+                .arg(
+                    Arg::with_name("rotate-every")
+                        .long("rotate-every")
+                        .takes_value(true)
+                        .help("How often the password must be changed, e.g. 90d; overrides folder policies"),
                 ),
         )
-        // Retrieves the password for the given title, decrypts it using the provided nonce, and displays the decrypted password.
+        // Retrieves the password for the given title, decrypts it, and displays the decrypted password.
         .subcommand(
             SubCommand::with_name("get")
-                .about("Retrieves a password: cargo run -- get --title/t <example_title> --nonce/n <your_nonce>")
+                .about("Retrieves a password: cargo run -- get --title/t <example_title>")
                 .arg(
                     Arg::with_name("title")
                         .long("title")
@@ -45,7 +154,443 @@ pub fn run_cli() {
                         .long("nonce")
                         .short('n')
                         .takes_value(true)
-                        .help("The nonce required for decryption"),
+                        .help("The nonce of an entry stored before authenticated encryption"),
+                ),
+        )
+        // Decrypts the metadata of every entry and prints the stored titles.
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists the titles of all stored passwords: cargo run -- list [--folder <folder>] [--tag <tag>]")
+                .arg(
+                    Arg::with_name("folder")
+                        .long("folder")
+                        .short('f')
+                        .takes_value(true)
+                        .help("Only list passwords in this folder or its subfolders"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Only list passwords carrying this tag; can be given several times"),
+                )
+        )
+        // Ranks entries by a fuzzy match of the query against their decrypted fields.
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Searches titles, usernames, URLs and tags: cargo run -- search <query>")
+                .arg(Arg::with_name("query").required(true).help("What to look for; typos are tolerated"))
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("The maximum number of results"),
+                )
+        )
+        // Finds the entries whose URLs match a page, the most specific match first.
+        .subcommand(
+            SubCommand::with_name("match")
+                .about("Lists the entries whose URLs match a page: cargo run -- match <url>")
+                .arg(Arg::with_name("url").required(true).help("The URL of the page, e.g. https://git.example.com/login"))
+        )
+        // Decrypts every entry and reports weak, reused, old and breached passwords.
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Audits all passwords for weak, reused, old and breached ones: cargo run -- audit [--breaches <path>]")
+                .arg(
+                    Arg::with_name("max-age")
+                        .long("max-age")
+                        .takes_value(true)
+                        .default_value("365d")
+                        .help("Report passwords not changed for longer than this, e.g. 180d or 1y"),
+                )
+                .arg(
+                    Arg::with_name("breaches")
+                        .long("breaches")
+                        .takes_value(true)
+                        .help("An offline Have I Been Pwned list: a directory of SHA-1 range files or a HASH:COUNT file"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["table", "json"])
+                        .help("The report format of text output; with --output json the report is the result"),
+                )
+        )
+        // Shows the tamper-evident log of reads and writes, and checks its chain.
+        .subcommand(
+            SubCommand::with_name("audit-log")
+                .about("Shows the audit log of reads and writes: cargo run -- audit-log [--limit 20] | audit-log verify")
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("20")
+                        .help("How many of the latest records to show"),
+                )
+                .subcommand(SubCommand::with_name("verify").about("Verifies the hash chain and signatures of the audit log")),
+        )
+        // Sets or clears how often passwords must be changed, per entry or per folder.
+        .subcommand(
+            SubCommand::with_name("expiry")
+                .about("Manages rotation policies: cargo run -- expiry set (--title/t <title> | --folder/f <folder>) --every 90d")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Sets a rotation policy on a password or a folder")
+                        .arg(Arg::with_name("title").long("title").short('t').takes_value(true).help("The title of the password"))
+                        .arg(Arg::with_name("folder").long("folder").short('f').takes_value(true).help("The folder, including its subfolders"))
+                        .group(ArgGroup::new("target").args(&["title", "folder"]).required(true))
+                        .arg(
+                            Arg::with_name("every")
+                                .long("every")
+                                .takes_value(true)
+                                .required(true)
+                                .help("How often the password must be changed, e.g. 90d or 1y"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Removes the rotation policy of a password or a folder")
+                        .arg(Arg::with_name("title").long("title").short('t').takes_value(true).help("The title of the password"))
+                        .arg(Arg::with_name("folder").long("folder").short('f').takes_value(true).help("The folder"))
+                        .group(ArgGroup::new("target").args(&["title", "folder"]).required(true)),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists the folder rotation policies")),
+        )
+        // Lists passwords that are due for rotation soon or already overdue.
+        .subcommand(
+            SubCommand::with_name("expiring")
+                .about("Lists passwords due for rotation: cargo run -- expiring [--within 14d]")
+                .arg(
+                    Arg::with_name("within")
+                        .long("within")
+                        .takes_value(true)
+                        .default_value("14d")
+                        .help("Also list passwords due within this time, e.g. 14d or 2w"),
+                )
+        )
+        // Encrypts a file and attaches it to an entry.
+        .subcommand(
+            SubCommand::with_name("attach")
+                .about("Attaches a file to an entry: cargo run -- attach --title/t <example_title> --file <path> [--name <name>]")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The title of the entry"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to attach"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .help("The name to store the file under; defaults to its file name"),
+                ),
+        )
+        // Decrypts an attachment into a file after verifying it.
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Extracts an attachment: cargo run -- extract --title/t <example_title> --name <name> [--out <path>]")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The title of the entry"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The name of the attachment"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .short('o')
+                        .takes_value(true)
+                        .help("Where to write the file; defaults to the attachment name in the current directory"),
+                ),
+        )
+        // Removes an attachment from an entry.
+        .subcommand(
+            SubCommand::with_name("detach")
+                .about("Removes an attachment: cargo run -- detach --title/t <example_title> --name <name>")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The title of the entry"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The name of the attachment"),
+                ),
+        )
+        // Runs a program with secrets from the vault in its environment.
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Runs a command with secrets as environment variables: cargo run -- exec --env DB_PASS=prod/db -- <command>")
+                .arg(
+                    Arg::with_name("env")
+                        .long("env")
+                        .short('e')
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true)
+                        .help("NAME=title or NAME=folder/title; add #field to pick a field, e.g. DB_USER=prod/db#username"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple_values(true)
+                        .last(true)
+                        .required(true)
+                        .help("The command to run and its arguments, after --"),
+                ),
+        )
+        // Renders a template, filling in secrets from the vault.
+        .subcommand(
+            SubCommand::with_name("inject")
+                .about("Fills {{ vault \"title\" \"field\" }} placeholders: cargo run -- inject --in <template> (--out <file> | --check)")
+                .arg(
+                    Arg::with_name("in")
+                        .long("in")
+                        .short('i')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The template to render"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .short('o')
+                        .takes_value(true)
+                        .required_unless_present("check")
+                        .help("Where to write the result, readable only by you"),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .conflicts_with("out")
+                        .help("Only check that every reference resolves, without decrypting anything secret"),
+                ),
+        )
+        // Serves the SSH key items of the vault to ssh over the agent protocol.
+        .subcommand(
+            SubCommand::with_name("ssh-agent")
+                .about("Runs an SSH agent holding the vault's SSH keys: cargo run -- ssh-agent [--socket <path>] [--confirm]")
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("Where to listen; defaults to ~/.local/state/pwvault/ssh-agent.sock"),
+                )
+                .arg(
+                    Arg::with_name("confirm")
+                        .long("confirm")
+                        .help("Ask on this terminal before every signature, as ssh_agent.confirm does"),
+                ),
+        )
+        // Serves the entries of the vault as JSON over a local HTTP API.
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Runs a local HTTP API for the vault, authenticated with API tokens: cargo run -- serve [--listen 127.0.0.1:8787 | --socket <path>]")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .conflicts_with("socket")
+                        .help("A localhost address and port to listen on instead of a Unix socket"),
+                )
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("Where to listen; defaults to ~/.local/state/pwvault/api.sock"),
+                ),
+        )
+        // Manages the tokens clients of `serve` authenticate with.
+        .subcommand(
+            SubCommand::with_name("token")
+                .about("Manages the API tokens accepted by `serve`")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Creates a token and prints it once: cargo run -- token create --name <name> [--scope folder:prod/*] [--read-only] [--ttl 24h]")
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .required(true)
+                                .help("A name to tell the token apart by, e.g. the client using it"),
+                        )
+                        .arg(
+                            Arg::with_name("read-only")
+                                .long("read-only")
+                                .help("Only allow listing and reading entries"),
+                        )
+                        .arg(
+                            Arg::with_name("scope")
+                                .long("scope")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .help("Limit the token to folder:<path>, folder:<path>/*, tag:<tag> or entry:<title>; repeat for more"),
+                        )
+                        .arg(
+                            Arg::with_name("ttl")
+                                .long("ttl")
+                                .takes_value(true)
+                                .help("How long the token lasts, such as 90m, 24h, 7d or 2w; forever when not given"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists the API tokens: cargo run -- token list"))
+                .subcommand(
+                    SubCommand::with_name("revoke")
+                        .about("Revokes an API token: cargo run -- token revoke --name <name>")
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The name of the token to revoke"),
+                        ),
+                ),
+        )
+        // Answers the browser extension over the native messaging protocol.
+        .subcommand(
+            SubCommand::with_name("native-host")
+                .about("Runs the native messaging host the browser extension fills logins with: cargo run -- native-host")
+                .arg(
+                    Arg::with_name("caller")
+                        .multiple_values(true)
+                        .hide(true)
+                        .help("What the browser passes to the host, such as the extension's origin"),
+                ),
+        )
+        // Shows this vault's public key, which others share entries with.
+        .subcommand(
+            SubCommand::with_name("identity")
+                .about("Shows the public key others use to share entries with this vault: cargo run -- identity")
+        )
+        // Shares a copy of an entry with the holder of a public key.
+        .subcommand(
+            SubCommand::with_name("share")
+                .about("Shares an entry with another vault: cargo run -- share --title/t <example_title> --with <public_key>")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The title of the entry to share"),
+                )
+                .arg(
+                    Arg::with_name("with")
+                        .long("with")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The recipient's public key, as printed by their `identity`"),
+                ),
+        )
+        // Revokes a share and re-keys the shared copy for everyone else.
+        .subcommand(
+            SubCommand::with_name("unshare")
+                .about("Stops sharing an entry with someone: cargo run -- unshare --title/t <example_title> --with <public_key>")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .required(true)
+                        .help("The title of the shared entry"),
+                )
+                .arg(
+                    Arg::with_name("with")
+                        .long("with")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The public key to revoke"),
+                ),
+        )
+        // Lists the entries shared from this vault, or shared with it by another vault file.
+        .subcommand(
+            SubCommand::with_name("shared")
+                .about("Lists shared entries: cargo run -- shared [--from <vault> [--title/t <example_title>]]")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("Read the entries another vault file shares with this one"),
+                )
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .requires("from")
+                        .help("Show one entry shared with this vault"),
+                ),
+        )
+        // Moves an entry, or a folder with everything in it, to another folder.
+        .subcommand(
+            SubCommand::with_name("mv")
+                .about("Moves passwords between folders: cargo run -- mv (--title/t <example_title> | --folder/f <folder>) --to <folder>")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .short('t')
+                        .takes_value(true)
+                        .help("The title of the password to move"),
+                )
+                .arg(
+                    Arg::with_name("folder")
+                        .long("folder")
+                        .short('f')
+                        .takes_value(true)
+                        .help("The folder to move, with its subfolders"),
+                )
+                .group(ArgGroup::new("source").args(&["title", "folder"]).required(true))
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The destination folder; / moves to the top level"),
+                )
+        )
+        // Validates a manifest of changes against the vault, then writes them all in one transaction.
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Changes many entries at once from a manifest: cargo run -- batch apply <file.json|yaml> [--dry-run]")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("apply")
+                        .about("Applies the adds, modifies, deletes and generates of a manifest, all of them or none")
+                        .arg(Arg::with_name("file").required(true).help("The manifest, as a .json, .yaml or .yml file"))
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .long("dry-run")
+                                .help("Validates the manifest and shows what it would change, without writing anything"),
+                        ),
                 ),
         )
         // Cleans the database by dropping and recreating the table.
@@ -53,10 +598,261 @@ pub fn run_cli() {
             SubCommand::with_name("clean")
                 .about("Cleans the database by removing all passwords: cargo run -- clean")
         )
+        // Converts the vault file between the plain and the encrypted container format.
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Converts the vault file format: cargo run -- convert --to <encrypted|plain>")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(["encrypted", "plain"])
+                        .help("The format to convert the vault to"),
+                ),
+        )
+        // Manages the registry of named vaults.
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("Manages named vaults: cargo run -- vault <create|list|remove|default>")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Creates a named vault, or registers an existing vault file: cargo run -- vault create <name> [--path <file>] [--encrypted]")
+                        .arg(Arg::with_name("name").required(true).help("The name of the vault"))
+                        .arg(
+                            Arg::with_name("path")
+                                .long("path")
+                                .takes_value(true)
+                                .help("Where to keep the vault file (defaults to the data directory)"),
+                        )
+                        .arg(
+                            Arg::with_name("encrypted")
+                                .long("encrypted")
+                                .help("Store the new vault as an encrypted container"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists the registered vaults"))
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes a vault from the registry: cargo run -- vault remove <name> [--delete-file]")
+                        .arg(Arg::with_name("name").required(true).help("The name of the vault"))
+                        .arg(
+                            Arg::with_name("delete-file")
+                                .long("delete-file")
+                                .help("Also delete the vault file"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("default")
+                        .about("Sets the vault used when --vault is not given: cargo run -- vault default <name>")
+                        .arg(Arg::with_name("name").required(true).help("The name of the vault")),
+                ),
+        )
+        // Team vaults: members with roles, and a collection key sealed for each of them.
+        .subcommand(
+            SubCommand::with_name("team")
+                .about("Manages the members of a team vault")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("Turns the vault into a team vault owned by you: cargo run -- team init"),
+                )
+                .subcommand(
+                    SubCommand::with_name("whoami")
+                        .about("Shows the key you are known by in team vaults: cargo run -- team whoami"),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists the members and their roles: cargo run -- team list"))
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Adds a member: cargo run -- team add --member <key> --role <owner|admin|writer|reader>")
+                        .arg(
+                            Arg::with_name("member")
+                                .long("member")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The member's key, as printed by their `team whoami`"),
+                        )
+                        .arg(
+                            Arg::with_name("role")
+                                .long("role")
+                                .takes_value(true)
+                                .required(true)
+                                .possible_values(["owner", "admin", "writer", "reader"])
+                                .help("What the member may do"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("role")
+                        .about("Changes a member's role: cargo run -- team role --member <key> --role <owner|admin|writer|reader>")
+                        .arg(
+                            Arg::with_name("member")
+                                .long("member")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The member's key, as printed by their `team whoami`"),
+                        )
+                        .arg(
+                            Arg::with_name("role")
+                                .long("role")
+                                .takes_value(true)
+                                .required(true)
+                                .possible_values(["owner", "admin", "writer", "reader"])
+                                .help("What the member may do"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes a member and rotates the team key: cargo run -- team remove --member <key>")
+                        .arg(
+                            Arg::with_name("member")
+                                .long("member")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The member's key, as printed by their `team whoami`"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("emergency")
+                .about("Manages emergency access, released to a contact after a waiting period unless denied")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Adds an emergency contact: cargo run -- emergency add --contact <key> --wait-days 7")
+                        .after_help(
+                            "The vault key is sealed for the contact when they are added. The wait is advisory: \
+                             it is kept by this program, and a contact holding a copy of the vault file can read it without waiting.",
+                        )
+                        .arg(
+                            Arg::with_name("contact")
+                                .long("contact")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The contact's key, as printed by their `team whoami`"),
+                        )
+                        .arg(
+                            Arg::with_name("wait-days")
+                                .long("wait-days")
+                                .takes_value(true)
+                                .default_value("7")
+                                .help("Days you have to deny a request before access is released, counted from the request or your last use of the vault, whichever is later; at most 3650"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes an emergency contact: cargo run -- emergency remove --contact <key>")
+                        .arg(
+                            Arg::with_name("contact")
+                                .long("contact")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The contact's key, as printed by their `team whoami`"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists the emergency contacts and their requests: cargo run -- emergency list"),
+                )
+                .subcommand(
+                    SubCommand::with_name("deny")
+                        .about("Denies a contact's pending request: cargo run -- emergency deny --contact <key>")
+                        .arg(
+                            Arg::with_name("contact")
+                                .long("contact")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The contact's key, as printed by their `team whoami`"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("request")
+                        .about("Asks another vault for emergency access: cargo run -- emergency request --from <vault>")
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Name or path of the vault you are an emergency contact of"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("access")
+                        .about("Reads another vault once access is released: cargo run -- emergency access --from <vault> [--title/t <example_title>]")
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Name or path of the vault you are an emergency contact of"),
+                        )
+                        .arg(
+                            Arg::with_name("title")
+                                .long("title")
+                                .short('t')
+                                .takes_value(true)
+                                .help("The entry to show; all titles are listed without it"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recovery")
+                .about("Splits the key of a team vault into recovery shares, or rebuilds it from them; plain vaults have no key of their own to split")
+                .subcommand_required(true)
+                .subcommand(
+                    SubCommand::with_name("split")
+                        .about("Splits the team key: cargo run -- recovery split --shares 5 --threshold 3")
+                        .arg(
+                            Arg::with_name("shares")
+                                .long("shares")
+                                .takes_value(true)
+                                .required(true)
+                                .help("How many shares to make, at most 255"),
+                        )
+                        .arg(
+                            Arg::with_name("threshold")
+                                .long("threshold")
+                                .takes_value(true)
+                                .required(true)
+                                .help("How many shares it takes to rebuild the key, at least 2"),
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(["words", "text"])
+                                .default_value("words")
+                                .help("Words to write down, or upper-case text for QR codes"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("combine")
+                        .about("Rebuilds the team key and recovers the team vault: cargo run -- recovery combine --share <share> ...")
+                        .arg(
+                            Arg::with_name("share")
+                                .long("share")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .help("A share, in either format; read one per line from standard input when not given"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Shows or changes the configuration file")
+                .subcommand_required(true)
+                .subcommand(SubCommand::with_name("show").about("Shows the effective configuration: cargo run -- config show"))
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Changes one setting: cargo run -- config set <key> <value>, e.g. generator.length 32")
+                        .arg(Arg::with_name("key").required(true).help("The setting to change, such as generator.length"))
+                        .arg(Arg::with_name("value").required(true).help("The new value")),
+                ),
+        )
         // Modify the password
         .subcommand(
             SubCommand::with_name("modify")
-                .about("Modifies an existing password: cargo run -- modify --title/t <example_title> --new_password/p <new_password> --nonce/n <nonce>")
+                .about("Modifies an existing password: cargo run -- modify --title/t <example_title> --new_password/p <new_password>")
                 .arg(
                     Arg::with_name("title")
                         .long("title")
@@ -70,21 +866,26 @@ pub fn run_cli() {
                         .long("new_password")
                         .short('p')
                         .takes_value(true)
-                        .required(true)
-                        .help("The new password to store"),
+                        .help("The new password to store; required for logins"),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .long("field")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("A field of a non-login item to change as name=value or name=@file; an empty value removes it"),
                 )
                 .arg(
                     Arg::with_name("nonce")
                         .long("nonce")
                         .short('n')
                         .takes_value(true)
-                        .required(true)
-                        .help("The nonce required for decrypting the old password"),
+                        .help("The nonce of the old password, if stored before authenticated encryption"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("delete")
-                .about("Deletes a password: cargo run -- delete --title/t <example_title> --nonce/n <nonce>")
+                .about("Deletes a password: cargo run -- delete --title/t <example_title>")
                 .arg(
                     Arg::with_name("title")
                         .long("title")
@@ -98,139 +899,2294 @@ pub fn run_cli() {
                         .long("nonce")
                         .short('n')
                         .takes_value(true)
-                        .required(true)
-                        .help("The nonce required for verifying the password"),
+                        .help("The nonce of the password, if stored before authenticated encryption"),
                 ),
         )
 
         .get_matches();
 
-    let key = generate_key();
+    // Scripts get a single JSON report instead of text. exec and native-host
+    // hand standard output to another program, so they never print one.
+    let config_file = config_path(matches.value_of("config"));
+    let json = match matches.value_of("output") {
+        Some(format) => format == "json",
+        None => load_config(&config_file, matches.is_present("config")).is_ok_and(|config| config.output == OutputFormat::Json),
+    };
+    if json && !matches!(matches.subcommand_name(), Some("exec" | "native-host")) {
+        start_report(&command_name(&matches));
+    }
 
-    if let Some(matches) = matches.subcommand_matches("add") {
-        let title = matches.value_of("title").unwrap();
-        let password = matches.value_of("password").unwrap();
+    run_command(&matches, &config_file);
+    if !finish_report() {
+        exit_with(1);
+    }
+}
+
+/// Runs the subcommand given on the command line.
+fn run_command(matches: &ArgMatches, config_file: &Path) {
+    // `config set` must work even if the current file does not validate.
+    if let Some(matches) = matches.subcommand_matches("config") {
+        run_config_command(matches, config_file);
+        return;
+    }
 
-        if title.is_empty() {
-            println!("Failed to add password: Title cannot be empty.");
+    let config = match load_config(config_file, matches.is_present("config")) {
+        Ok(config) => config,
+        Err(err) => {
+            fail!("Configuration error: {}", err);
             return;
         }
+    };
 
-        // Check if the password is empty
-        if password.is_empty() {
-            println!("Failed to add password: Password cannot be empty.");
+    // Vault management works on the registry rather than on an open vault.
+    if let Some(matches) = matches.subcommand_matches("vault") {
+        run_vault_command(matches);
+        return;
+    }
+
+    // Select the vault given by --vault, DATABASE_URL, the configuration or the registry default.
+    match resolve_vault(matches.value_of("vault"), config.default_vault.as_deref()) {
+        Ok(path) => select_vault(&path.to_string_lossy()),
+        Err(err) => {
+            fail!("{}", err);
+            return;
+        }
+    }
+
+    // Showing your member key needs no vault, so people can ask to join one.
+    if matches.subcommand_matches("team").and_then(|matches| matches.subcommand_matches("whoami")).is_some() {
+        match device_key() {
+            Ok(device) => {
+                say!("Member key: {}", member_key(&device));
+                emit(&PublicKey { public_key: member_key(&device) });
+            }
+            Err(err) => fail!("Failed to load device key: {}", err),
+        }
+        return;
+    }
+
+    // create the necessary database table if it doesn't already exist.
+    create_table_if_not_exists();
+
+    // The browser owns standard output, and is told itself when the vault is locked.
+    if matches.subcommand_matches("native-host").is_some() {
+        run_native_host();
+        return;
+    }
+
+    // Scripts holding a scoped token read through it, without the vault key.
+    if let Ok(secret) = env::var("PWVAULT_TOKEN") {
+        run_token_session(matches, &secret);
+        return;
+    }
+
+    // Recovering a team vault must work for someone who is not yet a member.
+    if let Some(matches) = matches.subcommand_matches("recovery").and_then(|matches| matches.subcommand_matches("combine")) {
+        run_recovery_combine(matches);
+        return;
+    }
+
+    // Team vaults are encrypted under a collection key only their members can unseal.
+    let team = if is_team_vault() {
+        match device_key().and_then(|device| unlock_team(&device)) {
+            Ok(team) => Some(team),
+            Err(err) => {
+                fail!("Failed to open team vault: {}", err);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    if let Some(team) = &team {
+        let required = required_role(matches);
+        if team.role < required {
+            fail!("Permission denied: this needs the {} role, and you are a {} of this team vault.", required.name(), team.role.name());
+            return;
+        }
+    }
+    let key = team.as_ref().map_or_else(generate_key, |team| team.key.clone());
+
+    // encrypt any titles left in plaintext by earlier versions of the vault.
+    if let Err(e) = migrate_plaintext_titles(&key) {
+        fail!("Failed to migrate plaintext titles: {:?}", e);
+        return;
+    }
+
+    // Refuse to work on a vault whose header does not match its contents.
+    // `clean` is still allowed since it discards the vault anyway.
+    if matches.subcommand_matches("clean").is_none() {
+        if let Err(err) = verify_vault_integrity() {
+            fail!("Vault integrity check failed: {}", err);
+            return;
+        }
+        if let Err(e) = upgrade_legacy_metadata(&key) {
+            fail!("Failed to upgrade entry metadata: {:?}", e);
+            return;
+        }
+    }
+
+    // Pending emergency requests are shown on every command until denied.
+    for contact in list_contacts(unix_now() as i64).unwrap_or_default() {
+        match contact.state {
+            AccessState::Waiting { requested_at, release_at } => eprintln!(
+                "Warning: {} asked for emergency access on {}; it is released on {} unless you run: emergency deny --contact {}",
+                contact.key,
+                format_timestamp(requested_at),
+                format_timestamp(release_at),
+                contact.key
+            ),
+            AccessState::Released { .. } => eprintln!("Warning: emergency access was released to {}", contact.key),
+            _ => {}
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("add") {
+        let title = matches.value_of("title").unwrap();
+        let item_type = ItemType::parse(matches.value_of("type").unwrap()).unwrap();
+
+        if title.trim().is_empty() {
+            fail!("Failed to add password: Title cannot be empty.");
             return;
         }
 
-        // Encrypt password first
-        let (encrypted_password, nonce) = encrypt_password(password, &key);
+        // Logins store their password; other items store their fields as JSON
+        let secret = match item_secret(item_type, matches.value_of("password"), matches, ItemFields::new()) {
+            Ok(secret) => secret,
+            Err(err) => {
+                fail!("Failed to add {}: {}", if item_type.is_login() { "password" } else { "item" }, err);
+                return;
+            }
+        };
+
+        // Folders and tags are stored encrypted, like the title
+        let folder = match matches.value_of("folder").map(normalize_folder).transpose() {
+            Ok(folder) => folder.flatten().map(|path| folder_label(&path, &key)),
+            Err(err) => {
+                fail!("Failed to add password: {}", err);
+                return;
+            }
+        };
+        let tags = match matches
+            .values_of("tag")
+            .into_iter()
+            .flatten()
+            .map(|tag| tag_label(tag, &key))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(tags) => tags,
+            Err(err) => {
+                fail!("Failed to add password: {}", err);
+                return;
+            }
+        };
+
+        let rotate_every_days = match matches.value_of("rotate-every").map(parse_rotation).transpose() {
+            Ok(days) => days,
+            Err(err) => {
+                fail!("Failed to add password: {}", err);
+                return;
+            }
+        };
+
+        let mut urls = match matches.values_of("url").into_iter().flatten().map(UrlRule::parse).collect::<Result<Vec<_>, _>>() {
+            Ok(urls) => urls,
+            Err(err) => {
+                fail!("Failed to add password: {}", err);
+                return;
+            }
+        };
+        // The first URL matched by host is kept as the URL of the entry
+        let url = urls.iter().position(|rule| rule.mode.is_default()).map(|i| urls.remove(i).url);
+
+        // The title is only stored encrypted; lookups go through its blind index
+        let index = title_index(title, &key);
+
+        // Encrypt password first, bound to the entry it belongs to
+        let encrypted_password = encrypt_password(&secret, &index, &key);
+
+        let metadata = EntryMetadata {
+            title: title.to_string(),
+            item_type,
+            username: matches.value_of("username").map(str::to_string),
+            url,
+            urls,
+            created: Some(unix_now()),
+            modified: Some(unix_now()),
+            rotate_every_days,
+        };
+        let encrypted_metadata = encrypt_metadata(&metadata, &index, &key);
 
         // Attempt to add the password to the database
-        match add_password(title, &encrypted_password) {
+        match add_password(&index, &encrypted_metadata, &encrypted_password, folder.as_ref(), &tags) {
             Ok(_) => {
-                // Only display nonce if password is successfully added
-                println!(
-                    "Password added for '{}'. Store this nonce securely: {}",
-                    title, nonce
-                );
+                say!("{} added for '{}'.", item_type.label(), title);
+                emit(&EntryRef { title: title.to_string() });
+                record_event("add", Some(title), &key);
+                sync_tokens(&key);
             }
             Err(diesel::result::Error::DatabaseError(
                 diesel::result::DatabaseErrorKind::UniqueViolation,
                 _,
-            )) => println!("Failed to add password: A password with the title '{}' already exists.", title),
+            )) => fail!("Failed to add password: A password with the title '{}' already exists.", title),
             Err(e) => panic!("Failed to add password: {:?}", e),
         }
     } else if let Some(matches) = matches.subcommand_matches("get") {
         // Check if title is provided; if not, print error and exit
         if let Some(title) = matches.value_of("title") {
+            // Only entries stored before authenticated encryption need a nonce
             let nonce = matches.value_of("nonce");
+            let index = title_index(title, &key);
+
+            if let Some(entry) = get_entry(&index) {
+                let encrypted_password = entry.encrypted_password;
+                let metadata = decrypt_metadata(&entry.encrypted_metadata, &index, &key);
+                let item_type = metadata.as_ref().map_or(ItemType::Login, |metadata| metadata.item_type);
+                match decrypt_password(&encrypted_password, &index, nonce, &key) {
+                    Ok(decrypted_password) => {
+                        let fields = if item_type.is_login() {
+                            say!("Decrypted password for '{}': {}", title, decrypted_password);
+                            None
+                        } else {
+                            match decode_fields(&decrypted_password) {
+                                Ok(fields) => {
+                                    say!("{} '{}':\n{}", item_type.label(), title, render_fields(item_type, &fields).trim_end());
+                                    Some(fields)
+                                }
+                                Err(err) => {
+                                    fail!("Failed to read '{}': {}", title, err);
+                                    return;
+                                }
+                            }
+                        };
+                        record_event("get", Some(title), &key);
+
+                        let attachments = list_attachments(&index, &key).unwrap_or_default();
+                        if !attachments.is_empty() {
+                            let names: Vec<String> = attachments
+                                .iter()
+                                .map(|info| format!("{} ({})", info.name, format_size(info.size)))
+                                .collect();
+                            say!("Attachments: {}", names.join(", "));
+                        }
+                        let read = metadata.clone().unwrap_or_else(|_| EntryMetadata {
+                            title: title.to_string(),
+                            ..Default::default()
+                        });
+                        emit(&secret_entry(&read, &decrypted_password, fields, &attachments));
+
+                        // Remind the user when the password is past its rotation date
+                        if let Ok(metadata) = &metadata {
+                            if let Ok(Some(policy)) = policy_for_entry(&index, metadata, &key) {
+                                if let Due::Overdue(days) = due(metadata, policy.rotate_every_days, unix_now()) {
+                                    say!(
+                                        "Warning: this password is {} day(s) past due; it must be changed every {} days.",
+                                        days, policy.rotate_every_days
+                                    );
+                                }
+                            }
+                        }
 
-            if nonce.is_none() {
-                println!("Error: Missing required argument --nonce for decryption");
+                        // Re-seal legacy entries now that the password is known
+                        if is_legacy_ciphertext(&encrypted_password)
+                            && update_password(&index, &encrypt_password(&decrypted_password, &index, &key), None).is_ok()
+                        {
+                            say!("Entry '{}' upgraded to authenticated encryption; its nonce is no longer needed.", title);
+                        }
+                    }
+                    Err(err) => {
+                        fail!("Failed to decrypt password for '{}': {}", title, err);
+                    }
+                }
+            } else {
+                let titles: Vec<String> = list_passwords()
+                    .iter()
+                    .filter_map(|entry| decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &key).ok())
+                    .map(|metadata| metadata.title)
+                    .collect();
+                match suggest(title, &titles).as_slice() {
+                    [] => fail!("No password found for '{}'.", title),
+                    suggestions => fail!(
+                        "No password found for '{}'. Did you mean {}?",
+                        title,
+                        suggestions.iter().map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(" or ")
+                    ),
+                }
+            }
+        } else {
+            fail!("Error: Missing required argument --title for retrieving the password");
+        }
+    } else if let Some(matches) = matches.subcommand_matches("list") {
+        let folder_filter = match matches.value_of("folder").map(normalize_folder).transpose() {
+            Ok(folder) => folder.flatten(),
+            Err(err) => {
+                fail!("{}", err);
+                return;
+            }
+        };
+        let tag_filters: Vec<&str> = matches.values_of("tag").into_iter().flatten().collect();
+        let labels = match EntryLabels::load(&key) {
+            Ok(labels) => labels,
+            Err(err) => {
+                fail!("Failed to decrypt folders and tags: {}", err);
                 return;
             }
+        };
 
-            if let Some(encrypted_password) = get_password(title) {
-                match decrypt_password(&encrypted_password, nonce.unwrap(), &key) {
-                    Ok(decrypted_password) => {
-                        println!("Decrypted password for '{}': {}", title, decrypted_password);
+        let entries: Vec<_> = list_passwords()
+            .into_iter()
+            .filter(|entry| match &folder_filter {
+                Some(filter) => labels.folder(&entry.title_index).is_some_and(|folder| is_in_folder(folder, filter)),
+                None => true,
+            })
+            .filter(|entry| tag_filters.iter().all(|tag| labels.has_tag(&entry.title_index, tag)))
+            .collect();
+        if entries.is_empty() && (folder_filter.is_some() || !tag_filters.is_empty()) {
+            say!("No passwords match the given folder and tags.");
+        } else if entries.is_empty() {
+            say!("No passwords stored.");
+        }
+        let mut listed = Vec::new();
+        for entry in entries {
+            match decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &key) {
+                Ok(metadata) => {
+                    let mut line = metadata.title.clone();
+                    if !metadata.item_type.is_login() {
+                        line.push_str(&format!("  ({})", metadata.item_type.name()));
                     }
-                    Err(err) => {
-                        println!("Failed to decrypt password for '{}': {}", title, err);
+                    if let Some(folder) = labels.folder(&entry.title_index) {
+                        line.push_str(&format!("  [{}]", folder));
                     }
+                    for tag in labels.tags(&entry.title_index) {
+                        line.push_str(&format!("  #{}", tag));
+                    }
+                    say!("{}", line);
+                    listed.push(listed_entry(&metadata, labels.folder(&entry.title_index), labels.tags(&entry.title_index)));
                 }
+                Err(err) => fail!("Failed to decrypt entry {}: {}", entry.id, err),
+            }
+        }
+        emit(&EntryList { entries: listed });
+    } else if let Some(matches) = matches.subcommand_matches("search") {
+        let query = matches.value_of("query").unwrap();
+        let limit = match matches.value_of("limit").unwrap().parse::<usize>() {
+            Ok(limit) => limit,
+            Err(_) => {
+                fail!("Error: --limit must be a number");
+                return;
+            }
+        };
+        let labels = match EntryLabels::load(&key) {
+            Ok(labels) => labels,
+            Err(err) => {
+                fail!("Failed to decrypt folders and tags: {}", err);
+                return;
+            }
+        };
+
+        // Everything is decrypted in memory; the vault itself only holds blind indexes
+        let candidates: Vec<Candidate> = list_passwords()
+            .into_iter()
+            .filter_map(|entry| {
+                let metadata = decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &key).ok()?;
+                let tags = labels.tags(&entry.title_index).to_vec();
+                Some(Candidate {
+                    title_index: entry.title_index,
+                    metadata,
+                    tags,
+                })
+            })
+            .collect();
+
+        let hits = search(query, &candidates);
+        if hits.is_empty() {
+            say!("No passwords match '{}'.", query);
+        }
+        let mut results = Vec::new();
+        for hit in hits.into_iter().take(limit) {
+            if hit.field == "title" {
+                say!("{}", hit.title);
             } else {
-                println!("No password found for '{}'.", title);
+                say!("{}  ({}: {})", hit.title, hit.field, hit.matched);
+            }
+            results.push(SearchResult {
+                title: hit.title,
+                field: hit.field,
+                matched: hit.matched,
+                score: hit.score,
+            });
+        }
+        emit(&EntryList { entries: results });
+    } else if let Some(matches) = matches.subcommand_matches("match") {
+        let url = matches.value_of("url").unwrap();
+        let matched = match page_url(url).and_then(|page| match_entries(&page, &key)) {
+            Ok(matched) => matched,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+
+        if matched.is_empty() {
+            say!("No entries match {}.", url);
+        }
+        let mut results = Vec::new();
+        for entry in matched {
+            match &entry.metadata.username {
+                Some(username) => say!("{} [{}]  ({})", entry.metadata.title, username, entry.rule),
+                None => say!("{}  ({})", entry.metadata.title, entry.rule),
+            }
+            results.push(MatchedEntry {
+                title: entry.metadata.title,
+                username: entry.metadata.username,
+                url: entry.rule.url,
+                mode: entry.rule.mode.name(),
+            });
+        }
+        emit(&EntryList { entries: results });
+    } else if let Some(matches) = matches.subcommand_matches("audit") {
+        let max_age_days = match parse_days(matches.value_of("max-age").unwrap()) {
+            Ok(days) => days,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+        let breaches = match matches.value_of("breaches").map(|path| BreachList::open(Path::new(path))).transpose() {
+            Ok(breaches) => breaches,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+
+        // Only logins have a password to audit
+        let inputs: Vec<AuditInput> = list_passwords()
+            .into_iter()
+            .filter_map(|entry| match decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &key) {
+                Ok(metadata) if !metadata.item_type.is_login() => None,
+                Ok(metadata) => Some(AuditInput {
+                    password: if is_legacy_ciphertext(&entry.encrypted_password) {
+                        Err("stored before authenticated encryption; read it once with its nonce".to_string())
+                    } else {
+                        decrypt_password(&entry.encrypted_password, &entry.title_index, None, &key)
+                    },
+                    title: metadata.title,
+                    modified: metadata.modified,
+                }),
+                Err(err) => Some(AuditInput {
+                    title: format!("entry {}", entry.id),
+                    password: Err(err),
+                    modified: None,
+                }),
+            })
+            .collect();
+
+        let options = AuditOptions { max_age_days, breaches };
+        let report = match audit(&inputs, &options, unix_now()) {
+            Ok(report) => report,
+            Err(err) => {
+                fail!("Audit failed: {}", err);
+                return;
+            }
+        };
+
+        // The JSON report of --output json carries the audit report as its result
+        if is_json() {
+            emit(&report);
+        } else if matches.value_of("format") == Some("json") {
+            say!("{}", serde_json::to_string_pretty(&report).expect("Failed to serialize audit report"));
+        } else {
+            say!("{}", render_table(&report).trim_end());
+        }
+    } else if let Some(matches) = matches.subcommand_matches("audit-log") {
+        let records = load_audit_log();
+        let problems = verify_chain(&records);
+        // Records from before a team key rotation are encrypted under an earlier key
+        let previous_keys = team.as_ref().map_or(&[][..], |team| team.previous_keys.as_slice());
+
+        let mut shown = Vec::new();
+        if matches.subcommand_matches("verify").is_none() {
+            let limit = match matches.value_of("limit").unwrap().parse::<usize>() {
+                Ok(limit) => limit,
+                Err(_) => {
+                    fail!("Error: --limit must be a number");
+                    return;
+                }
+            };
+            for record in records.iter().skip(records.len().saturating_sub(limit)) {
+                let title = match record.title_index.as_str() {
+                    "" => "-".to_string(),
+                    // Reads through PWVAULT_TOKEN log only the index of the entry
+                    index if record.encrypted_title.is_empty() => list_passwords()
+                        .into_iter()
+                        .find(|entry| entry.title_index == index)
+                        .and_then(|entry| decrypt_metadata(&entry.encrypted_metadata, index, &key).ok())
+                        .map_or_else(|| "?".to_string(), |metadata| metadata.title),
+                    index => std::iter::once(&key)
+                        .chain(previous_keys)
+                        .find_map(|key| decrypt_label("audit", &record.encrypted_title, index, key).ok())
+                        .unwrap_or_else(|| "?".to_string()),
+                };
+                say!(
+                    "{:>5}  {}  {:<7}  {}  (device {})",
+                    record.seq,
+                    format_timestamp(record.timestamp),
+                    record.action,
+                    title,
+                    record.device_id()
+                );
+                shown.push(LogEntry {
+                    seq: record.seq,
+                    timestamp: record.timestamp,
+                    action: record.action.clone(),
+                    title: if record.title_index.is_empty() { String::new() } else { title },
+                    device: record.device_id(),
+                });
+            }
+        }
+
+        if problems.is_empty() {
+            say!("Audit log verified: {} record(s), chain intact.", records.len());
+        } else {
+            fail!("Audit log verification failed:");
+            for problem in &problems {
+                say!("  - {}", problem);
+            }
+        }
+        emit(&AuditLog { records: shown, problems });
+    } else if let Some(matches) = matches.subcommand_matches("expiry") {
+        run_expiry_command(matches, &key);
+    } else if let Some(matches) = matches.subcommand_matches("expiring") {
+        let within_days = match parse_days(matches.value_of("within").unwrap()) {
+            Ok(days) => days,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+        let (labels, policies) = match EntryLabels::load(&key).and_then(|labels| Ok((labels, load_folder_policies(&key)?))) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                fail!("Failed to decrypt folders and policies: {}", err);
+                return;
+            }
+        };
+
+        let now = unix_now();
+        let mut expiring = Vec::new();
+        for entry in list_passwords() {
+            let metadata = match decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, &key) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    fail!("Failed to decrypt entry {}: {}", entry.id, err);
+                    continue;
+                }
+            };
+            let policy = match effective_policy(&metadata, labels.folder(&entry.title_index), &policies) {
+                Some(policy) => policy,
+                None => continue,
+            };
+            let (status, days) = match due(&metadata, policy.rotate_every_days, now) {
+                Due::Overdue(days) => ("overdue", Some(days)),
+                Due::In(days) if days <= within_days => ("due", Some(days)),
+                Due::In(_) => continue,
+                Due::Unknown => ("unknown", None),
+            };
+            expiring.push(ExpiringEntry {
+                title: metadata.title,
+                status,
+                days,
+                rotate_every_days: policy.rotate_every_days,
+                folder: policy.folder.clone(),
+            });
+        }
+
+        if expiring.is_empty() {
+            say!("No passwords are due for rotation within {} days.", within_days);
+        }
+        for entry in &expiring {
+            let status = match (entry.status, entry.days) {
+                ("overdue", Some(days)) => format!("overdue by {} day(s)", days),
+                ("due", Some(days)) => format!("due in {} day(s)", days),
+                _ => "last change unknown".to_string(),
+            };
+            let source = match &entry.folder {
+                Some(folder) => format!("every {}d, set on folder '{}'", entry.rotate_every_days, folder),
+                None => format!("every {}d", entry.rotate_every_days),
+            };
+            say!("{}  {} ({})", entry.title, status, source);
+        }
+        emit(&EntryList { entries: expiring });
+    } else if let Some(matches) = matches.subcommand_matches("attach") {
+        let title = matches.value_of("title").unwrap();
+        let file = Path::new(matches.value_of("file").unwrap());
+        let name = match matches.value_of("name") {
+            Some(name) => name.to_string(),
+            None => file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        };
+
+        let index = title_index(title, &key);
+        if get_entry(&index).is_none() {
+            fail!("No password found for '{}'.", title);
+            return;
+        }
+        match attach(&index, &name, file, &key) {
+            Ok(info) => {
+                say!("Attached '{}' ({}) to '{}'.", info.name, format_size(info.size), title);
+                emit(&Attachment {
+                    name: info.name,
+                    size: info.size,
+                });
+                record_event("attach", Some(title), &key);
+            }
+            Err(err) => fail!("Failed to attach '{}': {}", name, err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        let title = matches.value_of("title").unwrap();
+        let name = matches.value_of("name").unwrap();
+        let output = Path::new(matches.value_of("out").unwrap_or(name));
+
+        if output.exists() {
+            fail!("Error: {} already exists; choose another path with --out", output.display());
+            return;
+        }
+        match extract(&title_index(title, &key), name, output, &key) {
+            Ok(info) => {
+                say!("Extracted '{}' ({}) to {}.", info.name, format_size(info.size), output.display());
+                emit(&ExtractedFile {
+                    name: info.name,
+                    size: info.size,
+                    path: output.display().to_string(),
+                });
+                record_event("extract", Some(title), &key);
+            }
+            Err(err) => fail!("Failed to extract '{}': {}", name, err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("detach") {
+        let title = matches.value_of("title").unwrap();
+        let name = matches.value_of("name").unwrap();
+
+        match detach(&title_index(title, &key), name, &key) {
+            Ok(()) => {
+                say!("Removed '{}' from '{}'.", name, title);
+                record_event("detach", Some(title), &key);
+                emit(&DetachedFile {
+                    title: title.to_string(),
+                    name: name.to_string(),
+                });
+            }
+            Err(err) => fail!("Failed to remove '{}': {}", name, err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("exec") {
+        // Messages go to stderr, since stdout belongs to the command
+        let mut secrets = Vec::new();
+        for argument in matches.values_of("env").into_iter().flatten() {
+            let resolved = parse_env_mapping(argument)
+                .and_then(|mapping| Ok((mapping.name.clone(), resolve_secret(&mapping.reference, mapping.field.as_deref(), &key)?)));
+            match resolved {
+                Ok(secret) => secrets.push(secret),
+                Err(err) => {
+                    eprintln!("Failed to read {}: {}", argument.split('=').next().unwrap_or(argument), err);
+                    exit_with(1);
+                }
+            }
+        }
+        for (_, secret) in &secrets {
+            if let Err(err) = log_entry_event("exec", Some(&secret.title), &key) {
+                eprintln!("Warning: {}", err);
+            }
+        }
+
+        // The secrets are in memory now; an encrypted vault need not stay open while the command runs
+        close_vault();
+
+        let command: Vec<&str> = matches.values_of("command").unwrap().collect();
+        let status = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .envs(secrets.iter().map(|(name, secret)| (name, &secret.value)))
+            .status();
+        match status {
+            Ok(status) => exit_with(exit_code(status)),
+            Err(e) => {
+                eprintln!("Failed to run '{}': {}", command[0], e);
+                exit_with(if e.kind() == std::io::ErrorKind::NotFound { 127 } else { 126 });
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("inject") {
+        let input = Path::new(matches.value_of("in").unwrap());
+        let template = match std::fs::read_to_string(input) {
+            Ok(template) => template,
+            Err(e) => {
+                fail!("Failed to read {}: {}", input.display(), e);
+                exit_with(1);
+            }
+        };
+        let placeholders = match parse_template(&template) {
+            Ok(placeholders) => placeholders,
+            Err(err) => {
+                fail!("{}: {}", input.display(), err);
+                exit_with(1);
+            }
+        };
+
+        // Every reference is checked before anything is decrypted or written
+        let mut locations = Vec::new();
+        let mut problems = Vec::new();
+        for placeholder in &placeholders {
+            match locate_secret(&placeholder.reference, placeholder.field.as_deref(), &key) {
+                Ok(location) => locations.push(location),
+                Err(err) => problems.push(format!("{}:{}: {}", input.display(), placeholder.line, err)),
+            }
+        }
+        if !problems.is_empty() {
+            for problem in problems {
+                fail!("{}", problem);
+            }
+            exit_with(1);
+        }
+        if matches.is_present("check") {
+            say!("All {} reference(s) in {} resolve.", placeholders.len(), input.display());
+            emit(&Injection {
+                references: placeholders.len(),
+                written: None,
+            });
+            return;
+        }
+
+        let mut values = Vec::new();
+        let mut titles: Vec<String> = Vec::new();
+        for (placeholder, location) in placeholders.iter().zip(locations) {
+            match read_secret(location, &key) {
+                Ok(secret) => {
+                    values.push(secret.value);
+                    if !titles.contains(&secret.title) {
+                        titles.push(secret.title);
+                    }
+                }
+                Err(err) => {
+                    fail!("{}:{}: {}", input.display(), placeholder.line, err);
+                    exit_with(1);
+                }
+            }
+        }
+
+        let output = Path::new(matches.value_of("out").unwrap());
+        if let Err(err) = write_private(output, render_template(&template, &placeholders, &values).as_bytes()) {
+            fail!("Failed to write {}: {}", output.display(), err);
+            exit_with(1);
+        }
+        for title in &titles {
+            record_event("inject", Some(title), &key);
+        }
+        say!("Wrote {} with {} secret(s).", output.display(), values.len());
+        emit(&Injection {
+            references: values.len(),
+            written: Some(output.display().to_string()),
+        });
+    } else if let Some(matches) = matches.subcommand_matches("ssh-agent") {
+        let socket = match matches.value_of("socket").map(PathBuf::from).or_else(default_socket_path) {
+            Some(socket) => socket,
+            None => {
+                fail!("Error: Cannot locate the state directory; pass --socket");
+                return;
+            }
+        };
+
+        let (keys, problems) = load_agent_keys(&key);
+        for problem in problems {
+            say!("Skipping {}", problem);
+        }
+        if keys.is_empty() {
+            fail!("No SSH keys to serve; add one with: add --type ssh-key --title <title> --field private_key=@<file>");
+            return;
+        }
+        let listener = match bind_socket(&socket) {
+            Ok(listener) => listener,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+
+        // The keys are held in memory from here on; an encrypted vault need not stay open
+        close_vault();
+
+        let mut agent = Agent::new(keys);
+        if matches.is_present("confirm") || config.ssh_agent.confirm {
+            agent.confirm = Some(Box::new(confirm_on_terminal));
+        }
+        let log_key = key.clone();
+        agent.on_sign = Some(Box::new(move |agent_key| {
+            if let Err(err) = log_entry_event("sign", Some(&agent_key.title), &log_key) {
+                eprintln!("Warning: {}", err);
+            }
+            close_vault();
+        }));
+
+        for agent_key in agent.keys() {
+            say!("Serving '{}' ({})", agent_key.title, agent_key.fingerprint);
+        }
+        say!("SSH agent listening on {}; stop it with Ctrl-C.", socket.display());
+        say!("Use it with: export SSH_AUTH_SOCK={}", socket.display());
+        emit(&Listening {
+            address: socket.display().to_string(),
+            keys: agent
+                .keys()
+                .iter()
+                .map(|agent_key| ServedKey {
+                    title: agent_key.title.clone(),
+                    fingerprint: agent_key.fingerprint.clone(),
+                })
+                .collect(),
+        });
+        finish_report();
+        serve(listener, Arc::new(agent));
+    } else if matches.subcommand_matches("identity").is_some() {
+        match load_or_create_identity(&key) {
+            Ok(identity) => {
+                let public_key = encode_public_key(&identity.public_key);
+                say!("Public key: {}", public_key);
+                say!("Others can share entries with this vault by running: share --title <title> --with {}", public_key);
+                emit(&PublicKey { public_key });
+            }
+            Err(err) => fail!("Failed to load identity: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("share") {
+        let title = matches.value_of("title").unwrap();
+        let with = matches.value_of("with").unwrap();
+        let recipient = match parse_public_key(with) {
+            Ok(recipient) => recipient,
+            Err(err) => {
+                fail!("Failed to share '{}': {}", title, err);
+                return;
+            }
+        };
+
+        let index = title_index(title, &key);
+        if get_entry(&index).is_none() {
+            fail!("No password found for '{}'.", title);
+            return;
+        }
+        match share_entry(&index, &recipient, &key) {
+            Ok(shared) => {
+                if shared {
+                    say!("Shared '{}' with {}.", title, encode_public_key(&recipient));
+                    record_event("share", Some(title), &key);
+                } else {
+                    say!("'{}' is already shared with {}; its shared copy is up to date.", title, encode_public_key(&recipient));
+                }
+                emit(&ShareChange {
+                    title: title.to_string(),
+                    recipient: encode_public_key(&recipient),
+                    remaining: None,
+                });
+            }
+            Err(err) => fail!("Failed to share '{}': {}", title, err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("unshare") {
+        let title = matches.value_of("title").unwrap();
+        let recipient = match parse_public_key(matches.value_of("with").unwrap()) {
+            Ok(recipient) => recipient,
+            Err(err) => {
+                fail!("Failed to unshare '{}': {}", title, err);
+                return;
+            }
+        };
+
+        match revoke_share(&title_index(title, &key), &recipient, &key) {
+            Ok(remaining) => {
+                say!("Stopped sharing '{}' with {}.", title, encode_public_key(&recipient));
+                match remaining {
+                    0 => say!("'{}' is no longer shared.", title),
+                    _ => say!("The shared copy was re-keyed for the {} remaining recipient(s).", remaining),
+                }
+                say!("They may have kept what they already read; change the password if that matters.");
+                record_event("unshare", Some(title), &key);
+                emit(&ShareChange {
+                    title: title.to_string(),
+                    recipient: encode_public_key(&recipient),
+                    remaining: Some(remaining),
+                });
+            }
+            Err(err) => fail!("Failed to unshare '{}': {}", title, err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("shared") {
+        let from = match matches.value_of("from") {
+            Some(from) => from,
+            None => {
+                match list_shares(&key) {
+                    Ok(shares) => {
+                        if shares.is_empty() {
+                            say!("No entries are shared from this vault.");
+                        }
+                        for share in &shares {
+                            say!("{} -> {}", share.title, share.recipients.join(", "));
+                        }
+                        emit(&EntryList {
+                            entries: shares
+                                .into_iter()
+                                .map(|share| OutgoingShare {
+                                    title: share.title,
+                                    recipients: share.recipients,
+                                })
+                                .collect(),
+                        });
+                    }
+                    Err(err) => fail!("Failed to list shares: {}", err),
+                }
+                return;
+            }
+        };
+
+        let path = match resolve_vault(Some(from), None) {
+            Ok(path) => path,
+            Err(err) => {
+                fail!("{}", err);
+                return;
+            }
+        };
+        let received = match received_shares(&path, &key) {
+            Ok(received) => received,
+            Err(err) => {
+                fail!("Failed to read shares: {}", err);
+                return;
+            }
+        };
+
+        match matches.value_of("title") {
+            Some(title) => match received.items.iter().find(|item| item.metadata.title == title) {
+                Some(item) => {
+                    print_shared_item(item, &format!("shared by {}", received.sender));
+                    record_event("receive", Some(title), &key);
+                }
+                None => fail!("{} shares no entry titled '{}' with this vault.", path.display(), title),
+            },
+            None => {
+                if received.items.is_empty() {
+                    say!("{} shares no entries with this vault.", path.display());
+                } else {
+                    say!("Shared with this vault by {}:", received.sender);
+                }
+                for item in &received.items {
+                    say!("  {} ({})", item.metadata.title, item.metadata.item_type.name());
+                }
+                emit(&IncomingShares {
+                    entries: received.items.iter().map(|item| listed_entry(&item.metadata, None, &[])).collect(),
+                    sender: received.sender,
+                });
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("mv") {
+        let destination = match normalize_folder(matches.value_of("to").unwrap()) {
+            Ok(destination) => destination,
+            Err(err) => {
+                fail!("Failed to move: {}", err);
+                return;
+            }
+        };
+        let destination_name = match &destination {
+            Some(path) => format!("'{}'", path),
+            None => "the top level".to_string(),
+        };
+
+        if let Some(title) = matches.value_of("title") {
+            let index = title_index(title, &key);
+            let folder = destination.as_deref().map(|path| folder_label(path, &key));
+
+            match move_entries(&[(index, folder)]) {
+                Ok(_) => {
+                    say!("Moved '{}' to {}.", title, destination_name);
+                    emit(&EntryRef { title: title.to_string() });
+                    sync_tokens(&key);
+                }
+                Err(diesel::result::Error::NotFound) => fail!("No password found for '{}' to move.", title),
+                Err(e) => fail!("Failed to move '{}': {:?}", title, e),
             }
         } else {
-            println!("Error: Missing required argument --title for retrieving the password");
+            let source = match normalize_folder(matches.value_of("folder").unwrap()) {
+                Ok(Some(source)) => source,
+                Ok(None) => {
+                    fail!("Failed to move: the top level cannot be moved; move single passwords with --title");
+                    return;
+                }
+                Err(err) => {
+                    fail!("Failed to move: {}", err);
+                    return;
+                }
+            };
+            let labels = match EntryLabels::load(&key) {
+                Ok(labels) => labels,
+                Err(err) => {
+                    fail!("Failed to decrypt folders and tags: {}", err);
+                    return;
+                }
+            };
+
+            // Keep the layout below the moved folder: prod/db/x moved to archive becomes archive/db/x
+            let moves: Vec<_> = list_passwords()
+                .into_iter()
+                .filter_map(|entry| {
+                    let folder = labels.folder(&entry.title_index)?;
+                    if !is_in_folder(folder, &source) {
+                        return None;
+                    }
+                    let rest = folder.split('/').skip(source.split('/').count());
+                    let moved: Vec<&str> = destination.as_deref().into_iter().chain(rest).collect();
+                    let moved = normalize_folder(&moved.join("/")).ok().flatten();
+                    Some((entry.title_index, moved.map(|path| folder_label(&path, &key))))
+                })
+                .collect();
+
+            if moves.is_empty() {
+                fail!("No passwords found in folder '{}'.", source);
+                return;
+            }
+            match move_entries(&moves) {
+                Ok(_) => {
+                    say!("Moved {} password(s) from '{}' to {}.", moves.len(), source, destination_name);
+                    sync_tokens(&key);
+                }
+                Err(e) => fail!("Failed to move folder '{}': {:?}", source, e),
+            }
         }
+    } else if let Some(matches) = matches.subcommand_matches("team") {
+        run_team_command(matches, team.as_ref(), &key);
+    } else if let Some(matches) = matches.subcommand_matches("emergency") {
+        run_emergency_command(matches, &key);
+    } else if let Some(matches) = matches.subcommand_matches("serve") {
+        run_serve(matches, team.as_ref().map_or(Role::Owner, |team| team.role), &key);
+    } else if let Some(matches) = matches.subcommand_matches("token") {
+        run_token_command(matches, &key);
+    } else if let Some(matches) = matches.subcommand_matches("recovery").and_then(|matches| matches.subcommand_matches("split")) {
+        run_recovery_split(matches, &key);
+    } else if let Some(matches) = matches.subcommand_matches("batch").and_then(|matches| matches.subcommand_matches("apply")) {
+        run_batch_apply(matches, &config.generator, &key);
     } else if matches.subcommand_matches("clean").is_some() {
         clean_database(); // This cleans the database
-        println!("Database cleaned and reset."); // Print only once
+        say!("Database cleaned and reset."); // Print only once
+        record_event("clean", None, &key);
+    } else if let Some(matches) = matches.subcommand_matches("convert") {
+        let format = VaultFormat::parse(matches.value_of("to").unwrap()).unwrap();
+        let path = vault_path();
+
+        match convert_vault(&path, format) {
+            Ok(_) => say!("Vault '{}' converted to {} format.", path, matches.value_of("to").unwrap()),
+            Err(err) => fail!("Failed to convert vault: {}", err),
+        }
     } else if let Some(matches) = matches.subcommand_matches("modify") {
         let title = matches.value_of("title").unwrap();
-        let new_password = matches.value_of("new_password").unwrap();
-        let nonce = matches.value_of("nonce").unwrap();
-
-        let key = generate_key();
+        let new_password = matches.value_of("new_password");
+        let nonce = matches.value_of("nonce");
 
         // Step 1: Retrieve and decrypt the old password
-        if let Some(encrypted_password) = get_password(title) {
-            match decrypt_password(&encrypted_password, nonce, &key) {
-                Ok(_) => {
-                    // Step 2: Encrypt the new password
-                    let (new_encrypted_password, new_nonce) = encrypt_password(new_password, &key);
+        let index = title_index(title, &key);
+        if let Some(entry) = get_entry(&index) {
+            match decrypt_password(&entry.encrypted_password, &index, nonce, &key) {
+                Ok(old_secret) => {
+                    let mut metadata = match decrypt_metadata(&entry.encrypted_metadata, &index, &key) {
+                        Ok(metadata) => metadata,
+                        Err(err) => {
+                            fail!("Failed to decrypt entry metadata: {}", err);
+                            return;
+                        }
+                    };
+
+                    // Step 2: Encrypt the new password, or the changed fields, and record when it was changed
+                    let item_type = metadata.item_type;
+                    let fields = if item_type.is_login() {
+                        Ok(ItemFields::new())
+                    } else {
+                        decode_fields(&old_secret)
+                    };
+                    let new_secret = match fields.and_then(|fields| item_secret(item_type, new_password, matches, fields)) {
+                        Ok(secret) => secret,
+                        Err(err) => {
+                            fail!("Failed to update {}: {}", if item_type.is_login() { "password" } else { "item" }, err);
+                            return;
+                        }
+                    };
+                    let new_encrypted_password = encrypt_password(&new_secret, &index, &key);
+                    metadata.touch();
+                    let new_encrypted_metadata = encrypt_metadata(&metadata, &index, &key);
 
                     // Step 3: Update the password in the database
-                    match crate::database::update_password(title, &new_encrypted_password) {
+                    match update_password(&index, &new_encrypted_password, Some(&new_encrypted_metadata)) {
                         Ok(_) => {
-                            println!(
-                                "Password updated for '{}'. Store this new nonce securely: {}",
-                                title, new_nonce
-                            );
+                            say!("{} updated for '{}'.", item_type.label(), title);
+                            emit(&EntryRef { title: metadata.title.clone() });
+                            record_event("modify", Some(title), &key);
+                            if let Err(err) = refresh_shared_entry(&index, &key) {
+                                say!("Warning: the shared copy of '{}' was not updated: {}", title, err);
+                            }
                         }
                         Err(diesel::result::Error::NotFound) => {
-                            println!("No password found for '{}'. Update failed.", title);
+                            fail!("No password found for '{}'. Update failed.", title);
                         }
                         Err(e) => {
-                            println!("Failed to update password: {:?}", e);
+                            fail!("Failed to update password: {:?}", e);
                         }
                     }
                 }
-                Err(err) => println!("Failed to decrypt old password: {}", err),
+                Err(err) => fail!("Failed to decrypt old password: {}", err),
             }
         } else {
-            println!("No password found for '{}' to update.", title);
+            fail!("No password found for '{}' to update.", title);
         }
     } else if let Some(matches) = matches.subcommand_matches("delete") {
         let title = matches.value_of("title").unwrap();
-        let nonce = matches.value_of("nonce").unwrap();
-
-        let key = generate_key();
+        let nonce = matches.value_of("nonce");
 
         // Step 1: Retrieve the encrypted password for the given title
-        if let Some(encrypted_password) = get_password(title) {
-            // Step 2: Decrypt the password using the provided nonce and key
-            match decrypt_password(&encrypted_password, nonce, &key) {
+        let index = title_index(title, &key);
+        if let Some(encrypted_password) = get_password(&index) {
+            // Step 2: Decrypt the password to make sure the entry is intact
+            match decrypt_password(&encrypted_password, &index, nonce, &key) {
                 Ok(_) => {
                     // Step 3: Delete the password entry
-                    match crate::database::delete_password(title) {
-                        Ok(_) => println!("Password with title '{}' has been successfully deleted.", title),
+                    match crate::database::delete_password(&index) {
+                        Ok(_) => {
+                            say!("Password with title '{}' has been successfully deleted.", title);
+                            emit(&EntryRef { title: title.to_string() });
+                            record_event("delete", Some(title), &key);
+                        }
                         Err(diesel::result::Error::NotFound) => {
-                            println!("No password found for '{}' to delete.", title);
+                            fail!("No password found for '{}' to delete.", title);
                         }
                         Err(e) => {
-                            println!("Failed to delete password: {:?}", e);
+                            fail!("Failed to delete password: {:?}", e);
                         }
                     }
                 }
-                Err(err) => println!("Failed to verify password: {}", err),
+                Err(err) => fail!("Failed to verify password: {}", err),
             }
         } else {
-            println!("No password found for '{}' to delete.", title);
+            fail!("No password found for '{}' to delete.", title);
         }
     }
+}
+
+/// Builds the secret stored for an item: the password of a login, or the
+/// fields of any other type as JSON, starting from `fields` and applying the
+/// `--field` arguments.
+fn item_secret(item_type: ItemType, password: Option<&str>, matches: &ArgMatches, fields: ItemFields) -> Result<String, String> {
+    let updates = matches
+        .values_of("field")
+        .into_iter()
+        .flatten()
+        .map(parse_field)
+        .collect::<Result<Vec<_>, _>>()?;
 
+    if item_type.is_login() {
+        if !updates.is_empty() {
+            return Err("Logins take a password, not --field.".to_string());
+        }
+        match password {
+            None => Err("A password is required for logins.".to_string()),
+            Some("") => Err("Password cannot be empty.".to_string()),
+            Some(password) => Ok(password.to_string()),
+        }
+    } else {
+        if password.is_some() {
+            return Err(format!("{} items take --field, not a password.", item_type.name()));
+        }
+        apply_fields(item_type, fields, updates).map(|fields| encode_fields(&fields))
+    }
+}
 
+/// Prints an entry read from another vault, as `get` would; `origin` says where from.
+fn print_shared_item(item: &SharedItem, origin: &str) {
+    let metadata = &item.metadata;
+    say!("{} '{}', {}:", metadata.item_type.label(), metadata.title, origin);
+    if let Some(username) = &metadata.username {
+        say!("  username: {}", username);
+    }
+    if let Some(url) = &metadata.url {
+        say!("  url: {}", url);
+    }
+    for rule in &metadata.urls {
+        say!("  url: {}", rule);
+    }
+    if metadata.item_type.is_login() {
+        say!("  password: {}", item.secret);
+        emit(&secret_entry(metadata, &item.secret, None, &[]));
+    } else {
+        match decode_fields(&item.secret) {
+            Ok(fields) => {
+                say!("{}", render_fields(metadata.item_type, &fields).trim_end());
+                emit(&secret_entry(metadata, &item.secret, Some(fields), &[]));
+            }
+            Err(err) => fail!("Failed to read '{}': {}", metadata.title, err),
+        }
+    }
+}
+
+/// The JSON result of listing an entry, without its secret.
+fn listed_entry(metadata: &EntryMetadata, folder: Option<&str>, tags: &[String]) -> ListedEntry {
+    ListedEntry {
+        title: metadata.title.clone(),
+        item_type: metadata.item_type.name(),
+        folder: folder.map(str::to_string),
+        tags: tags.to_vec(),
+    }
+}
+
+/// The JSON result of reading an entry. Logins have no `fields`; their secret is the password.
+fn secret_entry(metadata: &EntryMetadata, secret: &str, fields: Option<ItemFields>, attachments: &[AttachmentInfo]) -> SecretEntry {
+    SecretEntry {
+        title: metadata.title.clone(),
+        item_type: metadata.item_type.name(),
+        username: metadata.username.clone(),
+        urls: url_rules(metadata).iter().map(UrlRule::to_string).collect(),
+        password: fields.is_none().then(|| secret.to_string()),
+        fields,
+        attachments: attachments
+            .iter()
+            .map(|info| Attachment {
+                name: info.name.clone(),
+                size: info.size,
+            })
+            .collect(),
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file only the current user
+/// can read, so the file is never seen half-written or with wider permissions.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let part_path = path.with_file_name(format!(".{}.part", file_name));
+    let _ = std::fs::remove_file(&part_path);
+    let written = create_private_file(&part_path)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| std::fs::rename(&part_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&part_path);
+    }
+    written
+}
+
+/// The status to exit with for a finished child: its exit code, or 128 plus
+/// the signal that killed it, as shells report it.
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// Exits with `code`, sealing an open encrypted vault first since
+/// `process::exit` skips the guard that would otherwise do it. A JSON
+/// report built so far is printed.
+fn exit_with(code: i32) -> ! {
+    finish_report();
+    close_vault();
+    std::process::exit(code)
+}
+
+/// Appends an event to the audit log. Titles are stored encrypted; `None`
+/// stands for an action on the whole vault. A failure to log is reported but
+/// does not undo the action.
+fn record_event(action: &str, title: Option<&str>, key: &Key) {
+    if let Err(err) = log_entry_event(action, title, key) {
+        say!("Warning: {}", err);
+    }
+}
+
+/// Appends an event to the audit log like `record_event`, but leaves the
+/// failure to the caller; `serve` and `ssh-agent` report it on stderr, as
+/// their JSON report has been printed by then, and `exec` too, as stdout
+/// belongs to the command it runs.
+fn log_entry_event(action: &str, title: Option<&str>, key: &Key) -> Result<(), String> {
+    let (index, encrypted_title) = match title {
+        Some(title) => {
+            let index = title_index(title, key);
+            let encrypted_title = encrypt_label("audit", title, &index, key);
+            (index, encrypted_title)
+        }
+        None => (String::new(), String::new()),
+    };
+    log_event(action, &index, &encrypted_title)
+}
+
+/// Seals entries that came into the scope of an API token for it, and
+/// re-keys those that left. A failure is reported but does not undo the action.
+fn sync_tokens(key: &Key) {
+    if let Err(err) = sync_token_grants(key) {
+        say!("Warning: {}", err);
+    }
+}
+
+/// The subcommand path that names a JSON report, e.g. `token create`.
+fn command_name(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut current = matches;
+    while let Some((name, matches)) = current.subcommand() {
+        names.push(name);
+        current = matches;
+    }
+    names.join(" ")
+}
+
+/// The least team role a command needs. Changing team membership is
+/// checked further by the team itself, against the roles involved.
+fn required_role(matches: &ArgMatches) -> Role {
+    match matches.subcommand() {
+        Some(("add" | "modify" | "delete" | "mv" | "attach" | "detach" | "batch", _)) => Role::Writer,
+        Some(("expiry", matches)) if matches.subcommand_matches("list").is_none() => Role::Writer,
+        Some(("share" | "unshare", _)) => Role::Admin,
+        Some(("emergency", matches)) if matches!(matches.subcommand_name(), Some("add" | "remove" | "deny")) => Role::Owner,
+        Some(("team", matches)) if matches.subcommand_matches("list").is_none() => Role::Admin,
+        Some(("token", matches)) if matches.subcommand_matches("list").is_none() => Role::Owner,
+        Some(("clean" | "convert" | "recovery", _)) => Role::Owner,
+        _ => Role::Reader,
+    }
+}
+
+/// Handles the `team` subcommands. `team` is the unlocked team, if the vault is one.
+fn run_team_command(matches: &ArgMatches, team: Option<&Team>, key: &Key) {
+    let device = match device_key() {
+        Ok(device) => device,
+        Err(err) => {
+            fail!("Failed to load device key: {}", err);
+            return;
+        }
+    };
+
+    if matches.subcommand_matches("init").is_some() {
+        match create_team(&device, key) {
+            Ok(team) => {
+                say!("The vault is now a team vault, re-encrypted under a new team key.");
+                say!("You are its owner: {}", team.member_key);
+                say!("Add members with: team add --member <their team whoami key> --role <owner|admin|writer|reader>");
+                record_event("team", None, &team.key);
+            }
+            Err(err) => fail!("Failed to create team: {}", err),
+        }
+        return;
+    }
+
+    let team = match team {
+        Some(team) => team,
+        None => {
+            fail!("This vault is not a team vault; run `team init` first.");
+            return;
+        }
+    };
+    if matches.subcommand_matches("list").is_some() {
+        say!("Team key epoch {}; {} member(s):", team.epoch, team.members.len());
+        for member in &team.members {
+            let you = if member.key == team.member_key { "  (you)" } else { "" };
+            say!("  {:<6}  {}{}", member.role.name(), member.key, you);
+        }
+        emit(&TeamMembers {
+            epoch: team.epoch,
+            members: team
+                .members
+                .iter()
+                .map(|member| TeamMember {
+                    key: member.key.clone(),
+                    role: member.role.name(),
+                    you: member.key == team.member_key,
+                })
+                .collect(),
+        });
+    } else if let Some(matches) = matches.subcommand_matches("add") {
+        let member = matches.value_of("member").unwrap();
+        let role = Role::parse(matches.value_of("role").unwrap()).unwrap();
+        match add_member(team, &device, member, role) {
+            Ok(()) => {
+                say!("Added {} as {}.", member, role.name());
+                record_event("grant", None, key);
+                emit(&team_member(team, member, role));
+            }
+            Err(err) => fail!("Failed to add member: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("role") {
+        let member = matches.value_of("member").unwrap();
+        let role = Role::parse(matches.value_of("role").unwrap()).unwrap();
+        match set_role(team, &device, member, role) {
+            Ok(()) => {
+                say!("{} is now {}.", member, role.name());
+                record_event("grant", None, key);
+                emit(&team_member(team, member, role));
+            }
+            Err(err) => fail!("Failed to change role: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("remove") {
+        let member = matches.value_of("member").unwrap();
+        match remove_member(team, &device, member) {
+            Ok(epoch) => {
+                say!("Removed {} from the team.", member);
+                say!("The team key was rotated (epoch {}) and the vault re-encrypted; nothing written from now on is readable with the old key.", epoch);
+                record_event("revoke", None, key);
+                emit(&RemovedKey {
+                    key: parse_member_key(member).unwrap_or_else(|_| member.to_string()),
+                    epoch: Some(epoch),
+                });
+            }
+            Err(err) => fail!("Failed to remove member: {}", err),
+        }
+    }
+}
+
+/// The result of `team add` and `team role`, with the key as the team stores it.
+fn team_member(team: &Team, member: &str, role: Role) -> TeamMember {
+    let key = parse_member_key(member).unwrap_or_else(|_| member.to_string());
+    TeamMember {
+        you: key == team.member_key,
+        key,
+        role: role.name(),
+    }
+}
+
+/// Handles `serve`. `role` is the team role of whoever runs it; below
+/// Writer the API only reads, whatever the tokens allow.
+fn run_serve(matches: &ArgMatches, role: Role, key: &Key) {
+    if is_container(Path::new(&vault_path())) {
+        fail!("Error: `serve` keeps the vault open while it runs, so it only serves plain vaults");
+        return;
+    }
+    if let Err(err) = sync_token_grants(key) {
+        fail!("Error: {}", err);
+        return;
+    }
+    let mut api = Api::new(key.clone());
+    api.read_only = role < Role::Writer;
+    let log_key = key.clone();
+    api.on_access = Some(Box::new(move |action, title| {
+        if let Err(err) = log_entry_event(&format!("api-{}", action), Some(title), &log_key) {
+            eprintln!("Warning: {}", err);
+        }
+    }));
+    let mode = if api.read_only { " (read-only)" } else { "" };
+
+    if let Some(address) = matches.value_of("listen") {
+        match bind_tcp(address) {
+            Ok(listener) => {
+                say!("HTTP API{} listening on http://{}/v1/entries; stop it with Ctrl-C.", mode, address);
+                emit(&Listening {
+                    address: format!("http://{}/v1/entries", address),
+                    keys: Vec::new(),
+                });
+                finish_report();
+                serve_tcp(listener, Arc::new(api));
+            }
+            Err(err) => fail!("Error: {}", err),
+        }
+        return;
+    }
+    let socket = match matches.value_of("socket").map(PathBuf::from).or_else(default_api_socket_path) {
+        Some(socket) => socket,
+        None => {
+            fail!("Error: Cannot locate the state directory; pass --socket or --listen");
+            return;
+        }
+    };
+    match bind_socket(&socket) {
+        Ok(listener) => {
+            say!("HTTP API{} listening on {}; stop it with Ctrl-C.", mode, socket.display());
+            say!("Try: curl --unix-socket {} -H 'Authorization: Bearer <token>' http://localhost/v1/entries", socket.display());
+            emit(&Listening {
+                address: socket.display().to_string(),
+                keys: Vec::new(),
+            });
+            finish_report();
+            serve_unix(listener, Arc::new(api));
+        }
+        Err(err) => fail!("Error: {}", err),
+    }
+}
+
+/// Handles the `token` subcommands.
+fn run_token_command(matches: &ArgMatches, key: &Key) {
+    let now = unix_now() as i64;
+    if let Some(matches) = matches.subcommand_matches("create") {
+        let name = matches.value_of("name").unwrap();
+        let scopes = match matches.values_of("scope").into_iter().flatten().map(Scope::parse).collect::<Result<Vec<_>, _>>() {
+            Ok(scopes) => scopes,
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+        let expires_at = match matches.value_of("ttl").map(parse_ttl).transpose() {
+            Ok(ttl) => ttl.map(|ttl| now + ttl),
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+        let options = TokenOptions {
+            scopes,
+            read_only: matches.is_present("read-only"),
+            expires_at,
+        };
+        match create_token(name, &options, now, key) {
+            Ok(secret) => {
+                say!("{}", secret);
+                eprintln!("Created {} token '{}'. It is shown only this once.", describe_token_access(options.read_only, &options.scopes), name.trim());
+                if let Some(expires_at) = expires_at {
+                    eprintln!("It expires on {}.", format_timestamp(expires_at));
+                }
+                emit(&TokenInfo {
+                    name: name.trim().to_string(),
+                    token: Some(secret),
+                    read_only: options.read_only,
+                    scopes: options.scopes.iter().map(Scope::to_string).collect(),
+                    created_at: now,
+                    expires_at,
+                    expired: false,
+                });
+            }
+            Err(err) => fail!("Failed to create token: {}", err),
+        }
+    } else if matches.subcommand_matches("list").is_some() {
+        match list_tokens() {
+            Ok(tokens) => {
+                if tokens.is_empty() {
+                    say!("This vault has no API tokens.");
+                }
+                for token in &tokens {
+                    let mut line = format!(
+                        "{}  {}  created {}",
+                        token.name,
+                        describe_token_access(token.read_only, &token.scopes()),
+                        format_timestamp(token.created_at)
+                    );
+                    match token.expires_at {
+                        Some(expires_at) if token.is_expired(now) => line.push_str(&format!("  expired {}", format_timestamp(expires_at))),
+                        Some(expires_at) => line.push_str(&format!("  expires {}", format_timestamp(expires_at))),
+                        None => {}
+                    }
+                    say!("{}", line);
+                }
+                emit(&EntryList {
+                    entries: tokens.iter().map(|token| token_info(token, now)).collect(),
+                });
+            }
+            Err(err) => fail!("{}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("revoke") {
+        let name = matches.value_of("name").unwrap();
+        match revoke_token(name, key) {
+            Ok(Some(token)) => {
+                say!("Revoked token '{}'.", name);
+                emit(&token_info(&token, now));
+            }
+            Ok(None) => fail!("No token named '{}'.", name),
+            Err(err) => fail!("{}", err),
+        }
+    }
+}
+
+/// Describes a stored token for the JSON report, without its secret.
+fn token_info(token: &ApiToken, now: i64) -> TokenInfo {
+    TokenInfo {
+        name: token.name.clone(),
+        token: None,
+        read_only: token.read_only,
+        scopes: token.scopes().iter().map(Scope::to_string).collect(),
+        created_at: token.created_at,
+        expires_at: token.expires_at,
+        expired: token.is_expired(now),
+    }
+}
+
+/// Describes what a token may do, e.g. `read-only, folder:prod/*`.
+fn describe_token_access(read_only: bool, scopes: &[Scope]) -> String {
+    let access = if read_only { "read-only" } else { "read-write" };
+    if scopes.is_empty() {
+        format!("{}, every entry", access)
+    } else {
+        format!("{}, {}", access, scopes.iter().map(Scope::to_string).collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Runs the native messaging host until the browser closes the pipe. Standard
+/// output carries the protocol, so everything else goes to standard error.
+fn run_native_host() {
+    let mut host = NativeHost::new(Box::new(|| {
+        let key = if is_team_vault() { device_key().and_then(|device| unlock_team(&device))?.key } else { generate_key() };
+        verify_vault_integrity().map_err(|err| format!("Vault integrity check failed: {}", err))?;
+        Ok(key)
+    }));
+    host.on_fill = Some(Box::new(|title, _origin, key| {
+        let index = title_index(title, key);
+        if let Err(err) = log_event("autofill", &index, &encrypt_label("audit", title, &index, key)) {
+            eprintln!("Warning: {}", err);
+        }
+    }));
+    host.after_request = Some(Box::new(close_vault));
+
+    if let Err(err) = serve_native_host(&mut io::stdin().lock(), &mut io::stdout().lock(), &host) {
+        eprintln!("Native messaging host stopped: {}", err);
+        exit_with(1);
+    }
+}
+
+/// Runs `get` or `list` with the token in `PWVAULT_TOKEN` instead of the vault
+/// key. Scoped tokens only decrypt the entries in their scope.
+fn run_token_session(matches: &ArgMatches, secret: &str) {
+    let token = match authenticate(secret.trim(), unix_now() as i64) {
+        Some(token) => token,
+        None => {
+            fail!("Error: PWVAULT_TOKEN is not a valid, unexpired token of this vault");
+            exit_with(1);
+        }
+    };
+    if !token.is_scoped() {
+        fail!("Error: only scoped tokens can be used from PWVAULT_TOKEN; use `serve` for the others");
+        exit_with(1);
+    }
+    if let Err(err) = verify_vault_integrity() {
+        fail!("Vault integrity check failed: {}", err);
+        exit_with(1);
+    }
+    let entries = match granted_entries(&token, secret.trim()) {
+        Ok(entries) => entries,
+        Err(err) => {
+            fail!("Error: {}", err);
+            exit_with(1);
+        }
+    };
+
+    match matches.subcommand() {
+        Some(("get", matches)) => {
+            let title = matches.value_of("title").unwrap_or_default();
+            let entry = match entries.iter().find(|entry| normalize_title(&entry.item.metadata.title) == normalize_title(title)) {
+                Some(entry) => entry,
+                None => {
+                    fail!("No password found for '{}' in the scope of token '{}'.", title, token.name);
+                    exit_with(1);
+                }
+            };
+            // Without the vault key the title cannot be encrypted for the log;
+            // `audit-log` looks it up by its index instead
+            if let Err(err) = log_event(&format!("token-get:{}", token.name), &entry.title_index, "") {
+                say!("Warning: {}", err);
+            }
+            let item = &entry.item;
+            let item_type = item.metadata.item_type;
+            if item_type.is_login() {
+                say!("Decrypted password for '{}': {}", title, item.secret);
+                emit(&secret_entry(&item.metadata, &item.secret, None, &[]));
+            } else {
+                match decode_fields(&item.secret) {
+                    Ok(fields) => {
+                        say!("{} '{}':\n{}", item_type.label(), title, render_fields(item_type, &fields).trim_end());
+                        emit(&secret_entry(&item.metadata, &item.secret, Some(fields), &[]));
+                    }
+                    Err(err) => fail!("Failed to read '{}': {}", title, err),
+                }
+            }
+        }
+        Some(("list", _)) => {
+            if entries.is_empty() {
+                say!("No passwords in the scope of token '{}'.", token.name);
+            }
+            for entry in &entries {
+                say!("{}", entry.item.metadata.title);
+            }
+            emit(&EntryList {
+                entries: entries.iter().map(|entry| listed_entry(&entry.item.metadata, None, &[])).collect(),
+            });
+        }
+        _ => {
+            fail!("Error: only get and list can be used with PWVAULT_TOKEN");
+            exit_with(1);
+        }
+    }
+}
+
+/// Splits an access state into its name and timestamps for the JSON report:
+/// requested at, released at and denied at.
+fn access_fields(state: &AccessState) -> (&'static str, Option<i64>, Option<i64>, Option<i64>) {
+    match *state {
+        AccessState::Idle => ("idle", None, None, None),
+        AccessState::Waiting { requested_at, release_at } => ("waiting", Some(requested_at), Some(release_at), None),
+        AccessState::Released { requested_at } => ("released", Some(requested_at), None, None),
+        AccessState::Denied { denied_at } => ("denied", None, None, Some(denied_at)),
+    }
+}
+
+/// Handles the `emergency` subcommands. `add`, `remove`, `list` and `deny`
+/// work on this vault; `request` and `access` on a vault you are a contact of.
+fn run_emergency_command(matches: &ArgMatches, key: &Key) {
+    let now = unix_now() as i64;
+    if matches.subcommand_matches("list").is_some() {
+        match list_contacts(now) {
+            Ok(contacts) => {
+                if contacts.is_empty() {
+                    say!("This vault has no emergency contacts.");
+                }
+                let mut listed = Vec::new();
+                for contact in contacts {
+                    let (state, requested_at, release_at, denied_at) = access_fields(&contact.state);
+                    let description = match contact.state {
+                        AccessState::Idle => "no request".to_string(),
+                        AccessState::Waiting { release_at, .. } => format!("requested, released on {}", format_timestamp(release_at)),
+                        AccessState::Released { requested_at } => format!("released, requested on {}", format_timestamp(requested_at)),
+                        AccessState::Denied { denied_at } => format!("denied on {}", format_timestamp(denied_at)),
+                    };
+                    say!("{}  wait {} day(s)  {}", contact.key, contact.wait_days, description);
+                    listed.push(EmergencyContact {
+                        key: contact.key,
+                        wait_days: contact.wait_days,
+                        state,
+                        requested_at,
+                        release_at,
+                        denied_at,
+                    });
+                }
+                emit(&EntryList { entries: listed });
+            }
+            Err(err) => fail!("Failed to list emergency contacts: {}", err),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("remove") {
+        let contact = matches.value_of("contact").unwrap();
+        match remove_contact(contact) {
+            Ok(true) => {
+                say!("Removed emergency contact {}.", contact);
+                record_event("emergency-remove", None, key);
+                emit(&RemovedKey {
+                    key: parse_member_key(contact).unwrap_or_else(|_| contact.to_string()),
+                    epoch: None,
+                });
+            }
+            Ok(false) => fail!("{} is not an emergency contact of this vault.", contact),
+            Err(err) => fail!("{}", err),
+        }
+        return;
+    }
+
+    let device = match device_key() {
+        Ok(device) => device,
+        Err(err) => {
+            fail!("Failed to load device key: {}", err);
+            return;
+        }
+    };
+    if let Some(matches) = matches.subcommand_matches("add") {
+        let contact = matches.value_of("contact").unwrap();
+        let wait_days = match matches.value_of("wait-days").unwrap().parse::<i64>() {
+            Ok(days) => days,
+            Err(_) => {
+                fail!("Error: --wait-days must be a number");
+                return;
+            }
+        };
+        match add_contact(&device, contact, wait_days, key, now) {
+            Ok(_) => {
+                say!("Added {} as an emergency contact.", contact);
+                say!("They can read this vault {} day(s) after asking, unless you run `emergency deny` first.", wait_days);
+                record_event("emergency-add", None, key);
+                emit(&EmergencyContact {
+                    key: parse_member_key(contact).unwrap_or_else(|_| contact.to_string()),
+                    wait_days,
+                    state: "idle",
+                    requested_at: None,
+                    release_at: None,
+                    denied_at: None,
+                });
+            }
+            Err(err) => fail!("Failed to add emergency contact: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("deny") {
+        let contact = matches.value_of("contact").unwrap();
+        match deny_request(&device, contact, now) {
+            Ok(_) => {
+                say!("Denied the emergency access request of {}.", contact);
+                record_event("emergency-deny", None, key);
+                emit(&EmergencyRequest {
+                    vault: None,
+                    contact: Some(parse_member_key(contact).unwrap_or_else(|_| contact.to_string())),
+                    state: "denied",
+                    requested_at: None,
+                    release_at: None,
+                    denied_at: Some(now),
+                });
+            }
+            Err(err) => fail!("Failed to deny: {}", err),
+        }
+    } else if let Some((name, matches)) = matches.subcommand() {
+        let path = match resolve_vault(matches.value_of("from"), None) {
+            Ok(path) => path,
+            Err(err) => {
+                fail!("{}", err);
+                return;
+            }
+        };
+        if name == "request" {
+            match request_access(&path, &device, now) {
+                Ok(state) => {
+                    match state {
+                        AccessState::Waiting { release_at, .. } => {
+                            say!("Asked {} for emergency access.", path.display());
+                            say!("It is released on {} unless it is denied before then.", format_timestamp(release_at));
+                        }
+                        AccessState::Released { .. } => say!("Access is released; read the vault with: emergency access --from {}", path.display()),
+                        _ => {
+                            fail!("The request was not recorded.");
+                            return;
+                        }
+                    }
+                    let (state, requested_at, release_at, denied_at) = access_fields(&state);
+                    emit(&EmergencyRequest {
+                        vault: Some(path.display().to_string()),
+                        contact: None,
+                        state,
+                        requested_at,
+                        release_at,
+                        denied_at,
+                    });
+                }
+                Err(err) => fail!("Failed to request access: {}", err),
+            }
+            return;
+        }
+
+        let items = match emergency_access(&path, &device, now) {
+            Ok(items) => items,
+            Err(err) => {
+                fail!("No emergency access: {}", err);
+                return;
+            }
+        };
+        let origin = format!("from {} (emergency access)", path.display());
+        match matches.value_of("title") {
+            Some(title) => match items.iter().find(|item| item.metadata.title == title) {
+                Some(item) => {
+                    print_shared_item(item, &origin);
+                    record_event("emergency-access", Some(title), key);
+                }
+                None => fail!("{} has no entry titled '{}'.", path.display(), title),
+            },
+            None => {
+                say!("Entries of {}:", path.display());
+                for item in &items {
+                    say!("  {} ({})", item.metadata.title, item.metadata.item_type.name());
+                }
+                emit(&EntryList {
+                    entries: items.iter().map(|item| listed_entry(&item.metadata, None, &[])).collect(),
+                });
+            }
+        }
+    }
+}
+
+/// Handles `batch apply`. Nothing is written unless every operation of the
+/// manifest is valid, and then all of them are written in one transaction.
+fn run_batch_apply(matches: &ArgMatches, generator: &GeneratorPolicy, key: &Key) {
+    let path = Path::new(matches.value_of("file").unwrap());
+    let dry_run = matches.is_present("dry-run");
+    let plan = match load_manifest(path).map_err(|err| vec![err]).and_then(|manifest| plan_batch(&manifest, generator, key)) {
+        Ok(plan) => plan,
+        Err(errors) => {
+            fail!("Failed to apply batch: {} problem(s) found, so nothing was written.", errors.len());
+            for err in errors {
+                fail!("  {}", err);
+            }
+            return;
+        }
+    };
+
+    for change in &plan.changes {
+        let marker = match change.action {
+            "add" | "generate" => "+",
+            "delete" => "-",
+            _ if change.diff.is_empty() => "=",
+            _ => "~",
+        };
+        say!("{} {}", marker, change.title);
+        for line in &change.diff {
+            say!("    {}", line);
+        }
+    }
+    if dry_run {
+        say!("Dry run: {} operation(s) are valid; nothing was written.", plan.changes.len());
+    } else {
+        if let Err(err) = plan.apply() {
+            fail!("{}", err);
+            return;
+        }
+        say!("Applied {} operation(s) from {}.", plan.changes.len(), path.display());
+        for change in plan.changes.iter().filter(|change| change.action == "delete" || !change.diff.is_empty()) {
+            let action = if change.action == "generate" { "add" } else { change.action };
+            record_event(&format!("batch-{}", action), Some(&change.title), key);
+            if change.action == "modify" {
+                if let Err(err) = refresh_shared_entry(&title_index(&change.title, key), key) {
+                    say!("Warning: the shared copy of '{}' was not updated: {}", change.title, err);
+                }
+            }
+        }
+        sync_tokens(key);
+    }
+    emit(&BatchResult {
+        dry_run,
+        changes: plan.changes,
+    });
+}
+
+/// Why `recovery` refuses plain vaults.
+const NOT_A_TEAM_VAULT: &str =
+    "Recovery shares are only for team vaults: a plain vault is opened by the key built into the program. Make it a team vault with `team init` first.";
+
+/// Handles `recovery split`, printing shares of the key that opens a team
+/// vault. A plain vault is opened by the key built into the program, which
+/// is no secret worth splitting.
+fn run_recovery_split(matches: &ArgMatches, key: &Key) {
+    if !is_team_vault() {
+        fail!("{}", NOT_A_TEAM_VAULT);
+        return;
+    }
+    let count = |name: &str| {
+        matches
+            .value_of(name)
+            .unwrap()
+            .parse::<u8>()
+            .map_err(|_| format!("Error: --{} must be a number from 1 to 255", name))
+    };
+    let (shares, threshold) = match (count("shares"), count("threshold")) {
+        (Ok(shares), Ok(threshold)) => (shares, threshold),
+        (Err(err), _) | (_, Err(err)) => {
+            fail!("{}", err);
+            return;
+        }
+    };
+    let format = ShareFormat::parse(matches.value_of("format").unwrap()).unwrap();
+
+    match split_secret(key.as_ref(), shares, threshold) {
+        Ok(split) => {
+            say!("Any {} of these {} shares rebuild the vault key with `recovery combine`.", threshold, shares);
+            say!("Give each to a different person and keep them offline: together they open the vault.");
+            say!("Removing a team member rotates the key; split it again afterwards.");
+            for share in &split {
+                say!();
+                say!("Share {} of {}:", share.index, shares);
+                say!("{}", share.encode(format));
+            }
+            record_event("recovery", None, key);
+            emit(&RecoveryShares {
+                threshold,
+                shares: split
+                    .iter()
+                    .map(|share| RecoveryShare {
+                        index: share.index,
+                        share: share.encode(format),
+                    })
+                    .collect(),
+            });
+        }
+        Err(err) => fail!("Failed to split the vault key: {}", err),
+    }
+}
+
+/// Handles `recovery combine`. The rebuilt key is checked against the team
+/// vault, which is recovered with this device as its owner.
+fn run_recovery_combine(matches: &ArgMatches) {
+    if !is_team_vault() {
+        fail!("{}", NOT_A_TEAM_VAULT);
+        return;
+    }
+    let encoded: Vec<String> = match matches.values_of("share") {
+        Some(values) => values.map(str::to_string).collect(),
+        None => {
+            say!("Enter the shares, one per line, then an empty line:");
+            io::stdin().lines().map_while(Result::ok).take_while(|line| !line.trim().is_empty()).collect()
+        }
+    };
+    let mut shares = Vec::new();
+    for (number, share) in encoded.iter().enumerate() {
+        match Share::decode(share) {
+            Ok(share) => shares.push(share),
+            Err(err) => {
+                fail!("Invalid share {}: {}", number + 1, err);
+                return;
+            }
+        }
+    }
+    let key = match combine_shares(&shares).and_then(|bytes| Key::from_slice(&bytes).ok_or_else(|| "The shares do not hold a vault key".to_string())) {
+        Ok(key) => key,
+        Err(err) => {
+            fail!("Failed to rebuild the vault key: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = verify_vault_integrity() {
+        fail!("Vault integrity check failed: {}", err);
+        return;
+    }
+    match device_key().and_then(|device| recover_team(&device, &key)) {
+        Ok(team) => {
+            say!("Team vault recovered; you are now its owner: {}", team.member_key);
+            say!("Remove members who have left with `team remove`, which also rotates the key, then split the new key.");
+            record_event("recover", None, &team.key);
+        }
+        Err(err) => fail!("Failed to recover the team vault: {}", err),
+    }
+}
+
+/// Handles the `expiry` subcommands, which manage rotation policies.
+fn run_expiry_command(matches: &ArgMatches, key: &Key) {
+    if matches.subcommand_matches("list").is_some() {
+        match load_folder_policies(key) {
+            Ok(policies) => {
+                if policies.is_empty() {
+                    say!("No folder policies set.");
+                }
+                for policy in &policies {
+                    say!("{}  every {}d", policy.path, policy.rotate_every_days);
+                }
+                emit(&EntryList {
+                    entries: policies
+                        .into_iter()
+                        .map(|policy| FolderRotation {
+                            folder: policy.path,
+                            rotate_every_days: policy.rotate_every_days,
+                        })
+                        .collect(),
+                });
+            }
+            Err(err) => fail!("Failed to decrypt folder policies: {}", err),
+        }
+        return;
+    }
+
+    let (matches, every) = match matches.subcommand() {
+        Some(("set", matches)) => match parse_rotation(matches.value_of("every").unwrap()) {
+            Ok(0) => {
+                fail!("Error: --every must be at least one day");
+                return;
+            }
+            Ok(days) => (matches, Some(days)),
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        },
+        Some((_, matches)) => (matches, None),
+        None => return,
+    };
+
+    if let Some(title) = matches.value_of("title") {
+        let index = title_index(title, key);
+        let entry = match get_entry(&index) {
+            Some(entry) => entry,
+            None => {
+                fail!("No password found for '{}'.", title);
+                return;
+            }
+        };
+        let mut metadata = match decrypt_metadata(&entry.encrypted_metadata, &index, key) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                fail!("Failed to decrypt entry metadata: {}", err);
+                return;
+            }
+        };
+
+        metadata.rotate_every_days = every;
+        match update_metadata(&index, &encrypt_metadata(&metadata, &index, key)) {
+            Ok(_) => {
+                match every {
+                    Some(days) => say!("'{}' must now be changed every {} days.", title, days),
+                    None => say!("Rotation policy removed from '{}'.", title),
+                }
+                emit(&RotationPolicy {
+                    title: Some(metadata.title.clone()),
+                    folder: None,
+                    rotate_every_days: every,
+                });
+            }
+            Err(e) => fail!("Failed to update '{}': {:?}", title, e),
+        }
+    } else {
+        let path = match normalize_folder(matches.value_of("folder").unwrap()) {
+            Ok(Some(path)) => path,
+            Ok(None) => {
+                fail!("Error: policies are set on a folder or a password, not on the top level");
+                return;
+            }
+            Err(err) => {
+                fail!("Error: {}", err);
+                return;
+            }
+        };
+
+        let result = match every {
+            Some(days) => set_folder_policy(&policy_label(
+                &FolderPolicy {
+                    path: path.clone(),
+                    rotate_every_days: days,
+                },
+                key,
+            )),
+            None => clear_folder_policy(&label_index("folder", &path, key)),
+        };
+        match result {
+            Ok(_) => {
+                match every {
+                    Some(days) => say!("Passwords in '{}' must now be changed every {} days.", path, days),
+                    None => say!("Rotation policy removed from folder '{}'.", path),
+                }
+                emit(&RotationPolicy {
+                    title: None,
+                    folder: Some(path),
+                    rotate_every_days: every,
+                });
+            }
+            Err(diesel::result::Error::NotFound) => fail!("Folder '{}' has no rotation policy.", path),
+            Err(e) => fail!("Failed to update the policy of '{}': {:?}", path, e),
+        }
+    }
+}
+
+/// Handles the `config` subcommands.
+fn run_config_command(matches: &ArgMatches, path: &Path) {
+    if matches.subcommand_matches("show").is_some() {
+        match load_config(path, false) {
+            Ok(config) => {
+                say!("# {}", path.display());
+                say!("{}", render_config(&config).trim_end());
+                emit(&config);
+            }
+            Err(err) => fail!("Configuration error: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("set") {
+        let key = matches.value_of("key").unwrap();
+        let value = matches.value_of("value").unwrap();
+
+        match set_config_value(path, key, value) {
+            Ok(_) => {
+                say!("Set '{}' in {}.", key, path.display());
+                emit(&ConfigValue {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                });
+            }
+            Err(err) => fail!("Failed to set '{}': {}", key, err),
+        }
+    }
+}
+
+/// Handles the `vault` subcommands, which manage the registry of named vaults.
+fn run_vault_command(matches: &ArgMatches) {
+    if let Some(matches) = matches.subcommand_matches("create") {
+        let name = matches.value_of("name").unwrap();
+
+        let path = match register_vault(name, matches.value_of("path")) {
+            Ok(path) => path,
+            Err(err) => {
+                fail!("Failed to create vault: {}", err);
+                return;
+            }
+        };
+        let path_str = path.to_string_lossy().into_owned();
+
+        if path.exists() {
+            say!("Registered existing vault '{}' at {}.", name, path_str);
+            emit(&registered_vault(name, &path_str));
+            return;
+        }
+
+        // Create the tables and the signed header of the new vault
+        select_vault(&path_str);
+        create_table_if_not_exists();
+        if let Err(err) = verify_vault_integrity() {
+            fail!("Failed to initialise vault '{}': {}", name, err);
+            return;
+        }
+
+        if matches.is_present("encrypted") && !is_container(&path) {
+            if let Err(err) = convert_vault(&path_str, VaultFormat::Encrypted) {
+                fail!("Failed to encrypt vault '{}': {}", name, err);
+                return;
+            }
+        }
+        say!("Vault '{}' created at {}.", name, path_str);
+        emit(&registered_vault(name, &path_str));
+    } else if matches.subcommand_matches("list").is_some() {
+        let registry = match load_registry() {
+            Ok(registry) => registry,
+            Err(err) => {
+                fail!("{}", err);
+                return;
+            }
+        };
+        if registry.vaults.is_empty() {
+            say!("No vaults registered.");
+        }
+        let mut vaults = Vec::new();
+        for (name, entry) in &registry.vaults {
+            let default = registry.default.as_deref() == Some(name.as_str());
+            let encrypted = is_container(Path::new(&entry.path));
+            say!(
+                "{} {} {} ({})",
+                if default { "*" } else { " " },
+                name,
+                entry.path,
+                if encrypted { "encrypted" } else { "plain" }
+            );
+            vaults.push(VaultInfo {
+                name: name.clone(),
+                path: entry.path.clone(),
+                encrypted,
+                default,
+            });
+        }
+        emit(&EntryList { entries: vaults });
+    } else if let Some(matches) = matches.subcommand_matches("remove") {
+        let name = matches.value_of("name").unwrap();
+
+        match unregister_vault(name) {
+            Ok(entry) => {
+                let removed = VaultInfo {
+                    name: name.to_string(),
+                    path: entry.path.clone(),
+                    encrypted: is_container(Path::new(&entry.path)),
+                    default: false,
+                };
+                if matches.is_present("delete-file") {
+                    match std::fs::remove_file(&entry.path) {
+                        Ok(_) => say!("Vault '{}' removed and {} deleted.", name, entry.path),
+                        Err(err) => say!("Vault '{}' removed, but {} could not be deleted: {}", name, entry.path, err),
+                    }
+                } else {
+                    say!("Vault '{}' removed from the registry; {} was kept.", name, entry.path);
+                }
+                emit(&removed);
+            }
+            Err(err) => fail!("Failed to remove vault: {}", err),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("default") {
+        let name = matches.value_of("name").unwrap();
+
+        match set_default_vault(name) {
+            Ok(entry) => {
+                say!("Vault '{}' is now the default.", name);
+                emit(&VaultInfo {
+                    name: name.to_string(),
+                    encrypted: is_container(Path::new(&entry.path)),
+                    path: entry.path,
+                    default: true,
+                });
+            }
+            Err(err) => fail!("Failed to set default vault: {}", err),
+        }
+    }
+}
+
+/// Describes a vault `vault create` registered; the first one registered
+/// becomes the default.
+fn registered_vault(name: &str, path: &str) -> VaultInfo {
+    VaultInfo {
+        name: name.to_string(),
+        path: path.to_string(),
+        encrypted: is_container(Path::new(path)),
+        default: load_registry().is_ok_and(|registry| registry.default.as_deref() == Some(name)),
+    }
 }
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use sodiumoxide::randombytes::randombytes_uniform;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Settings read from `config.toml`. Every field has a default, so a missing
+/// file or a partial one is fine; unknown keys are rejected to catch typos.
+/// Reserved settings are validated but not used by any command yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Vault used when neither `--vault` nor `DATABASE_URL` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_vault: Option<String>,
+    /// Reserved: seconds before a copied password would be cleared from the
+    /// clipboard (0 keeps it). Nothing copies to the clipboard yet.
+    pub clipboard_timeout: u64,
+    /// Reserved: seconds of inactivity before an unlocked vault would be locked
+    /// again (0 never locks). Vaults are not locked on a timer yet.
+    pub auto_lock_timeout: u64,
+    pub output: OutputFormat,
+    /// Rules for the passwords `batch apply` generates.
+    pub generator: GeneratorPolicy,
+    pub kdf: KdfParams,
+    pub sync: SyncConfig,
+    pub ssh_agent: SshAgentConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+/// Rules for generated passwords.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+}
+
+/// Reserved: the Argon2id cost of deriving a key from a master password.
+/// Vaults have no master password yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KdfParams {
+    pub ops_limit: u64,
+    pub memory_mib: u64,
+}
+
+/// Reserved: peers to synchronise the vault with, as `host:port`. Vaults are
+/// not synchronised yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    pub peers: Vec<String>,
+}
+
+/// Settings of the `ssh-agent` command.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshAgentConfig {
+    /// Ask on the terminal before every signature.
+    pub confirm: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            default_vault: None,
+            clipboard_timeout: 45,
+            auto_lock_timeout: 300,
+            output: OutputFormat::Text,
+            generator: GeneratorPolicy::default(),
+            kdf: KdfParams::default(),
+            sync: SyncConfig::default(),
+            ssh_agent: SshAgentConfig::default(),
+        }
+    }
+}
+
+impl Default for GeneratorPolicy {
+    fn default() -> Self {
+        GeneratorPolicy {
+            length: 24,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+        }
+    }
+}
+
+impl GeneratorPolicy {
+    /// Generates a random password of `length` characters, with at least one
+    /// character of every allowed class.
+    pub fn generate(&self) -> String {
+        let classes: Vec<&[u8]> = [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(allowed, _)| *allowed)
+        .map(|(_, class)| class)
+        .collect();
+        let alphabet: Vec<u8> = classes.concat();
+        let pick = |set: &[u8]| set[randombytes_uniform(set.len() as u32) as usize];
+
+        let mut password: Vec<u8> = classes.iter().map(|class| pick(class)).collect();
+        while password.len() < self.length {
+            password.push(pick(&alphabet));
+        }
+        // Shuffle, so the guaranteed characters are not always first
+        for i in (1..password.len()).rev() {
+            password.swap(i, randombytes_uniform(i as u32 + 1) as usize);
+        }
+        String::from_utf8(password).expect("Generated passwords are ASCII")
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        // libsodium's "moderate" Argon2id limits
+        KdfParams {
+            ops_limit: 3,
+            memory_mib: 256,
+        }
+    }
+}
+
+impl Config {
+    /// Checks that every setting is within a usable range.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(vault) = &self.default_vault {
+            if vault.trim().is_empty() {
+                return Err("default_vault cannot be empty".to_string());
+            }
+        }
+        if self.clipboard_timeout > 86_400 {
+            return Err("clipboard_timeout must be at most 86400 seconds".to_string());
+        }
+        if self.auto_lock_timeout > 86_400 {
+            return Err("auto_lock_timeout must be at most 86400 seconds".to_string());
+        }
+
+        let generator = &self.generator;
+        if !(8..=1024).contains(&generator.length) {
+            return Err("generator.length must be between 8 and 1024".to_string());
+        }
+        if !(generator.lowercase || generator.uppercase || generator.digits || generator.symbols) {
+            return Err("generator must allow at least one character class".to_string());
+        }
+
+        if !(1..=32).contains(&self.kdf.ops_limit) {
+            return Err("kdf.ops_limit must be between 1 and 32".to_string());
+        }
+        if !(8..=4096).contains(&self.kdf.memory_mib) {
+            return Err("kdf.memory_mib must be between 8 and 4096".to_string());
+        }
+
+        for peer in &self.sync.peers {
+            validate_peer(peer)?;
+        }
+        Ok(())
+    }
+}
+
+/// A peer must be `host:port` with a non-zero port.
+fn validate_peer(peer: &str) -> Result<(), String> {
+    let valid = match peer.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0),
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("sync peer '{}' must be written as host:port", peer))
+    }
+}
+
+/// Location of the configuration file: `--config` if given, otherwise
+/// `$XDG_CONFIG_HOME/pwvault/config.toml` (usually `~/.config/pwvault`).
+pub fn config_path(override_path: Option<&str>) -> PathBuf {
+    if let Some(path) = override_path {
+        return PathBuf::from(path);
+    }
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("pwvault")
+        .join("config.toml")
+}
+
+/// Loads and validates the configuration. A missing file means the defaults,
+/// unless the file was named explicitly with `--config`.
+pub fn load_config(path: &Path, explicit: bool) -> Result<Config, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let config: Config =
+        toml::from_str(&contents).map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+    config
+        .validate()
+        .map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+/// Renders the effective configuration, defaults included.
+pub fn render_config(config: &Config) -> String {
+    toml::to_string(config).expect("Failed to serialize configuration")
+}
+
+/// Sets a dotted `key` such as `generator.length` in the file at `path`.
+/// The value is read as a TOML value when possible (`30`, `true`,
+/// `["host:7000"]`) and as a plain string otherwise. The file is only
+/// written if the result is still a valid configuration.
+pub fn set_config_value(path: &Path, key: &str, value: &str) -> Result<Config, String> {
+    let mut table = match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str::<toml::value::Table>(&contents)
+            .map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => toml::value::Table::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    insert_dotted(&mut table, key, parse_value(value))?;
+
+    let table = toml::Value::Table(table);
+    let config: Config = table
+        .clone()
+        .try_into()
+        .map_err(|e| format!("Invalid value for '{}': {}", key, e))?;
+    config.validate()?;
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let contents = toml::to_string(&table).map_err(|e| format!("Failed to serialize configuration: {}", e))?;
+    fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+fn parse_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn insert_dotted(table: &mut toml::value::Table, key: &str, value: toml::Value) -> Result<(), String> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(format!("Invalid configuration key '{}'", key));
+    }
+
+    let (last, sections) = parts.split_last().unwrap();
+    let mut current = table;
+    for section in sections {
+        current = current
+            .entry(section.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("'{}' is not a section", section))?;
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("pwvault_config_test_{}_{}.toml", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_load_config_defaults_and_partial_file() {
+        // Setup
+        let path = temp_config("load");
+
+        // Act & Assert: A missing file gives the defaults, unless it was named explicitly
+        assert_eq!(load_config(&path, false), Ok(Config::default()));
+        assert!(load_config(&path, true).is_err());
+
+        // Act: Only some settings are given
+        fs::write(&path, "output = \"json\"\n[generator]\nlength = 32\n").unwrap();
+        let config = load_config(&path, true).expect("Config should load");
+
+        // Assert
+        assert_eq!(config.output, OutputFormat::Json);
+        assert_eq!(config.generator.length, 32);
+        assert!(config.generator.symbols);
+        assert_eq!(config.kdf, KdfParams::default());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_load_config_rejects_invalid_settings() {
+        // Setup
+        let path = temp_config("invalid");
+
+        // Act & Assert: Unknown keys, wrong types and out-of-range values are reported
+        for contents in [
+            "clipbaord_timeout = 10\n",
+            "output = \"yaml\"\n",
+            "[generator]\nlength = 4\n",
+            "[kdf]\nmemory_mib = 0\n",
+            "[sync]\npeers = [\"no-port\"]\n",
+        ] {
+            fs::write(&path, contents).unwrap();
+            let result = load_config(&path, true);
+            assert!(result.is_err(), "'{}' should be rejected", contents);
+            assert!(result.unwrap_err().contains("Invalid configuration"));
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_set_config_value() {
+        // Setup
+        let path = temp_config("set");
+
+        // Act
+        set_config_value(&path, "generator.length", "40").expect("Setting a number should succeed");
+        set_config_value(&path, "default_vault", "work").expect("Setting a string should succeed");
+        set_config_value(&path, "output", "json").expect("Setting a value after a section should succeed");
+        set_config_value(&path, "sync.peers", "[\"10.0.0.2:7000\"]").expect("Setting a list should succeed");
+
+        // Assert: Settings are kept, and invalid ones leave the file untouched
+        let config = load_config(&path, true).unwrap();
+        assert_eq!(config.generator.length, 40);
+        assert_eq!(config.default_vault, Some("work".to_string()));
+        assert_eq!(config.output, OutputFormat::Json);
+        assert_eq!(config.sync.peers, vec!["10.0.0.2:7000".to_string()]);
+        assert!(set_config_value(&path, "generator.lenght", "40").is_err());
+        assert!(set_config_value(&path, "kdf.ops_limit", "0").is_err());
+        assert_eq!(load_config(&path, true), Ok(config));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_generated_passwords_follow_the_policy() {
+        // Arrange
+        let policy = GeneratorPolicy {
+            length: 12,
+            symbols: false,
+            ..GeneratorPolicy::default()
+        };
+
+        // Act
+        let passwords: Vec<String> = (0..50).map(|_| policy.generate()).collect();
+
+        // Assert: Every allowed class is used, and only those
+        for password in &passwords {
+            assert_eq!(password.len(), 12);
+            assert!(password.bytes().any(|c| c.is_ascii_lowercase()), "{}", password);
+            assert!(password.bytes().any(|c| c.is_ascii_uppercase()), "{}", password);
+            assert!(password.bytes().any(|c| c.is_ascii_digit()), "{}", password);
+            assert!(password.bytes().all(|c| c.is_ascii_alphanumeric()), "{}", password);
+        }
+        assert_ne!(passwords[0], passwords[1]);
+    }
+}
@@ -0,0 +1,477 @@
+use crate::encryption::{derive_subkey, generate_key, SUBKEY_CONTAINER};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::secretstream::xchacha20poly1305 as stream;
+use sodiumoxide::crypto::secretstream::{Header, Key, Stream, Tag};
+use std::env;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Magic bytes at the start of an encrypted vault container.
+const MAGIC: &[u8; 8] = b"PWVAULT\x01";
+
+/// Size of the plaintext chunks the database file is split into.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How a vault file is stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VaultFormat {
+    /// A regular SQLite file with per-field encryption.
+    Plain,
+    /// The whole SQLite file sealed inside an encrypted container.
+    Encrypted,
+}
+
+impl VaultFormat {
+    /// Parses a format name as accepted by `VAULT_FORMAT` and `convert --to`.
+    pub fn parse(name: &str) -> Result<VaultFormat, String> {
+        match name {
+            "plain" => Ok(VaultFormat::Plain),
+            "encrypted" => Ok(VaultFormat::Encrypted),
+            other => Err(format!("Unknown vault format '{}': expected 'plain' or 'encrypted'", other)),
+        }
+    }
+}
+
+/// The decrypted working copy of an encrypted vault. It lives in a private
+/// temporary directory for the lifetime of the process and is sealed back
+/// into the container by `close_vault`.
+struct OpenVault {
+    container_path: PathBuf,
+    working_dir: PathBuf,
+    working_path: PathBuf,
+    /// Digest of the working copy when it was opened; `None` for a new vault.
+    digest: Option<sha256::Digest>,
+    /// Held until the working copy is sealed, so no other process opens the
+    /// container meanwhile and loses its changes or ours.
+    _lock: File,
+}
+
+static OPEN_VAULT: Mutex<Option<OpenVault>> = Mutex::new(None);
+
+/// Seals the open vault (if any) when dropped, so the plaintext working copy
+/// never outlives the process, even when a command panics.
+pub struct VaultGuard;
+
+impl Drop for VaultGuard {
+    fn drop(&mut self) {
+        close_vault();
+    }
+}
+
+/// Derives the key used for whole-file encryption from the vault key.
+fn container_key() -> Key {
+    Key::from_slice(&derive_subkey(&generate_key(), SUBKEY_CONTAINER)).expect("Container key has the wrong length")
+}
+
+/// Format used when a vault file is created, selected through `VAULT_FORMAT`.
+pub fn new_vault_format() -> Result<VaultFormat, String> {
+    match env::var("VAULT_FORMAT") {
+        Ok(name) => VaultFormat::parse(&name),
+        Err(_) => Ok(VaultFormat::Plain),
+    }
+}
+
+/// Resolves the SQLite file to connect to for the vault at `vault_path`.
+/// Plain vaults are used in place; encrypted containers are decrypted into a
+/// private working copy on first use and the copy's path is returned instead.
+pub fn connection_path(vault_path: &str) -> Result<String, String> {
+    let container_path = PathBuf::from(vault_path);
+    let mut open_vault = OPEN_VAULT.lock().unwrap();
+
+    if let Some(open) = open_vault.as_ref() {
+        if open.container_path == container_path {
+            return Ok(open.working_path.to_string_lossy().into_owned());
+        }
+    }
+
+    let is_new_container = !container_path.exists() && new_vault_format()? == VaultFormat::Encrypted;
+    if !is_new_container && !is_container(&container_path) {
+        return Ok(vault_path.to_string());
+    }
+
+    // Another vault was open in this process; seal it before switching.
+    if let Some(previous) = open_vault.take() {
+        seal_open_vault(previous)?;
+    }
+    let lock = lock_container(&container_path)?;
+
+    let working_dir = working_dir_root().join(format!(
+        "pwvault-{}-{}",
+        std::process::id(),
+        hex_encode(&sodiumoxide::randombytes::randombytes(8))
+    ));
+    create_private_dir(&working_dir).map_err(|e| format!("Failed to create working directory: {}", e))?;
+    let working_path = working_dir.join("vault.db");
+
+    let digest = if is_new_container {
+        None
+    } else {
+        let opened = open_file(&container_path, &working_path, &container_key()).and_then(|_| file_digest(&working_path));
+        match opened {
+            Ok(digest) => Some(digest),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&working_dir);
+                return Err(e);
+            }
+        }
+    };
+
+    let path = working_path.to_string_lossy().into_owned();
+    *open_vault = Some(OpenVault {
+        container_path,
+        working_dir,
+        working_path,
+        digest,
+        _lock: lock,
+    });
+    Ok(path)
+}
+
+/// Seals the working copy of an open encrypted vault back into its container
+/// if it changed, then removes the plaintext copy.
+pub fn close_vault() {
+    let open = OPEN_VAULT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    if let Some(open) = open {
+        if let Err(e) = seal_open_vault(open) {
+            eprintln!("Failed to seal encrypted vault: {}", e);
+        }
+    }
+}
+
+fn seal_open_vault(open: OpenVault) -> Result<(), String> {
+    let changed = match (open.digest, file_digest(&open.working_path)) {
+        (Some(before), Ok(after)) => before != after,
+        (None, Ok(_)) => true,
+        (_, Err(_)) => false, // Nothing was ever written to a new vault.
+    };
+
+    let result = if changed {
+        seal_file(&open.working_path, &open.container_path, &container_key())
+    } else {
+        Ok(())
+    };
+
+    let _ = fs::remove_dir_all(&open.working_dir);
+    result
+}
+
+/// Converts the vault at `vault_path` between the plain and encrypted formats.
+pub fn convert_vault(vault_path: &str, format: VaultFormat) -> Result<(), String> {
+    // Make sure no decrypted working copy is still pending a write-back.
+    close_vault();
+
+    let path = Path::new(vault_path);
+    if !path.exists() {
+        return Err(format!("Vault {} does not exist", vault_path));
+    }
+    let _lock = lock_container(path)?;
+
+    match (format, is_container(path)) {
+        (VaultFormat::Encrypted, true) | (VaultFormat::Plain, false) => Err(format!(
+            "Vault {} is already {}",
+            vault_path,
+            if format == VaultFormat::Encrypted { "encrypted" } else { "plain" }
+        )),
+        (VaultFormat::Encrypted, false) => seal_file(path, path, &container_key()),
+        (VaultFormat::Plain, true) => {
+            let tmp_path = sibling_tmp_path(path);
+            open_file(path, &tmp_path, &container_key())
+                .and_then(|_| fs::rename(&tmp_path, path).map_err(write_error))
+                .inspect_err(|_| {
+                    let _ = fs::remove_file(&tmp_path);
+                })
+        }
+    }
+}
+
+/// Takes the exclusive lock on the container at `path`, held through a lock
+/// file next to it. Fails at once when another process holds it. The lock
+/// file is left in place; removing it could split the lock between processes.
+fn lock_container(path: &Path) -> Result<File, String> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    let lock_path = path.with_file_name(name);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let lock = options
+        .open(&lock_path)
+        .map_err(|e| format!("Failed to create lock file {}: {}", lock_path.display(), e))?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "Vault {} is open in another process; try again once it has finished",
+            path.display()
+        )),
+        Err(TryLockError::Error(e)) => Err(format!("Failed to lock {}: {}", lock_path.display(), e)),
+    }
+}
+
+/// Returns true if the file at `path` is an encrypted vault container.
+/// Missing or unreadable files are reported as not being containers.
+pub fn is_container(path: &Path) -> bool {
+    let mut magic = [0u8; 8];
+    match File::open(path) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+/// Encrypts the plain SQLite file at `plain_path` into a container at
+/// `container_path`. The file is streamed in chunks and written to a temporary
+/// file first, so an interrupted write never leaves a half-written vault.
+pub fn seal_file(plain_path: &Path, container_path: &Path, key: &Key) -> Result<(), String> {
+    let mut input = BufReader::new(
+        File::open(plain_path).map_err(|e| format!("Failed to open {}: {}", plain_path.display(), e))?,
+    );
+
+    let tmp_path = sibling_tmp_path(container_path);
+    let result = (|| -> Result<(), String> {
+        let file = File::create(&tmp_path)
+            .map_err(|e| format!("Failed to create {}: {}", tmp_path.display(), e))?;
+        let mut output = BufWriter::new(file);
+
+        let (mut encryptor, header) =
+            Stream::init_push(key).map_err(|_| "Failed to initialise encryption stream".to_string())?;
+        output.write_all(MAGIC).map_err(write_error)?;
+        output.write_all(header.as_ref()).map_err(write_error)?;
+
+        // Read one chunk ahead so the last chunk can be tagged as final.
+        let mut current = read_chunk(&mut input)?;
+        loop {
+            let next = if current.len() == CHUNK_SIZE { read_chunk(&mut input)? } else { Vec::new() };
+            let tag = if next.is_empty() { Tag::Final } else { Tag::Message };
+            let ciphertext = encryptor
+                .push(&current, Some(MAGIC), tag)
+                .map_err(|_| "Failed to encrypt vault chunk".to_string())?;
+            output.write_all(&(ciphertext.len() as u32).to_be_bytes()).map_err(write_error)?;
+            output.write_all(&ciphertext).map_err(write_error)?;
+            if next.is_empty() {
+                break;
+            }
+            current = next;
+        }
+
+        output.flush().map_err(write_error)?;
+        output.get_ref().sync_all().map_err(write_error)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    fs::rename(&tmp_path, container_path)
+        .map_err(|e| format!("Failed to replace {}: {}", container_path.display(), e))
+}
+
+/// Decrypts the container at `container_path` into a plain SQLite file at
+/// `plain_path`. Fails if the container was modified, truncated or extended.
+pub fn open_file(container_path: &Path, plain_path: &Path, key: &Key) -> Result<(), String> {
+    let mut input = BufReader::new(
+        File::open(container_path).map_err(|e| format!("Failed to open {}: {}", container_path.display(), e))?,
+    );
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(|_| "Vault container is truncated".to_string())?;
+    if &magic != MAGIC {
+        return Err("File is not an encrypted vault container".to_string());
+    }
+
+    let mut header_bytes = [0u8; stream::HEADERBYTES];
+    input.read_exact(&mut header_bytes).map_err(|_| "Vault container is truncated".to_string())?;
+    let header = Header::from_slice(&header_bytes).ok_or("Invalid vault container header")?;
+    let mut decryptor = Stream::init_pull(&header, key)
+        .map_err(|_| "Failed to initialise decryption stream".to_string())?;
+
+    let mut output = BufWriter::new(
+        create_private_file(plain_path).map_err(|e| format!("Failed to create {}: {}", plain_path.display(), e))?,
+    );
+
+    loop {
+        let mut length = [0u8; 4];
+        input.read_exact(&mut length).map_err(|_| "Vault container is truncated".to_string())?;
+        let mut ciphertext = vec![0u8; u32::from_be_bytes(length) as usize];
+        input.read_exact(&mut ciphertext).map_err(|_| "Vault container is truncated".to_string())?;
+
+        let (plaintext, tag) = decryptor
+            .pull(&ciphertext, Some(MAGIC))
+            .map_err(|_| "Failed to decrypt vault: wrong key or the file was tampered with".to_string())?;
+        output.write_all(&plaintext).map_err(write_error)?;
+
+        if tag == Tag::Final {
+            break;
+        }
+    }
+
+    // Nothing may follow the final chunk.
+    let mut trailing = [0u8; 1];
+    if input.read(&mut trailing).map_err(write_error)? != 0 {
+        return Err("Vault container has unexpected trailing data".to_string());
+    }
+
+    output.flush().map_err(write_error)
+}
+
+/// Computes a SHA-256 digest of a file, used to tell whether a decrypted
+/// working copy was modified and needs to be sealed again.
+pub fn file_digest(path: &Path) -> Result<sha256::Digest, String> {
+    let mut input = BufReader::new(File::open(path).map_err(write_error)?);
+    let mut state = sha256::State::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = input.read(&mut buffer).map_err(write_error)?;
+        if read == 0 {
+            break;
+        }
+        state.update(&buffer[..read]);
+    }
+    Ok(state.finalize())
+}
+
+/// Creates a directory only the current user can access, used to hold the
+/// decrypted working copy of a container while the vault is open.
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+/// Creates or truncates a file only the current user can read.
+pub fn create_private_file(path: &Path) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+fn read_chunk(input: &mut impl Read) -> Result<Vec<u8>, String> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    input
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| format!("Failed to read vault: {}", e))?;
+    Ok(chunk)
+}
+
+/// Prefers the per-user runtime directory, which is usually memory-backed.
+fn working_dir_root() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(env::temp_dir)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn sibling_tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn write_error(e: io::Error) -> String {
+    format!("Vault container I/O error: {}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("container_test_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        // Arrange: A plaintext file spanning several chunks
+        let key = stream::gen_key();
+        let plain = temp_path("roundtrip.db");
+        let sealed = temp_path("roundtrip.vault");
+        let restored = temp_path("roundtrip_restored.db");
+        let contents: Vec<u8> = (0..CHUNK_SIZE * 2 + 17).map(|i| (i % 251) as u8).collect();
+        fs::write(&plain, &contents).unwrap();
+
+        // Act
+        seal_file(&plain, &sealed, &key).expect("Sealing should succeed");
+        open_file(&sealed, &restored, &key).expect("Opening should succeed");
+
+        // Assert
+        assert!(is_container(&sealed));
+        assert!(!is_container(&plain));
+        assert_eq!(fs::read(&restored).unwrap(), contents);
+
+        for path in [&plain, &sealed, &restored] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_open_detects_tampering_and_truncation() {
+        // Arrange
+        let key = stream::gen_key();
+        let plain = temp_path("tamper.db");
+        let sealed = temp_path("tamper.vault");
+        let restored = temp_path("tamper_restored.db");
+        fs::write(&plain, vec![7u8; CHUNK_SIZE + 10]).unwrap();
+        seal_file(&plain, &sealed, &key).unwrap();
+        let original = fs::read(&sealed).unwrap();
+
+        // Act & Assert: Flipping a ciphertext byte is rejected
+        let mut tampered = original.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        fs::write(&sealed, &tampered).unwrap();
+        assert!(open_file(&sealed, &restored, &key).is_err());
+
+        // Act & Assert: Dropping the final chunk is rejected
+        fs::write(&sealed, &original[..original.len() - 40]).unwrap();
+        assert!(open_file(&sealed, &restored, &key).is_err());
+
+        // Act & Assert: The wrong key is rejected
+        fs::write(&sealed, &original).unwrap();
+        assert!(open_file(&sealed, &restored, &stream::gen_key()).is_err());
+
+        for path in [&plain, &sealed, &restored] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_lock_container_is_exclusive() {
+        // Arrange
+        let container = temp_path("locked.vault");
+
+        // Act
+        let held = lock_container(&container).expect("The first lock should succeed");
+        let while_held = lock_container(&container);
+        drop(held);
+        let after_release = lock_container(&container);
+
+        // Assert
+        assert!(while_held.unwrap_err().contains("is open in another process"));
+        assert!(after_release.is_ok());
+
+        drop(after_release);
+        let _ = fs::remove_file(temp_path("locked.vault.lock"));
+    }
+}
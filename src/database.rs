@@ -1,65 +1,561 @@
 use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
 use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::secretbox::Key;
+use crate::audit_log::{self, LogRecord};
+use crate::container;
+use crate::encryption::{
+    decrypt_label, decrypt_metadata, decrypt_password, encrypt_label, encrypt_metadata, encrypt_password, is_legacy_ciphertext,
+    label_index, title_index, unix_now, EntryMetadata,
+};
+use crate::expiry::FolderPolicy;
+use crate::header;
 use crate::schema::passwords; // Import the schema module
 use crate::schema::passwords::dsl as passwords_dsl; // Alias the DSL for operations
+use crate::schema::{attachments, entry_folders, entry_tags, folder_policies, folders, share_grants, shared_entries, tags, token_grants};
+use crate::vaults;
+use std::collections::HashMap;
 use std::env;
+use std::sync::Mutex;
 
 #[derive(Queryable)]
 pub struct Password {
     pub id: i32,
-    pub title: String,
+    pub title_index: String,
+    pub encrypted_metadata: String,
     pub encrypted_password: String,
 }
 
 #[derive(Insertable)]
 #[table_name = "passwords"] // Link this struct to the passwords table
 pub struct NewPassword<'a> {
-    pub title: &'a str,
+    pub title_index: &'a str,
+    pub encrypted_metadata: &'a str,
     pub encrypted_password: &'a str,
 }
 
+#[derive(Queryable)]
+pub struct Folder {
+    pub id: i32,
+    pub path_index: String,
+    pub encrypted_path: String,
+}
+
+#[derive(Queryable)]
+pub struct Tag {
+    pub id: i32,
+    pub tag_index: String,
+    pub encrypted_name: String,
+}
+
+/// A folder path or tag as it is stored: its blind index and its encrypted name.
+pub struct Label {
+    pub index: String,
+    pub encrypted: String,
+}
+
+/// The folders and tags of a vault, and the entries they are attached to.
+pub struct Organization {
+    pub folders: Vec<Folder>,
+    pub tags: Vec<Tag>,
+    /// `(title_index, folder_id)` of every entry filed in a folder.
+    pub entry_folders: Vec<(String, i32)>,
+    /// `(title_index, tag_id)` of every tagged entry.
+    pub entry_tags: Vec<(String, i32)>,
+}
+
+/// A column reported by `PRAGMA table_info`.
+#[derive(QueryableByName)]
+struct TableColumn {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// A row of the pre-encryption `passwords` table, which stored titles in plaintext.
+#[derive(QueryableByName)]
+struct LegacyPassword {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Text"]
+    title: String,
+    #[sql_type = "Text"]
+    encrypted_password: String,
+}
+
+/// The vault chosen for this process through `select_vault`.
+static SELECTED_VAULT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Makes every following connection open the vault at `path`.
+pub fn select_vault(path: &str) {
+    *SELECTED_VAULT.lock().unwrap() = Some(path.to_string());
+}
+
+/// Forgets the selected vault, so connections follow `DATABASE_URL` again.
+#[cfg(test)]
+pub fn deselect_vault() {
+    *SELECTED_VAULT.lock().unwrap() = None;
+}
+
+/// Returns the path of the vault file: the selected vault, otherwise
+/// `DATABASE_URL`, otherwise the default vault in the data directory.
+pub fn vault_path() -> String {
+    if let Some(path) = SELECTED_VAULT.lock().unwrap().as_ref() {
+        return path.clone();
+    }
+    env::var("DATABASE_URL")
+        .unwrap_or_else(|_| vaults::default_vault_path().to_string_lossy().into_owned())
+}
+
 /// Establishes a connection to the SQLite database.
+/// Encrypted vault containers are opened transparently.
 pub fn establish_connection() -> SqliteConnection {
-    let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| "passwords.db".to_string());
-    SqliteConnection::establish(&db_url)
-        .expect(&format!("Error connecting to {}", db_url))
+    let db_url = vault_path();
+    if let Some(parent) = std::path::Path::new(&db_url).parent() {
+        if !parent.as_os_str().is_empty() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+    }
+    let connection_path = container::connection_path(&db_url)
+        .unwrap_or_else(|e| panic!("Error opening {}: {}", db_url, e));
+    let connection = SqliteConnection::establish(&connection_path)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", db_url));
+
+    // Wait for other processes holding a write lock instead of failing at once.
+    connection
+        .execute("PRAGMA busy_timeout = 5000;")
+        .expect("Failed to configure the database connection");
+    connection
 }
 
-/// Creates the `passwords` table if it does not already exist.
-pub fn create_table_if_not_exists() {
-    let connection = establish_connection();
+/// Runs a write inside an immediate transaction and re-signs the vault header
+/// before committing, so the header always describes the committed entries.
+pub fn write_transaction<T, F>(connection: &SqliteConnection, write: F) -> Result<T, diesel::result::Error>
+where
+    F: FnOnce() -> Result<T, diesel::result::Error>,
+{
+    let (result, header) = connection.immediate_transaction::<_, diesel::result::Error, _>(|| {
+        let result = write()?;
+        let header = header::refresh_header(connection)?;
+        Ok((result, header))
+    })?;
 
+    header::advance_anchor(&header);
+    Ok(result)
+}
+
+fn create_passwords_table(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
     diesel::sql_query(
         "CREATE TABLE IF NOT EXISTS passwords (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
-            title TEXT NOT NULL UNIQUE,
+            title_index TEXT NOT NULL UNIQUE,
+            encrypted_metadata TEXT NOT NULL,
             encrypted_password TEXT NOT NULL
         );",
     )
-    .execute(&connection)
-    .expect("Failed to create table");
+    .execute(connection)
+    .map(|_| ())
 }
 
-/// Cleans the database by dropping and recreating the `passwords` table.
+/// Creates the folder, tag and folder policy tables, which live alongside `passwords`.
+fn create_organization_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path_index TEXT NOT NULL UNIQUE,
+            encrypted_path TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tag_index TEXT NOT NULL UNIQUE,
+            encrypted_name TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS entry_folders (
+            title_index TEXT PRIMARY KEY,
+            folder_id INTEGER NOT NULL REFERENCES folders (id)
+        );",
+        "CREATE TABLE IF NOT EXISTS entry_tags (
+            title_index TEXT NOT NULL,
+            tag_id INTEGER NOT NULL REFERENCES tags (id),
+            PRIMARY KEY (title_index, tag_id)
+        );",
+        "CREATE TABLE IF NOT EXISTS folder_policies (
+            path_index TEXT PRIMARY KEY,
+            encrypted_policy TEXT NOT NULL
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Creates the attachment tables. Chunks are stored as BLOBs.
+fn create_attachment_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title_index TEXT NOT NULL,
+            name_index TEXT NOT NULL,
+            encrypted_info TEXT NOT NULL,
+            wrapped_key TEXT NOT NULL,
+            stream_header TEXT NOT NULL,
+            UNIQUE (title_index, name_index)
+        );",
+        "CREATE TABLE IF NOT EXISTS attachment_chunks (
+            attachment_id INTEGER NOT NULL REFERENCES attachments (id),
+            seq INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            PRIMARY KEY (attachment_id, seq)
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Creates the sharing identity table and the tables of entries shared from this vault.
+fn create_sharing_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS identity (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            public_key TEXT NOT NULL,
+            encrypted_secret_key TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS shared_entries (
+            title_index TEXT PRIMARY KEY,
+            wrapped_key TEXT NOT NULL,
+            encrypted_entry TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS share_grants (
+            title_index TEXT NOT NULL REFERENCES shared_entries (title_index),
+            recipient_key TEXT NOT NULL,
+            sealed_key TEXT NOT NULL,
+            PRIMARY KEY (title_index, recipient_key)
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Creates the tables of team vaults: the team and its signed membership.
+fn create_team_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS team (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            team_id TEXT NOT NULL,
+            founder TEXT NOT NULL,
+            epoch BIGINT NOT NULL,
+            key_check TEXT NOT NULL,
+            encrypted_history TEXT NOT NULL,
+            signed_by TEXT NOT NULL,
+            signature TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS team_members (
+            member_key TEXT PRIMARY KEY,
+            role TEXT NOT NULL,
+            granted_by TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            wrapped_key TEXT NOT NULL
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Creates the tables of emergency access: the contacts and their signed
+/// requests and denials.
+fn create_emergency_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS emergency_contacts (
+            contact_key TEXT PRIMARY KEY,
+            wait_days BIGINT NOT NULL,
+            added_at BIGINT NOT NULL,
+            sealed_key TEXT NOT NULL,
+            granted_by TEXT NOT NULL,
+            signature TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS emergency_records (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            contact_key TEXT NOT NULL,
+            action TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            signature TEXT NOT NULL
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Creates the table of tokens for the HTTP API and the keys sealed for scoped tokens.
+fn create_token_tables(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let statements = [
+        "CREATE TABLE IF NOT EXISTS api_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            token_hash TEXT NOT NULL UNIQUE,
+            public_key TEXT NOT NULL,
+            read_only BOOLEAN NOT NULL,
+            scope TEXT,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT
+        );",
+        "CREATE TABLE IF NOT EXISTS token_grants (
+            token_id INTEGER NOT NULL REFERENCES api_tokens (id),
+            title_index TEXT NOT NULL REFERENCES shared_entries (title_index),
+            sealed_key TEXT NOT NULL,
+            PRIMARY KEY (token_id, title_index)
+        );",
+    ];
+    for statement in statements {
+        diesel::sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Adds the columns of scoped, expiring tokens to an `api_tokens` table
+/// created before they existed. Older tokens keep access to every entry.
+fn upgrade_token_table(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let columns = diesel::sql_query("PRAGMA table_info(api_tokens);").load::<TableColumn>(connection)?;
+    if columns.iter().any(|column| column.name == "public_key") {
+        return Ok(());
+    }
+    write_transaction(connection, || {
+        for statement in [
+            "ALTER TABLE api_tokens ADD COLUMN public_key TEXT NOT NULL DEFAULT '';",
+            "ALTER TABLE api_tokens ADD COLUMN scope TEXT;",
+            "ALTER TABLE api_tokens ADD COLUMN expires_at BIGINT;",
+        ] {
+            diesel::sql_query(statement).execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Adds the date a contact was added to an `emergency_contacts` table created
+/// before it was signed. Their old signatures no longer match, so contacts
+/// added before must be added again.
+fn upgrade_emergency_table(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    let columns = diesel::sql_query("PRAGMA table_info(emergency_contacts);").load::<TableColumn>(connection)?;
+    if columns.iter().any(|column| column.name == "added_at") {
+        return Ok(());
+    }
+    write_transaction(connection, || {
+        diesel::sql_query("ALTER TABLE emergency_contacts ADD COLUMN added_at BIGINT NOT NULL DEFAULT 0;").execute(connection)?;
+        Ok(())
+    })
+}
+
+/// Creates the `passwords`, folder and tag, attachment, sharing, team, emergency access, API token, `vault_header` and `audit_log` tables if they do not already exist.
+pub fn create_table_if_not_exists() {
+    let connection = establish_connection();
+
+    create_passwords_table(&connection).expect("Failed to create table");
+    create_organization_tables(&connection).expect("Failed to create folder and tag tables");
+    create_attachment_tables(&connection).expect("Failed to create attachment tables");
+    create_sharing_tables(&connection).expect("Failed to create sharing tables");
+    create_team_tables(&connection).expect("Failed to create team tables");
+    create_emergency_tables(&connection).expect("Failed to create emergency access tables");
+    upgrade_emergency_table(&connection).expect("Failed to upgrade emergency access table");
+    create_token_tables(&connection).expect("Failed to create API token tables");
+    upgrade_token_table(&connection).expect("Failed to upgrade API token table");
+    header::create_header_table(&connection).expect("Failed to create vault header table");
+    audit_log::create_audit_log_table(&connection).expect("Failed to create audit log table");
+}
+
+/// Checks the vault against its authenticated header.
+/// See `header::verify_vault_integrity` for what is detected.
+pub fn verify_vault_integrity() -> Result<(), String> {
+    let connection = establish_connection();
+    header::verify_vault_integrity(&connection)
+}
+
+/// Converts a `passwords` table created before titles were encrypted.
+/// Each plaintext title is replaced by its blind index and encrypted metadata;
+/// the encrypted passwords are carried over unchanged. Returns the number of
+/// migrated entries.
+pub fn migrate_plaintext_titles(key: &Key) -> Result<usize, diesel::result::Error> {
+    let connection = establish_connection();
+
+    let columns = diesel::sql_query("PRAGMA table_info(passwords);")
+        .load::<TableColumn>(&connection)?;
+    if !columns.iter().any(|column| column.name == "title") {
+        return Ok(0);
+    }
+
+    write_transaction(&connection, || {
+        diesel::sql_query("ALTER TABLE passwords RENAME TO passwords_legacy;").execute(&connection)?;
+        create_passwords_table(&connection)?;
+
+        let legacy_rows = diesel::sql_query("SELECT id, title, encrypted_password FROM passwords_legacy;")
+            .load::<LegacyPassword>(&connection)?;
+
+        for row in &legacy_rows {
+            let metadata = EntryMetadata {
+                title: row.title.clone(),
+                ..Default::default()
+            };
+            let index = title_index(&row.title, key);
+            diesel::insert_into(passwords::table)
+                .values((
+                    passwords_dsl::id.eq(row.id),
+                    passwords_dsl::encrypted_metadata.eq(encrypt_metadata(&metadata, &index, key)),
+                    passwords_dsl::title_index.eq(index),
+                    passwords_dsl::encrypted_password.eq(&row.encrypted_password),
+                ))
+                .execute(&connection)?;
+        }
+
+        diesel::sql_query("DROP TABLE passwords_legacy;").execute(&connection)?;
+        Ok(legacy_rows.len())
+    })
+}
+
+/// Re-encrypts metadata written before authenticated encryption so it is
+/// bound to its entry. Returns the number of upgraded entries.
+pub fn upgrade_legacy_metadata(key: &Key) -> Result<usize, diesel::result::Error> {
+    let connection = establish_connection();
+
+    let legacy_rows: Vec<Password> = passwords_dsl::passwords
+        .load::<Password>(&connection)?
+        .into_iter()
+        .filter(|row| is_legacy_ciphertext(&row.encrypted_metadata))
+        .collect();
+    if legacy_rows.is_empty() {
+        return Ok(0);
+    }
+
+    write_transaction(&connection, || {
+        for row in &legacy_rows {
+            let metadata = decrypt_metadata(&row.encrypted_metadata, &row.title_index, key)
+                .map_err(|e| diesel::result::Error::DeserializationError(e.into()))?;
+            diesel::update(passwords_dsl::passwords.filter(passwords_dsl::id.eq(row.id)))
+                .set(passwords_dsl::encrypted_metadata.eq(encrypt_metadata(&metadata, &row.title_index, key)))
+                .execute(&connection)?;
+        }
+        Ok(legacy_rows.len())
+    })
+}
+
+/// Re-encrypts every entry, folder, tag and folder policy from `old_key` to
+/// `new_key`, inside the caller's transaction. Titles and labels get new blind
+/// indexes; the returned map takes each old title index to the new one.
+/// Entries that still need their legacy nonce cannot be re-encrypted.
+pub fn rekey_entries(
+    connection: &SqliteConnection,
+    old_key: &Key,
+    new_key: &Key,
+) -> Result<HashMap<String, String>, diesel::result::Error> {
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+    let mut title_indexes = HashMap::new();
+
+    for row in passwords_dsl::passwords.load::<Password>(connection)? {
+        let metadata = decrypt_metadata(&row.encrypted_metadata, &row.title_index, old_key).map_err(fail)?;
+        let password = decrypt_password(&row.encrypted_password, &row.title_index, None, old_key).map_err(|e| {
+            fail(format!("'{}' cannot be re-encrypted ({}); read it once with `get --nonce` first", metadata.title, e))
+        })?;
+        let index = title_index(&metadata.title, new_key);
+        diesel::update(passwords_dsl::passwords.filter(passwords_dsl::id.eq(row.id)))
+            .set((
+                passwords_dsl::title_index.eq(&index),
+                passwords_dsl::encrypted_metadata.eq(encrypt_metadata(&metadata, &index, new_key)),
+                passwords_dsl::encrypted_password.eq(encrypt_password(&password, &index, new_key)),
+            ))
+            .execute(connection)?;
+        diesel::update(entry_folders::table.filter(entry_folders::title_index.eq(&row.title_index)))
+            .set(entry_folders::title_index.eq(&index))
+            .execute(connection)?;
+        diesel::update(entry_tags::table.filter(entry_tags::title_index.eq(&row.title_index)))
+            .set(entry_tags::title_index.eq(&index))
+            .execute(connection)?;
+        title_indexes.insert(row.title_index, index);
+    }
+
+    for folder in folders::table.load::<Folder>(connection)? {
+        let path = decrypt_label("folder", &folder.encrypted_path, &folder.path_index, old_key).map_err(fail)?;
+        let index = label_index("folder", &path, new_key);
+        diesel::update(folders::table.filter(folders::id.eq(folder.id)))
+            .set((folders::path_index.eq(&index), folders::encrypted_path.eq(encrypt_label("folder", &path, &index, new_key))))
+            .execute(connection)?;
+    }
+    for tag in tags::table.load::<Tag>(connection)? {
+        let name = decrypt_label("tag", &tag.encrypted_name, &tag.tag_index, old_key).map_err(fail)?;
+        let index = label_index("tag", &name, new_key);
+        diesel::update(tags::table.filter(tags::id.eq(tag.id)))
+            .set((tags::tag_index.eq(&index), tags::encrypted_name.eq(encrypt_label("tag", &name, &index, new_key))))
+            .execute(connection)?;
+    }
+
+    // A policy is indexed like its folder, by the path stored inside it
+    for (path_index, encrypted_policy) in folder_policies::table.load::<(String, String)>(connection)? {
+        let json = decrypt_label("policy", &encrypted_policy, &path_index, old_key).map_err(fail)?;
+        let policy = serde_json::from_str::<FolderPolicy>(&json)
+            .map_err(|e| fail(format!("Failed to parse folder policy: {}", e)))?;
+        let index = label_index("folder", &policy.path, new_key);
+        diesel::update(folder_policies::table.filter(folder_policies::path_index.eq(&path_index)))
+            .set((
+                folder_policies::path_index.eq(&index),
+                folder_policies::encrypted_policy.eq(encrypt_label("policy", &json, &index, new_key)),
+            ))
+            .execute(connection)?;
+    }
+    Ok(title_indexes)
+}
+
+/// Cleans the database by dropping and recreating the `passwords`, folder and tag, attachment and share tables.
+/// The vault header, the sharing identity, the team, the emergency contacts and the API tokens are kept, and the header is re-signed for the now empty vault.
 pub fn clean_database() {
     let connection = establish_connection();
 
-    // Drop the table if it exists
-    diesel::sql_query("DROP TABLE IF EXISTS passwords;")
-        .execute(&connection)
-        .expect("Failed to drop table");
+    write_transaction(&connection, || {
+        // Drop the tables if they exist
+        for table in [
+            "passwords",
+            "entry_folders",
+            "entry_tags",
+            "folders",
+            "tags",
+            "folder_policies",
+            "attachment_chunks",
+            "attachments",
+            "share_grants",
+            "token_grants",
+            "shared_entries",
+        ] {
+            diesel::sql_query(format!("DROP TABLE IF EXISTS {};", table)).execute(&connection)?;
+        }
 
-    // Recreate the table
-    create_table_if_not_exists();
+        // Recreate the tables
+        create_passwords_table(&connection)?;
+        create_organization_tables(&connection)?;
+        create_attachment_tables(&connection)?;
+        create_sharing_tables(&connection)?;
+        create_token_tables(&connection)
+    })
+    .expect("Failed to clean database");
 }
 
-/// Adds a new password to the database.
-pub fn add_password(title: &str, encrypted_password: &str) -> Result<(), diesel::result::Error> {
+/// Adds a new password to the database, filed under `folder` and carrying
+/// `tags`, all in one transaction.
+/// Entries are keyed by the blind index of their title rather than the title itself.
+pub fn add_password(
+    title_index: &str,
+    encrypted_metadata: &str,
+    encrypted_password: &str,
+    folder: Option<&Label>,
+    tags: &[Label],
+) -> Result<(), diesel::result::Error> {
     let connection = establish_connection();
 
     // Check if the password title already exists
     let existing_password = passwords_dsl::passwords
-        .filter(passwords_dsl::title.eq(title))
+        .filter(passwords_dsl::title_index.eq(title_index))
         .first::<Password>(&connection)
         .optional()?;
 
@@ -72,37 +568,95 @@ pub fn add_password(title: &str, encrypted_password: &str) -> Result<(), diesel:
 
     // Create a new password struct for insertion
     let new_password = NewPassword {
-        title,
+        title_index,
+        encrypted_metadata,
         encrypted_password,
     };
 
-    // Insert the new password
-    diesel::insert_into(passwords::table)
-        .values(&new_password)
-        .execute(&connection)
-        .map(|_| ())
+    // Insert the new password together with its folder and tags
+    write_transaction(&connection, || {
+        diesel::insert_into(passwords::table)
+            .values(&new_password)
+            .execute(&connection)?;
+        file_entry(&connection, title_index, folder)?;
+        tag_entry(&connection, title_index, tags)
+    })
 }
 
-/// Retrieves a password from the database by its title.
-pub fn get_password(title: &str) -> Option<String> {
+/// Retrieves a password from the database by the blind index of its title.
+pub fn get_password(title_index: &str) -> Option<String> {
     let connection = establish_connection();
 
     passwords_dsl::passwords // Use the aliased DSL for querying
-        .filter(passwords_dsl::title.eq(title))
+        .filter(passwords_dsl::title_index.eq(title_index))
         .select(passwords_dsl::encrypted_password)
         .first::<String>(&connection)
         .optional()
         .expect("Failed to retrieve password")
 }
 
-/// Updates the encrypted password for a given title.
-pub fn update_password(title: &str, new_encrypted_password: &str) -> Result<(), diesel::result::Error> {
+/// Retrieves every stored entry, ordered by insertion.
+pub fn list_passwords() -> Vec<Password> {
+    let connection = establish_connection();
+
+    passwords_dsl::passwords
+        .order(passwords_dsl::id.asc())
+        .load::<Password>(&connection)
+        .expect("Failed to list passwords")
+}
+
+/// Replaces the encrypted metadata of an entry, leaving its password alone.
+pub fn update_metadata(title_index: &str, new_encrypted_metadata: &str) -> Result<(), diesel::result::Error> {
+    let connection = establish_connection();
+
+    let updated_rows = write_transaction(&connection, || {
+        diesel::update(passwords_dsl::passwords.filter(passwords_dsl::title_index.eq(title_index)))
+            .set(passwords_dsl::encrypted_metadata.eq(new_encrypted_metadata))
+            .execute(&connection)
+    })?;
+
+    if updated_rows == 0 {
+        return Err(diesel::result::Error::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Retrieves a whole entry by the blind index of its title.
+pub fn get_entry(title_index: &str) -> Option<Password> {
+    let connection = establish_connection();
+
+    passwords_dsl::passwords
+        .filter(passwords_dsl::title_index.eq(title_index))
+        .first::<Password>(&connection)
+        .optional()
+        .expect("Failed to retrieve entry")
+}
+
+/// Updates the encrypted password for a given title index, and its metadata
+/// when `new_encrypted_metadata` is given.
+pub fn update_password(
+    title_index: &str,
+    new_encrypted_password: &str,
+    new_encrypted_metadata: Option<&str>,
+) -> Result<(), diesel::result::Error> {
     let connection = establish_connection();
 
     // Find the password entry by title and update the encrypted password
-    let updated_rows = diesel::update(passwords_dsl::passwords.filter(passwords_dsl::title.eq(title)))
-        .set(passwords_dsl::encrypted_password.eq(new_encrypted_password))
-        .execute(&connection)?;
+    let updated_rows = write_transaction(&connection, || {
+        let entry = passwords_dsl::passwords.filter(passwords_dsl::title_index.eq(title_index));
+        match new_encrypted_metadata {
+            Some(encrypted_metadata) => diesel::update(entry)
+                .set((
+                    passwords_dsl::encrypted_password.eq(new_encrypted_password),
+                    passwords_dsl::encrypted_metadata.eq(encrypted_metadata),
+                ))
+                .execute(&connection),
+            None => diesel::update(entry)
+                .set(passwords_dsl::encrypted_password.eq(new_encrypted_password))
+                .execute(&connection),
+        }
+    })?;
 
     // If no rows were updated, return an error
     if updated_rows == 0 {
@@ -112,14 +666,17 @@ pub fn update_password(title: &str, new_encrypted_password: &str) -> Result<(),
     Ok(())
 }
 
-/// Deletes a password entry by its title.
+/// Deletes a password entry by its title index.
 /// Returns `Ok(())` if successful, or an error if the title does not exist.
-pub fn delete_password(title: &str) -> Result<(), diesel::result::Error> {
+pub fn delete_password(title_index: &str) -> Result<(), diesel::result::Error> {
     let connection = establish_connection();
 
-    // Delete the password where title matches
-    let deleted_rows = diesel::delete(passwords_dsl::passwords.filter(passwords_dsl::title.eq(title)))
-        .execute(&connection)?;
+    // Delete the password where title matches, along with its folder, tags, attachments and shares
+    let deleted_rows = write_transaction(&connection, || {
+        let deleted_rows = remove_entry(&connection, title_index)?;
+        prune_labels(&connection)?;
+        Ok(deleted_rows)
+    })?;
 
     // If no rows were deleted, return an error
     if deleted_rows == 0 {
@@ -129,7 +686,258 @@ pub fn delete_password(title: &str) -> Result<(), diesel::result::Error> {
     Ok(())
 }
 
+/// A change to one entry, as written by `apply_entry_writes`.
+pub enum EntryWrite {
+    Insert {
+        title_index: String,
+        encrypted_metadata: String,
+        encrypted_password: String,
+        folder: Option<Label>,
+        tags: Vec<Label>,
+    },
+    /// Replaces the password and metadata of an entry, and its folder or its
+    /// tags when given; `Some(None)` takes it out of its folder.
+    Update {
+        title_index: String,
+        encrypted_metadata: String,
+        encrypted_password: String,
+        folder: Option<Option<Label>>,
+        tags: Option<Vec<Label>>,
+    },
+    Delete {
+        title_index: String,
+    },
+}
+
+/// Writes changes to entries in order, in one transaction: either all of
+/// them are written or, if any fails, none. Inserting an existing title
+/// fails with a unique violation, updating or deleting a missing one with `NotFound`.
+pub fn apply_entry_writes(writes: &[EntryWrite]) -> Result<(), diesel::result::Error> {
+    let connection = establish_connection();
+
+    write_transaction(&connection, || {
+        for write in writes {
+            match write {
+                EntryWrite::Insert {
+                    title_index,
+                    encrypted_metadata,
+                    encrypted_password,
+                    folder,
+                    tags,
+                } => {
+                    diesel::insert_into(passwords::table)
+                        .values(&NewPassword {
+                            title_index,
+                            encrypted_metadata,
+                            encrypted_password,
+                        })
+                        .execute(&connection)?;
+                    file_entry(&connection, title_index, folder.as_ref())?;
+                    tag_entry(&connection, title_index, tags)?;
+                }
+                EntryWrite::Update {
+                    title_index,
+                    encrypted_metadata,
+                    encrypted_password,
+                    folder,
+                    tags,
+                } => {
+                    let updated_rows = diesel::update(passwords_dsl::passwords.filter(passwords_dsl::title_index.eq(title_index)))
+                        .set((
+                            passwords_dsl::encrypted_password.eq(encrypted_password),
+                            passwords_dsl::encrypted_metadata.eq(encrypted_metadata),
+                        ))
+                        .execute(&connection)?;
+                    if updated_rows == 0 {
+                        return Err(diesel::result::Error::NotFound);
+                    }
+                    if let Some(folder) = folder {
+                        file_entry(&connection, title_index, folder.as_ref())?;
+                    }
+                    if let Some(tags) = tags {
+                        diesel::delete(entry_tags::table.filter(entry_tags::title_index.eq(title_index))).execute(&connection)?;
+                        tag_entry(&connection, title_index, tags)?;
+                    }
+                }
+                EntryWrite::Delete { title_index } => {
+                    if remove_entry(&connection, title_index)? == 0 {
+                        return Err(diesel::result::Error::NotFound);
+                    }
+                }
+            }
+        }
+        prune_labels(&connection)
+    })
+}
+
+/// Moves entries to other folders in one transaction. Each move names an entry
+/// by its title index and its new folder, `None` meaning the top level.
+/// Fails with `NotFound` if any entry does not exist.
+pub fn move_entries(moves: &[(String, Option<Label>)]) -> Result<(), diesel::result::Error> {
+    let connection = establish_connection();
+
+    write_transaction(&connection, || {
+        for (title_index, folder) in moves {
+            let exists = passwords_dsl::passwords
+                .filter(passwords_dsl::title_index.eq(title_index))
+                .select(passwords_dsl::id)
+                .first::<i32>(&connection)
+                .optional()?;
+            if exists.is_none() {
+                return Err(diesel::result::Error::NotFound);
+            }
+            file_entry(&connection, title_index, folder.as_ref())?;
+        }
+        prune_labels(&connection)
+    })
+}
+
+/// Loads every folder and tag, and which entries they are attached to.
+pub fn load_organization() -> Organization {
+    let connection = establish_connection();
+
+    Organization {
+        folders: folders::table
+            .order(folders::id.asc())
+            .load::<Folder>(&connection)
+            .expect("Failed to list folders"),
+        tags: tags::table
+            .order(tags::id.asc())
+            .load::<Tag>(&connection)
+            .expect("Failed to list tags"),
+        entry_folders: entry_folders::table
+            .load::<(String, i32)>(&connection)
+            .expect("Failed to list entry folders"),
+        entry_tags: entry_tags::table
+            .load::<(String, i32)>(&connection)
+            .expect("Failed to list entry tags"),
+    }
+}
+
+/// Sets the rotation policy of a folder, given as the folder's blind index
+/// and the encrypted policy.
+pub fn set_folder_policy(policy: &Label) -> Result<(), diesel::result::Error> {
+    let connection = establish_connection();
+
+    write_transaction(&connection, || {
+        diesel::replace_into(folder_policies::table)
+            .values((
+                folder_policies::path_index.eq(&policy.index),
+                folder_policies::encrypted_policy.eq(&policy.encrypted),
+            ))
+            .execute(&connection)
+            .map(|_| ())
+    })
+}
+
+/// Removes the rotation policy of a folder.
+/// Returns `NotFound` if the folder has no policy.
+pub fn clear_folder_policy(path_index: &str) -> Result<(), diesel::result::Error> {
+    let connection = establish_connection();
+
+    let deleted_rows = write_transaction(&connection, || {
+        diesel::delete(folder_policies::table.filter(folder_policies::path_index.eq(path_index))).execute(&connection)
+    })?;
+
+    if deleted_rows == 0 {
+        return Err(diesel::result::Error::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Retrieves every folder policy as `(path_index, encrypted_policy)`.
+pub fn list_folder_policies() -> Vec<(String, String)> {
+    let connection = establish_connection();
+
+    folder_policies::table
+        .order(folder_policies::path_index.asc())
+        .load::<(String, String)>(&connection)
+        .expect("Failed to list folder policies")
+}
+
+/// Appends an event to the audit log, signed with this device's key.
+/// `title_index` and `encrypted_title` are empty for vault-wide actions.
+pub fn log_event(action: &str, title_index: &str, encrypted_title: &str) -> Result<(), String> {
+    let device = audit_log::device_key()?;
+    let connection = establish_connection();
+
+    write_transaction(&connection, || {
+        audit_log::append_record(&connection, action, title_index, encrypted_title, &device, unix_now() as i64)
+    })
+    .map(|_| ())
+    .map_err(|e| format!("Failed to append to the audit log: {}", e))
+}
+
+/// Retrieves every audit log record, oldest first.
+pub fn load_audit_log() -> Vec<LogRecord> {
+    let connection = establish_connection();
+
+    audit_log::load_records(&connection).expect("Failed to read the audit log")
+}
+
+/// Deletes an entry along with its folder, tags, attachments and shares.
+/// Returns how many entries were deleted: 0 if there was none.
+fn remove_entry(connection: &SqliteConnection, title_index: &str) -> Result<usize, diesel::result::Error> {
+    let deleted_rows = diesel::delete(passwords_dsl::passwords.filter(passwords_dsl::title_index.eq(title_index)))
+        .execute(connection)?;
+    file_entry(connection, title_index, None)?;
+    diesel::delete(entry_tags::table.filter(entry_tags::title_index.eq(title_index))).execute(connection)?;
+    diesel::sql_query(
+        "DELETE FROM attachment_chunks WHERE attachment_id IN (SELECT id FROM attachments WHERE title_index = ?);",
+    )
+    .bind::<Text, _>(title_index)
+    .execute(connection)?;
+    diesel::delete(attachments::table.filter(attachments::title_index.eq(title_index))).execute(connection)?;
+    diesel::delete(share_grants::table.filter(share_grants::title_index.eq(title_index))).execute(connection)?;
+    diesel::delete(token_grants::table.filter(token_grants::title_index.eq(title_index))).execute(connection)?;
+    diesel::delete(shared_entries::table.filter(shared_entries::title_index.eq(title_index))).execute(connection)?;
+    Ok(deleted_rows)
+}
+
+/// Files an entry under `folder`, creating the folder if needed, or takes it
+/// out of any folder when `folder` is `None`.
+fn file_entry(connection: &SqliteConnection, title_index: &str, folder: Option<&Label>) -> Result<(), diesel::result::Error> {
+    diesel::delete(entry_folders::table.filter(entry_folders::title_index.eq(title_index))).execute(connection)?;
+
+    if let Some(folder) = folder {
+        diesel::insert_or_ignore_into(folders::table)
+            .values((folders::path_index.eq(&folder.index), folders::encrypted_path.eq(&folder.encrypted)))
+            .execute(connection)?;
+        let folder_id = folders::table
+            .filter(folders::path_index.eq(&folder.index))
+            .select(folders::id)
+            .first::<i32>(connection)?;
+        diesel::insert_into(entry_folders::table)
+            .values((entry_folders::title_index.eq(title_index), entry_folders::folder_id.eq(folder_id)))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Adds tags to an entry, creating tags that do not exist yet.
+fn tag_entry(connection: &SqliteConnection, title_index: &str, tags: &[Label]) -> Result<(), diesel::result::Error> {
+    for tag in tags {
+        diesel::insert_or_ignore_into(tags::table)
+            .values((tags::tag_index.eq(&tag.index), tags::encrypted_name.eq(&tag.encrypted)))
+            .execute(connection)?;
+        let tag_id = tags::table
+            .filter(tags::tag_index.eq(&tag.index))
+            .select(tags::id)
+            .first::<i32>(connection)?;
+        diesel::insert_or_ignore_into(entry_tags::table)
+            .values((entry_tags::title_index.eq(title_index), entry_tags::tag_id.eq(tag_id)))
+            .execute(connection)?;
+    }
+    Ok(())
+}
 
+/// Removes folders and tags no entry refers to any more.
+fn prune_labels(connection: &SqliteConnection) -> Result<(), diesel::result::Error> {
+    diesel::sql_query("DELETE FROM folders WHERE id NOT IN (SELECT folder_id FROM entry_folders);").execute(connection)?;
+    diesel::sql_query("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM entry_tags);").execute(connection)?;
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -142,6 +950,9 @@ mod tests {
         // Set a separate database for testing
         env::set_var("DATABASE_URL", "test_passwords.db");
 
+        // Keep rollback anchors of test vaults out of the user's state directory
+        env::set_var("XDG_STATE_HOME", env::temp_dir().join("pwvault_test_state"));
+
         // Clean up any existing test database
         let _ = fs::remove_file("test_passwords.db");
 
@@ -171,7 +982,7 @@ mod tests {
         let encrypted_password = "test_encrypted_password";
 
         // Act: Add a password
-        let result = add_password(title, encrypted_password);
+        let result = add_password(title, "test_metadata", encrypted_password, None, &[]);
         assert!(
             result.is_ok(),
             "Failed to add password: {:?}",
@@ -219,7 +1030,7 @@ mod tests {
         let encrypted_password = "test_password";
 
         // Act: Add the password for the first time
-        let result1 = add_password(title, encrypted_password);
+        let result1 = add_password(title, "test_metadata", encrypted_password, None, &[]);
         assert!(
             result1.is_ok(),
             "Failed to add first password: {:?}",
@@ -227,7 +1038,7 @@ mod tests {
         );
 
         // Act: Attempt to add the same password again (duplicate)
-        let result2 = add_password(title, encrypted_password);
+        let result2 = add_password(title, "test_metadata", encrypted_password, None, &[]);
 
         // Assert: The second addition should fail
         assert!(
@@ -244,7 +1055,7 @@ mod tests {
         // Arrange: Add a test entry
         let title = "clean_test_entry";
         let encrypted_password = "test_password";
-        let result = add_password(title, encrypted_password);
+        let result = add_password(title, "test_metadata", encrypted_password, None, &[]);
         assert!(
             result.is_ok(),
             "Failed to add password before cleaning: {:?}",
@@ -262,5 +1073,215 @@ mod tests {
             "Expected database to be empty after cleaning, but found data."
         );
     }
-}
 
+    #[test]
+    fn test_migrate_plaintext_titles() {
+        // Setup: Replace the table with the legacy plaintext-title layout
+        setup_test_database();
+        let connection = establish_connection();
+        diesel::sql_query("DROP TABLE passwords;")
+            .execute(&connection)
+            .expect("Failed to drop table");
+        diesel::sql_query(
+            "CREATE TABLE passwords (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL UNIQUE,
+                encrypted_password TEXT NOT NULL
+            );",
+        )
+        .execute(&connection)
+        .expect("Failed to create legacy table");
+        diesel::sql_query("INSERT INTO passwords (title, encrypted_password) VALUES ('Legacy Bank', 'legacy_ciphertext');")
+            .execute(&connection)
+            .expect("Failed to insert legacy row");
+
+        // Act: Migrate the table
+        let key = crate::encryption::generate_key();
+        let migrated = migrate_plaintext_titles(&key).expect("Migration should succeed");
+
+        // Assert: The entry is reachable through its blind index only
+        assert_eq!(migrated, 1);
+        assert_eq!(get_password("Legacy Bank"), None);
+        assert_eq!(
+            get_password(&title_index("legacy bank", &key)),
+            Some("legacy_ciphertext".to_string())
+        );
+
+        // Assert: Running the migration again is a no-op
+        assert_eq!(migrate_plaintext_titles(&key).expect("Second migration should succeed"), 0);
+    }
+
+    #[test]
+    fn test_integrity_detects_swapped_entries() {
+        // Setup: Initialize the test database with two entries
+        setup_test_database();
+        add_password("index_a", "metadata_a", "password_a", None, &[]).unwrap();
+        add_password("index_b", "metadata_b", "password_b", None, &[]).unwrap();
+        assert_eq!(verify_vault_integrity(), Ok(()));
+
+        // Act: Swap the ciphertexts behind the password manager's back
+        let connection = establish_connection();
+        diesel::sql_query(
+            "UPDATE passwords SET encrypted_password = CASE title_index
+                WHEN 'index_a' THEN 'password_b' ELSE 'password_a' END;",
+        )
+        .execute(&connection)
+        .expect("Failed to swap entries");
+
+        // Assert
+        assert!(verify_vault_integrity().is_err());
+    }
+
+    #[test]
+    fn test_integrity_detects_deleted_entries() {
+        // Setup
+        setup_test_database();
+        add_password("index_a", "metadata_a", "password_a", None, &[]).unwrap();
+
+        // Act: Remove the row directly
+        let connection = establish_connection();
+        diesel::sql_query("DELETE FROM passwords;")
+            .execute(&connection)
+            .expect("Failed to delete entry");
+
+        // Assert
+        let result = verify_vault_integrity();
+        assert!(
+            result.as_ref().is_err_and(|e| e.contains("deleted")),
+            "Expected deletion to be detected, got {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_audit_log_survives_clean_and_detects_truncation() {
+        // Setup
+        setup_test_database();
+        log_event("add", "index_a", "title_a").unwrap();
+        clean_database();
+        log_event("clean", "", "").unwrap();
+
+        // Assert: The log is kept by clean and its chain is intact
+        let records = load_audit_log();
+        assert_eq!(records.iter().map(|r| r.action.as_str()).collect::<Vec<_>>(), vec!["add", "clean"]);
+        assert!(audit_log::verify_chain(&records).is_empty());
+        assert!(verify_vault_integrity().is_ok());
+
+        // Act: Drop the newest record directly
+        let connection = establish_connection();
+        diesel::sql_query("DELETE FROM audit_log WHERE seq = 2;")
+            .execute(&connection)
+            .expect("Failed to delete record");
+
+        // Assert: The chain alone looks fine, but the header notices
+        assert!(audit_log::verify_chain(&load_audit_log()).is_empty());
+        assert!(verify_vault_integrity().is_err());
+    }
+
+    #[test]
+    fn test_integrity_detects_rollback() {
+        // Setup: Keep a copy of the vault, then write to it again
+        setup_test_database();
+        add_password("index_a", "metadata_a", "password_a", None, &[]).unwrap();
+        assert_eq!(verify_vault_integrity(), Ok(()));
+        fs::copy("test_passwords.db", "test_passwords_old.db").unwrap();
+        add_password("index_b", "metadata_b", "password_b", None, &[]).unwrap();
+
+        // Act: Put the older copy back in place
+        fs::rename("test_passwords_old.db", "test_passwords.db").unwrap();
+
+        // Assert
+        let result = verify_vault_integrity();
+        assert!(
+            result.as_ref().is_err_and(|e| e.contains("rolled back")),
+            "Expected rollback to be detected, got {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_folders_and_tags() {
+        // Setup
+        setup_test_database();
+        let label = |index: &str| Label {
+            index: index.to_string(),
+            encrypted: format!("encrypted_{}", index),
+        };
+
+        // Act: File two entries in a folder, one of them tagged
+        add_password("index_a", "metadata_a", "password_a", Some(&label("prod")), &[label("db"), label("db")])
+            .expect("Adding an organized entry should succeed");
+        add_password("index_b", "metadata_b", "password_b", Some(&label("prod")), &[]).unwrap();
+
+        // Assert: Folders and tags are shared, and covered by the header
+        let organization = load_organization();
+        assert_eq!(organization.folders.len(), 1);
+        assert_eq!(organization.tags.len(), 1);
+        assert_eq!(organization.entry_folders.len(), 2);
+        assert_eq!(organization.entry_tags, vec![("index_a".to_string(), organization.tags[0].id)]);
+        assert_eq!(verify_vault_integrity(), Ok(()));
+
+        // Act: Move one entry to the top level and delete the other
+        move_entries(&[("index_a".to_string(), None)]).expect("Moving should succeed");
+        delete_password("index_b").unwrap();
+
+        // Assert: Unused folders are pruned while used tags stay
+        let organization = load_organization();
+        assert!(organization.folders.is_empty());
+        assert_eq!(organization.tags.len(), 1);
+        assert!(move_entries(&[("missing".to_string(), None)]).is_err());
+
+        // Act & Assert: Tampering with the links is detected
+        let connection = establish_connection();
+        diesel::sql_query("DELETE FROM entry_tags;").execute(&connection).unwrap();
+        assert!(verify_vault_integrity().is_err());
+    }
+
+    #[test]
+    fn test_entry_writes_are_all_or_nothing() {
+        // Setup
+        setup_test_database();
+        let label = |index: &str| Label {
+            index: index.to_string(),
+            encrypted: format!("encrypted_{}", index),
+        };
+        add_password("index_a", "metadata_a", "password_a", Some(&label("prod")), &[label("db")]).unwrap();
+        let insert = |index: &str| EntryWrite::Insert {
+            title_index: index.to_string(),
+            encrypted_metadata: format!("metadata_{}", index),
+            encrypted_password: format!("password_{}", index),
+            folder: None,
+            tags: vec![label("web")],
+        };
+
+        // Act: A batch whose last write fails
+        let failed = apply_entry_writes(&[insert("index_b"), EntryWrite::Delete { title_index: "missing".to_string() }]);
+
+        // Assert: Nothing of it was written
+        assert_eq!(failed, Err(diesel::result::Error::NotFound));
+        assert_eq!(get_password("index_b"), None);
+        assert_eq!(verify_vault_integrity(), Ok(()));
+
+        // Act: A batch that adds, changes and deletes
+        apply_entry_writes(&[
+            insert("index_b"),
+            EntryWrite::Update {
+                title_index: "index_b".to_string(),
+                encrypted_metadata: "metadata_b2".to_string(),
+                encrypted_password: "password_b2".to_string(),
+                folder: Some(Some(label("prod"))),
+                tags: Some(Vec::new()),
+            },
+            EntryWrite::Delete { title_index: "index_a".to_string() },
+        ])
+        .expect("A valid batch should be written");
+
+        // Assert
+        assert_eq!(get_password("index_a"), None);
+        assert_eq!(get_password("index_b"), Some("password_b2".to_string()));
+        let organization = load_organization();
+        assert_eq!(organization.entry_folders.len(), 1);
+        assert!(organization.tags.is_empty());
+        assert_eq!(verify_vault_integrity(), Ok(()));
+    }
+}
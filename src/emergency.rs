@@ -0,0 +1,466 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::secretbox::Key;
+use sodiumoxide::crypto::sign;
+use crate::audit_log::{format_timestamp, last_activity};
+use crate::container::is_container;
+use crate::database::{establish_connection, write_transaction};
+use crate::encryption::{decrypt_metadata, decrypt_password};
+use crate::header::{load_header, verify_vault_integrity};
+use crate::schema::{emergency_contacts, emergency_records, passwords};
+use crate::sharing::SharedItem;
+use crate::team::{member_key, open_sealed, parse_member_key, seal_for, sign_bytes, verify_bytes};
+use std::path::Path;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// The longest waiting period accepted, about ten years.
+pub const MAX_WAIT_DAYS: i64 = 3_650;
+
+/// Where an emergency contact's access to the vault stands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessState {
+    /// Nothing has been requested since the last denial, if any.
+    Idle,
+    /// Requested, and released at `release_at` unless denied before then.
+    Waiting { requested_at: i64, release_at: i64 },
+    /// The waiting period ran out without a denial.
+    Released { requested_at: i64 },
+    /// The last request was denied.
+    Denied { denied_at: i64 },
+}
+
+/// An emergency contact of the vault.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyContact {
+    /// The contact's member key, as printed by their `team whoami`.
+    pub key: String,
+    pub wait_days: i64,
+    pub state: AccessState,
+}
+
+#[derive(Queryable)]
+struct ContactRow {
+    contact_key: String,
+    wait_days: i64,
+    added_at: i64,
+    sealed_key: String,
+    granted_by: String,
+    signature: String,
+}
+
+#[derive(Queryable)]
+struct RecordRow {
+    _id: i32,
+    contact_key: String,
+    action: String,
+    created_at: i64,
+    signature: String,
+}
+
+/// The bytes the signature of a contact covers.
+fn contact_bytes(vault_id: &str, contact_key: &str, wait_days: i64, added_at: i64) -> Vec<u8> {
+    format!("pwvault-emergency-contact-v2\n{}\n{}\n{}\n{}", vault_id, contact_key, wait_days, added_at).into_bytes()
+}
+
+/// The bytes the signature of a request or denial covers.
+fn record_bytes(vault_id: &str, contact_key: &str, action: &str, created_at: i64) -> Vec<u8> {
+    format!("pwvault-emergency-record-v1\n{}\n{}\n{}\n{}", vault_id, contact_key, action, created_at).into_bytes()
+}
+
+/// Makes `contact` an emergency contact who can read the vault `wait_days`
+/// after asking for it, unless they are denied first. Adding a contact again
+/// changes their waiting period.
+///
+/// The vault key is sealed for them now, not on release, so the wait is only
+/// kept by this program: a contact holding a copy of the vault file can
+/// unseal the key without asking.
+pub fn add_contact(device: &sign::SecretKey, contact: &str, wait_days: i64, key: &Key, now: i64) -> Result<(), String> {
+    let contact = parse_member_key(contact)?;
+    if contact == member_key(device) {
+        return Err("You cannot be your own emergency contact".to_string());
+    }
+    if !(0..=MAX_WAIT_DAYS).contains(&wait_days) {
+        return Err(format!("The waiting period must be between 0 and {} days", MAX_WAIT_DAYS));
+    }
+    let connection = establish_connection();
+    let vault_id = vault_id(&connection)?;
+    let sealed_key = seal_for(key, &contact)?;
+
+    write_transaction(&connection, || {
+        diesel::replace_into(emergency_contacts::table)
+            .values((
+                emergency_contacts::contact_key.eq(&contact),
+                emergency_contacts::wait_days.eq(wait_days),
+                emergency_contacts::added_at.eq(now),
+                emergency_contacts::sealed_key.eq(&sealed_key),
+                emergency_contacts::granted_by.eq(member_key(device)),
+                emergency_contacts::signature.eq(sign_bytes(&contact_bytes(&vault_id, &contact, wait_days, now), device)),
+            ))
+            .execute(&connection)
+    })
+    .map(|_| ())
+    .map_err(|e| format!("Failed to add the emergency contact: {}", e))
+}
+
+/// Removes an emergency contact and their requests. Returns whether they were one.
+pub fn remove_contact(contact: &str) -> Result<bool, String> {
+    let contact = parse_member_key(contact)?;
+    let connection = establish_connection();
+    write_transaction(&connection, || {
+        diesel::delete(emergency_records::table.filter(emergency_records::contact_key.eq(&contact))).execute(&connection)?;
+        diesel::delete(emergency_contacts::table.filter(emergency_contacts::contact_key.eq(&contact))).execute(&connection)
+    })
+    .map(|removed| removed > 0)
+    .map_err(|e| format!("Failed to remove the emergency contact: {}", e))
+}
+
+/// Lists the emergency contacts of the selected vault and where their access
+/// stands at `now`.
+pub fn list_contacts(now: i64) -> Result<Vec<EmergencyContact>, String> {
+    let connection = establish_connection();
+    let vault_id = vault_id(&connection)?;
+    let (contacts, records) = load_emergency(&connection)?;
+    contacts
+        .iter()
+        .map(|contact| {
+            Ok(EmergencyContact {
+                key: contact.contact_key.clone(),
+                wait_days: contact.wait_days,
+                state: access_state(&vault_id, contact, &records, owner_activity(&connection, contact)?, now),
+            })
+        })
+        .collect()
+}
+
+/// Denies the pending request of `contact`. Only the member who added the
+/// contact can, since theirs is the signature the contact checks.
+pub fn deny_request(device: &sign::SecretKey, contact: &str, now: i64) -> Result<(), String> {
+    let contact = parse_member_key(contact)?;
+    let connection = establish_connection();
+    let vault_id = vault_id(&connection)?;
+    let (contacts, records) = load_emergency(&connection)?;
+    let row = contacts
+        .iter()
+        .find(|row| row.contact_key == contact)
+        .ok_or_else(|| format!("{} is not an emergency contact of this vault", contact))?;
+    if row.granted_by != member_key(device) {
+        return Err(format!("Only the member who added this contact can deny their requests: {}", row.granted_by));
+    }
+    if matches!(access_state(&vault_id, row, &records, owner_activity(&connection, row)?, now), AccessState::Idle | AccessState::Denied { .. }) {
+        return Err(format!("{} has no pending request", contact));
+    }
+
+    let signature = sign_bytes(&record_bytes(&vault_id, &contact, "deny", now), device);
+    write_transaction(&connection, || insert_record(&connection, &contact, "deny", now, &signature))
+        .map_err(|e| format!("Failed to deny the request: {}", e))
+}
+
+/// Asks the vault file at `path` for emergency access, as `device`. The
+/// request is written into that vault, where its owners see it. Asking again
+/// while a request is pending changes nothing.
+pub fn request_access(path: &Path, device: &sign::SecretKey, now: i64) -> Result<AccessState, String> {
+    let other = open_other_vault(path, false)?;
+    let vault_id = vault_id(&other)?;
+    let (contacts, records) = load_emergency(&other)?;
+    let me = member_key(device);
+    let contact = my_contact(&contacts, &vault_id, &me, path)?;
+    verify_vault_integrity(&other).map_err(|e| format!("{} failed its integrity check: {}", path.display(), e))?;
+    let active_at = owner_activity(&other, contact)?;
+
+    match access_state(&vault_id, contact, &records, active_at, now) {
+        AccessState::Idle | AccessState::Denied { .. } => {
+            let signature = sign_bytes(&record_bytes(&vault_id, &me, "request", now), device);
+            write_transaction(&other, || insert_record(&other, &me, "request", now, &signature))
+                .map_err(|e| format!("Failed to write the request into {}: {}", path.display(), e))?;
+            Ok(access_state(&vault_id, contact, &load_emergency(&other)?.1, active_at, now))
+        }
+        state => Ok(state),
+    }
+}
+
+/// Reads every entry of the vault file at `path`, once emergency access has
+/// been released to `device`. The other vault is only read.
+pub fn emergency_access(path: &Path, device: &sign::SecretKey, now: i64) -> Result<Vec<SharedItem>, String> {
+    let other = open_other_vault(path, true)?;
+    let vault_id = vault_id(&other)?;
+    let (contacts, records) = load_emergency(&other)?;
+    let contact = my_contact(&contacts, &vault_id, &member_key(device), path)?;
+
+    match access_state(&vault_id, contact, &records, owner_activity(&other, contact)?, now) {
+        AccessState::Released { .. } => {}
+        AccessState::Idle => return Err(format!("Ask for access first with: emergency request --from {}", path.display())),
+        AccessState::Waiting { release_at, .. } => return Err(format!("Access is released on {} unless it is denied before then", format_timestamp(release_at))),
+        AccessState::Denied { denied_at } => return Err(format!("Your request was denied on {}", format_timestamp(denied_at))),
+    }
+    let key = open_sealed(&contact.sealed_key, device).map_err(|_| "Failed to unseal the vault key".to_string())?;
+
+    let rows = passwords::table
+        .select((passwords::title_index, passwords::encrypted_metadata, passwords::encrypted_password))
+        .load::<(String, String, String)>(&other)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut items = rows
+        .iter()
+        .map(|(title_index, encrypted_metadata, encrypted_password)| {
+            Ok(SharedItem {
+                metadata: decrypt_metadata(encrypted_metadata, title_index, &key)?,
+                secret: decrypt_password(encrypted_password, title_index, None, &key)?,
+            })
+        })
+        .collect::<Result<Vec<SharedItem>, String>>()?;
+    items.sort_by(|a, b| a.metadata.title.cmp(&b.metadata.title));
+    Ok(items)
+}
+
+/// Seals `new_key` for every emergency contact, inside the caller's
+/// transaction, after the vault was re-encrypted.
+pub fn reseal_emergency_keys(connection: &SqliteConnection, new_key: &Key) -> Result<(), diesel::result::Error> {
+    let contacts = emergency_contacts::table.select(emergency_contacts::contact_key).load::<String>(connection)?;
+    for contact in &contacts {
+        let sealed_key = seal_for(new_key, contact).map_err(|e| diesel::result::Error::DeserializationError(e.into()))?;
+        diesel::update(emergency_contacts::table.filter(emergency_contacts::contact_key.eq(contact)))
+            .set(emergency_contacts::sealed_key.eq(sealed_key))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Works out where a contact's access stands at `now`. Requests must be
+/// signed by the contact and denials by the member who added them; others
+/// are ignored. A denial cancels every request before it.
+///
+/// The contact dates their own requests, so the wait runs from the latest of
+/// the request, the contact being added and `active_at`, the last time the
+/// member who added them used the vault. A backdated request gains nothing.
+fn access_state(vault_id: &str, contact: &ContactRow, records: &[RecordRow], active_at: Option<i64>, now: i64) -> AccessState {
+    let mut requested_at = None;
+    let mut denied_at = None;
+    for record in records.iter().filter(|record| record.contact_key == contact.contact_key) {
+        let signer = match record.action.as_str() {
+            "request" => &contact.contact_key,
+            "deny" => &contact.granted_by,
+            _ => continue,
+        };
+        let signed_bytes = record_bytes(vault_id, &contact.contact_key, &record.action, record.created_at);
+        if !verify_bytes(&signed_bytes, signer, &record.signature) {
+            continue;
+        }
+        if record.action == "deny" {
+            requested_at = None;
+            denied_at = Some(record.created_at);
+        } else if requested_at.is_none() {
+            requested_at = Some(record.created_at);
+        }
+    }
+
+    match (requested_at, denied_at) {
+        (Some(requested_at), _) => {
+            let waits_from = requested_at.max(contact.added_at).max(active_at.unwrap_or(i64::MIN));
+            // A wait that overflows is never over
+            let release_at = contact
+                .wait_days
+                .checked_mul(SECONDS_PER_DAY)
+                .and_then(|wait| waits_from.checked_add(wait))
+                .unwrap_or(i64::MAX);
+            if now >= release_at {
+                AccessState::Released { requested_at }
+            } else {
+                AccessState::Waiting { requested_at, release_at }
+            }
+        }
+        (None, Some(denied_at)) => AccessState::Denied { denied_at },
+        (None, None) => AccessState::Idle,
+    }
+}
+
+/// Finds the contact row of `me` in another vault and checks who signed it.
+fn my_contact<'a>(contacts: &'a [ContactRow], vault_id: &str, me: &str, path: &Path) -> Result<&'a ContactRow, String> {
+    let contact = contacts
+        .iter()
+        .find(|contact| contact.contact_key == me)
+        .ok_or_else(|| format!("You are not an emergency contact of {}; your key is {}", path.display(), me))?;
+    if !verify_bytes(&contact_bytes(vault_id, me, contact.wait_days, contact.added_at), &contact.granted_by, &contact.signature) {
+        return Err(format!("Your emergency access to {} is not signed by whoever granted it", path.display()));
+    }
+    Ok(contact)
+}
+
+/// When the member who added `contact` last used the vault, by the newest
+/// record they signed in its audit log.
+fn owner_activity(connection: &SqliteConnection, contact: &ContactRow) -> Result<Option<i64>, String> {
+    last_activity(connection, &contact.granted_by).map_err(|e| format!("Failed to read the audit log: {}", e))
+}
+
+fn load_emergency(connection: &SqliteConnection) -> Result<(Vec<ContactRow>, Vec<RecordRow>), String> {
+    let contacts = emergency_contacts::table
+        .order(emergency_contacts::contact_key.asc())
+        .load::<ContactRow>(connection)
+        .map_err(|e| format!("Failed to read emergency contacts: {}", e))?;
+    let records = emergency_records::table
+        .order(emergency_records::id.asc())
+        .load::<RecordRow>(connection)
+        .map_err(|e| format!("Failed to read emergency requests: {}", e))?;
+    Ok((contacts, records))
+}
+
+fn insert_record(connection: &SqliteConnection, contact: &str, action: &str, created_at: i64, signature: &str) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(emergency_records::table)
+        .values((
+            emergency_records::contact_key.eq(contact),
+            emergency_records::action.eq(action),
+            emergency_records::created_at.eq(created_at),
+            emergency_records::signature.eq(signature),
+        ))
+        .execute(connection)
+        .map(|_| ())
+}
+
+/// The id signatures are bound to, so records cannot be replayed into another vault.
+fn vault_id(connection: &SqliteConnection) -> Result<String, String> {
+    load_header(connection)
+        .map_err(|e| format!("Failed to read vault header: {}", e))?
+        .map(|header| header.vault_id)
+        .ok_or_else(|| "The vault has no header yet".to_string())
+}
+
+/// Opens another plain vault file, which must already exist.
+fn open_other_vault(path: &Path, read_only: bool) -> Result<SqliteConnection, String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    if is_container(path) {
+        return Err(format!("{} is an encrypted container; it must be a plain vault", path.display()));
+    }
+    let url = if read_only { format!("file:{}?mode=ro", path.display()) } else { path.display().to_string() };
+    let connection = SqliteConnection::establish(&url).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    connection
+        .execute("PRAGMA busy_timeout = 5000;")
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_test_entry, test_vault, TestVault};
+    use sodiumoxide::crypto::secretbox;
+
+    const DAY: i64 = SECONDS_PER_DAY;
+    const START: i64 = 1_700_000_000;
+
+    /// Setup: An isolated test vault holding one entry, with `contact` added
+    /// as an emergency contact of `owner` at `START`, with a wait of two days.
+    fn setup(owner: &sign::SecretKey, contact: &sign::SecretKey, key: &Key) -> TestVault {
+        let vault = test_vault();
+        add_test_entry("bank", "s3cret", None, key);
+        add_contact(owner, &member_key(contact), 2, key, START).unwrap();
+        vault
+    }
+
+    #[test]
+    fn test_access_is_released_after_the_wait_unless_denied() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let (_, owner) = sign::gen_keypair();
+        let (_, contact) = sign::gen_keypair();
+        let vault = setup(&owner, &contact, &key);
+        let path = Path::new(&vault.path);
+        let start = START;
+
+        // Act: A request, denied on the next day
+        let requested = request_access(path, &contact, start).unwrap();
+        let too_early = emergency_access(path, &contact, start + DAY);
+        let denied_by_contact = deny_request(&contact, &member_key(&contact), start + DAY);
+        deny_request(&owner, &member_key(&contact), start + DAY).unwrap();
+        let after_denial = emergency_access(path, &contact, start + 3 * DAY);
+
+        // Act: A second request nobody denies
+        request_access(path, &contact, start + 4 * DAY).unwrap();
+        let released = emergency_access(path, &contact, start + 6 * DAY).unwrap();
+
+        // Assert
+        assert_eq!(requested, AccessState::Waiting { requested_at: start, release_at: start + 2 * DAY });
+        assert!(too_early.unwrap_err().starts_with("Access is released on"));
+        assert!(denied_by_contact.unwrap_err().starts_with("Only the member who added this contact"));
+        assert!(after_denial.unwrap_err().starts_with("Your request was denied"));
+        assert_eq!(released.len(), 1);
+        assert_eq!((released[0].metadata.title.as_str(), released[0].secret.as_str()), ("bank", "s3cret"));
+        assert_eq!(list_contacts(start + 6 * DAY).unwrap()[0].state, AccessState::Released { requested_at: start + 4 * DAY });
+    }
+
+    #[test]
+    fn test_unsigned_changes_are_ignored() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let (_, owner) = sign::gen_keypair();
+        let (_, contact) = sign::gen_keypair();
+        let (_, stranger) = sign::gen_keypair();
+        let vault = setup(&owner, &contact, &key);
+        let path = Path::new(&vault.path);
+        let start = START;
+        request_access(path, &contact, start).unwrap();
+        let connection = establish_connection();
+
+        // Act: A denial signed by someone else, then a shortened wait
+        insert_record(&connection, &member_key(&contact), "deny", start, &sign_bytes(b"forged", &stranger)).unwrap();
+        let state = list_contacts(start).unwrap()[0].state.clone();
+        diesel::update(emergency_contacts::table).set(emergency_contacts::wait_days.eq(0)).execute(&connection).unwrap();
+        let shortened = emergency_access(path, &contact, start);
+
+        // Assert
+        assert_eq!(state, AccessState::Waiting { requested_at: start, release_at: start + 2 * DAY });
+        assert!(shortened.unwrap_err().contains("is not signed by whoever granted it"));
+        assert!(request_access(path, &stranger, start).unwrap_err().starts_with("You are not an emergency contact"));
+    }
+
+    #[test]
+    fn test_backdated_requests_still_wait() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let (_, owner) = sign::gen_keypair();
+        let (_, contact) = sign::gen_keypair();
+        let vault = setup(&owner, &contact, &key);
+        let path = Path::new(&vault.path);
+        let connection = establish_connection();
+        let vault_id = vault_id(&connection).unwrap();
+        let contact_key = member_key(&contact);
+        let backdated = START - 10 * DAY;
+
+        // Act: A request dated before the contact was added
+        let signature = sign_bytes(&record_bytes(&vault_id, &contact_key, "request", backdated), &contact);
+        insert_record(&connection, &contact_key, "request", backdated, &signature).unwrap();
+        let before_owner_use = list_contacts(START).unwrap()[0].state.clone();
+
+        // Act: The owner used the vault a week later, after the request
+        write_transaction(&connection, || crate::audit_log::append_record(&connection, "get", "", "", &owner, START + 7 * DAY)).unwrap();
+        let after_owner_use = list_contacts(START + 8 * DAY).unwrap()[0].state.clone();
+        let too_early = emergency_access(path, &contact, START + 8 * DAY);
+
+        // Assert
+        assert_eq!(before_owner_use, AccessState::Waiting { requested_at: backdated, release_at: START + 2 * DAY });
+        assert_eq!(after_owner_use, AccessState::Waiting { requested_at: backdated, release_at: START + 9 * DAY });
+        assert!(too_early.unwrap_err().starts_with("Access is released on"));
+        assert_eq!(emergency_access(path, &contact, START + 9 * DAY).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_waiting_period_is_bounded() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let (_, owner) = sign::gen_keypair();
+        let (_, contact) = sign::gen_keypair();
+        let _vault = setup(&owner, &contact, &key);
+        let contact_key = member_key(&contact);
+
+        // Act
+        let too_long = add_contact(&owner, &contact_key, MAX_WAIT_DAYS + 1, &key, START);
+        let negative = add_contact(&owner, &contact_key, -1, &key, START);
+        let longest = add_contact(&owner, &contact_key, MAX_WAIT_DAYS, &key, START);
+
+        // Assert
+        assert!(too_long.unwrap_err().contains("between 0 and"));
+        assert!(negative.is_err());
+        assert!(longest.is_ok());
+        assert_eq!(list_contacts(START).unwrap()[0].wait_days, MAX_WAIT_DAYS);
+    }
+}
@@ -1,18 +1,113 @@
+use serde::{Deserialize, Serialize};
+use crate::items::ItemType;
+use crate::url_match::UrlRule;
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as aead;
+use sodiumoxide::crypto::auth::hmacsha256;
+use sodiumoxide::crypto::kdf::blake2b;
 use sodiumoxide::crypto::secretbox;
 use sodiumoxide::crypto::secretbox::{Key, Nonce};
 
-/// Encrypts a password using a randomly generated key and nonce.
-pub fn encrypt_password(password: &str, key: &Key) -> (String, String) {
-    let nonce = secretbox::gen_nonce();
-    let ciphertext = secretbox::seal(password.as_bytes(), &nonce, key);
-    (
-        base64::encode(ciphertext),
-        base64::encode(nonce.as_ref()),
-    )
+/// Context used when deriving purpose-specific subkeys from the vault key.
+const SUBKEY_CONTEXT: [u8; blake2b::CONTEXTBYTES] = *b"pwvault_";
+
+/// Subkey used to compute the blind index of entry titles.
+pub const SUBKEY_TITLE_INDEX: u64 = 1;
+/// Subkey used to encrypt entry metadata (titles and other non-secret fields).
+pub const SUBKEY_METADATA: u64 = 2;
+/// Subkey used for whole-file encryption of vault containers.
+pub const SUBKEY_CONTAINER: u64 = 3;
+/// Subkey used to encrypt passwords with authenticated encryption.
+pub const SUBKEY_PASSWORD: u64 = 4;
+/// Subkey used to authenticate the vault header.
+pub const SUBKEY_HEADER: u64 = 5;
+/// Subkey used to compute the blind index of folder paths and tags.
+pub const SUBKEY_LABEL_INDEX: u64 = 6;
+/// Subkey used to wrap the random keys of file attachments.
+pub const SUBKEY_ATTACHMENT: u64 = 7;
+/// Subkey used to wrap the sharing identity and the keys of shared entries.
+pub const SUBKEY_SHARING: u64 = 8;
+
+/// Prefix of values sealed with XChaCha20-Poly1305. Values without it were
+/// written by earlier versions with secretbox.
+const AEAD_PREFIX: &str = "v2:";
+
+/// Metadata stored alongside each entry. The whole struct is serialized and
+/// encrypted, so nothing describing the entry is left in plaintext.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EntryMetadata {
+    pub title: String,
+    /// The kind of item. Logins, the default, leave it out.
+    #[serde(default, skip_serializing_if = "ItemType::is_login")]
+    pub item_type: ItemType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Further URLs of the entry, each with its own match mode. `url` is matched by host.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<UrlRule>,
+    /// Unix time the entry was added. Unknown for entries from before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    /// Unix time the password was last set. Unknown for entries from before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// How often the password must be changed, overriding any folder policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_every_days: Option<u64>,
+}
+
+impl EntryMetadata {
+    /// Records that the password was set just now.
+    pub fn touch(&mut self) {
+        self.modified = Some(unix_now());
+    }
+}
+
+/// The current Unix time in seconds.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Encrypts a password with XChaCha20-Poly1305. The title index is bound in as
+/// associated data, so the ciphertext cannot be moved to another entry, and the
+/// nonce is stored with the ciphertext.
+pub fn encrypt_password(password: &str, title_index: &str, key: &Key) -> String {
+    let password_key = aead::Key(derive_subkey(key, SUBKEY_PASSWORD));
+    seal_bound(password.as_bytes(), "password", title_index, &password_key)
+}
+
+/// Decrypts an encrypted password for the entry with the given title index.
+/// Entries stored before authenticated encryption still need the nonce that
+/// was handed out when they were added.
+pub fn decrypt_password(
+    encrypted_password: &str,
+    title_index: &str,
+    nonce: Option<&str>,
+    key: &Key,
+) -> Result<String, String> {
+    let plaintext = if is_legacy_ciphertext(encrypted_password) {
+        let nonce = nonce.ok_or("This entry was stored before authenticated encryption and needs its --nonce")?;
+        open_legacy(encrypted_password, nonce, key)?
+    } else {
+        let password_key = aead::Key(derive_subkey(key, SUBKEY_PASSWORD));
+        open_bound(encrypted_password, "password", title_index, &password_key)
+            .map_err(|_| "Failed to decrypt password: key may be incorrect or the entry was tampered with".to_string())?
+    };
+
+    String::from_utf8(plaintext).map_err(|_| "Failed to parse decrypted bytes into a valid string".to_string())
 }
 
-/// Decrypts an encrypted password.
-pub fn decrypt_password(encrypted_password: &str, nonce_str: &str, key: &Key) -> Result<String, String> {
+/// Returns true for values written before the move to authenticated encryption.
+pub fn is_legacy_ciphertext(encrypted: &str) -> bool {
+    !encrypted.starts_with(AEAD_PREFIX)
+}
+
+/// Opens a password sealed with secretbox and a nonce held by the user.
+fn open_legacy(encrypted_password: &str, nonce_str: &str, key: &Key) -> Result<Vec<u8>, String> {
     let ciphertext = match base64::decode(encrypted_password) {
         Ok(decoded) => decoded,
         Err(_) => return Err("Failed to decode ciphertext".to_string()),
@@ -29,14 +124,171 @@ pub fn decrypt_password(encrypted_password: &str, nonce_str: &str, key: &Key) ->
     };
 
     match secretbox::open(&ciphertext, &nonce, key) {
-        Ok(decrypted) => match String::from_utf8(decrypted) {
-            Ok(password) => Ok(password),
-            Err(_) => Err("Failed to parse decrypted bytes into a valid string".to_string()),
-        },
+        Ok(decrypted) => Ok(decrypted),
         Err(_) => Err("Failed to decrypt password: nonce or key may be incorrect".to_string()),
     }
 }
 
+/// Seals `plaintext` with XChaCha20-Poly1305, binding it to the kind of value
+/// and the entry it belongs to. Returns the prefixed `nonce || ciphertext`.
+fn seal_bound(plaintext: &[u8], kind: &str, title_index: &str, key: &aead::Key) -> String {
+    let associated_data = format!("{}:{}", kind, title_index);
+    let nonce = aead::gen_nonce();
+    let mut sealed = nonce.as_ref().to_vec();
+    sealed.extend(aead::seal(plaintext, Some(associated_data.as_bytes()), &nonce, key));
+    format!("{}{}", AEAD_PREFIX, base64::encode(sealed))
+}
+
+/// Opens a value produced by `seal_bound` for the same kind and entry.
+fn open_bound(sealed: &str, kind: &str, title_index: &str, key: &aead::Key) -> Result<Vec<u8>, String> {
+    let encoded = sealed.strip_prefix(AEAD_PREFIX).ok_or("Value is not sealed with authenticated encryption")?;
+    let sealed = base64::decode(encoded).map_err(|_| "Failed to decode ciphertext".to_string())?;
+    if sealed.len() < aead::NONCEBYTES {
+        return Err("Ciphertext is truncated".to_string());
+    }
+
+    let (nonce_bytes, ciphertext) = sealed.split_at(aead::NONCEBYTES);
+    let nonce = aead::Nonce::from_slice(nonce_bytes).ok_or("Failed to parse nonce")?;
+    let associated_data = format!("{}:{}", kind, title_index);
+    aead::open(ciphertext, Some(associated_data.as_bytes()), &nonce, key)
+        .map_err(|_| "Failed to authenticate ciphertext".to_string())
+}
+
+/// Derives a 32-byte subkey for a single purpose from the vault key.
+pub fn derive_subkey(key: &Key, subkey_id: u64) -> [u8; 32] {
+    let master = blake2b::Key::from_slice(key.as_ref()).expect("Vault key has the wrong length");
+    let mut subkey = [0u8; 32];
+    blake2b::derive_from_key(&mut subkey, subkey_id, SUBKEY_CONTEXT, &master)
+        .expect("Failed to derive subkey");
+    subkey
+}
+
+/// Normalizes a title so lookups ignore case and surrounding whitespace.
+pub fn normalize_title(title: &str) -> String {
+    title.trim().to_lowercase()
+}
+
+/// Computes the blind index of a title: an HMAC of the normalized title under
+/// a vault subkey. Equal titles map to equal indexes without revealing the name.
+pub fn title_index(title: &str, key: &Key) -> String {
+    let index_key = hmacsha256::Key(derive_subkey(key, SUBKEY_TITLE_INDEX));
+    let tag = hmacsha256::authenticate(normalize_title(title).as_bytes(), &index_key);
+    base64::encode(tag.as_ref())
+}
+
+/// Encrypts entry metadata, bound to the entry's title index like passwords.
+pub fn encrypt_metadata(metadata: &EntryMetadata, title_index: &str, key: &Key) -> String {
+    let metadata_key = aead::Key(derive_subkey(key, SUBKEY_METADATA));
+    let plaintext = serde_json::to_vec(metadata).expect("Failed to serialize metadata");
+    seal_bound(&plaintext, "metadata", title_index, &metadata_key)
+}
+
+/// Decrypts entry metadata produced by `encrypt_metadata`. Metadata written
+/// before authenticated encryption (secretbox with a prepended nonce) is still
+/// accepted so it can be upgraded.
+pub fn decrypt_metadata(encrypted_metadata: &str, title_index: &str, key: &Key) -> Result<EntryMetadata, String> {
+    let plaintext = if is_legacy_ciphertext(encrypted_metadata) {
+        open_legacy_metadata(encrypted_metadata, key)?
+    } else {
+        let metadata_key = aead::Key(derive_subkey(key, SUBKEY_METADATA));
+        open_bound(encrypted_metadata, "metadata", title_index, &metadata_key)
+            .map_err(|_| "Failed to decrypt metadata: key may be incorrect or the entry was tampered with".to_string())?
+    };
+
+    serde_json::from_slice(&plaintext).map_err(|_| "Failed to parse decrypted metadata".to_string())
+}
+
+fn open_legacy_metadata(encrypted_metadata: &str, key: &Key) -> Result<Vec<u8>, String> {
+    let sealed = match base64::decode(encrypted_metadata) {
+        Ok(decoded) => decoded,
+        Err(_) => return Err("Failed to decode metadata".to_string()),
+    };
+
+    if sealed.len() < secretbox::NONCEBYTES {
+        return Err("Encrypted metadata is truncated".to_string());
+    }
+
+    let (nonce_bytes, ciphertext) = sealed.split_at(secretbox::NONCEBYTES);
+    let nonce = Nonce::from_slice(nonce_bytes).ok_or("Failed to parse metadata nonce")?;
+    let metadata_key = Key(derive_subkey(key, SUBKEY_METADATA));
+
+    secretbox::open(ciphertext, &nonce, &metadata_key)
+        .map_err(|_| "Failed to decrypt metadata: key may be incorrect".to_string())
+}
+
+/// Computes the blind index of a folder path or tag. `kind` separates the two,
+/// so a folder and a tag with the same name get unrelated indexes.
+pub fn label_index(kind: &str, label: &str, key: &Key) -> String {
+    let index_key = hmacsha256::Key(derive_subkey(key, SUBKEY_LABEL_INDEX));
+    let input = format!("{}:{}", kind, normalize_title(label));
+    base64::encode(hmacsha256::authenticate(input.as_bytes(), &index_key).as_ref())
+}
+
+/// Encrypts a folder path or tag, bound to its blind index.
+pub fn encrypt_label(kind: &str, label: &str, label_index: &str, key: &Key) -> String {
+    let metadata_key = aead::Key(derive_subkey(key, SUBKEY_METADATA));
+    seal_bound(label.as_bytes(), kind, label_index, &metadata_key)
+}
+
+/// Decrypts a folder path or tag produced by `encrypt_label`.
+pub fn decrypt_label(kind: &str, encrypted_label: &str, label_index: &str, key: &Key) -> Result<String, String> {
+    let metadata_key = aead::Key(derive_subkey(key, SUBKEY_METADATA));
+    let plaintext = open_bound(encrypted_label, kind, label_index, &metadata_key)?;
+    String::from_utf8(plaintext).map_err(|_| "Label is not valid UTF-8".to_string())
+}
+
+/// Wraps the random key of an attachment under the vault key, bound to the
+/// attachment it belongs to.
+pub fn wrap_attachment_key(attachment_key: &[u8], attachment_index: &str, key: &Key) -> String {
+    let wrapping_key = aead::Key(derive_subkey(key, SUBKEY_ATTACHMENT));
+    seal_bound(attachment_key, "attachment", attachment_index, &wrapping_key)
+}
+
+/// Unwraps a key produced by `wrap_attachment_key`.
+pub fn unwrap_attachment_key(wrapped_key: &str, attachment_index: &str, key: &Key) -> Result<Vec<u8>, String> {
+    let wrapping_key = aead::Key(derive_subkey(key, SUBKEY_ATTACHMENT));
+    open_bound(wrapped_key, "attachment", attachment_index, &wrapping_key)
+}
+
+/// Wraps a sharing secret under the vault key: the identity's secret key, or
+/// the key of a shared entry, bound to what it belongs to.
+pub fn wrap_sharing_key(secret_key: &[u8], binding: &str, key: &Key) -> String {
+    let wrapping_key = aead::Key(derive_subkey(key, SUBKEY_SHARING));
+    seal_bound(secret_key, "sharing", binding, &wrapping_key)
+}
+
+/// Unwraps a key produced by `wrap_sharing_key`.
+pub fn unwrap_sharing_key(wrapped_key: &str, binding: &str, key: &Key) -> Result<Vec<u8>, String> {
+    let wrapping_key = aead::Key(derive_subkey(key, SUBKEY_SHARING));
+    open_bound(wrapped_key, "sharing", binding, &wrapping_key)
+}
+
+/// Seals the shared copy of an entry under the entry's own key, which is all
+/// a recipient holds.
+pub fn seal_shared_entry(plaintext: &[u8], title_index: &str, entry_key: &aead::Key) -> String {
+    seal_bound(plaintext, "shared", title_index, entry_key)
+}
+
+/// Opens a value produced by `seal_shared_entry`.
+pub fn open_shared_entry(sealed: &str, title_index: &str, entry_key: &aead::Key) -> Result<Vec<u8>, String> {
+    open_bound(sealed, "shared", title_index, entry_key)
+}
+
+/// Computes the authentication tag of the vault header.
+pub fn header_mac(header: &[u8], key: &Key) -> String {
+    let header_key = hmacsha256::Key(derive_subkey(key, SUBKEY_HEADER));
+    base64::encode(hmacsha256::authenticate(header, &header_key).as_ref())
+}
+
+/// Checks a tag produced by `header_mac` in constant time.
+pub fn verify_header_mac(header: &[u8], mac: &str, key: &Key) -> bool {
+    let header_key = hmacsha256::Key(derive_subkey(key, SUBKEY_HEADER));
+    match base64::decode(mac).ok().and_then(|tag| hmacsha256::Tag::from_slice(&tag)) {
+        Some(tag) => hmacsha256::verify(&tag, header, &header_key),
+        None => false,
+    }
+}
+
 /// Generates a new random encryption key.
 pub fn generate_key() -> Key {
     Key::from_slice(b"an example very very secret key.") // 32 bytes
@@ -56,28 +308,115 @@ mod tests {
         let password = "test_password";
 
         // Act
-        let (encrypted_password, nonce) = encrypt_password(password, &key);
-        let decrypted_password = decrypt_password(&encrypted_password, &nonce, &key)
+        let encrypted_password = encrypt_password(password, "index", &key);
+        let decrypted_password = decrypt_password(&encrypted_password, "index", None, &key)
             .expect("Decryption should succeed");
 
         // Assert
         assert_eq!(decrypted_password, password);
+        assert!(!is_legacy_ciphertext(&encrypted_password));
     }
 
     #[test]
     fn test_decrypt_with_invalid_nonce() {
         // Arrange
         let key = secretbox::gen_key();
-        let password = "test_password";
-        let (_, _nonce) = encrypt_password(password, &key);
 
         // Modify nonce
         let invalid_nonce = "invalid_nonce";
 
         // Act
-        let result = decrypt_password("invalid_encrypted_data", invalid_nonce, &key);
+        let result = decrypt_password("invalid_encrypted_data", "index", Some(invalid_nonce), &key);
 
         // Assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_password_is_bound_to_its_entry() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let encrypted_password = encrypt_password("bank_password", "bank_index", &key);
+
+        // Act: Try to read the ciphertext as if it belonged to another title
+        let result = decrypt_password(&encrypted_password, "email_index", None, &key);
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_decrypt_legacy_password_requires_nonce() {
+        // Arrange: A password sealed the way earlier versions did
+        let key = secretbox::gen_key();
+        let nonce = secretbox::gen_nonce();
+        let legacy = base64::encode(secretbox::seal(b"old_password", &nonce, &key));
+        let legacy_nonce = base64::encode(nonce.as_ref());
+
+        // Act & Assert
+        assert!(is_legacy_ciphertext(&legacy));
+        assert!(decrypt_password(&legacy, "index", None, &key).is_err());
+        assert_eq!(
+            decrypt_password(&legacy, "index", Some(&legacy_nonce), &key),
+            Ok("old_password".to_string())
+        );
+    }
+
+    #[test]
+    fn test_title_index_is_normalized_and_keyed() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let other_key = secretbox::gen_key();
+
+        // Act
+        let index = title_index("My Bank", &key);
+
+        // Assert: Case and surrounding whitespace do not change the index
+        assert_eq!(index, title_index("  my bank ", &key));
+        assert_ne!(index, title_index("my bank 2", &key));
+        // A different vault key yields an unrelated index
+        assert_ne!(index, title_index("My Bank", &other_key));
+        assert!(!index.contains("bank"));
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_metadata() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let metadata = EntryMetadata {
+            title: "prod database".to_string(),
+            item_type: ItemType::Note,
+            username: Some("postgres".to_string()),
+            url: None,
+            urls: vec![UrlRule::parse("domain:example.com").unwrap()],
+            created: Some(1_600_000_000),
+            modified: Some(1_700_000_000),
+            rotate_every_days: Some(90),
+        };
+
+        // Act
+        let encrypted = encrypt_metadata(&metadata, "index", &key);
+        let decrypted = decrypt_metadata(&encrypted, "index", &key).expect("Decryption should succeed");
+
+        // Assert
+        assert_eq!(decrypted, metadata);
+        assert!(decrypt_metadata(&encrypted, "index", &secretbox::gen_key()).is_err());
+        assert!(decrypt_metadata(&encrypted, "other_index", &key).is_err());
+    }
+
+    #[test]
+    fn test_labels_are_indexed_and_encrypted_by_kind() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let index = label_index("folder", "Prod/DB", &key);
+
+        // Act
+        let encrypted = encrypt_label("folder", "Prod/DB", &index, &key);
+
+        // Assert: Folders and tags with the same name do not share an index or ciphertext
+        assert_eq!(index, label_index("folder", "prod/db", &key));
+        assert_ne!(index, label_index("tag", "prod/db", &key));
+        assert_eq!(decrypt_label("folder", &encrypted, &index, &key), Ok("Prod/DB".to_string()));
+        assert!(decrypt_label("tag", &encrypted, &index, &key).is_err());
+    }
 }
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox::Key;
+use crate::audit::parse_days;
+use crate::database::{list_folder_policies, Label};
+use crate::encryption::{decrypt_label, encrypt_label, label_index, EntryMetadata};
+use crate::labels::{is_in_folder, EntryLabels};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The longest rotation period accepted, about a hundred years.
+pub const MAX_ROTATE_EVERY_DAYS: u64 = 36_500;
+
+/// A rotation policy set on a folder. It applies to the folder's subfolders too.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FolderPolicy {
+    pub path: String,
+    pub rotate_every_days: u64,
+}
+
+/// The policy that applies to an entry, and where it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectivePolicy {
+    pub rotate_every_days: u64,
+    /// The folder the policy is set on, or `None` for a policy on the entry itself.
+    pub folder: Option<String>,
+}
+
+/// When an entry's password is due for rotation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Due {
+    /// Due in this many whole days (0 means today).
+    In(u64),
+    /// Past due by this many whole days.
+    Overdue(u64),
+    /// The password was last changed before changes were recorded.
+    Unknown,
+}
+
+/// Loads and decrypts the rotation policies of every folder.
+pub fn load_folder_policies(key: &Key) -> Result<Vec<FolderPolicy>, String> {
+    list_folder_policies()
+        .iter()
+        .map(|(path_index, encrypted_policy)| {
+            let json = decrypt_label("policy", encrypted_policy, path_index, key)?;
+            serde_json::from_str(&json).map_err(|e| format!("Failed to parse folder policy: {}", e))
+        })
+        .collect()
+}
+
+/// Indexes and encrypts a folder policy for storage. The index is the same
+/// as the folder's, so a policy can be found from the folder path alone.
+pub fn policy_label(policy: &FolderPolicy, key: &Key) -> Label {
+    let index = label_index("folder", &policy.path, key);
+    let json = serde_json::to_string(policy).expect("Failed to serialize folder policy");
+    Label {
+        encrypted: encrypt_label("policy", &json, &index, key),
+        index,
+    }
+}
+
+/// The policy for an entry: its own if it has one, otherwise the policy of
+/// the nearest enclosing folder.
+pub fn effective_policy(
+    metadata: &EntryMetadata,
+    folder: Option<&str>,
+    policies: &[FolderPolicy],
+) -> Option<EffectivePolicy> {
+    if let Some(days) = metadata.rotate_every_days {
+        return Some(EffectivePolicy {
+            rotate_every_days: days,
+            folder: None,
+        });
+    }
+
+    let folder = folder?;
+    policies
+        .iter()
+        .filter(|policy| is_in_folder(folder, &policy.path))
+        .max_by_key(|policy| policy.path.split('/').count())
+        .map(|policy| EffectivePolicy {
+            rotate_every_days: policy.rotate_every_days,
+            folder: Some(policy.path.clone()),
+        })
+}
+
+/// Looks up the policy of a single entry in the selected vault.
+pub fn policy_for_entry(title_index: &str, metadata: &EntryMetadata, key: &Key) -> Result<Option<EffectivePolicy>, String> {
+    if metadata.rotate_every_days.is_some() {
+        return Ok(effective_policy(metadata, None, &[]));
+    }
+    let labels = EntryLabels::load(key)?;
+    let policies = load_folder_policies(key)?;
+    Ok(effective_policy(metadata, labels.folder(title_index), &policies))
+}
+
+/// Parses how often a password must be changed, such as `90d`, as
+/// `parse_days` does, up to `MAX_ROTATE_EVERY_DAYS`.
+pub fn parse_rotation(value: &str) -> Result<u64, String> {
+    let days = parse_days(value)?;
+    if days > MAX_ROTATE_EVERY_DAYS {
+        return Err(format!("Invalid rotation period '{}': it can be at most {} days", value.trim(), MAX_ROTATE_EVERY_DAYS));
+    }
+    Ok(days)
+}
+
+/// When the password is due, counting from its last change, or its creation
+/// for entries that were never modified.
+pub fn due(metadata: &EntryMetadata, rotate_every_days: u64, now: u64) -> Due {
+    let changed = match metadata.modified.or(metadata.created) {
+        Some(changed) => changed,
+        None => return Due::Unknown,
+    };
+    // Saturate, so periods stored before they were bounded cannot overflow
+    let due_at = changed.saturating_add(rotate_every_days.saturating_mul(SECONDS_PER_DAY));
+    if now > due_at {
+        Due::Overdue((now - due_at) / SECONDS_PER_DAY)
+    } else {
+        Due::In((due_at - now) / SECONDS_PER_DAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn folder_policy(path: &str, days: u64) -> FolderPolicy {
+        FolderPolicy {
+            path: path.to_string(),
+            rotate_every_days: days,
+        }
+    }
+
+    #[test]
+    fn test_effective_policy_prefers_entry_then_nearest_folder() {
+        // Arrange
+        let policies = [folder_policy("prod", 90), folder_policy("prod/db", 30)];
+        let mut metadata = EntryMetadata::default();
+
+        // Act & Assert: The nearest folder wins
+        let policy = effective_policy(&metadata, Some("prod/db/postgres"), &policies).unwrap();
+        assert_eq!(policy.rotate_every_days, 30);
+        assert_eq!(policy.folder, Some("prod/db".to_string()));
+        assert_eq!(effective_policy(&metadata, Some("staging"), &policies), None);
+
+        // Act & Assert: A policy on the entry overrides its folders
+        metadata.rotate_every_days = Some(7);
+        let policy = effective_policy(&metadata, Some("prod/db"), &policies).unwrap();
+        assert_eq!((policy.rotate_every_days, policy.folder), (7, None));
+    }
+
+    #[test]
+    fn test_due() {
+        // Arrange
+        let changed_days_ago = |days: u64| EntryMetadata {
+            modified: Some(NOW - days * SECONDS_PER_DAY),
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert_eq!(due(&changed_days_ago(80), 90, NOW), Due::In(10));
+        assert_eq!(due(&changed_days_ago(95), 90, NOW), Due::Overdue(5));
+        assert_eq!(due(&EntryMetadata::default(), 90, NOW), Due::Unknown);
+        assert_eq!(due(&changed_days_ago(1), 1_000_000_000_000_000, NOW), Due::In((u64::MAX - NOW) / SECONDS_PER_DAY));
+    }
+
+    #[test]
+    fn test_parse_rotation_bounds_the_period() {
+        // Act & Assert
+        assert_eq!(parse_rotation("90d"), Ok(90));
+        assert_eq!(parse_rotation("100y"), Ok(MAX_ROTATE_EVERY_DAYS));
+        assert!(parse_rotation("101y").is_err());
+        assert!(parse_rotation("1000000000000000d").is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::hash::sha256;
+use crate::audit_log;
+use crate::encryption::{generate_key, header_mac, is_legacy_ciphertext, verify_header_mac};
+use crate::schema::attachments::dsl as attachments_dsl;
+use crate::schema::entry_folders::dsl as entry_folders_dsl;
+use crate::schema::entry_tags::dsl as entry_tags_dsl;
+use crate::schema::folder_policies::dsl as folder_policies_dsl;
+use crate::schema::folders::dsl as folders_dsl;
+use crate::schema::passwords::dsl as passwords_dsl;
+use crate::schema::{api_tokens, emergency_contacts, emergency_records, identity, share_grants, shared_entries, team, team_members, token_grants};
+use crate::schema::tags::dsl as tags_dsl;
+use crate::schema::vault_header;
+use crate::schema::vault_header::dsl as header_dsl;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// The authenticated header of a vault. It records a digest of every entry and
+/// a generation counter that increases with each write, all covered by a MAC
+/// under a subkey of the vault key.
+#[derive(Queryable, Insertable, Debug, Clone, PartialEq)]
+#[table_name = "vault_header"]
+pub struct VaultHeader {
+    pub id: i32,
+    pub vault_id: String,
+    pub generation: i64,
+    pub entry_count: i64,
+    pub manifest: String,
+    pub mac: String,
+}
+
+impl VaultHeader {
+    /// The bytes covered by the header MAC.
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!(
+            "pwvault-header-v1\n{}\n{}\n{}\n{}",
+            self.vault_id, self.generation, self.entry_count, self.manifest
+        )
+        .into_bytes()
+    }
+}
+
+/// Creates the `vault_header` table if it does not already exist.
+pub fn create_header_table(connection: &SqliteConnection) -> QueryResult<()> {
+    diesel::sql_query(
+        "CREATE TABLE IF NOT EXISTS vault_header (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            vault_id TEXT NOT NULL,
+            generation BIGINT NOT NULL,
+            entry_count BIGINT NOT NULL,
+            manifest TEXT NOT NULL,
+            mac TEXT NOT NULL
+        );",
+    )
+    .execute(connection)
+    .map(|_| ())
+}
+
+/// Loads the vault header, if the vault has one.
+pub fn load_header(connection: &SqliteConnection) -> QueryResult<Option<VaultHeader>> {
+    header_dsl::vault_header.first::<VaultHeader>(connection).optional()
+}
+
+/// Computes a digest over every entry, sorted by title index, together with
+/// the number of entries. Any added, removed, modified or swapped row changes it.
+/// Folders, tags, folder policies, attachments, shares, the team, emergency access, API tokens and the audit log are covered
+/// too once the vault uses them, so vaults without any keep the manifest they were signed with.
+fn compute_manifest(connection: &SqliteConnection) -> QueryResult<(i64, String)> {
+    let rows = passwords_dsl::passwords
+        .select((
+            passwords_dsl::title_index,
+            passwords_dsl::encrypted_metadata,
+            passwords_dsl::encrypted_password,
+        ))
+        .order(passwords_dsl::title_index.asc())
+        .load::<(String, String, String)>(connection)?;
+
+    let mut state = sha256::State::new();
+    for (title_index, encrypted_metadata, encrypted_password) in &rows {
+        hash_row(&mut state, &[title_index, encrypted_metadata, encrypted_password]);
+    }
+
+    let folders = folders_dsl::folders
+        .order(folders_dsl::id.asc())
+        .load::<(i32, String, String)>(connection)?
+        .into_iter()
+        .map(|(id, path_index, encrypted_path)| vec![id.to_string(), path_index, encrypted_path]);
+    let tags = tags_dsl::tags
+        .order(tags_dsl::id.asc())
+        .load::<(i32, String, String)>(connection)?
+        .into_iter()
+        .map(|(id, tag_index, encrypted_name)| vec![id.to_string(), tag_index, encrypted_name]);
+    let entry_folders = entry_folders_dsl::entry_folders
+        .order(entry_folders_dsl::title_index.asc())
+        .load::<(String, i32)>(connection)?
+        .into_iter()
+        .map(|(title_index, folder_id)| vec![title_index, folder_id.to_string()]);
+    let entry_tags = entry_tags_dsl::entry_tags
+        .order((entry_tags_dsl::title_index.asc(), entry_tags_dsl::tag_id.asc()))
+        .load::<(String, i32)>(connection)?
+        .into_iter()
+        .map(|(title_index, tag_id)| vec![title_index, tag_id.to_string()]);
+
+    let folder_policies = folder_policies_dsl::folder_policies
+        .order(folder_policies_dsl::path_index.asc())
+        .load::<(String, String)>(connection)?
+        .into_iter()
+        .map(|(path_index, encrypted_policy)| vec![path_index, encrypted_policy]);
+
+    // Chunks are authenticated by the attachment's own key when extracted,
+    // so covering the row holding that key is enough
+    let attachments = attachments_dsl::attachments
+        .order(attachments_dsl::id.asc())
+        .load::<(i32, String, String, String, String, String)>(connection)?
+        .into_iter()
+        .map(|(id, title_index, name_index, encrypted_info, wrapped_key, stream_header)| {
+            vec![id.to_string(), title_index, name_index, encrypted_info, wrapped_key, stream_header]
+        });
+
+    let identity = identity::table
+        .select((identity::public_key, identity::encrypted_secret_key))
+        .load::<(String, String)>(connection)?
+        .into_iter()
+        .map(|(public_key, encrypted_secret_key)| vec![public_key, encrypted_secret_key]);
+    let shared_entries = shared_entries::table
+        .order(shared_entries::title_index.asc())
+        .load::<(String, String, String)>(connection)?
+        .into_iter()
+        .map(|(title_index, wrapped_key, encrypted_entry)| vec![title_index, wrapped_key, encrypted_entry]);
+    let share_grants = share_grants::table
+        .order((share_grants::title_index.asc(), share_grants::recipient_key.asc()))
+        .load::<(String, String, String)>(connection)?
+        .into_iter()
+        .map(|(title_index, recipient_key, sealed_key)| vec![title_index, recipient_key, sealed_key]);
+
+    let team = team::table
+        .select((team::team_id, team::founder, team::epoch, team::key_check, team::encrypted_history, team::signed_by, team::signature))
+        .load::<(String, String, i64, String, String, String, String)>(connection)?
+        .into_iter()
+        .map(|(team_id, founder, epoch, key_check, encrypted_history, signed_by, signature)| {
+            vec![team_id, founder, epoch.to_string(), key_check, encrypted_history, signed_by, signature]
+        });
+    let team_members = team_members::table
+        .order(team_members::member_key.asc())
+        .load::<(String, String, String, String, String)>(connection)?
+        .into_iter()
+        .map(|(member_key, role, granted_by, signature, wrapped_key)| vec![member_key, role, granted_by, signature, wrapped_key]);
+
+    let emergency_contacts = emergency_contacts::table
+        .order(emergency_contacts::contact_key.asc())
+        .load::<(String, i64, i64, String, String, String)>(connection)?
+        .into_iter()
+        .map(|(contact_key, wait_days, added_at, sealed_key, granted_by, signature)| {
+            vec![contact_key, wait_days.to_string(), added_at.to_string(), sealed_key, granted_by, signature]
+        });
+    let emergency_records = emergency_records::table
+        .order(emergency_records::id.asc())
+        .load::<(i32, String, String, i64, String)>(connection)?
+        .into_iter()
+        .map(|(id, contact_key, action, created_at, signature)| vec![id.to_string(), contact_key, action, created_at.to_string(), signature]);
+
+    let api_tokens = api_tokens::table
+        .order(api_tokens::id.asc())
+        .load::<(i32, String, String, String, bool, Option<String>, i64, Option<i64>)>(connection)?
+        .into_iter()
+        .map(|(id, name, token_hash, public_key, read_only, scope, created_at, expires_at)| {
+            vec![
+                id.to_string(),
+                name,
+                token_hash,
+                public_key,
+                read_only.to_string(),
+                scope.unwrap_or_default(),
+                created_at.to_string(),
+                expires_at.map(|expires_at| expires_at.to_string()).unwrap_or_default(),
+            ]
+        });
+    let token_grants = token_grants::table
+        .order((token_grants::token_id.asc(), token_grants::title_index.asc()))
+        .load::<(i32, String, String)>(connection)?
+        .into_iter()
+        .map(|(token_id, title_index, sealed_key)| vec![token_id.to_string(), title_index, sealed_key]);
+
+    // The chain links every record to the newest one, so covering it is enough
+    let audit_log_tip = audit_log::last_record(connection)?
+        .into_iter()
+        .map(|record| vec![record.seq.to_string(), record.hash]);
+
+    let sections: [(&str, Vec<Vec<String>>); 16] = [
+        ("folders", folders.collect()),
+        ("tags", tags.collect()),
+        ("entry_folders", entry_folders.collect()),
+        ("entry_tags", entry_tags.collect()),
+        ("folder_policies", folder_policies.collect()),
+        ("attachments", attachments.collect()),
+        ("identity", identity.collect()),
+        ("shared_entries", shared_entries.collect()),
+        ("share_grants", share_grants.collect()),
+        ("team", team.collect()),
+        ("team_members", team_members.collect()),
+        ("emergency_contacts", emergency_contacts.collect()),
+        ("emergency_records", emergency_records.collect()),
+        ("api_tokens", api_tokens.collect()),
+        ("token_grants", token_grants.collect()),
+        ("audit_log", audit_log_tip.collect()),
+    ];
+    for (table, section_rows) in sections.iter().filter(|(_, section_rows)| !section_rows.is_empty()) {
+        state.update(format!("[{}]\n", table).as_bytes());
+        for row in section_rows {
+            hash_row(&mut state, &row.iter().collect::<Vec<_>>());
+        }
+    }
+
+    Ok((rows.len() as i64, base64::encode(state.finalize().as_ref())))
+}
+
+fn hash_row(state: &mut sha256::State, fields: &[&String]) {
+    for field in fields {
+        state.update(field.as_bytes());
+        state.update(b"\0");
+    }
+    state.update(b"\n");
+}
+
+/// Re-signs the header after a write, bumping its generation. Must run inside
+/// the same transaction as the write so concurrent writers cannot interleave.
+/// The returned header should be passed to `advance_anchor` once committed.
+pub fn refresh_header(connection: &SqliteConnection) -> QueryResult<VaultHeader> {
+    let (vault_id, generation) = match load_header(connection)? {
+        Some(existing) => (existing.vault_id, existing.generation + 1),
+        None => (new_vault_id(), 1),
+    };
+    let (entry_count, manifest) = compute_manifest(connection)?;
+
+    let mut header = VaultHeader {
+        id: 1,
+        vault_id,
+        generation,
+        entry_count,
+        manifest,
+        mac: String::new(),
+    };
+    header.mac = header_mac(&header.signed_bytes(), &generate_key());
+
+    diesel::replace_into(vault_header::table)
+        .values(&header)
+        .execute(connection)?;
+    Ok(header)
+}
+
+/// Checks the vault against its header: the header must authenticate, the
+/// entries must match the manifest, and the generation must not be older than
+/// the last one this machine has seen. Vaults from before headers existed are
+/// trusted on first use and given a header.
+pub fn verify_vault_integrity(connection: &SqliteConnection) -> Result<(), String> {
+    let header = match load_header(connection).map_err(|e| format!("Failed to read vault header: {}", e))? {
+        Some(header) => header,
+        None => return initialize_header(connection),
+    };
+
+    if !verify_header_mac(&header.signed_bytes(), &header.mac, &generate_key()) {
+        return Err("The vault header failed authentication; it was modified or belongs to another key".to_string());
+    }
+
+    let (entry_count, manifest) =
+        compute_manifest(connection).map_err(|e| format!("Failed to read entries: {}", e))?;
+    if entry_count < header.entry_count {
+        return Err(format!(
+            "{} entries were deleted outside the password manager",
+            header.entry_count - entry_count
+        ));
+    }
+    if entry_count != header.entry_count || manifest != header.manifest {
+        return Err("Entries were added, modified or swapped outside the password manager".to_string());
+    }
+
+    if let Some(last_seen) = read_anchor(&header.vault_id) {
+        if header.generation < last_seen {
+            return Err(format!(
+                "The vault was rolled back to an older copy (generation {}, last seen {})",
+                header.generation, last_seen
+            ));
+        }
+    }
+
+    advance_anchor(&header);
+    Ok(())
+}
+
+/// Gives a vault without a header its first one. Refused if any entry was
+/// written with authenticated encryption, since those vaults always had a
+/// header and a missing one means it was stripped.
+fn initialize_header(connection: &SqliteConnection) -> Result<(), String> {
+    let ciphertexts = passwords_dsl::passwords
+        .select((passwords_dsl::encrypted_metadata, passwords_dsl::encrypted_password))
+        .load::<(String, String)>(connection)
+        .map_err(|e| format!("Failed to read entries: {}", e))?;
+
+    if ciphertexts
+        .iter()
+        .any(|(metadata, password)| !is_legacy_ciphertext(metadata) || !is_legacy_ciphertext(password))
+    {
+        return Err("The vault header is missing".to_string());
+    }
+
+    let header = connection
+        .immediate_transaction(|| refresh_header(connection))
+        .map_err(|e| format!("Failed to create vault header: {}", e))?;
+    advance_anchor(&header);
+    Ok(())
+}
+
+/// Records the header's generation as the newest one seen on this machine.
+/// The anchor lives outside the vault file, so replacing the vault with an
+/// older copy is noticed on the next open.
+pub fn advance_anchor(header: &VaultHeader) {
+    if read_anchor(&header.vault_id).is_some_and(|last_seen| last_seen >= header.generation) {
+        return;
+    }
+    if let Some(path) = anchor_path(&header.vault_id) {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(&path, header.generation.to_string()) {
+            eprintln!("Warning: failed to record vault generation: {}", e);
+        }
+    }
+}
+
+fn read_anchor(vault_id: &str) -> Option<i64> {
+    let path = anchor_path(vault_id)?;
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Anchors are kept in the user's state directory, keyed by vault ID.
+fn anchor_path(vault_id: &str) -> Option<PathBuf> {
+    Some(state_dir()?.join("anchors").join(vault_id))
+}
+
+/// The per-user directory for state that must live outside the vault file,
+/// `$XDG_STATE_HOME/pwvault` (default `~/.local/state/pwvault`).
+pub fn state_dir() -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_dir.join("pwvault"))
+}
+
+fn new_vault_id() -> String {
+    sodiumoxide::randombytes::randombytes(16)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
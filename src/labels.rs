@@ -0,0 +1,130 @@
+use sodiumoxide::crypto::secretbox::Key;
+use crate::database::{load_organization, Label};
+use crate::encryption::{decrypt_label, encrypt_label, label_index, normalize_title};
+use std::collections::HashMap;
+
+/// The decrypted folder and tags of every entry, keyed by title index.
+#[derive(Debug, Default)]
+pub struct EntryLabels {
+    folders: HashMap<String, String>,
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl EntryLabels {
+    /// Loads and decrypts the folders and tags of the selected vault.
+    pub fn load(key: &Key) -> Result<Self, String> {
+        let organization = load_organization();
+
+        let mut folder_paths = HashMap::new();
+        for folder in &organization.folders {
+            let path = decrypt_label("folder", &folder.encrypted_path, &folder.path_index, key)?;
+            folder_paths.insert(folder.id, path);
+        }
+        let mut tag_names = HashMap::new();
+        for tag in &organization.tags {
+            let name = decrypt_label("tag", &tag.encrypted_name, &tag.tag_index, key)?;
+            tag_names.insert(tag.id, name);
+        }
+
+        let mut labels = EntryLabels::default();
+        for (title_index, folder_id) in organization.entry_folders {
+            if let Some(path) = folder_paths.get(&folder_id) {
+                labels.folders.insert(title_index, path.clone());
+            }
+        }
+        for (title_index, tag_id) in organization.entry_tags {
+            if let Some(name) = tag_names.get(&tag_id) {
+                labels.tags.entry(title_index).or_default().push(name.clone());
+            }
+        }
+        for tags in labels.tags.values_mut() {
+            tags.sort();
+        }
+        Ok(labels)
+    }
+
+    /// The folder an entry is filed under, if any.
+    pub fn folder(&self, title_index: &str) -> Option<&str> {
+        self.folders.get(title_index).map(String::as_str)
+    }
+
+    /// The tags of an entry, sorted by name.
+    pub fn tags(&self, title_index: &str) -> &[String] {
+        self.tags.get(title_index).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Whether an entry carries `tag`, ignoring case.
+    pub fn has_tag(&self, title_index: &str, tag: &str) -> bool {
+        self.tags(title_index)
+            .iter()
+            .any(|name| normalize_title(name) == normalize_title(tag))
+    }
+}
+
+/// Turns a folder path such as `/prod//db/` into `prod/db`. The top level
+/// (`/` or an empty path) gives `None`.
+pub fn normalize_folder(path: &str) -> Result<Option<String>, String> {
+    let segments: Vec<&str> = path
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    if segments.iter().any(|segment| *segment == "." || *segment == "..") {
+        return Err(format!("Invalid folder '{}': '.' and '..' are not allowed", path));
+    }
+    if segments.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(segments.join("/")))
+    }
+}
+
+/// Whether `folder` is `filter` or one of its subfolders, ignoring case.
+pub fn is_in_folder(folder: &str, filter: &str) -> bool {
+    let folder = folder.to_lowercase();
+    let filter = filter.to_lowercase();
+    folder == filter || folder.starts_with(&format!("{}/", filter))
+}
+
+/// Indexes and encrypts a normalized folder path for storage.
+pub fn folder_label(path: &str, key: &Key) -> Label {
+    let index = label_index("folder", path, key);
+    Label {
+        encrypted: encrypt_label("folder", path, &index, key),
+        index,
+    }
+}
+
+/// Indexes and encrypts a tag for storage.
+pub fn tag_label(tag: &str, key: &Key) -> Result<Label, String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err("Tags cannot be empty".to_string());
+    }
+    let index = label_index("tag", tag, key);
+    Ok(Label {
+        encrypted: encrypt_label("tag", tag, &index, key),
+        index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_folder() {
+        // Act & Assert
+        assert_eq!(normalize_folder("/prod//db/ postgres /"), Ok(Some("prod/db/postgres".to_string())));
+        assert_eq!(normalize_folder("/"), Ok(None));
+        assert!(normalize_folder("prod/../secrets").is_err());
+    }
+
+    #[test]
+    fn test_is_in_folder() {
+        // Act & Assert: Subfolders match, folders sharing a prefix do not
+        assert!(is_in_folder("prod/db/postgres", "prod"));
+        assert!(is_in_folder("Prod/DB", "prod/db"));
+        assert!(!is_in_folder("production", "prod"));
+    }
+}
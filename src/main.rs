@@ -1,16 +1,44 @@
 #[macro_use]
 extern crate diesel;
 
+#[macro_use]
+mod output;
+mod api;
+mod audit;
+mod audit_log;
+mod attachments;
+mod batch;
+mod container;
 mod database;
+mod emergency;
 mod encryption;
+mod expiry;
+mod header;
+mod items;
+mod labels;
+mod native_host;
 mod cli;
+mod config;
+mod recovery;
+mod references;
 mod schema;
+mod search;
+mod sharing;
+mod ssh_agent;
+mod strength;
+mod team;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod url_match;
+mod vaults;
 
 use cli::run_cli;
 
 fn main() {
-    // create the necessary database table if it doesn't already exist.
-    database::create_table_if_not_exists();
+    // seal an encrypted vault again once the program finishes, even on panic.
+    let _vault_guard = container::VaultGuard;
 
     // start the command-line interface (CLI) for user interaction.
     run_cli();
@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sodiumoxide::crypto::secretbox::Key;
+use crate::encryption::{decrypt_password, normalize_title};
+use crate::url_match::{match_entries, page_url};
+use std::io::{self, Read, Write};
+
+/// Longest message the host accepts. Browsers may send up to 4 GiB, but the
+/// requests of a page are tiny.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Longest message Chrome accepts from a native messaging host.
+const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+
+/// A request from the browser extension. Every request may also carry an
+/// `id`, which is sent back with its response. `origin` may be the full URL
+/// of the page, which exact and starts-with URL rules need.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum HostRequest {
+    /// Whether the vault is unlocked on this device.
+    Status,
+    /// The logins with a URL matching the page, without their passwords.
+    Candidates { origin: String },
+    /// The username and password of one of those logins.
+    Fill { origin: String, title: String },
+}
+
+/// A login offered for a page.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Candidate {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub url: String,
+}
+
+/// Unlocks the vault for a request, or says why it cannot be.
+pub type Unlock = Box<dyn Fn() -> Result<Key, String>>;
+
+/// Called after a login was filled into a page, with its title and the origin.
+pub type FillHook = Box<dyn Fn(&str, &str, &Key)>;
+
+/// Answers the browser extension over the native messaging protocol. The vault
+/// is unlocked again for every request, so a vault that cannot be unlocked on
+/// this device, or fails its integrity check, gives nothing away.
+pub struct NativeHost {
+    unlock: Unlock,
+    /// Told about each login filled, e.g. to record it in the audit log.
+    pub on_fill: Option<FillHook>,
+    /// Run after each request, e.g. to seal an encrypted vault again.
+    pub after_request: Option<Box<dyn Fn()>>,
+}
+
+impl NativeHost {
+    pub fn new(unlock: Unlock) -> NativeHost {
+        NativeHost {
+            unlock,
+            on_fill: None,
+            after_request: None,
+        }
+    }
+
+    /// Answers one message, already stripped of its length prefix.
+    pub fn handle(&self, message: &[u8]) -> Value {
+        let value: Value = match serde_json::from_slice(message) {
+            Ok(value) => value,
+            Err(e) => return error_response(&format!("Invalid JSON message: {}", e)),
+        };
+        let id = value.get("id").cloned();
+        let mut response = match HostRequest::deserialize(&value) {
+            Ok(request) => self.answer(request),
+            Err(e) => error_response(&format!("Invalid request: {}", e)),
+        };
+        if let (Some(id), Some(object)) = (id, response.as_object_mut()) {
+            object.insert("id".to_string(), id);
+        }
+        if let Some(after_request) = &self.after_request {
+            after_request();
+        }
+        response
+    }
+
+    fn answer(&self, request: HostRequest) -> Value {
+        let key = match (self.unlock)() {
+            Ok(key) => key,
+            Err(err) => return json!({ "type": "locked", "error": err }),
+        };
+        let result = match request {
+            HostRequest::Status => Ok(json!({ "type": "status", "unlocked": true })),
+            HostRequest::Candidates { origin } => candidates(&origin, &key).map(|logins| {
+                let logins: Vec<Candidate> = logins.into_iter().map(|(_, candidate)| candidate).collect();
+                json!({ "type": "candidates", "origin": origin, "logins": logins })
+            }),
+            HostRequest::Fill { origin, title } => self.fill(&origin, &title, &key),
+        };
+        result.unwrap_or_else(|err| error_response(&err))
+    }
+
+    /// Gives the password of `title`, but only if one of its URLs matches the page,
+    /// so a page can never ask for the logins of another site.
+    fn fill(&self, origin: &str, title: &str, key: &Key) -> Result<Value, String> {
+        let ((title_index, encrypted_password), candidate) = candidates(origin, key)?
+            .into_iter()
+            .find(|(_, candidate)| normalize_title(&candidate.title) == normalize_title(title))
+            .ok_or_else(|| format!("No login called '{}' matches {}", title, origin))?;
+        let password = decrypt_password(&encrypted_password, &title_index, None, key)?;
+        if let Some(on_fill) = &self.on_fill {
+            on_fill(&candidate.title, origin, key);
+        }
+        Ok(json!({
+            "type": "fill",
+            "title": candidate.title,
+            "username": candidate.username,
+            "password": password,
+        }))
+    }
+}
+
+fn error_response(message: &str) -> Value {
+    json!({ "type": "error", "error": message })
+}
+
+/// A matching login with its title index and encrypted password.
+type Match = ((String, String), Candidate);
+
+/// The logins with a URL rule matching the page, the most specific first.
+/// Only web pages get logins.
+fn candidates(origin: &str, key: &Key) -> Result<Vec<Match>, String> {
+    let page = page_url(origin)?;
+    let logins = match_entries(&page, key)?
+        .into_iter()
+        .filter(|entry| entry.metadata.item_type.is_login())
+        .map(|entry| {
+            let candidate = Candidate {
+                title: entry.metadata.title,
+                username: entry.metadata.username,
+                url: entry.rule.url,
+            };
+            ((entry.title_index, entry.encrypted_password), candidate)
+        })
+        .collect();
+    Ok(logins)
+}
+
+/// Reads one message: a 32-bit length in native byte order, then that many
+/// bytes of JSON. Returns `None` once the browser closes the pipe.
+pub fn read_message(input: &mut impl Read) -> Result<Option<Vec<u8>>, String> {
+    let mut length = [0u8; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(format!("Failed to read a message: {}", e)),
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_REQUEST_LEN {
+        return Err(format!("Refused a message of {} bytes", length));
+    }
+    let mut message = vec![0u8; length];
+    input
+        .read_exact(&mut message)
+        .map_err(|e| format!("Failed to read a message: {}", e))?;
+    Ok(Some(message))
+}
+
+/// Writes one message with its length prefix.
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<(), String> {
+    let mut bytes = serde_json::to_vec(message).expect("Failed to serialize a message");
+    if bytes.len() > MAX_RESPONSE_LEN {
+        bytes = serde_json::to_vec(&error_response("The response is too large for the browser")).expect("Failed to serialize a message");
+    }
+    output
+        .write_all(&(bytes.len() as u32).to_ne_bytes())
+        .and_then(|_| output.write_all(&bytes))
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Failed to write a message: {}", e))
+}
+
+/// Answers messages until the browser closes the pipe. A message that cannot
+/// be framed ends the session, since the stream cannot be trusted after it.
+pub fn serve(input: &mut impl Read, output: &mut impl Write, host: &NativeHost) -> Result<(), String> {
+    while let Some(message) = read_message(input)? {
+        write_message(output, &host.handle(&message))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{generate_key, EntryMetadata};
+    use crate::testing::{add_test_item, test_vault};
+
+    fn login(title: &str, url: &str) -> EntryMetadata {
+        EntryMetadata {
+            title: title.to_string(),
+            username: Some("me".to_string()),
+            url: Some(url.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn frame(message: &str) -> Vec<u8> {
+        let mut framed = (message.len() as u32).to_ne_bytes().to_vec();
+        framed.extend(message.as_bytes());
+        framed
+    }
+
+    fn unframe(mut output: &[u8]) -> Vec<Value> {
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(serde_json::from_slice(&message).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn test_native_host_fills_only_matching_logins() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        add_test_item(&login("GitHub", "https://github.com/login"), "gh-secret", None, &key);
+        add_test_item(&login("GitHub Enterprise", "github.example.com"), "ghe-secret", None, &key);
+        add_test_item(&login("Bank", "https://bank.example.com"), "bank-secret", None, &key);
+        let host = NativeHost::new(Box::new(|| Ok(generate_key())));
+        let mut input = Vec::new();
+        for message in [
+            r#"{"id":1,"type":"candidates","origin":"https://github.com"}"#,
+            r#"{"id":2,"type":"fill","origin":"https://github.com","title":"github"}"#,
+            r#"{"id":3,"type":"fill","origin":"https://github.com","title":"Bank"}"#,
+            r#"{"id":4,"type":"candidates","origin":"http://github.com"}"#,
+            r#"{"id":5,"type":"candidates","origin":"https://github.example.com"}"#,
+            r#"not json"#,
+        ] {
+            input.extend(frame(message));
+        }
+        let mut output = Vec::new();
+
+        // Act
+        serve(&mut input.as_slice(), &mut output, &host).unwrap();
+        let responses = unframe(&output);
+
+        // Assert
+        assert_eq!(responses.len(), 6);
+        assert_eq!(responses[0]["logins"], json!([{ "title": "GitHub", "username": "me", "url": "https://github.com/login" }]));
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!((responses[1]["type"].as_str(), responses[1]["password"].as_str()), (Some("fill"), Some("gh-secret")));
+        assert_eq!(responses[2]["error"], "No login called 'Bank' matches https://github.com");
+        assert!(responses[2].get("password").is_none());
+        assert_eq!(responses[3]["logins"], json!([]));
+        assert_eq!(responses[4]["logins"][0]["title"], "GitHub Enterprise");
+        assert_eq!(responses[5]["type"], "error");
+    }
+
+    #[test]
+    fn test_native_host_answers_locked_without_the_vault() {
+        // Arrange
+        let host = NativeHost::new(Box::new(|| Err("This device is not a member of the team vault".to_string())));
+
+        // Act
+        let status = host.handle(br#"{"type":"status"}"#);
+        let fill = host.handle(br#"{"id":"a","type":"fill","origin":"https://github.com","title":"GitHub"}"#);
+
+        // Assert
+        assert_eq!(status["type"], "locked");
+        assert_eq!(fill, json!({ "type": "locked", "error": "This device is not a member of the team vault", "id": "a" }));
+    }
+
+    #[test]
+    fn test_native_host_refuses_oversized_messages() {
+        // Arrange
+        let input = (MAX_REQUEST_LEN as u32 + 1).to_ne_bytes();
+        let host = NativeHost::new(Box::new(|| Ok(generate_key())));
+
+        // Act
+        let result = serve(&mut input.as_slice(), &mut Vec::new(), &host);
+
+        // Assert
+        assert_eq!(result, Err(format!("Refused a message of {} bytes", MAX_REQUEST_LEN + 1)));
+    }
+}
@@ -0,0 +1,474 @@
+use serde::Serialize;
+use serde_json::Value;
+use crate::batch::Change;
+use crate::items::ItemFields;
+use std::cell::RefCell;
+
+/// Version of the JSON reports. It only changes when a field is removed or
+/// changes meaning; fields may be added within a version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What a command prints with `--output json`, as a single JSON document.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Report {
+    pub schema_version: u32,
+    /// The subcommand that ran, e.g. `add` or `token create`.
+    pub command: String,
+    pub ok: bool,
+    /// What the command produced or changed, as one of the result types
+    /// below. Failed commands have none, nor do those like `clean` or
+    /// `convert` that only report what they did in their messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+    /// The lines the command would have printed as text, warnings included.
+    pub messages: Vec<String>,
+}
+
+/// Why a command failed.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ErrorReport {
+    pub message: String,
+}
+
+/// An entry that was added, changed, moved or deleted.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntryRef {
+    pub title: String,
+}
+
+/// An entry read with its secret: a password for logins, fields for the other types.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SecretEntry {
+    pub title: String,
+    #[serde(rename = "type")]
+    pub item_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Every URL of the entry as `mode:url`.
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<ItemFields>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A file attached to an entry.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub size: u64,
+}
+
+/// An attachment written out by `extract`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ExtractedFile {
+    pub name: String,
+    pub size: u64,
+    pub path: String,
+}
+
+/// An attachment `detach` removed from an entry.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DetachedFile {
+    pub title: String,
+    pub name: String,
+}
+
+/// The placeholders of an `inject` template, and the file written from it
+/// unless only checked.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Injection {
+    pub references: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub written: Option<String>,
+}
+
+/// Where `serve` or `ssh-agent` listens, and the keys the agent holds. Both
+/// run until stopped, so their report is printed once they listen.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Listening {
+    pub address: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<ServedKey>,
+}
+
+/// A key served by `ssh-agent`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ServedKey {
+    pub title: String,
+    pub fingerprint: String,
+}
+
+/// The public key of `identity`, or the member key of `team whoami`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PublicKey {
+    pub public_key: String,
+}
+
+/// An API token. Its secret is only shown by `token create`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TokenInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub read_only: bool,
+    /// The scopes of the token, e.g. `folder:prod/*`; empty for every entry.
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    pub expired: bool,
+}
+
+/// An emergency contact of `emergency list`, and where its request stands.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EmergencyContact {
+    pub key: String,
+    pub wait_days: i64,
+    /// `idle`, `waiting`, `released` or `denied`.
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_at: Option<i64>,
+}
+
+/// Where a request stands after `emergency request`, made on `vault`, or
+/// `emergency deny`, of `contact`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EmergencyRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// `waiting`, `released` or `denied`.
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_at: Option<i64>,
+}
+
+/// A member `team remove` or a contact `emergency remove` removed. `epoch`
+/// is that of the team key rotated by the removal of a member.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RemovedKey {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<i64>,
+}
+
+/// The shares `recovery split` made of the vault key.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RecoveryShares {
+    pub threshold: u8,
+    pub shares: Vec<RecoveryShare>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RecoveryShare {
+    pub index: u8,
+    /// The share as words or text, as chosen with `--format`.
+    pub share: String,
+}
+
+/// A folder rotation policy of `expiry list`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FolderRotation {
+    pub folder: String,
+    pub rotate_every_days: u64,
+}
+
+/// The policy `expiry set` put on, or `expiry clear` removed from, an entry
+/// or a folder. `rotate_every_days` is absent once cleared.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RotationPolicy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotate_every_days: Option<u64>,
+}
+
+/// A setting written by `config set`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ConfigValue {
+    pub key: String,
+    pub value: String,
+}
+
+/// A registered vault of `vault list`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct VaultInfo {
+    pub name: String,
+    pub path: String,
+    pub encrypted: bool,
+    pub default: bool,
+}
+
+/// The changes of `batch apply`: written, or with `--dry-run` only planned.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BatchResult {
+    pub dry_run: bool,
+    pub changes: Vec<Change>,
+}
+
+/// The members of a team vault, as `team list` shows them.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TeamMembers {
+    pub epoch: i64,
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TeamMember {
+    pub key: String,
+    pub role: &'static str,
+    /// Whether this is the member running the command.
+    pub you: bool,
+}
+
+/// An entry `shared` shares from this vault, and whom with.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OutgoingShare {
+    pub title: String,
+    pub recipients: Vec<String>,
+}
+
+/// An entry `share` shared, or `unshare` stopped sharing, with `recipient`.
+/// `remaining` is how many recipients are left after `unshare`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ShareChange {
+    pub title: String,
+    pub recipient: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<usize>,
+}
+
+/// The entries another vault shares with this one, as `shared --from` lists them.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct IncomingShares {
+    pub sender: String,
+    pub entries: Vec<ListedEntry>,
+}
+
+/// An entry of `list`, `shared` or `emergency access`, without its secret.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ListedEntry {
+    pub title: String,
+    #[serde(rename = "type")]
+    pub item_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// The entries a command lists.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntryList<T> {
+    pub entries: Vec<T>,
+}
+
+/// A hit of `search`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    /// `title`, `username`, `url` or `tag`.
+    pub field: &'static str,
+    pub matched: String,
+    pub score: i32,
+}
+
+/// An entry of `match`, with the URL rule that matched best.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MatchedEntry {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub url: String,
+    pub mode: &'static str,
+}
+
+/// A record of `audit-log`. `title` is empty for actions on the whole vault.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LogEntry {
+    pub seq: i64,
+    pub timestamp: i64,
+    pub action: String,
+    pub title: String,
+    pub device: String,
+}
+
+/// The records `audit-log` shows, and what is wrong with the chain.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AuditLog {
+    pub records: Vec<LogEntry>,
+    pub problems: Vec<String>,
+}
+
+/// A password of `expiring`. `days` is how many days it is overdue, or due
+/// in; both are unknown for passwords last changed before changes were recorded.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ExpiringEntry {
+    pub title: String,
+    /// `overdue`, `due` or `unknown`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<u64>,
+    pub rotate_every_days: u64,
+    /// The folder the policy is set on, if not on the entry itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+}
+
+thread_local! {
+    /// The report being built, when the output is JSON.
+    static REPORT: RefCell<Option<Report>> = const { RefCell::new(None) };
+}
+
+/// Builds a JSON report for `command` instead of printing text.
+pub fn start_report(command: &str) {
+    REPORT.with(|report| {
+        *report.borrow_mut() = Some(Report {
+            schema_version: SCHEMA_VERSION,
+            command: command.to_string(),
+            ok: true,
+            result: None,
+            error: None,
+            messages: Vec::new(),
+        })
+    });
+}
+
+pub fn is_json() -> bool {
+    REPORT.with(|report| report.borrow().is_some())
+}
+
+/// Prints a line, or keeps it as a message of the report.
+pub fn say(line: String) {
+    let kept = REPORT.with(|report| match report.borrow_mut().as_mut() {
+        Some(report) => {
+            report.messages.push(line.clone());
+            true
+        }
+        None => false,
+    });
+    if !kept {
+        println!("{}", line);
+    }
+}
+
+/// Prints why the command failed, or marks the report as failed. Only the
+/// first failure is the error; later ones are kept as messages.
+pub fn fail(message: String) {
+    let kept = REPORT.with(|report| match report.borrow_mut().as_mut() {
+        Some(report) => {
+            if report.error.is_none() {
+                report.ok = false;
+                report.error = Some(ErrorReport { message: message.clone() });
+            } else {
+                report.messages.push(message.clone());
+            }
+            true
+        }
+        None => false,
+    });
+    if !kept {
+        println!("{}", message);
+    }
+}
+
+/// Sets the result of the report; text output shows it through `say` instead.
+pub fn emit<T: Serialize>(result: &T) {
+    REPORT.with(|report| {
+        if let Some(report) = report.borrow_mut().as_mut() {
+            report.result = Some(serde_json::to_value(result).expect("Failed to serialize a result"));
+        }
+    });
+}
+
+/// Takes the report built so far, leaving none, so it is printed only once.
+pub fn take_report() -> Option<Report> {
+    REPORT.with(|report| report.borrow_mut().take())
+}
+
+/// Prints the report, if there is one. Returns false if the command failed.
+pub fn finish_report() -> bool {
+    match take_report() {
+        Some(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).expect("Failed to serialize a report"));
+            report.ok
+        }
+        None => true,
+    }
+}
+
+/// Prints a line of text output; with `--output json` it becomes a message of the report.
+macro_rules! say {
+    () => {
+        $crate::output::say(String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::say(format!($($arg)*))
+    };
+}
+
+/// Prints why a command failed; with `--output json` it becomes the error of the report.
+macro_rules! fail {
+    ($($arg:tt)*) => {
+        $crate::output::fail(format!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_report_collects_result_messages_and_first_error() {
+        // Arrange
+        start_report("token create");
+
+        // Act
+        say!("Created token '{}'.", "ci");
+        emit(&EntryRef { title: "GitHub".to_string() });
+        fail!("Failed to add password: {}", "first");
+        fail!("Failed to add password: {}", "second");
+        let report = take_report().unwrap();
+
+        // Assert
+        assert!(!is_json());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "schema_version": SCHEMA_VERSION,
+                "command": "token create",
+                "ok": false,
+                "result": { "title": "GitHub" },
+                "error": { "message": "Failed to add password: first" },
+                "messages": ["Created token 'ci'.", "Failed to add password: second"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_report_without_json_output() {
+        // Act
+        say!("Password added for '{}'.", "GitHub");
+        emit(&EntryRef { title: "GitHub".to_string() });
+
+        // Assert
+        assert!(!is_json());
+        assert_eq!(take_report(), None);
+        assert!(finish_report());
+    }
+}
@@ -0,0 +1,342 @@
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::randombytes::randombytes;
+
+/// Version byte at the start of every encoded share.
+const SHARE_VERSION: u8 = 1;
+
+/// Prefix of shares written as text.
+const TEXT_PREFIX: &str = "PWVS";
+
+/// Bytes of a share besides its value: version, set id, threshold, index
+/// and checksum.
+const SHARE_OVERHEAD: usize = 1 + 4 + 1 + 1 + 4;
+
+/// One word per byte value, in sorted order. The first four letters of every
+/// word are unique.
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alley", "amber", "angle", "ankle", "apple",
+    "april", "apron", "arena", "armor", "arrow", "atlas", "attic", "audio", "aunt", "award", "axis", "baby",
+    "bacon", "badge", "bagel", "baker", "banjo", "barn", "basil", "beach", "beard", "bench", "berry", "bike",
+    "bison", "blade", "bloom", "board", "boat", "bonus", "book", "border", "bottle", "bowl", "brain", "brick",
+    "bridge", "broom", "bucket", "bunny", "butter", "cabin", "cactus", "camel", "canal", "candle", "canoe", "canyon",
+    "carbon", "carpet", "castle", "cattle", "cedar", "cello", "chair", "chalk", "cherry", "chess", "chief", "cider",
+    "cinema", "circus", "clock", "cloud", "clover", "coach", "cobra", "cocoa", "comet", "coral", "cotton", "cougar",
+    "crane", "crater", "crayon", "crown", "cube", "dagger", "daisy", "dancer", "delta", "desert", "dinner", "doctor",
+    "donkey", "dragon", "drum", "eagle", "earth", "easel", "echo", "elbow", "eleven", "ember", "engine", "envoy",
+    "falcon", "farm", "fence", "ferry", "fiddle", "finger", "flute", "fossil", "fox", "frog", "galaxy", "garden",
+    "garlic", "gecko", "giant", "ginger", "globe", "goose", "grape", "gravel", "guitar", "hammer", "harbor", "hazel",
+    "helmet", "hermit", "hippo", "honey", "hotel", "husky", "igloo", "island", "ivory", "jacket", "jaguar", "jelly",
+    "jigsaw", "jungle", "kayak", "kettle", "kitten", "koala", "ladder", "lagoon", "lamp", "lemon", "lens", "lily",
+    "lion", "lizard", "locket", "magnet", "mango", "maple", "marble", "meadow", "melon", "mirror", "mitten", "monkey",
+    "moose", "mosaic", "motor", "muffin", "museum", "napkin", "nectar", "needle", "nest", "noodle", "nutmeg", "oasis",
+    "ocean", "olive", "onion", "orange", "orbit", "orchid", "otter", "owl", "oyster", "paddle", "palace", "panda",
+    "parrot", "peach", "pebble", "pencil", "pepper", "piano", "pigeon", "pillow", "pilot", "planet", "plum", "pocket",
+    "pony", "potato", "prism", "puzzle", "quartz", "quilt", "rabbit", "radar", "radish", "raven", "ribbon", "river",
+    "robot", "rocket", "rose", "ruby", "saddle", "salmon", "sandal", "scarf", "shadow", "shark", "shell", "silver",
+    "sketch", "sloth", "snail", "spider", "sponge", "squid", "statue", "summit", "sunset", "swan", "table", "tiger",
+    "timber", "tomato", "tower", "tulip", "turtle", "valley", "velvet", "violin", "wagon", "walnut", "walrus", "whale",
+    "window", "wizard", "yacht", "zebra",
+];
+
+/// One of the shares a secret was split into. Any `threshold` shares of the
+/// same set rebuild the secret; fewer reveal nothing about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+    /// Random id shared by the shares of one split, so shares of different
+    /// splits are not combined by mistake.
+    pub set_id: [u8; 4],
+    pub threshold: u8,
+    /// The point the share was taken at, from 1.
+    pub index: u8,
+    value: Vec<u8>,
+}
+
+/// How shares are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareFormat {
+    /// One word per byte, easy to write down.
+    Words,
+    /// Upper-case hex in groups, which fits the alphanumeric mode of QR codes.
+    Text,
+}
+
+impl ShareFormat {
+    /// Parses a format name as accepted by `recovery split --format`.
+    pub fn parse(name: &str) -> Result<ShareFormat, String> {
+        match name {
+            "words" => Ok(ShareFormat::Words),
+            "text" => Ok(ShareFormat::Text),
+            other => Err(format!("Unknown share format '{}': expected 'words' or 'text'", other)),
+        }
+    }
+}
+
+impl Share {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![SHARE_VERSION];
+        bytes.extend_from_slice(&self.set_id);
+        bytes.push(self.threshold);
+        bytes.push(self.index);
+        bytes.extend_from_slice(&self.value);
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Share, String> {
+        if bytes.len() <= SHARE_OVERHEAD {
+            return Err("The share is too short".to_string());
+        }
+        let (body, stored) = bytes.split_at(bytes.len() - 4);
+        if checksum(body) != stored {
+            return Err("The share's checksum does not match; check it for typos".to_string());
+        }
+        if body[0] != SHARE_VERSION {
+            return Err(format!("Unsupported share version {}", body[0]));
+        }
+        let (threshold, index) = (body[5], body[6]);
+        if threshold < 2 || index == 0 {
+            return Err("The share is malformed".to_string());
+        }
+        Ok(Share {
+            set_id: [body[1], body[2], body[3], body[4]],
+            threshold,
+            index,
+            value: body[7..].to_vec(),
+        })
+    }
+
+    /// Writes the share out in `format`.
+    pub fn encode(&self, format: ShareFormat) -> String {
+        let bytes = self.to_bytes();
+        match format {
+            ShareFormat::Words => bytes.iter().map(|byte| WORDS[*byte as usize]).collect::<Vec<_>>().join(" "),
+            ShareFormat::Text => {
+                let hex: Vec<String> = bytes.chunks(2).map(|pair| pair.iter().map(|byte| format!("{:02X}", byte)).collect()).collect();
+                format!("{}-{}", TEXT_PREFIX, hex.join("-"))
+            }
+        }
+    }
+
+    /// Reads a share written in either format. Case, spacing and the dashes
+    /// between groups are ignored.
+    pub fn decode(encoded: &str) -> Result<Share, String> {
+        let encoded = encoded.trim();
+        let text = encoded.to_ascii_uppercase();
+        let bytes = match text.strip_prefix(TEXT_PREFIX) {
+            Some(hex) => {
+                let digits: Vec<u8> = hex.bytes().filter(|c| *c != b'-' && !c.is_ascii_whitespace()).collect();
+                if !digits.len().is_multiple_of(2) {
+                    return Err("The share has an odd number of hex digits".to_string());
+                }
+                digits
+                    .chunks(2)
+                    .map(|pair| {
+                        std::str::from_utf8(pair)
+                            .ok()
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                            .ok_or_else(|| format!("Invalid hex digits '{}' in the share", String::from_utf8_lossy(pair)))
+                    })
+                    .collect::<Result<Vec<u8>, String>>()?
+            }
+            None => encoded
+                .split_whitespace()
+                .map(|word| {
+                    WORDS
+                        .binary_search(&word.to_lowercase().as_str())
+                        .map(|position| position as u8)
+                        .map_err(|_| format!("Unknown word '{}' in the share", word))
+                })
+                .collect::<Result<Vec<u8>, String>>()?,
+        };
+        Share::from_bytes(&bytes)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> [u8; 4] {
+    let digest = sha256::hash(bytes);
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&digest.as_ref()[..4]);
+    checksum
+}
+
+/// Multiplies in GF(2^8) modulo the AES polynomial x^8 + x^4 + x^3 + x + 1.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// The multiplicative inverse, as a^254 since a^255 = 1 for every a != 0.
+/// Zero has no inverse and maps to zero.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    let mut exponent = 254u8;
+    while exponent != 0 {
+        if exponent & 1 != 0 {
+            result = gf_mul(result, power);
+        }
+        power = gf_mul(power, power);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Evaluates the polynomial with `coefficients`, lowest degree first, at `x`.
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0, |acc, coefficient| gf_mul(acc, x) ^ coefficient)
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which rebuild it.
+/// Each byte is the constant term of its own random polynomial of degree
+/// `threshold - 1` over GF(256), and share `i` holds the polynomials at `i`.
+pub fn split_secret(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<Share>, String> {
+    if threshold < 2 {
+        return Err("The threshold must be at least 2".to_string());
+    }
+    if shares < threshold {
+        return Err(format!("Cannot require {} shares out of only {}", threshold, shares));
+    }
+    if secret.is_empty() {
+        return Err("There is no secret to split".to_string());
+    }
+
+    let set_id = randombytes(4);
+    let polynomials: Vec<Vec<u8>> = secret
+        .iter()
+        .map(|byte| {
+            let mut coefficients = vec![*byte];
+            coefficients.extend(randombytes(threshold as usize - 1));
+            coefficients
+        })
+        .collect();
+    Ok((1..=shares)
+        .map(|index| Share {
+            set_id: [set_id[0], set_id[1], set_id[2], set_id[3]],
+            threshold,
+            index,
+            value: polynomials.iter().map(|coefficients| evaluate(coefficients, index)).collect(),
+        })
+        .collect())
+}
+
+/// Rebuilds the secret from at least `threshold` shares of one split, by
+/// Lagrange interpolation at zero.
+pub fn combine_shares(shares: &[Share]) -> Result<Vec<u8>, String> {
+    let first = shares.first().ok_or("No shares given")?;
+    if shares.iter().any(|share| share.set_id != first.set_id) {
+        return Err("The shares come from different splits".to_string());
+    }
+    if shares.iter().any(|share| share.threshold != first.threshold || share.value.len() != first.value.len()) {
+        return Err("The shares do not agree on their threshold; one of them is damaged".to_string());
+    }
+    let mut distinct: Vec<&Share> = Vec::new();
+    for share in shares {
+        if !distinct.iter().any(|seen| seen.index == share.index) {
+            distinct.push(share);
+        }
+    }
+    if distinct.len() < first.threshold as usize {
+        return Err(format!("{} different shares are needed, only {} given", first.threshold, distinct.len()));
+    }
+
+    let used = &distinct[..first.threshold as usize];
+    let mut secret = vec![0u8; first.value.len()];
+    for share in used {
+        // The Lagrange basis polynomial of this share, at zero
+        let basis = used
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1, |acc, other| gf_mul(acc, gf_mul(other.index, gf_inv(other.index ^ share.index))));
+        for (byte, value) in secret.iter_mut().zip(&share.value) {
+            *byte ^= gf_mul(basis, *value);
+        }
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gf256_arithmetic() {
+        // Act & Assert: Products from FIPS-197
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        assert_eq!(gf_mul(0x53, 0xca), 0x01);
+        assert_eq!(gf_inv(0x53), 0xca);
+        assert_eq!(gf_inv(0), 0);
+
+        // Assert: Every non-zero element has an inverse, and multiplication distributes over addition
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "{:#04x} has no inverse", a);
+            for b in 0..=255u8 {
+                assert_eq!(gf_mul(a, b), gf_mul(b, a));
+                assert_eq!(gf_mul(a, b ^ 0x1d), gf_mul(a, b) ^ gf_mul(a, 0x1d));
+            }
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_of_shares_rebuild_the_secret() {
+        // Arrange
+        let secret: Vec<u8> = (0..32).map(|i| i * 7).collect();
+
+        // Act
+        let shares = split_secret(&secret, 5, 3).unwrap();
+
+        // Assert: Every choice of three shares works; two do not
+        assert_eq!(shares.len(), 5);
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let chosen = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(combine_shares(&chosen).unwrap(), secret);
+                }
+            }
+        }
+        assert_eq!(combine_shares(&shares).unwrap(), secret);
+        assert_eq!(combine_shares(&shares[..2]), Err("3 different shares are needed, only 2 given".to_string()));
+        let repeated = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine_shares(&repeated).is_err());
+        assert!(split_secret(&secret, 2, 3).is_err());
+        assert!(split_secret(&secret, 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_share_encodings_round_trip_and_catch_mistakes() {
+        // Arrange
+        let shares = split_secret(&[42; 32], 3, 2).unwrap();
+        let other_split = split_secret(&[42; 32], 3, 2).unwrap();
+        let words = shares[0].encode(ShareFormat::Words);
+        let text = shares[1].encode(ShareFormat::Text);
+
+        // Act
+        let from_words = Share::decode(&words.to_uppercase()).unwrap();
+        let from_text = Share::decode(&text.to_lowercase().replace('-', " ")).unwrap();
+        let mut typo: Vec<&str> = words.split(' ').collect();
+        typo[10] = if typo[10] == "acid" { "acorn" } else { "acid" };
+
+        // Assert
+        assert_eq!(words.split(' ').count(), 32 + SHARE_OVERHEAD);
+        assert!(text.starts_with("PWVS-") && text.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-'));
+        assert_eq!((from_words, from_text.clone()), (shares[0].clone(), shares[1].clone()));
+        assert_eq!(combine_shares(&[from_text, shares[2].clone()]).unwrap(), vec![42; 32]);
+        assert_eq!(Share::decode(&typo.join(" ")).err(), Some("The share's checksum does not match; check it for typos".to_string()));
+        assert_eq!(Share::decode("acid acorn zzz").err(), Some("Unknown word 'zzz' in the share".to_string()));
+        assert_eq!(combine_shares(&[shares[0].clone(), other_split[1].clone()]), Err("The shares come from different splits".to_string()));
+        assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,193 @@
+use sodiumoxide::crypto::secretbox::Key;
+use crate::database::{get_entry, Password};
+use crate::encryption::{decrypt_metadata, decrypt_password, title_index, EntryMetadata};
+use crate::items::decode_fields;
+use crate::labels::{normalize_folder, EntryLabels};
+
+/// Fields every entry has besides those of its type.
+const METADATA_FIELDS: [&str; 2] = ["username", "url"];
+
+/// A value read from the vault on behalf of another program.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedSecret {
+    /// Title of the entry the value was read from.
+    pub title: String,
+    pub value: String,
+}
+
+/// An `exec --env NAME=reference` argument.
+#[derive(Debug, PartialEq)]
+pub struct EnvMapping {
+    pub name: String,
+    pub reference: String,
+    pub field: Option<String>,
+}
+
+/// Parses `NAME=reference` or `NAME=reference#field`.
+pub fn parse_env_mapping(argument: &str) -> Result<EnvMapping, String> {
+    let (name, reference) = argument
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid --env '{}': expected NAME=title", argument))?;
+    let valid_name = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("Invalid --env '{}': '{}' is not a variable name", argument, name));
+    }
+
+    // A suffix like `#username` picks a field; other uses of `#` are part of the title
+    let (reference, field) = match reference.rsplit_once('#') {
+        Some((entry, field)) if !field.is_empty() && field.chars().all(|c| c.is_ascii_lowercase() || c == '_') => {
+            (entry, Some(field.to_string()))
+        }
+        _ => (reference, None),
+    };
+    if reference.trim().is_empty() {
+        return Err(format!("Invalid --env '{}': no entry given", argument));
+    }
+    Ok(EnvMapping {
+        name: name.to_string(),
+        reference: reference.to_string(),
+        field,
+    })
+}
+
+/// Finds the entry a reference names: its title, or `folder/title` for an
+/// entry filed in that folder.
+fn find_entry(reference: &str, key: &Key) -> Result<Option<(Password, EntryMetadata)>, String> {
+    let by_title = |title: &str| -> Result<Option<(Password, EntryMetadata)>, String> {
+        let index = title_index(title, key);
+        match get_entry(&index) {
+            Some(entry) => {
+                let metadata = decrypt_metadata(&entry.encrypted_metadata, &index, key)?;
+                Ok(Some((entry, metadata)))
+            }
+            None => Ok(None),
+        }
+    };
+
+    if let Some(found) = by_title(reference)? {
+        return Ok(Some(found));
+    }
+    let (folder, title) = match reference.rsplit_once('/') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let folder = normalize_folder(folder)?;
+    match by_title(title)? {
+        Some(found) if EntryLabels::load(key)?.folder(&found.0.title_index) == folder.as_deref() => Ok(Some(found)),
+        _ => Ok(None),
+    }
+}
+
+/// The entry and field a reference names, found without decrypting the
+/// entry's password or fields.
+pub struct SecretLocation {
+    entry: Password,
+    metadata: EntryMetadata,
+    field: String,
+}
+
+/// Finds the entry `reference` names and checks that its type has `field`.
+/// Without a field, logins give their password and other items their first
+/// field, such as the `token` of an API token. `username` and `url` can be
+/// read from any entry.
+pub fn locate_secret(reference: &str, field: Option<&str>, key: &Key) -> Result<SecretLocation, String> {
+    let (entry, metadata) = find_entry(reference, key)?.ok_or_else(|| format!("No password found for '{}'", reference))?;
+    let item_type = metadata.item_type;
+    let specs = item_type.fields();
+    let field = field.unwrap_or(specs[0].name);
+
+    if !METADATA_FIELDS.contains(&field) && !specs.iter().any(|spec| spec.name == field) {
+        let names: Vec<&str> = specs.iter().map(|spec| spec.name).chain(METADATA_FIELDS).collect();
+        return Err(format!("{} items have no field '{}'; use {}", item_type.name(), field, names.join(", ")));
+    }
+    Ok(SecretLocation {
+        entry,
+        metadata,
+        field: field.to_string(),
+    })
+}
+
+/// Decrypts the value at `location`.
+pub fn read_secret(location: SecretLocation, key: &Key) -> Result<ResolvedSecret, String> {
+    let SecretLocation { entry, metadata, field } = location;
+    let value = match field.as_str() {
+        "username" => metadata.username,
+        "url" => metadata.url,
+        _ => {
+            let secret = decrypt_password(&entry.encrypted_password, &entry.title_index, None, key)?;
+            if metadata.item_type.is_login() {
+                Some(secret)
+            } else {
+                decode_fields(&secret)?.remove(&field)
+            }
+        }
+    };
+
+    match value {
+        Some(value) => Ok(ResolvedSecret {
+            title: metadata.title,
+            value,
+        }),
+        None => Err(format!("'{}' has no {}", metadata.title, field)),
+    }
+}
+
+/// Finds and decrypts one field of the entry `reference` names.
+pub fn resolve_secret(reference: &str, field: Option<&str>, key: &Key) -> Result<ResolvedSecret, String> {
+    read_secret(locate_secret(reference, field, key)?, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::{encode_fields, ItemFields, ItemType};
+    use crate::testing::{add_test_item, test_vault};
+    use sodiumoxide::crypto::secretbox;
+
+    #[test]
+    fn test_parse_env_mapping() {
+        // Act & Assert
+        let mapping = parse_env_mapping("DB_PASS=prod/db").unwrap();
+        assert_eq!((mapping.name.as_str(), mapping.reference.as_str(), mapping.field), ("DB_PASS", "prod/db", None));
+        let mapping = parse_env_mapping("DB_USER=prod/db#username").unwrap();
+        assert_eq!((mapping.reference.as_str(), mapping.field.as_deref()), ("prod/db", Some("username")));
+        assert_eq!(parse_env_mapping("CHANNEL=#General").unwrap().reference, "#General");
+        assert!(parse_env_mapping("1PASS=db").is_err());
+        assert!(parse_env_mapping("DB_PASS").is_err());
+        assert!(parse_env_mapping("DB_PASS=").is_err());
+    }
+
+    #[test]
+    fn test_resolve_secret_by_title_and_folder() {
+        // Arrange
+        let _vault = test_vault();
+        let key = secretbox::gen_key();
+        let login = EntryMetadata {
+            title: "db".to_string(),
+            username: Some("deploy".to_string()),
+            ..Default::default()
+        };
+        add_test_item(&login, "hunter2", Some("prod"), &key);
+        let api_token = EntryMetadata {
+            title: "svc key".to_string(),
+            item_type: ItemType::ApiToken,
+            ..Default::default()
+        };
+        let token = ItemFields::from([("token".to_string(), "abc123".to_string())]);
+        add_test_item(&api_token, &encode_fields(&token), None, &key);
+
+        // Act
+        let by_title = resolve_secret("db", None, &key).unwrap();
+        let by_folder = resolve_secret("prod/db", Some("username"), &key).unwrap();
+        let token = resolve_secret("svc key", None, &key).unwrap();
+
+        // Assert
+        assert_eq!((by_title.title.as_str(), by_title.value.as_str()), ("db", "hunter2"));
+        assert_eq!(by_folder.value, "deploy");
+        assert_eq!(token.value, "abc123");
+        assert_eq!(resolve_secret("staging/db", None, &key), Err("No password found for 'staging/db'".to_string()));
+        assert_eq!(resolve_secret("svc key", Some("expires"), &key), Err("'svc key' has no expires".to_string()));
+        assert!(resolve_secret("db", Some("token"), &key).unwrap_err().contains("login items have no field 'token'"));
+    }
+}
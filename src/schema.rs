@@ -2,7 +2,204 @@
 table! {
     passwords (id) {
         id -> Integer,
-        title -> Text,
+        title_index -> Text,
+        encrypted_metadata -> Text,
         encrypted_password -> Text,
     }
 }
+
+// Defines the authenticated vault header, a single row describing the vault contents.
+table! {
+    vault_header (id) {
+        id -> Integer,
+        vault_id -> Text,
+        generation -> BigInt,
+        entry_count -> BigInt,
+        manifest -> Text,
+        mac -> Text,
+    }
+}
+
+// Folders entries can be filed under. The path is encrypted and looked up through its blind index.
+table! {
+    folders (id) {
+        id -> Integer,
+        path_index -> Text,
+        encrypted_path -> Text,
+    }
+}
+
+// Tags entries can carry. The name is encrypted and looked up through its blind index.
+table! {
+    tags (id) {
+        id -> Integer,
+        tag_index -> Text,
+        encrypted_name -> Text,
+    }
+}
+
+// The folder of each entry, by the blind index of the entry's title.
+table! {
+    entry_folders (title_index) {
+        title_index -> Text,
+        folder_id -> Integer,
+    }
+}
+
+// The tags of each entry, by the blind index of the entry's title.
+table! {
+    entry_tags (title_index, tag_id) {
+        title_index -> Text,
+        tag_id -> Integer,
+    }
+}
+
+// Rotation policies set on folders, by the blind index of the folder path.
+table! {
+    folder_policies (path_index) {
+        path_index -> Text,
+        encrypted_policy -> Text,
+    }
+}
+
+// The append-only audit log. Each record is chained to the previous one by its hash.
+table! {
+    audit_log (seq) {
+        seq -> BigInt,
+        timestamp -> BigInt,
+        action -> Text,
+        title_index -> Text,
+        encrypted_title -> Text,
+        device_key -> Text,
+        prev_hash -> Text,
+        hash -> Text,
+        signature -> Text,
+    }
+}
+
+// Files attached to entries. The name, size and digest are encrypted; the
+// contents live in `attachment_chunks`, encrypted under the wrapped key.
+table! {
+    attachments (id) {
+        id -> Integer,
+        title_index -> Text,
+        name_index -> Text,
+        encrypted_info -> Text,
+        wrapped_key -> Text,
+        stream_header -> Text,
+    }
+}
+
+// The encrypted chunks of each attachment, in order.
+table! {
+    attachment_chunks (attachment_id, seq) {
+        attachment_id -> Integer,
+        seq -> Integer,
+        data -> Binary,
+    }
+}
+
+// This vault's keypair for sharing. The secret key is wrapped by the vault key.
+table! {
+    identity (id) {
+        id -> Integer,
+        public_key -> Text,
+        encrypted_secret_key -> Text,
+    }
+}
+
+// Copies of the entries shared from this vault, encrypted under a key of their
+// own that is wrapped by the vault key.
+table! {
+    shared_entries (title_index) {
+        title_index -> Text,
+        wrapped_key -> Text,
+        encrypted_entry -> Text,
+    }
+}
+
+// The key of a shared entry, boxed for each recipient's public key.
+table! {
+    share_grants (title_index, recipient_key) {
+        title_index -> Text,
+        recipient_key -> Text,
+        sealed_key -> Text,
+    }
+}
+
+// The team a vault belongs to: its founding owner and a commitment to the
+// current collection key, signed by an owner or admin.
+table! {
+    team (id) {
+        id -> Integer,
+        team_id -> Text,
+        founder -> Text,
+        epoch -> BigInt,
+        key_check -> Text,
+        encrypted_history -> Text,
+        signed_by -> Text,
+        signature -> Text,
+    }
+}
+
+// Team members, each signed by whoever granted the role, with the collection
+// key sealed for their key.
+table! {
+    team_members (member_key) {
+        member_key -> Text,
+        role -> Text,
+        granted_by -> Text,
+        signature -> Text,
+        wrapped_key -> Text,
+    }
+}
+
+// Emergency contacts, each signed by the member who added them, with the
+// vault key sealed for their key.
+table! {
+    emergency_contacts (contact_key) {
+        contact_key -> Text,
+        wait_days -> BigInt,
+        added_at -> BigInt,
+        sealed_key -> Text,
+        granted_by -> Text,
+        signature -> Text,
+    }
+}
+
+// Signed emergency access requests from contacts and denials from owners.
+table! {
+    emergency_records (id) {
+        id -> Integer,
+        contact_key -> Text,
+        action -> Text,
+        created_at -> BigInt,
+        signature -> Text,
+    }
+}
+
+// Tokens for the HTTP API. Only a hash of each token is kept.
+table! {
+    api_tokens (id) {
+        id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        public_key -> Text,
+        read_only -> Bool,
+        scope -> Nullable<Text>,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+    }
+}
+
+// The keys of shared copies sealed for the scoped API tokens whose scope
+// covers the entry.
+table! {
+    token_grants (token_id, title_index) {
+        token_id -> Integer,
+        title_index -> Text,
+        sealed_key -> Text,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(api_tokens, shared_entries, token_grants);
@@ -0,0 +1,214 @@
+use crate::encryption::EntryMetadata;
+
+/// Score of a title that matches the query exactly; nothing scores higher.
+const EXACT_SCORE: i32 = 1000;
+/// Lowest score `suggest` offers as a "did you mean" candidate.
+const SUGGESTION_SCORE: i32 = 400;
+
+/// A decrypted entry to search through.
+pub struct Candidate {
+    pub title_index: String,
+    pub metadata: EntryMetadata,
+    pub tags: Vec<String>,
+}
+
+/// An entry matching a search, with the field that matched best.
+#[derive(Debug, PartialEq)]
+pub struct SearchHit {
+    pub title: String,
+    pub title_index: String,
+    /// `title`, `username`, `url` or `tag`.
+    pub field: &'static str,
+    pub matched: String,
+    pub score: i32,
+}
+
+/// Ranks entries by how well their title, username, URL or tags match
+/// `query`. Titles weigh the most; the best field of each entry counts.
+pub fn search(query: &str, candidates: &[Candidate]) -> Vec<SearchHit> {
+    let mut hits: Vec<SearchHit> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let metadata = &candidate.metadata;
+            let fields = std::iter::once(("title", &metadata.title, 0))
+                .chain(metadata.username.iter().map(|username| ("username", username, 30)))
+                .chain(metadata.url.iter().chain(metadata.urls.iter().map(|rule| &rule.url)).map(|url| ("url", url, 40)))
+                .chain(candidate.tags.iter().map(|tag| ("tag", tag, 20)));
+
+            fields
+                .filter_map(|(field, value, penalty)| {
+                    fuzzy_score(query, value).map(|score| (field, value, score - penalty))
+                })
+                .max_by_key(|(_, _, score)| *score)
+                .map(|(field, value, score)| SearchHit {
+                    title: metadata.title.clone(),
+                    title_index: candidate.title_index.clone(),
+                    field,
+                    matched: value.clone(),
+                    score,
+                })
+        })
+        .collect();
+
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.title.cmp(&b.title)));
+    hits
+}
+
+/// Titles close enough to `title` to be offered as "did you mean", best first.
+pub fn suggest(title: &str, titles: &[String]) -> Vec<String> {
+    let mut scored: Vec<(i32, &String)> = titles
+        .iter()
+        .filter_map(|candidate| fuzzy_score(title, candidate).map(|score| (score, candidate)))
+        .filter(|(score, _)| *score >= SUGGESTION_SCORE)
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().take(3).map(|(_, candidate)| candidate.clone()).collect()
+}
+
+/// Scores how well `candidate` matches `query`, ignoring case, or `None` if it
+/// does not match at all. From best to worst: exact match, prefix, substring,
+/// all query characters in order, and finally a few typos away from the
+/// candidate or one of its words.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let query: Vec<char> = query.trim().to_lowercase().chars().collect();
+    let candidate: Vec<char> = candidate.trim().to_lowercase().chars().collect();
+    if query.is_empty() || candidate.is_empty() {
+        return None;
+    }
+
+    if query == candidate {
+        return Some(EXACT_SCORE);
+    }
+    if candidate.starts_with(&query) {
+        return Some(900 - (candidate.len() - query.len()).min(99) as i32);
+    }
+    if let Some(position) = candidate.windows(query.len()).position(|window| window == query.as_slice()) {
+        return Some(800 - position.min(99) as i32);
+    }
+    if let Some(score) = subsequence_score(&query, &candidate) {
+        return Some(score);
+    }
+
+    let allowed_typos = match query.len() {
+        0..=4 => 1,
+        5..=8 => 2,
+        _ => 3,
+    };
+    let distance = std::iter::once(candidate.as_slice())
+        .chain(candidate.split(|c| !c.is_alphanumeric()).filter(|word| !word.is_empty()))
+        .map(|word| edit_distance(&query, word))
+        .min()
+        .unwrap_or(usize::MAX);
+    if distance <= allowed_typos {
+        Some(500 - 50 * distance as i32)
+    } else {
+        None
+    }
+}
+
+/// Matches the query characters in order anywhere in the candidate. Runs of
+/// consecutive characters score higher than scattered ones.
+fn subsequence_score(query: &[char], candidate: &[char]) -> Option<i32> {
+    let mut score = 600;
+    let mut previous: Option<usize> = None;
+    let mut next = 0;
+    for c in query {
+        let offset = candidate[next..].iter().position(|candidate_char| candidate_char == c)?;
+        let position = next + offset;
+        match previous {
+            Some(previous) if position == previous + 1 => score += 10,
+            _ => score -= offset.min(20) as i32,
+        }
+        previous = Some(position);
+        next = position + 1;
+    }
+    // Keep every subsequence below a substring match and above a typo
+    Some(score.clamp(501, 700))
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of
+/// two neighbouring characters.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(title: &str, username: Option<&str>, url: Option<&str>, tags: &[&str]) -> Candidate {
+        Candidate {
+            title_index: format!("index_{}", title),
+            metadata: EntryMetadata {
+                title: title.to_string(),
+                username: username.map(str::to_string),
+                url: url.map(str::to_string),
+                ..Default::default()
+            },
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_score_ranks_match_kinds() {
+        // Act
+        let exact = fuzzy_score("github", "GitHub").unwrap();
+        let prefix = fuzzy_score("git", "github").unwrap();
+        let substring = fuzzy_score("hub", "github").unwrap();
+        let subsequence = fuzzy_score("gthb", "github").unwrap();
+        let typo = fuzzy_score("gihtub", "github").unwrap();
+
+        // Assert
+        assert!(exact > prefix && prefix > substring && substring > subsequence && subsequence > typo);
+        assert_eq!(fuzzy_score("gitlab", "bank"), None);
+        assert!(fuzzy_score("gihub", "https://github.com").is_some(), "Typos are matched against words");
+    }
+
+    #[test]
+    fn test_search_covers_all_fields() {
+        // Arrange
+        let candidates = [
+            candidate("work email", Some("alice"), Some("https://mail.example.com"), &[]),
+            candidate("postgres", Some("dbadmin"), None, &["production"]),
+            candidate("alice", None, None, &[]),
+        ];
+
+        // Act
+        let by_username = search("alice", &candidates);
+        let by_tag = search("prod", &candidates);
+
+        // Assert: A title match outranks an equally good username match
+        assert_eq!(by_username.len(), 2);
+        assert_eq!(by_username[0].title, "alice");
+        assert_eq!((by_username[1].field, by_username[1].matched.as_str()), ("username", "alice"));
+        assert_eq!(by_tag[0].title, "postgres");
+        assert_eq!(by_tag[0].field, "tag");
+    }
+
+    #[test]
+    fn test_suggest_titles() {
+        // Arrange
+        let titles = vec!["google".to_string(), "gmail".to_string(), "bank".to_string()];
+
+        // Act & Assert
+        assert_eq!(suggest("gogle", &titles), vec!["google".to_string()]);
+        assert!(suggest("unrelated", &titles).is_empty());
+    }
+}
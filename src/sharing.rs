@@ -0,0 +1,548 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as aead;
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::secretbox::Key;
+use crate::container::is_container;
+use crate::database::{establish_connection, write_transaction, Password};
+use crate::encryption::{
+    decrypt_metadata, decrypt_password, open_shared_entry, seal_shared_entry, unwrap_sharing_key, wrap_sharing_key,
+    EntryMetadata,
+};
+use crate::schema::passwords::dsl as passwords_dsl;
+use crate::schema::{identity, share_grants, shared_entries, token_grants};
+use crate::tokens::{has_token_grants, reseal_token_grants};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The keypair others use to share entries with this vault, and this vault
+/// uses to share entries with them.
+pub struct Identity {
+    pub public_key: box_::PublicKey,
+    secret_key: box_::SecretKey,
+}
+
+/// The copy of an entry a recipient can read: its metadata and its secret,
+/// the password of a login or the JSON fields of any other item.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SharedItem {
+    pub metadata: EntryMetadata,
+    pub secret: String,
+}
+
+/// An entry shared from this vault and the public keys it is shared with.
+#[derive(Debug, PartialEq)]
+pub struct OutgoingShare {
+    pub title: String,
+    pub recipients: Vec<String>,
+}
+
+/// The entries another vault shares with this one.
+#[derive(Debug)]
+pub struct ReceivedShares {
+    /// The public key of the vault that shared them.
+    pub sender: String,
+    pub items: Vec<SharedItem>,
+}
+
+/// Encodes a public key the way `identity` prints it and `share --with` takes it.
+pub fn encode_public_key(public_key: &box_::PublicKey) -> String {
+    base64::encode(public_key.as_ref())
+}
+
+pub fn parse_public_key(encoded: &str) -> Result<box_::PublicKey, String> {
+    base64::decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| box_::PublicKey::from_slice(&bytes))
+        .ok_or_else(|| format!("Invalid public key '{}': expected the 44-character key printed by `identity`", encoded))
+}
+
+/// Loads the identity of the vault, creating it on first use.
+pub fn load_or_create_identity(key: &Key) -> Result<Identity, String> {
+    let connection = establish_connection();
+    if let Some(identity) = load_identity(&connection, key)? {
+        return Ok(identity);
+    }
+
+    let (public_key, secret_key) = box_::gen_keypair();
+    let encoded = encode_public_key(&public_key);
+    write_transaction(&connection, || {
+        diesel::insert_into(identity::table)
+            .values((
+                identity::id.eq(1),
+                identity::public_key.eq(&encoded),
+                identity::encrypted_secret_key.eq(wrap_sharing_key(secret_key.as_ref(), &identity_binding(&encoded), key)),
+            ))
+            .execute(&connection)
+    })
+    .map_err(|e| format!("Failed to create the sharing identity: {}", e))?;
+    Ok(Identity { public_key, secret_key })
+}
+
+fn load_identity(connection: &SqliteConnection, key: &Key) -> Result<Option<Identity>, String> {
+    let row = identity::table
+        .select((identity::public_key, identity::encrypted_secret_key))
+        .first::<(String, String)>(connection)
+        .optional()
+        .map_err(|e| format!("Failed to read the sharing identity: {}", e))?;
+    let (encoded, encrypted_secret_key) = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let public_key = parse_public_key(&encoded)?;
+    let secret_key = unwrap_sharing_key(&encrypted_secret_key, &identity_binding(&encoded), key)
+        .ok()
+        .and_then(|bytes| box_::SecretKey::from_slice(&bytes))
+        .ok_or("Failed to unwrap the sharing identity")?;
+    Ok(Some(Identity { public_key, secret_key }))
+}
+
+fn identity_binding(public_key: &str) -> String {
+    format!("identity:{}", public_key)
+}
+
+/// Shares an entry with the holder of `recipient`. The entry is copied under
+/// a key of its own, and that key is boxed for the recipient. Sharing an entry
+/// again refreshes the copy. Returns false if it was already shared with them.
+pub fn share_entry(title_index: &str, recipient: &box_::PublicKey, key: &Key) -> Result<bool, String> {
+    let identity = load_or_create_identity(key)?;
+    if *recipient == identity.public_key {
+        return Err("That is this vault's own public key".to_string());
+    }
+    let recipient_key = encode_public_key(recipient);
+
+    let connection = establish_connection();
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+    write_transaction(&connection, || {
+        let entry = load_entry(&connection, title_index)?;
+        let item = shared_item(&entry, key).map_err(fail)?;
+        let entry_key = match load_entry_key(&connection, title_index, key).map_err(fail)? {
+            Some(entry_key) => entry_key,
+            None => aead::gen_key(),
+        };
+        store_shared_entry(&connection, title_index, &item, &entry_key, key)?;
+
+        let granted = share_grants::table
+            .filter(share_grants::title_index.eq(title_index))
+            .filter(share_grants::recipient_key.eq(&recipient_key))
+            .count()
+            .get_result::<i64>(&connection)?;
+        if granted > 0 {
+            return Ok(false);
+        }
+        diesel::insert_into(share_grants::table)
+            .values((
+                share_grants::title_index.eq(title_index),
+                share_grants::recipient_key.eq(&recipient_key),
+                share_grants::sealed_key.eq(seal_for(&entry_key, recipient, &identity)),
+            ))
+            .execute(&connection)?;
+        Ok(true)
+    })
+    .map_err(|e| transaction_error(e, "share"))
+}
+
+/// Stops sharing an entry with the holder of `recipient`. The entry is re-keyed
+/// so later changes stay out of their reach, and the new key is boxed for the
+/// remaining recipients. Returns how many remain.
+pub fn revoke_share(title_index: &str, recipient: &box_::PublicKey, key: &Key) -> Result<usize, String> {
+    let recipient_key = encode_public_key(recipient);
+    let connection = establish_connection();
+    if load_identity(&connection, key)?.is_none() {
+        return Err("This vault has not shared anything".to_string());
+    }
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+
+    write_transaction(&connection, || {
+        let revoked = diesel::delete(
+            share_grants::table
+                .filter(share_grants::title_index.eq(title_index))
+                .filter(share_grants::recipient_key.eq(&recipient_key)),
+        )
+        .execute(&connection)?;
+        if revoked == 0 {
+            return Err(fail(format!("The entry is not shared with {}", recipient_key)));
+        }
+        rekey_shared_entry(&connection, title_index, key)?;
+        share_grants::table
+            .filter(share_grants::title_index.eq(title_index))
+            .count()
+            .get_result::<i64>(&connection)
+            .map(|remaining| remaining as usize)
+    })
+    .map_err(|e| transaction_error(e, "revoke share"))
+}
+
+/// Gives an entry a shared copy if it has none yet, inside the caller's
+/// transaction, and returns the key the copy is sealed under.
+pub fn ensure_shared_copy(connection: &SqliteConnection, title_index: &str, key: &Key) -> Result<aead::Key, diesel::result::Error> {
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+    if let Some(entry_key) = load_entry_key(connection, title_index, key).map_err(fail)? {
+        return Ok(entry_key);
+    }
+    let entry = load_entry(connection, title_index)?;
+    let item = shared_item(&entry, key).map_err(fail)?;
+    let entry_key = aead::gen_key();
+    store_shared_entry(connection, title_index, &item, &entry_key, key)?;
+    Ok(entry_key)
+}
+
+/// Re-keys the shared copy of an entry after a recipient or API token lost
+/// access, inside the caller's transaction, and seals the new key for those
+/// left. The copy is removed once nobody is left.
+pub fn rekey_shared_entry(connection: &SqliteConnection, title_index: &str, key: &Key) -> Result<(), diesel::result::Error> {
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+    let remaining = share_grants::table
+        .filter(share_grants::title_index.eq(title_index))
+        .select(share_grants::recipient_key)
+        .load::<String>(connection)?;
+    if remaining.is_empty() && !has_token_grants(connection, title_index)? {
+        diesel::delete(shared_entries::table.filter(shared_entries::title_index.eq(title_index))).execute(connection)?;
+        return Ok(());
+    }
+
+    let entry = load_entry(connection, title_index)?;
+    let item = shared_item(&entry, key).map_err(fail)?;
+    let entry_key = aead::gen_key();
+    store_shared_entry(connection, title_index, &item, &entry_key, key)?;
+    if !remaining.is_empty() {
+        let identity = load_identity(connection, key).map_err(fail)?.ok_or_else(|| fail("The sharing identity is missing".to_string()))?;
+        for other in &remaining {
+            let other_key = parse_public_key(other).map_err(fail)?;
+            diesel::update(
+                share_grants::table
+                    .filter(share_grants::title_index.eq(title_index))
+                    .filter(share_grants::recipient_key.eq(other)),
+            )
+            .set(share_grants::sealed_key.eq(seal_for(&entry_key, &other_key, &identity)))
+            .execute(connection)?;
+        }
+    }
+    reseal_token_grants(connection, title_index, &entry_key)
+}
+
+/// Brings the shared copy of an entry up to date after it changed. Entries
+/// that are not shared are left alone.
+pub fn refresh_shared_entry(title_index: &str, key: &Key) -> Result<(), String> {
+    let connection = establish_connection();
+    let entry_key = match load_entry_key(&connection, title_index, key)? {
+        Some(entry_key) => entry_key,
+        None => return Ok(()),
+    };
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+
+    write_transaction(&connection, || {
+        let entry = load_entry(&connection, title_index)?;
+        let item = shared_item(&entry, key).map_err(fail)?;
+        store_shared_entry(&connection, title_index, &item, &entry_key, key)
+    })
+    .map_err(|e| transaction_error(e, "update shared copy"))
+}
+
+/// The entries shared from this vault, sorted by title.
+pub fn list_shares(key: &Key) -> Result<Vec<OutgoingShare>, String> {
+    let connection = establish_connection();
+    let grants = share_grants::table
+        .order((share_grants::title_index.asc(), share_grants::recipient_key.asc()))
+        .select((share_grants::title_index, share_grants::recipient_key))
+        .load::<(String, String)>(&connection)
+        .map_err(|e| format!("Failed to list shares: {}", e))?;
+
+    let mut shares: Vec<(String, OutgoingShare)> = Vec::new();
+    for (title_index, recipient_key) in grants {
+        match shares.last_mut() {
+            Some((index, share)) if *index == title_index => share.recipients.push(recipient_key),
+            _ => {
+                let entry = load_entry(&connection, &title_index).map_err(|e| format!("Failed to list shares: {}", e))?;
+                let metadata = decrypt_metadata(&entry.encrypted_metadata, &title_index, key)?;
+                shares.push((
+                    title_index,
+                    OutgoingShare {
+                        title: metadata.title,
+                        recipients: vec![recipient_key],
+                    },
+                ));
+            }
+        }
+    }
+
+    let mut shares: Vec<OutgoingShare> = shares.into_iter().map(|(_, share)| share).collect();
+    shares.sort_by(|a, b| a.title.cmp(&b.title));
+    Ok(shares)
+}
+
+/// Reads the entries the vault file at `path` shares with this vault. The
+/// other vault is only read, and must be a plain vault file.
+pub fn received_shares(path: &Path, key: &Key) -> Result<ReceivedShares, String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    if is_container(path) {
+        return Err(format!("{} is an encrypted container; convert it to a plain vault to read its shares", path.display()));
+    }
+    let identity = load_identity(&establish_connection(), key)?
+        .ok_or("This vault has no identity yet; run `identity` and give its public key to the sender")?;
+    let my_key = encode_public_key(&identity.public_key);
+
+    let other = SqliteConnection::establish(&format!("file:{}?mode=ro", path.display()))
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let unreadable = |e: diesel::result::Error| format!("{} does not share any entries: {}", path.display(), e);
+    let sender = identity::table
+        .select(identity::public_key)
+        .first::<String>(&other)
+        .optional()
+        .map_err(unreadable)?
+        .ok_or_else(|| format!("{} does not share any entries", path.display()))?;
+    let sender_key = parse_public_key(&sender)?;
+    let grants = share_grants::table
+        .filter(share_grants::recipient_key.eq(&my_key))
+        .select((share_grants::title_index, share_grants::sealed_key))
+        .load::<(String, String)>(&other)
+        .map_err(unreadable)?;
+
+    let mut items = Vec::new();
+    for (title_index, sealed_key) in &grants {
+        let encrypted_entry = shared_entries::table
+            .filter(shared_entries::title_index.eq(title_index))
+            .select(shared_entries::encrypted_entry)
+            .first::<String>(&other)
+            .map_err(unreadable)?;
+        let entry_key = open_from(sealed_key, &sender_key, &identity)?;
+        let json = open_shared_entry(&encrypted_entry, title_index, &entry_key)?;
+        items.push(serde_json::from_slice::<SharedItem>(&json).map_err(|e| format!("Failed to parse shared entry: {}", e))?);
+    }
+    items.sort_by(|a, b| a.metadata.title.cmp(&b.metadata.title));
+    Ok(ReceivedShares { sender, items })
+}
+
+/// Re-wraps the identity and the keys of shared entries from `old_key` to
+/// `new_key`, inside the caller's transaction, and moves shared entries to
+/// their new title indexes. Recipients and API tokens keep their grants.
+pub fn rekey_shares(
+    connection: &SqliteConnection,
+    title_indexes: &HashMap<String, String>,
+    old_key: &Key,
+    new_key: &Key,
+) -> Result<(), diesel::result::Error> {
+    let fail = |message: String| diesel::result::Error::DeserializationError(message.into());
+    // Grants follow their shared entry to its new index below; check them once it has.
+    diesel::sql_query("PRAGMA defer_foreign_keys = ON;").execute(connection)?;
+
+    if let Some((encoded, encrypted_secret_key)) = identity::table
+        .select((identity::public_key, identity::encrypted_secret_key))
+        .first::<(String, String)>(connection)
+        .optional()?
+    {
+        let binding = identity_binding(&encoded);
+        let secret_key = unwrap_sharing_key(&encrypted_secret_key, &binding, old_key).map_err(fail)?;
+        diesel::update(identity::table)
+            .set(identity::encrypted_secret_key.eq(wrap_sharing_key(&secret_key, &binding, new_key)))
+            .execute(connection)?;
+    }
+
+    for (old_index, wrapped_key, encrypted_entry) in shared_entries::table.load::<(String, String, String)>(connection)? {
+        let entry_key = unwrap_sharing_key(&wrapped_key, &old_index, old_key)
+            .ok()
+            .and_then(|bytes| aead::Key::from_slice(&bytes))
+            .ok_or_else(|| fail("Failed to unwrap the key of the shared entry".to_string()))?;
+        let json = open_shared_entry(&encrypted_entry, &old_index, &entry_key).map_err(fail)?;
+        let index = title_indexes.get(&old_index).unwrap_or(&old_index);
+        diesel::update(shared_entries::table.filter(shared_entries::title_index.eq(&old_index)))
+            .set((
+                shared_entries::title_index.eq(index),
+                shared_entries::wrapped_key.eq(wrap_sharing_key(entry_key.as_ref(), index, new_key)),
+                shared_entries::encrypted_entry.eq(seal_shared_entry(&json, index, &entry_key)),
+            ))
+            .execute(connection)?;
+        diesel::update(share_grants::table.filter(share_grants::title_index.eq(&old_index)))
+            .set(share_grants::title_index.eq(index))
+            .execute(connection)?;
+        diesel::update(token_grants::table.filter(token_grants::title_index.eq(&old_index)))
+            .set(token_grants::title_index.eq(index))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+fn load_entry(connection: &SqliteConnection, title_index: &str) -> QueryResult<Password> {
+    passwords_dsl::passwords
+        .filter(passwords_dsl::title_index.eq(title_index))
+        .first::<Password>(connection)
+}
+
+fn shared_item(entry: &Password, key: &Key) -> Result<SharedItem, String> {
+    Ok(SharedItem {
+        metadata: decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, key)?,
+        secret: decrypt_password(&entry.encrypted_password, &entry.title_index, None, key)?,
+    })
+}
+
+fn load_entry_key(connection: &SqliteConnection, title_index: &str, key: &Key) -> Result<Option<aead::Key>, String> {
+    let wrapped_key = shared_entries::table
+        .filter(shared_entries::title_index.eq(title_index))
+        .select(shared_entries::wrapped_key)
+        .first::<String>(connection)
+        .optional()
+        .map_err(|e| format!("Failed to read shared entry: {}", e))?;
+    wrapped_key
+        .map(|wrapped_key| {
+            unwrap_sharing_key(&wrapped_key, title_index, key)
+                .ok()
+                .and_then(|bytes| aead::Key::from_slice(&bytes))
+                .ok_or_else(|| "Failed to unwrap the key of the shared entry".to_string())
+        })
+        .transpose()
+}
+
+fn store_shared_entry(
+    connection: &SqliteConnection,
+    title_index: &str,
+    item: &SharedItem,
+    entry_key: &aead::Key,
+    key: &Key,
+) -> QueryResult<()> {
+    let json = serde_json::to_vec(item).expect("Failed to serialize shared entry");
+    diesel::replace_into(shared_entries::table)
+        .values((
+            shared_entries::title_index.eq(title_index),
+            shared_entries::wrapped_key.eq(wrap_sharing_key(entry_key.as_ref(), title_index, key)),
+            shared_entries::encrypted_entry.eq(seal_shared_entry(&json, title_index, entry_key)),
+        ))
+        .execute(connection)
+        .map(|_| ())
+}
+
+/// Boxes an entry key from this vault's identity to a recipient, as `nonce || ciphertext`.
+fn seal_for(entry_key: &aead::Key, recipient: &box_::PublicKey, identity: &Identity) -> String {
+    let nonce = box_::gen_nonce();
+    let mut sealed = nonce.as_ref().to_vec();
+    sealed.extend(box_::seal(entry_key.as_ref(), &nonce, recipient, &identity.secret_key));
+    base64::encode(sealed)
+}
+
+/// Opens an entry key boxed by `seal_for` in the sender's vault.
+fn open_from(sealed: &str, sender: &box_::PublicKey, identity: &Identity) -> Result<aead::Key, String> {
+    let sealed = base64::decode(sealed).map_err(|_| "Failed to decode shared key".to_string())?;
+    if sealed.len() < box_::NONCEBYTES {
+        return Err("Shared key is truncated".to_string());
+    }
+    let (nonce, ciphertext) = sealed.split_at(box_::NONCEBYTES);
+    let nonce = box_::Nonce::from_slice(nonce).ok_or("Failed to parse nonce")?;
+    box_::open(ciphertext, &nonce, sender, &identity.secret_key)
+        .ok()
+        .and_then(|bytes| aead::Key::from_slice(&bytes))
+        .ok_or_else(|| "Failed to open shared key: it was not sealed for this vault by the sender".to_string())
+}
+
+fn transaction_error(e: diesel::result::Error, action: &str) -> String {
+    match e {
+        diesel::result::Error::NotFound => "No password found for this title".to_string(),
+        diesel::result::Error::DeserializationError(message) => message.to_string(),
+        e => format!("Failed to {}: {}", action, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{create_table_if_not_exists, delete_password, select_vault, update_password};
+    use crate::encryption::{encrypt_password, title_index};
+    use crate::testing::{add_test_item, test_vault, TestVault};
+    use sodiumoxide::crypto::secretbox;
+
+    /// Setup: The test vault holding one entry, and a recipient vault beside
+    /// it with an identity of its own. Returns the recipient's public key and
+    /// vault path.
+    fn setup(key: &Key) -> (TestVault, box_::PublicKey, String) {
+        let vault = test_vault();
+        let recipient_vault = vault.dir.join("test_share_recipient.db").to_string_lossy().into_owned();
+        select_vault(&recipient_vault);
+        create_table_if_not_exists();
+        let recipient = load_or_create_identity(key).unwrap().public_key;
+
+        select_vault(&vault.path);
+        let metadata = EntryMetadata {
+            title: "github".to_string(),
+            username: Some("octocat".to_string()),
+            ..Default::default()
+        };
+        add_test_item(&metadata, "hunter2", None, key);
+        (vault, recipient, recipient_vault)
+    }
+
+    /// Reads the shares of the test vault as the recipient vault sees them.
+    fn receive(vault: &TestVault, recipient_vault: &str, key: &Key) -> Result<ReceivedShares, String> {
+        select_vault(recipient_vault);
+        let received = received_shares(Path::new(&vault.path), key);
+        select_vault(&vault.path);
+        received
+    }
+
+    #[test]
+    fn test_share_and_receive_between_vaults() {
+        // Arrange
+        let key = secretbox::gen_key();
+        let (vault, recipient, recipient_vault) = setup(&key);
+        let index = title_index("github", &key);
+
+        // Act
+        let shared = share_entry(&index, &recipient, &key).unwrap();
+        let again = share_entry(&index, &recipient, &key).unwrap();
+        update_password(&index, &encrypt_password("correct horse", &index, &key), None).unwrap();
+        refresh_shared_entry(&index, &key).unwrap();
+        let received = receive(&vault, &recipient_vault, &key).unwrap();
+
+        // Assert: The recipient sees the latest version
+        assert!(shared && !again);
+        assert_eq!(received.sender, encode_public_key(&load_or_create_identity(&key).unwrap().public_key));
+        assert_eq!(received.items.len(), 1);
+        assert_eq!(received.items[0].metadata.username.as_deref(), Some("octocat"));
+        assert_eq!(received.items[0].secret, "correct horse");
+        assert_eq!(
+            list_shares(&key).unwrap(),
+            vec![OutgoingShare {
+                title: "github".to_string(),
+                recipients: vec![encode_public_key(&recipient)],
+            }]
+        );
+        let own_key = load_or_create_identity(&key).unwrap().public_key;
+        assert_eq!(share_entry(&index, &own_key, &key), Err("That is this vault's own public key".to_string()));
+    }
+
+    #[test]
+    fn test_revoking_a_share_rekeys_the_entry() {
+        // Arrange: Share with the recipient vault and a third party
+        let key = secretbox::gen_key();
+        let (vault, recipient, recipient_vault) = setup(&key);
+        let (other, _) = box_::gen_keypair();
+        let index = title_index("github", &key);
+        share_entry(&index, &recipient, &key).unwrap();
+        share_entry(&index, &other, &key).unwrap();
+        let connection = establish_connection();
+        let key_before = load_entry_key(&connection, &index, &key).unwrap().unwrap();
+
+        // Act
+        let remaining = revoke_share(&index, &recipient, &key).unwrap();
+        let key_after = load_entry_key(&connection, &index, &key).unwrap().unwrap();
+        let received = receive(&vault, &recipient_vault, &key).unwrap();
+
+        // Assert: The revoked vault sees nothing and the old key opens nothing
+        assert_eq!(remaining, 1);
+        assert_ne!(key_before, key_after);
+        assert!(received.items.is_empty());
+        let encrypted_entry = shared_entries::table.select(shared_entries::encrypted_entry).first::<String>(&connection).unwrap();
+        assert!(open_shared_entry(&encrypted_entry, &index, &key_before).is_err());
+        assert!(open_shared_entry(&encrypted_entry, &index, &key_after).is_ok());
+        assert!(revoke_share(&index, &recipient, &key).unwrap_err().starts_with("The entry is not shared with"));
+
+        // Act & Assert: Revoking the last recipient, or deleting the entry, removes the shared copy
+        assert_eq!(revoke_share(&index, &other, &key), Ok(0));
+        assert!(load_entry_key(&connection, &index, &key).unwrap().is_none());
+        share_entry(&index, &other, &key).unwrap();
+        delete_password(&index).unwrap();
+        assert!(list_shares(&key).unwrap().is_empty());
+        assert!(load_entry_key(&connection, &index, &key).unwrap().is_none());
+    }
+}
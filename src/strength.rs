@@ -0,0 +1,278 @@
+/// Passwords and words attackers try first, most common first. The position
+/// in the list stands in for the number of guesses needed.
+const COMMON_WORDS: &[&str] = &[
+    "123456", "password", "123456789", "12345678", "12345", "qwerty", "1234567", "111111", "1234567890",
+    "123123", "abc123", "1234", "password1", "iloveyou", "1q2w3e4r", "000000", "qwerty123", "zaq12wsx",
+    "dragon", "sunshine", "princess", "letmein", "654321", "monkey", "1qaz2wsx", "123321", "qwertyuiop",
+    "superman", "asdfghjkl", "trustno1", "welcome", "football", "baseball", "master", "shadow", "michael",
+    "jennifer", "hunter", "admin", "root", "login", "starwars", "freedom", "whatever", "qazwsx", "ninja",
+    "mustang", "access", "batman", "secret", "charlie", "lovely", "flower", "hello", "changeme", "default",
+    "guest", "summer", "winter", "spring", "autumn", "love", "pass", "test", "welcome1", "computer",
+    "internet", "soccer", "hockey", "killer", "pepper", "cheese", "matrix", "orange", "banana", "apple",
+    "chocolate", "cookie", "purple", "ginger", "maggie", "jordan", "daniel", "thomas", "robert", "george",
+    "andrew", "joshua", "ashley", "jessica", "nicole", "amanda", "samsung", "google", "facebook", "linkedin",
+    "company", "office", "server", "database", "secure", "private", "monday", "friday", "january", "december",
+];
+
+/// Keyboard rows used to detect walks such as `asdf` or `poiu`.
+const KEYBOARD_ROWS: &[&str] = &["qwertyuiop", "asdfghjkl", "zxcvbnm", "1234567890"];
+
+/// Estimated guessability of a password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    /// 0 (trivial) to 4 (very hard to guess), on the same scale as zxcvbn.
+    pub score: u8,
+    /// Base-10 logarithm of the estimated number of guesses.
+    pub guesses_log10: f64,
+    /// Patterns that made the password easier to guess.
+    pub warnings: Vec<String>,
+}
+
+/// A part of the password that an attacker can guess as a whole.
+struct PatternMatch {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    warning: &'static str,
+}
+
+/// Estimates how many guesses a password needs, in the spirit of zxcvbn: the
+/// password is split into the cheapest sequence of known patterns (common
+/// words, sequences, repeats, keyboard walks, years) and brute-forced characters.
+pub fn estimate_strength(password: &str) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    if chars.is_empty() {
+        return Strength {
+            score: 0,
+            guesses_log10: 0.0,
+            warnings: vec!["is empty".to_string()],
+        };
+    }
+
+    let matches = find_patterns(&chars);
+
+    // best[i] is the cheapest way, in log10 guesses, to guess the first i characters
+    let mut best = vec![f64::INFINITY; chars.len() + 1];
+    let mut chosen: Vec<Option<usize>> = vec![None; chars.len() + 1];
+    best[0] = 0.0;
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + (cardinality(chars[end - 1]) as f64).log10();
+        for (i, pattern) in matches.iter().enumerate().filter(|(_, pattern)| pattern.end == end) {
+            let cost = best[pattern.start] + pattern.guesses.log10();
+            if cost < best[end] {
+                best[end] = cost;
+                chosen[end] = Some(i);
+            }
+        }
+    }
+
+    // Walk back through the chosen patterns to explain the estimate
+    let mut warnings = Vec::new();
+    let mut position = chars.len();
+    while position > 0 {
+        match chosen[position] {
+            Some(i) => {
+                let warning = matches[i].warning.to_string();
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+                position = matches[i].start;
+            }
+            None => position -= 1,
+        }
+    }
+    warnings.reverse();
+    if chars.len() < 8 {
+        warnings.push("is shorter than 8 characters".to_string());
+    }
+
+    let guesses_log10 = best[chars.len()];
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    Strength {
+        score,
+        guesses_log10,
+        warnings,
+    }
+}
+
+/// Size of the character class an attacker has to try for `c`.
+fn cardinality(c: char) -> u32 {
+    if c.is_ascii_lowercase() || c.is_ascii_uppercase() {
+        26
+    } else if c.is_ascii_digit() {
+        10
+    } else if c.is_ascii() {
+        33
+    } else {
+        100
+    }
+}
+
+/// Undoes common character substitutions such as `p4ssw0rd`.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+fn find_patterns(chars: &[char]) -> Vec<PatternMatch> {
+    let lower: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let unleeted: Vec<char> = lower.iter().map(|c| unleet(*c)).collect();
+    let mut matches = Vec::new();
+
+    // Common passwords and words, also with capitals and substitutions
+    for (rank, word) in COMMON_WORDS.iter().enumerate() {
+        let word: Vec<char> = word.chars().collect();
+        for start in 0..chars.len().saturating_sub(word.len() - 1) {
+            let end = start + word.len();
+            let plain = lower[start..end] == word[..];
+            if !plain && unleeted[start..end] != word[..] {
+                continue;
+            }
+            let uppercase = chars[start..end].iter().filter(|c| c.is_ascii_uppercase()).count();
+            let case_variations = match uppercase {
+                0 => 1.0,
+                _ if uppercase == word.len() || chars[start].is_ascii_uppercase() && uppercase == 1 => 2.0,
+                _ => 2f64.powi(uppercase as i32),
+            };
+            let substitutions = if plain { 1.0 } else { 2.0 };
+            matches.push(PatternMatch {
+                start,
+                end,
+                guesses: (rank + 1) as f64 * case_variations * substitutions,
+                warning: "contains a common password or word",
+            });
+        }
+    }
+
+    // Runs of repeated characters such as `aaaa`
+    let mut start = 0;
+    while start < chars.len() {
+        let end = (start..chars.len()).find(|&i| chars[i] != chars[start]).unwrap_or(chars.len());
+        if end - start >= 3 {
+            matches.push(PatternMatch {
+                start,
+                end,
+                guesses: (cardinality(chars[start]) * (end - start) as u32) as f64,
+                warning: "contains repeated characters",
+            });
+        }
+        start = end;
+    }
+
+    // Ascending or descending sequences such as `abcd` or `9876`
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let step = lower[start + 1] as i32 - lower[start] as i32;
+        if step.abs() != 1 || !lower[start].is_ascii_alphanumeric() {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 2;
+        while end < chars.len() && lower[end] as i32 - lower[end - 1] as i32 == step {
+            end += 1;
+        }
+        if end - start >= 3 {
+            let base = if lower[start].is_ascii_digit() { 10.0 } else { 26.0 };
+            let direction = if step < 0 { 2.0 } else { 1.0 };
+            matches.push(PatternMatch {
+                start,
+                end,
+                guesses: base * (end - start) as f64 * direction,
+                warning: "contains a sequence like abc or 123",
+            });
+        }
+        start = end - 1;
+    }
+
+    // Walks along a keyboard row such as `asdf`
+    let text: String = lower.iter().collect();
+    for row in KEYBOARD_ROWS {
+        let reversed: String = row.chars().rev().collect();
+        for walk in [row.to_string(), reversed] {
+            for length in 4..=walk.len() {
+                for window in 0..=walk.len() - length {
+                    let pattern = &walk[window..window + length];
+                    for (start, _) in text.match_indices(pattern) {
+                        let start = text[..start].chars().count();
+                        matches.push(PatternMatch {
+                            start,
+                            end: start + length,
+                            guesses: 47.0 * length as f64,
+                            warning: "contains a keyboard pattern",
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Recent years such as `1987` or `2024`
+    for start in 0..chars.len().saturating_sub(3) {
+        let digits: String = chars[start..start + 4].iter().collect();
+        if let Ok(year) = digits.parse::<u32>() {
+            if (1900..=2099).contains(&year) {
+                matches.push(PatternMatch {
+                    start,
+                    end: start + 4,
+                    guesses: 200.0,
+                    warning: "contains a year",
+                });
+            }
+        }
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_and_patterned_passwords_are_weak() {
+        // Act & Assert
+        for password in ["password", "P@ssw0rd", "qwerty123", "abcdef", "aaaaaaaa", "asdfghjk", "summer2024"] {
+            let strength = estimate_strength(password);
+            assert!(strength.score <= 2, "'{}' should be weak, got {:?}", password, strength);
+            assert!(!strength.warnings.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_random_passwords_are_strong() {
+        // Act
+        let strength = estimate_strength("x7#Kq9!vLm2$Rt8w");
+
+        // Assert
+        assert_eq!(strength.score, 4);
+        assert!(strength.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_warnings_explain_the_estimate() {
+        // Act
+        let strength = estimate_strength("Monkey6789");
+
+        // Assert
+        assert_eq!(
+            strength.warnings,
+            vec![
+                "contains a common password or word".to_string(),
+                "contains a sequence like abc or 123".to_string()
+            ]
+        );
+    }
+}
@@ -0,0 +1,638 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::secretbox::{self, Key};
+use sodiumoxide::crypto::sign::{self, ed25519};
+use crate::attachments::rekey_attachments;
+use crate::database::{establish_connection, rekey_entries, write_transaction};
+use crate::emergency::reseal_emergency_keys;
+use crate::encryption::{decrypt_label, encrypt_label};
+use crate::schema::{team, team_members};
+use crate::sharing::rekey_shares;
+use std::collections::HashMap;
+
+/// What a team member may do. Each role includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Reads entries.
+    Reader,
+    /// Also adds, changes and deletes entries.
+    Writer,
+    /// Also shares entries and manages writers and readers.
+    Admin,
+    /// Also manages admins and owners, and cleans or converts the vault.
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Admin, Role::Writer, Role::Reader];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    /// Whether a member with this role may grant, change or revoke `role`.
+    pub fn can_manage(self, role: Role) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Admin => role <= Role::Writer,
+            Role::Writer | Role::Reader => false,
+        }
+    }
+}
+
+/// A member of a team vault, identified by their device's public key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub key: String,
+    pub role: Role,
+}
+
+/// A team vault unlocked by one of its members.
+pub struct Team {
+    /// The collection key everything in the vault is encrypted under.
+    pub key: Key,
+    /// Collection keys replaced by rotations, newest first. Audit records
+    /// written before a rotation are encrypted under them.
+    pub previous_keys: Vec<Key>,
+    /// How many times the collection key has been rotated, plus one.
+    pub epoch: i64,
+    /// The caller's key and role.
+    pub member_key: String,
+    pub role: Role,
+    /// Every member, owners first.
+    pub members: Vec<Member>,
+}
+
+#[derive(Queryable)]
+struct TeamRow {
+    _id: i32,
+    team_id: String,
+    founder: String,
+    epoch: i64,
+    key_check: String,
+    encrypted_history: String,
+    signed_by: String,
+    signature: String,
+}
+
+impl TeamRow {
+    /// The bytes the signature of the team row covers.
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!("pwvault-team-v1\n{}\n{}\n{}\n{}", self.team_id, self.founder, self.epoch, self.key_check).into_bytes()
+    }
+}
+
+#[derive(Queryable)]
+struct MemberRow {
+    member_key: String,
+    role: String,
+    granted_by: String,
+    signature: String,
+    wrapped_key: String,
+}
+
+/// The bytes the signature of a membership covers.
+fn membership_bytes(team_id: &str, member_key: &str, role: &str) -> Vec<u8> {
+    format!("pwvault-team-member-v1\n{}\n{}\n{}", team_id, member_key, role).into_bytes()
+}
+
+/// The key a device is known by in team vaults.
+pub fn member_key(device: &sign::SecretKey) -> String {
+    base64::encode(device.public_key().as_ref())
+}
+
+/// Whether the selected vault is a team vault.
+pub fn is_team_vault() -> bool {
+    let connection = establish_connection();
+    team::table.count().get_result::<i64>(&connection).is_ok_and(|count| count > 0)
+}
+
+/// Turns the selected vault into a team vault owned by `device`. Everything
+/// in it is re-encrypted from `vault_key` to a new random collection key.
+pub fn create_team(device: &sign::SecretKey, vault_key: &Key) -> Result<Team, String> {
+    if is_team_vault() {
+        return Err("This vault is already a team vault".to_string());
+    }
+    let connection = establish_connection();
+    let team_id = base64::encode(sodiumoxide::randombytes::randombytes(16));
+    let me = member_key(device);
+    let key = secretbox::gen_key();
+
+    write_transaction(&connection, || {
+        rekey_vault(&connection, vault_key, &key)?;
+        let mut row = TeamRow {
+            _id: 1,
+            team_id: team_id.clone(),
+            founder: me.clone(),
+            epoch: 1,
+            key_check: key_check(&team_id, 1, &key),
+            encrypted_history: encrypt_history(&[], &team_id, &key),
+            signed_by: me.clone(),
+            signature: String::new(),
+        };
+        row.signature = sign_bytes(&row.signed_bytes(), device);
+        insert_team_row(&connection, &row)?;
+        diesel::insert_into(team_members::table)
+            .values((
+                team_members::member_key.eq(&me),
+                team_members::role.eq(Role::Owner.name()),
+                team_members::granted_by.eq(&me),
+                team_members::signature.eq(sign_bytes(&membership_bytes(&team_id, &me, Role::Owner.name()), device)),
+                team_members::wrapped_key.eq(seal_for(&key, &me).map_err(fail)?),
+            ))
+            .execute(&connection)
+    })
+    .map_err(|e| transaction_error(e, "create the team"))?;
+
+    Ok(Team {
+        key,
+        previous_keys: Vec::new(),
+        epoch: 1,
+        member_key: me.clone(),
+        role: Role::Owner,
+        members: vec![Member { key: me, role: Role::Owner }],
+    })
+}
+
+/// Unlocks the selected team vault for `device`. The membership table is
+/// checked first: every role must be signed by a member allowed to grant it,
+/// tracing back to the founding owner.
+pub fn unlock_team(device: &sign::SecretKey) -> Result<Team, String> {
+    let connection = establish_connection();
+    let (row, rows) = load_team(&connection)?;
+    let valid = signed_members(&row, &rows);
+    if let Some(unsigned) = rows.iter().find(|member| !valid.contains_key(&member.member_key)) {
+        return Err(format!("The membership of {} is not signed by an owner or admin of the team", unsigned.member_key));
+    }
+    let key_signed = valid.get(&row.signed_by).is_some_and(|role| *role >= Role::Admin)
+        && verify_bytes(&row.signed_bytes(), &row.signed_by, &row.signature);
+    if !key_signed {
+        return Err("The team key is not signed by an owner or admin of the team".to_string());
+    }
+
+    let me = member_key(device);
+    let mine = rows.iter().find(|member| member.member_key == me).ok_or_else(|| {
+        format!("This is a team vault and you are not a member. Ask an owner or admin to run: team add --member {} --role <role>", me)
+    })?;
+    let key = open_sealed(&mine.wrapped_key, device)?;
+    if key_check(&row.team_id, row.epoch, &key) != row.key_check {
+        return Err("Your copy of the team key does not match the key signed for the team".to_string());
+    }
+    let previous_keys = decrypt_history(&row.encrypted_history, &row.team_id, &key)?;
+
+    let mut members: Vec<Member> = valid.into_iter().map(|(key, role)| Member { key, role }).collect();
+    members.sort_by(|a, b| b.role.cmp(&a.role).then_with(|| a.key.cmp(&b.key)));
+    Ok(Team {
+        key,
+        previous_keys,
+        epoch: row.epoch,
+        role: Role::parse(&mine.role).unwrap_or(Role::Reader),
+        member_key: me,
+        members,
+    })
+}
+
+/// Adds a member with `role`, sealing the collection key for them.
+pub fn add_member(team: &Team, device: &sign::SecretKey, member: &str, role: Role) -> Result<(), String> {
+    let member = parse_member_key(member)?;
+    if !team.role.can_manage(role) {
+        return Err(format!("Your role ({}) cannot add {}s", team.role.name(), role.name()));
+    }
+    if team.members.iter().any(|existing| existing.key == member) {
+        return Err(format!("{} is already a member; use `team role` to change their role", member));
+    }
+
+    let connection = establish_connection();
+    let (row, _) = load_team(&connection)?;
+    write_transaction(&connection, || {
+        diesel::insert_into(team_members::table)
+            .values((
+                team_members::member_key.eq(&member),
+                team_members::role.eq(role.name()),
+                team_members::granted_by.eq(&team.member_key),
+                team_members::signature.eq(sign_bytes(&membership_bytes(&row.team_id, &member, role.name()), device)),
+                team_members::wrapped_key.eq(seal_for(&team.key, &member).map_err(fail)?),
+            ))
+            .execute(&connection)
+    })
+    .map(|_| ())
+    .map_err(|e| transaction_error(e, "add the member"))
+}
+
+/// Gives a member another role.
+pub fn set_role(team: &Team, device: &sign::SecretKey, member: &str, role: Role) -> Result<(), String> {
+    let member = parse_member_key(member)?;
+    let current = check_can_manage(team, &member)?;
+    if !team.role.can_manage(role) {
+        return Err(format!("Your role ({}) cannot make members {}s", team.role.name(), role.name()));
+    }
+    if current == Role::Owner && role != Role::Owner && owner_count(team) == 1 {
+        return Err("The team must keep at least one owner".to_string());
+    }
+
+    let connection = establish_connection();
+    let (row, _) = load_team(&connection)?;
+    write_transaction(&connection, || {
+        diesel::update(team_members::table.filter(team_members::member_key.eq(&member)))
+            .set((
+                team_members::role.eq(role.name()),
+                team_members::granted_by.eq(&team.member_key),
+                team_members::signature.eq(sign_bytes(&membership_bytes(&row.team_id, &member, role.name()), device)),
+            ))
+            .execute(&connection)?;
+        resign_membership(&connection, device)
+    })
+    .map_err(|e| transaction_error(e, "change the role"))
+}
+
+/// Removes a member. The collection key is rotated and the whole vault is
+/// re-encrypted under the new key, which is sealed for the remaining members
+/// only, so nothing written from now on is readable by the removed member.
+/// Returns the new epoch.
+pub fn remove_member(team: &Team, device: &sign::SecretKey, member: &str) -> Result<i64, String> {
+    let member = parse_member_key(member)?;
+    let current = check_can_manage(team, &member)?;
+    if current == Role::Owner && owner_count(team) == 1 {
+        return Err("The team must keep at least one owner".to_string());
+    }
+
+    let connection = establish_connection();
+    let (mut row, rows) = load_team(&connection)?;
+    let new_key = secretbox::gen_key();
+    let mut history = vec![team.key.clone()];
+    history.extend(team.previous_keys.iter().cloned());
+
+    write_transaction(&connection, || {
+        diesel::delete(team_members::table.filter(team_members::member_key.eq(&member))).execute(&connection)?;
+        rekey_vault(&connection, &team.key, &new_key)?;
+        for remaining in rows.iter().filter(|row| row.member_key != member) {
+            diesel::update(team_members::table.filter(team_members::member_key.eq(&remaining.member_key)))
+                .set(team_members::wrapped_key.eq(seal_for(&new_key, &remaining.member_key).map_err(fail)?))
+                .execute(&connection)?;
+        }
+
+        row.epoch += 1;
+        row.key_check = key_check(&row.team_id, row.epoch, &new_key);
+        row.encrypted_history = encrypt_history(&history, &row.team_id, &new_key);
+        diesel::delete(team::table).execute(&connection)?;
+        insert_team_row(&connection, &row)?;
+        resign_membership(&connection, device)?;
+        Ok(row.epoch)
+    })
+    .map_err(|e| transaction_error(e, "remove the member"))
+}
+
+/// Makes `device` the founding owner of the selected team vault, given the
+/// current collection key rebuilt from recovery shares. Memberships that were
+/// validly signed are kept and re-signed by `device`; any others are dropped.
+pub fn recover_team(device: &sign::SecretKey, key: &Key) -> Result<Team, String> {
+    let connection = establish_connection();
+    let (row, rows) = load_team(&connection)?;
+    if key_check(&row.team_id, row.epoch, key) != row.key_check {
+        return Err("This is not the current team key; shares split before the key was last rotated no longer work".to_string());
+    }
+    let valid = signed_members(&row, &rows);
+    let me = member_key(device);
+
+    write_transaction(&connection, || {
+        for unsigned in rows.iter().filter(|member| !valid.contains_key(&member.member_key)) {
+            diesel::delete(team_members::table.filter(team_members::member_key.eq(&unsigned.member_key))).execute(&connection)?;
+        }
+        diesel::replace_into(team_members::table)
+            .values((
+                team_members::member_key.eq(&me),
+                team_members::role.eq(Role::Owner.name()),
+                team_members::granted_by.eq(&me),
+                team_members::signature.eq(sign_bytes(&membership_bytes(&row.team_id, &me, Role::Owner.name()), device)),
+                team_members::wrapped_key.eq(seal_for(key, &me).map_err(fail)?),
+            ))
+            .execute(&connection)?;
+        diesel::update(team::table).set(team::founder.eq(&me)).execute(&connection)?;
+        resign_membership(&connection, device)
+    })
+    .map_err(|e| transaction_error(e, "recover the team"))?;
+    unlock_team(device)
+}
+
+/// Re-encrypts everything in the vault from `old_key` to `new_key`. The audit
+/// log is left as it is, since its records are signed.
+fn rekey_vault(connection: &SqliteConnection, old_key: &Key, new_key: &Key) -> Result<(), diesel::result::Error> {
+    let title_indexes = rekey_entries(connection, old_key, new_key)?;
+    rekey_attachments(connection, &title_indexes, old_key, new_key)?;
+    rekey_shares(connection, &title_indexes, old_key, new_key)?;
+    reseal_emergency_keys(connection, new_key)
+}
+
+/// Re-signs, with the key of the member making a change, whatever the change
+/// left without a valid signature: the founding owner if they were removed,
+/// roles granted by a member who can no longer grant them, and the team row.
+fn resign_membership(connection: &SqliteConnection, device: &sign::SecretKey) -> Result<(), diesel::result::Error> {
+    let me = member_key(device);
+    let (mut row, rows) = load_team(connection).map_err(fail)?;
+    let my_role = rows
+        .iter()
+        .find(|member| member.member_key == me)
+        .and_then(|member| Role::parse(&member.role))
+        .ok_or_else(|| fail("You are no longer a member of the team".to_string()))?;
+
+    if !rows.iter().any(|member| member.member_key == row.founder) {
+        row.founder = me.clone();
+    }
+    let valid = signed_members(&row, &rows);
+    for member in rows.iter().filter(|member| !valid.contains_key(&member.member_key)) {
+        let role = Role::parse(&member.role).ok_or_else(|| fail(format!("Unknown role '{}'", member.role)))?;
+        if !my_role.can_manage(role) && member.member_key != me {
+            return Err(fail(format!("Your role ({}) cannot vouch for {}s", my_role.name(), role.name())));
+        }
+        diesel::update(team_members::table.filter(team_members::member_key.eq(&member.member_key)))
+            .set((
+                team_members::granted_by.eq(&me),
+                team_members::signature.eq(sign_bytes(&membership_bytes(&row.team_id, &member.member_key, &member.role), device)),
+            ))
+            .execute(connection)?;
+    }
+
+    row.signed_by = me;
+    row.signature = sign_bytes(&row.signed_bytes(), device);
+    diesel::delete(team::table).execute(connection)?;
+    insert_team_row(connection, &row)
+}
+
+/// The members whose role is signed by someone allowed to grant it, found by
+/// following signatures out from the founding owner.
+fn signed_members(row: &TeamRow, rows: &[MemberRow]) -> HashMap<String, Role> {
+    let mut valid: HashMap<String, Role> = HashMap::new();
+    loop {
+        let vouched: Vec<(String, Role)> = rows
+            .iter()
+            .filter(|member| !valid.contains_key(&member.member_key))
+            .filter_map(|member| {
+                let role = Role::parse(&member.role)?;
+                let vouched = if member.member_key == row.founder {
+                    member.granted_by == row.founder && role == Role::Owner
+                } else {
+                    valid.get(&member.granted_by).is_some_and(|granter| granter.can_manage(role))
+                };
+                let signed = verify_bytes(&membership_bytes(&row.team_id, &member.member_key, &member.role), &member.granted_by, &member.signature);
+                (vouched && signed).then(|| (member.member_key.clone(), role))
+            })
+            .collect();
+        if vouched.is_empty() {
+            return valid;
+        }
+        valid.extend(vouched);
+    }
+}
+
+fn check_can_manage(team: &Team, member: &str) -> Result<Role, String> {
+    if member == team.member_key {
+        return Err("You cannot change your own membership; ask another owner or admin".to_string());
+    }
+    let current = team
+        .members
+        .iter()
+        .find(|existing| existing.key == member)
+        .map(|existing| existing.role)
+        .ok_or_else(|| format!("{} is not a member of the team", member))?;
+    if !team.role.can_manage(current) {
+        return Err(format!("Your role ({}) cannot manage {}s", team.role.name(), current.name()));
+    }
+    Ok(current)
+}
+
+fn owner_count(team: &Team) -> usize {
+    team.members.iter().filter(|member| member.role == Role::Owner).count()
+}
+
+fn load_team(connection: &SqliteConnection) -> Result<(TeamRow, Vec<MemberRow>), String> {
+    let row = team::table
+        .first::<TeamRow>(connection)
+        .optional()
+        .map_err(|e| format!("Failed to read the team: {}", e))?
+        .ok_or("This vault is not a team vault; run `team init` first")?;
+    let rows = team_members::table
+        .load::<MemberRow>(connection)
+        .map_err(|e| format!("Failed to read the team members: {}", e))?;
+    Ok((row, rows))
+}
+
+fn insert_team_row(connection: &SqliteConnection, row: &TeamRow) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(team::table)
+        .values((
+            team::id.eq(1),
+            team::team_id.eq(&row.team_id),
+            team::founder.eq(&row.founder),
+            team::epoch.eq(row.epoch),
+            team::key_check.eq(&row.key_check),
+            team::encrypted_history.eq(&row.encrypted_history),
+            team::signed_by.eq(&row.signed_by),
+            team::signature.eq(&row.signature),
+        ))
+        .execute(connection)
+        .map(|_| ())
+}
+
+/// Checks and normalizes a member key as printed by `team whoami`.
+pub fn parse_member_key(encoded: &str) -> Result<String, String> {
+    base64::decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| sign::PublicKey::from_slice(&bytes))
+        .map(|public_key| base64::encode(public_key.as_ref()))
+        .ok_or_else(|| format!("Invalid member key '{}': expected the key printed by `team whoami`", encoded))
+}
+
+/// A commitment to the collection key of an epoch, so a member can tell the
+/// key sealed for them is the one the team signed.
+fn key_check(team_id: &str, epoch: i64, key: &Key) -> String {
+    let mut state = sha256::State::new();
+    state.update(format!("pwvault-team-key-v1\n{}\n{}\n", team_id, epoch).as_bytes());
+    state.update(key.as_ref());
+    base64::encode(state.finalize().as_ref())
+}
+
+fn encrypt_history(keys: &[Key], team_id: &str, key: &Key) -> String {
+    let encoded: Vec<String> = keys.iter().map(|old| base64::encode(old.as_ref())).collect();
+    let json = serde_json::to_string(&encoded).expect("Failed to serialize key history");
+    encrypt_label("team", &json, team_id, key)
+}
+
+fn decrypt_history(encrypted: &str, team_id: &str, key: &Key) -> Result<Vec<Key>, String> {
+    let json = decrypt_label("team", encrypted, team_id, key)?;
+    let encoded: Vec<String> = serde_json::from_str(&json).map_err(|e| format!("Failed to parse key history: {}", e))?;
+    encoded
+        .iter()
+        .map(|old| {
+            base64::decode(old)
+                .ok()
+                .and_then(|bytes| Key::from_slice(&bytes))
+                .ok_or_else(|| "Invalid key in the key history".to_string())
+        })
+        .collect()
+}
+
+/// Seals a key for a member, converting their signing key to an X25519 key.
+pub fn seal_for(key: &Key, member: &str) -> Result<String, String> {
+    let public_key = base64::decode(member)
+        .ok()
+        .and_then(|bytes| sign::PublicKey::from_slice(&bytes))
+        .and_then(|public_key| ed25519::to_curve25519_pk(&public_key).ok())
+        .ok_or_else(|| format!("Invalid member key '{}'", member))?;
+    Ok(base64::encode(sealedbox::seal(key.as_ref(), &public_key)))
+}
+
+/// Opens a key sealed for `device` by `seal_for`.
+pub fn open_sealed(sealed: &str, device: &sign::SecretKey) -> Result<Key, String> {
+    let public_key = ed25519::to_curve25519_pk(&device.public_key()).map_err(|_| "Invalid device key")?;
+    let secret_key = ed25519::to_curve25519_sk(device).map_err(|_| "Invalid device key")?;
+    base64::decode(sealed)
+        .ok()
+        .and_then(|sealed| sealedbox::open(&sealed, &public_key, &secret_key).ok())
+        .and_then(|bytes| Key::from_slice(&bytes))
+        .ok_or_else(|| "Failed to unseal the key".to_string())
+}
+
+/// Signs `bytes` with a device key, as base64.
+pub fn sign_bytes(bytes: &[u8], device: &sign::SecretKey) -> String {
+    base64::encode(sign::sign_detached(bytes, device).as_ref())
+}
+
+/// Checks a signature made by `sign_bytes` with the member key `signer`.
+pub fn verify_bytes(bytes: &[u8], signer: &str, signature: &str) -> bool {
+    let public_key = base64::decode(signer).ok().and_then(|bytes| sign::PublicKey::from_slice(&bytes));
+    let signature = base64::decode(signature).ok().and_then(|bytes| sign::Signature::from_bytes(&bytes).ok());
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => sign::verify_detached(&signature, bytes, &public_key),
+        _ => false,
+    }
+}
+
+fn fail(message: String) -> diesel::result::Error {
+    diesel::result::Error::DeserializationError(message.into())
+}
+
+fn transaction_error(e: diesel::result::Error, action: &str) -> String {
+    match e {
+        diesel::result::Error::DeserializationError(message) => message.to_string(),
+        e => format!("Failed to {}: {}", action, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::get_entry;
+    use crate::encryption::{decrypt_password, title_index};
+    use crate::testing::{add_test_entry, test_vault, TestVault};
+
+    /// Setup: An isolated test vault holding one entry under `vault_key`.
+    fn setup(vault_key: &Key) -> TestVault {
+        let vault = test_vault();
+        add_test_entry("db", "pw", None, vault_key);
+        vault
+    }
+
+    fn read_entry(key: &Key) -> Option<String> {
+        let index = title_index("db", key);
+        get_entry(&index).and_then(|entry| decrypt_password(&entry.encrypted_password, &index, None, key).ok())
+    }
+
+    #[test]
+    fn test_members_unlock_and_removal_rotates_the_key() {
+        // Arrange
+        let vault_key = secretbox::gen_key();
+        let _vault = setup(&vault_key);
+        let (_, owner) = sign::gen_keypair();
+        let (_, reader) = sign::gen_keypair();
+
+        // Act
+        let team = create_team(&owner, &vault_key).unwrap();
+        add_member(&team, &owner, &member_key(&reader), Role::Reader).unwrap();
+        let as_reader = unlock_team(&reader).unwrap();
+
+        // Assert: The vault is only readable with the team key, which the reader shares
+        assert_eq!(read_entry(&vault_key), None);
+        assert_eq!(read_entry(&team.key).as_deref(), Some("pw"));
+        assert_eq!((as_reader.role, as_reader.key.clone()), (Role::Reader, team.key.clone()));
+        assert!(add_member(&as_reader, &reader, &member_key(&sign::gen_keypair().1), Role::Reader).is_err());
+        assert_eq!(remove_member(&team, &owner, &member_key(&owner)).unwrap_err(), "You cannot change your own membership; ask another owner or admin");
+
+        // Act: Remove the reader
+        let epoch = remove_member(&unlock_team(&owner).unwrap(), &owner, &member_key(&reader)).unwrap();
+        let rotated = unlock_team(&owner).unwrap();
+
+        // Assert: The key changed, the entry moved to it, and the reader is locked out
+        assert_eq!((epoch, rotated.epoch), (2, 2));
+        assert_ne!(rotated.key, team.key);
+        assert_eq!(rotated.previous_keys, vec![team.key.clone()]);
+        assert_eq!(read_entry(&team.key), None);
+        assert_eq!(read_entry(&rotated.key).as_deref(), Some("pw"));
+        assert!(unlock_team(&reader).err().unwrap().contains("you are not a member"));
+    }
+
+    #[test]
+    fn test_unlock_rejects_unsigned_roles() {
+        // Arrange
+        let vault_key = secretbox::gen_key();
+        let _vault = setup(&vault_key);
+        let (_, owner) = sign::gen_keypair();
+        let (_, admin) = sign::gen_keypair();
+        let (_, writer) = sign::gen_keypair();
+        let team = create_team(&owner, &vault_key).unwrap();
+        add_member(&team, &owner, &member_key(&admin), Role::Admin).unwrap();
+        let as_admin = unlock_team(&admin).unwrap();
+        add_member(&as_admin, &admin, &member_key(&writer), Role::Writer).unwrap();
+
+        // Act: Demoting the admin re-signs the writer they added
+        set_role(&unlock_team(&owner).unwrap(), &owner, &member_key(&admin), Role::Reader).unwrap();
+        let after_demotion = unlock_team(&writer);
+
+        // Act: The writer promotes themselves by editing the table
+        let connection = establish_connection();
+        diesel::update(team_members::table.filter(team_members::member_key.eq(member_key(&writer))))
+            .set(team_members::role.eq("owner"))
+            .execute(&connection)
+            .unwrap();
+        let forged = unlock_team(&owner);
+
+        // Assert
+        assert_eq!(after_demotion.map(|team| team.role).ok(), Some(Role::Writer));
+        assert_eq!(
+            forged.err(),
+            Some(format!("The membership of {} is not signed by an owner or admin of the team", member_key(&writer)))
+        );
+    }
+
+    #[test]
+    fn test_recover_team_with_the_team_key() {
+        // Arrange: The owner leaves; someone else holds the key rebuilt from recovery shares
+        let vault_key = secretbox::gen_key();
+        let _vault = setup(&vault_key);
+        let (_, owner) = sign::gen_keypair();
+        let (_, reader) = sign::gen_keypair();
+        let (_, successor) = sign::gen_keypair();
+        let team = create_team(&owner, &vault_key).unwrap();
+        add_member(&team, &owner, &member_key(&reader), Role::Reader).unwrap();
+
+        // Act
+        let wrong_key = recover_team(&successor, &vault_key);
+        let recovered = recover_team(&successor, &team.key).unwrap();
+
+        // Assert: The successor owns the team and the other memberships still hold
+        assert!(wrong_key.err().unwrap().starts_with("This is not the current team key"));
+        assert_eq!((recovered.role, recovered.key.clone()), (Role::Owner, team.key.clone()));
+        assert_eq!(unlock_team(&reader).map(|team| team.role).ok(), Some(Role::Reader));
+        assert!(remove_member(&recovered, &successor, &member_key(&owner)).is_ok());
+        assert!(unlock_team(&owner).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::ops::Range;
+
+/// A `{{ vault "title" "field" }}` placeholder found in a template.
+#[derive(Debug, PartialEq)]
+pub struct Placeholder {
+    /// Line of the template the placeholder starts on, from 1.
+    pub line: usize,
+    pub reference: String,
+    /// The field to insert; `None` for the entry's password or first field.
+    pub field: Option<String>,
+    span: Range<usize>,
+}
+
+/// Finds the vault placeholders of a template. Other `{{ ... }}` text is
+/// left alone, so templates meant for other tools can be used as they are.
+pub fn parse_template(template: &str) -> Result<Vec<Placeholder>, String> {
+    let mut placeholders = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = template[offset..].find("{{") {
+        let start = offset + found;
+        let line = template[..start].matches('\n').count() + 1;
+        let inner_start = start + 2;
+        let inner = template[inner_start..].trim_start();
+        if !(inner.starts_with("vault ") || inner.starts_with("vault\"") || inner.starts_with("vault\t")) {
+            offset = inner_start;
+            continue;
+        }
+
+        let close = template[inner_start..]
+            .find("}}")
+            .ok_or_else(|| format!("line {}: the placeholder is never closed with }}}}", line))?;
+        let end = inner_start + close + 2;
+        let arguments = parse_arguments(template[inner_start..inner_start + close].trim()["vault".len()..].trim())
+            .map_err(|e| format!("line {}: {}", line, e))?;
+        let (reference, field) = match arguments.as_slice() {
+            [reference] => (reference.clone(), None),
+            [reference, field] => (reference.clone(), Some(field.clone())),
+            _ => return Err(format!("line {}: expected {{{{ vault \"title\" \"field\" }}}}", line)),
+        };
+        placeholders.push(Placeholder {
+            line,
+            reference,
+            field,
+            span: start..end,
+        });
+        offset = end;
+    }
+    Ok(placeholders)
+}
+
+/// Reads the double-quoted arguments of a placeholder. `\"` and `\\` stand
+/// for a quote and a backslash.
+fn parse_arguments(text: &str) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            None => return Ok(arguments),
+            Some('"') => {}
+            Some(_) => return Err("arguments must be written in double quotes".to_string()),
+        }
+
+        let mut argument = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(escaped @ ('"' | '\\')) => argument.push(escaped),
+                    _ => return Err("only \\\" and \\\\ can be escaped".to_string()),
+                },
+                Some(c) => argument.push(c),
+                None => return Err("a quoted argument is never closed".to_string()),
+            }
+        }
+        arguments.push(argument);
+    }
+}
+
+/// Replaces each placeholder with its value, in the order they were found.
+pub fn render_template(template: &str, placeholders: &[Placeholder], values: &[String]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut offset = 0;
+    for (placeholder, value) in placeholders.iter().zip(values) {
+        output.push_str(&template[offset..placeholder.span.start]);
+        output.push_str(value);
+        offset = placeholder.span.end;
+    }
+    output.push_str(&template[offset..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render_template() {
+        // Arrange
+        let template = "user = {{ vault \"prod/db\" \"username\" }}\npass = {{vault \"prod/db\"}}\nname = {{ .Release.Name }}\n";
+
+        // Act
+        let placeholders = parse_template(template).unwrap();
+        let rendered = render_template(template, &placeholders, &["deploy".to_string(), "hunter2".to_string()]);
+
+        // Assert: Placeholders of other tools are kept
+        assert_eq!(placeholders.len(), 2);
+        assert_eq!((placeholders[0].line, placeholders[0].field.as_deref()), (1, Some("username")));
+        assert_eq!((placeholders[1].line, placeholders[1].reference.as_str(), placeholders[1].field.as_deref()), (2, "prod/db", None));
+        assert_eq!(rendered, "user = deploy\npass = hunter2\nname = {{ .Release.Name }}\n");
+    }
+
+    #[test]
+    fn test_parse_template_rejects_malformed_placeholders() {
+        // Act & Assert
+        assert_eq!(parse_template("a\n{{ vault \"say \\\"hi\\\"\" }}").unwrap()[0].reference, "say \"hi\"");
+        assert_eq!(parse_template("x\n{{ vault \"db\" ").unwrap_err(), "line 2: the placeholder is never closed with }}");
+        assert_eq!(parse_template("{{ vault db }}").unwrap_err(), "line 1: arguments must be written in double quotes");
+        assert!(parse_template("{{ vault }}").is_err());
+        assert!(parse_template("{{ vault \"a\" \"b\" \"c\" }}").is_err());
+        assert!(parse_template("{{ vault \"open }}").is_err());
+    }
+}
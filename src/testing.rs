@@ -0,0 +1,66 @@
+use sodiumoxide::crypto::secretbox::Key;
+use crate::database::{add_password, create_table_if_not_exists, deselect_vault, select_vault};
+use crate::encryption::{encrypt_metadata, encrypt_password, title_index, EntryMetadata};
+use crate::labels::folder_label;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Held by the test using a vault. The selected vault and the environment
+/// belong to the whole process, so tests with a vault run one at a time.
+static VAULT_LOCK: Mutex<()> = Mutex::new(());
+static NEXT_VAULT: AtomicUsize = AtomicUsize::new(0);
+
+/// A vault of one test, in a scratch directory that also holds its state
+/// directory and any other files of the test. It stays selected, and the
+/// directory in place, until dropped; then `DATABASE_URL` applies again.
+pub struct TestVault {
+    pub path: String,
+    pub dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for TestVault {
+    fn drop(&mut self) {
+        deselect_vault();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Setup: Selects an empty vault of the calling test, with its tables created
+/// and its rollback anchors and device key kept out of the user's state directory.
+pub fn test_vault() -> TestVault {
+    // A test that failed while holding the lock does not fail the others
+    let lock = VAULT_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let dir = env::temp_dir().join(format!("pwvault_test_{}_{}", std::process::id(), NEXT_VAULT.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create the test directory");
+    env::set_var("XDG_STATE_HOME", dir.join("state"));
+
+    let path = dir.join("test_passwords.db").to_string_lossy().into_owned();
+    select_vault(&path);
+    create_table_if_not_exists();
+    TestVault { path, dir, _lock: lock }
+}
+
+/// Adds a login titled `title` to the selected vault, filed under `folder`.
+/// Returns its title index.
+pub fn add_test_entry(title: &str, secret: &str, folder: Option<&str>, key: &Key) -> String {
+    let metadata = EntryMetadata {
+        title: title.to_string(),
+        ..Default::default()
+    };
+    add_test_item(&metadata, secret, folder, key)
+}
+
+/// Adds an entry with the given metadata to the selected vault, filed under
+/// `folder`. Returns its title index.
+pub fn add_test_item(metadata: &EntryMetadata, secret: &str, folder: Option<&str>, key: &Key) -> String {
+    let index = title_index(&metadata.title, key);
+    let folder = folder.map(|path| folder_label(path, key));
+    add_password(&index, &encrypt_metadata(metadata, &index, key), &encrypt_password(secret, &index, key), folder.as_ref(), &[])
+        .expect("Failed to add a test entry");
+    index
+}
@@ -0,0 +1,469 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as aead;
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::secretbox::Key;
+use sodiumoxide::randombytes::randombytes;
+use crate::database::{establish_connection, list_passwords, write_transaction};
+use crate::encryption::{decrypt_metadata, normalize_title, open_shared_entry};
+use crate::labels::{is_in_folder, normalize_folder, EntryLabels};
+use crate::schema::{api_tokens, shared_entries, token_grants};
+use crate::sharing::{ensure_shared_copy, rekey_shared_entry, SharedItem};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Prefix of every API token, so they are easy to spot in logs and configs.
+const TOKEN_PREFIX: &str = "pwv_";
+
+/// An API token, without its secret.
+#[derive(Queryable, Debug, Clone, PartialEq)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    token_hash: String,
+    /// The key the entries in its scope are sealed for. Its secret half is
+    /// derived from the token itself.
+    public_key: String,
+    /// Read-only tokens can list and read entries but not change them.
+    pub read_only: bool,
+    /// The scopes, one per line; `None` for a token with access to every entry.
+    scope: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl ApiToken {
+    /// The entries the token is limited to, or nothing for a token with
+    /// access to every entry.
+    pub fn scopes(&self) -> Vec<Scope> {
+        self.scope
+            .iter()
+            .flat_map(|scope| scope.lines())
+            .filter_map(|scope| Scope::parse(scope).ok())
+            .collect()
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        self.scope.is_some()
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// A part of the vault a token can be limited to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scope {
+    /// `folder:prod` is the entries of one folder, `folder:prod/*` also those
+    /// of its subfolders.
+    Folder { path: String, subfolders: bool },
+    /// `tag:db` is the entries tagged `db`.
+    Tag(String),
+    /// `entry:GitHub` is the entry with that title.
+    Entry(String),
+}
+
+impl Scope {
+    pub fn parse(scope: &str) -> Result<Scope, String> {
+        let invalid = || format!("Invalid scope '{}': expected folder:<path>, folder:<path>/*, tag:<tag> or entry:<title>", scope);
+        let (kind, value) = scope.trim().split_once(':').ok_or_else(invalid)?;
+        let value = value.trim();
+        match kind {
+            "folder" => {
+                let (path, subfolders) = match value.strip_suffix("/*") {
+                    Some(path) => (path, true),
+                    None if value == "*" => ("", true),
+                    None => (value, false),
+                };
+                match normalize_folder(path)? {
+                    Some(path) => Ok(Scope::Folder { path, subfolders }),
+                    None => Err(format!("Invalid scope '{}': the top level is every entry; leave out --scope instead", scope)),
+                }
+            }
+            "tag" if !value.is_empty() => Ok(Scope::Tag(value.to_string())),
+            "entry" if !value.is_empty() => Ok(Scope::Entry(value.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Whether an entry with this title, folder and tags is in the scope.
+    pub fn covers(&self, title: &str, folder: Option<&str>, tags: &[String]) -> bool {
+        match self {
+            Scope::Folder { path, subfolders: true } => folder.is_some_and(|folder| is_in_folder(folder, path)),
+            Scope::Folder { path, subfolders: false } => folder.is_some_and(|folder| folder.to_lowercase() == path.to_lowercase()),
+            Scope::Tag(tag) => tags.iter().any(|name| normalize_title(name) == normalize_title(tag)),
+            Scope::Entry(entry) => normalize_title(title) == normalize_title(entry),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scope::Folder { path, subfolders: true } => write!(f, "folder:{}/*", path),
+            Scope::Folder { path, subfolders: false } => write!(f, "folder:{}", path),
+            Scope::Tag(tag) => write!(f, "tag:{}", tag),
+            Scope::Entry(title) => write!(f, "entry:{}", title),
+        }
+    }
+}
+
+/// What a new token may do.
+#[derive(Debug, Clone, Default)]
+pub struct TokenOptions {
+    /// The entries it is limited to; empty for every entry.
+    pub scopes: Vec<Scope>,
+    pub read_only: bool,
+    pub expires_at: Option<i64>,
+}
+
+/// An entry a scoped token can decrypt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrantedEntry {
+    pub title_index: String,
+    pub item: SharedItem,
+}
+
+/// Parses a lifetime such as `90m`, `24h`, `7d` or `2w` into seconds.
+pub fn parse_ttl(ttl: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid lifetime '{}': expected a number followed by s, m, h, d or w, such as 24h", ttl);
+    let ttl = ttl.trim();
+    let unit = match ttl.chars().last().ok_or_else(invalid)? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    match ttl[..ttl.len() - 1].parse::<i64>() {
+        Ok(amount) if amount > 0 => amount.checked_mul(unit).ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+fn hash_token(secret: &str) -> String {
+    base64::encode(sha256::hash(secret.as_bytes()).as_ref())
+}
+
+/// The keypair entries are sealed for, derived from the token so it need not
+/// be stored.
+fn token_keypair(secret: &str) -> (box_::PublicKey, box_::SecretKey) {
+    let seed = sha256::hash(format!("pwvault-token-key:{}", secret).as_bytes());
+    box_::keypair_from_seed(&box_::Seed(seed.0))
+}
+
+/// Creates a token called `name` and returns its secret, which is shown once
+/// and never stored. A scoped token is given the keys of the entries in its
+/// scope right away.
+pub fn create_token(name: &str, options: &TokenOptions, now: i64, key: &Key) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The token needs a name".to_string());
+    }
+    let secret = format!("{}{}", TOKEN_PREFIX, base64::encode_config(randombytes(32), base64::URL_SAFE_NO_PAD));
+    let (public_key, _) = token_keypair(&secret);
+    let scope = (!options.scopes.is_empty())
+        .then(|| options.scopes.iter().map(Scope::to_string).collect::<Vec<_>>().join("\n"));
+
+    let connection = establish_connection();
+    let result = write_transaction(&connection, || {
+        diesel::insert_into(api_tokens::table)
+            .values((
+                api_tokens::name.eq(name),
+                api_tokens::token_hash.eq(hash_token(&secret)),
+                api_tokens::public_key.eq(base64::encode(public_key.as_ref())),
+                api_tokens::read_only.eq(options.read_only),
+                api_tokens::scope.eq(&scope),
+                api_tokens::created_at.eq(now),
+                api_tokens::expires_at.eq(options.expires_at),
+            ))
+            .execute(&connection)
+    });
+    match result {
+        Ok(_) => {}
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(format!("A token named '{}' already exists", name));
+        }
+        Err(e) => return Err(format!("Failed to create the token: {}", e)),
+    }
+    sync_token_grants(key)?;
+    Ok(secret)
+}
+
+/// Lists the tokens of the selected vault, oldest first.
+pub fn list_tokens() -> Result<Vec<ApiToken>, String> {
+    api_tokens::table
+        .order(api_tokens::id.asc())
+        .load::<ApiToken>(&establish_connection())
+        .map_err(|e| format!("Failed to read API tokens: {}", e))
+}
+
+/// Revokes the token called `name`. The entries it could decrypt are re-keyed
+/// so their later changes stay out of its reach. Returns the token, if there was one.
+pub fn revoke_token(name: &str, key: &Key) -> Result<Option<ApiToken>, String> {
+    let connection = establish_connection();
+    let token = match api_tokens::table
+        .filter(api_tokens::name.eq(name.trim()))
+        .first::<ApiToken>(&connection)
+        .optional()
+        .map_err(|e| format!("Failed to read API tokens: {}", e))?
+    {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    write_transaction(&connection, || {
+        let granted = token_grants::table
+            .filter(token_grants::token_id.eq(token.id))
+            .select(token_grants::title_index)
+            .load::<String>(&connection)?;
+        diesel::delete(token_grants::table.filter(token_grants::token_id.eq(token.id))).execute(&connection)?;
+        diesel::delete(api_tokens::table.filter(api_tokens::id.eq(token.id))).execute(&connection)?;
+        for title_index in &granted {
+            rekey_shared_entry(&connection, title_index, key)?;
+        }
+        Ok(())
+    })
+    .map(|_| Some(token))
+    .map_err(|e| format!("Failed to revoke the token: {}", e))
+}
+
+/// Finds the token a client presented. Tokens are looked up by hash, so
+/// revoking one takes effect on the next request. Expired tokens are refused.
+pub fn authenticate(secret: &str, now: i64) -> Option<ApiToken> {
+    if !secret.starts_with(TOKEN_PREFIX) {
+        return None;
+    }
+    api_tokens::table
+        .filter(api_tokens::token_hash.eq(hash_token(secret)))
+        .first::<ApiToken>(&establish_connection())
+        .ok()
+        .filter(|token| !token.is_expired(now))
+}
+
+/// Decrypts the entries a scoped token was given, with the token itself.
+/// Nothing outside its scope can be opened this way.
+pub fn granted_entries(token: &ApiToken, secret: &str) -> Result<Vec<GrantedEntry>, String> {
+    let (public_key, secret_key) = token_keypair(secret);
+    if base64::encode(public_key.as_ref()) != token.public_key {
+        return Err(format!("This is not the secret of token '{}'", token.name));
+    }
+    let grants = token_grants::table
+        .inner_join(shared_entries::table.on(shared_entries::title_index.eq(token_grants::title_index)))
+        .filter(token_grants::token_id.eq(token.id))
+        .select((token_grants::title_index, token_grants::sealed_key, shared_entries::encrypted_entry))
+        .load::<(String, String, String)>(&establish_connection())
+        .map_err(|e| format!("Failed to read the entries of token '{}': {}", token.name, e))?;
+
+    let mut entries = Vec::new();
+    for (title_index, sealed_key, encrypted_entry) in grants {
+        let entry_key = base64::decode(&sealed_key)
+            .ok()
+            .and_then(|sealed| sealedbox::open(&sealed, &public_key, &secret_key).ok())
+            .and_then(|bytes| aead::Key::from_slice(&bytes))
+            .ok_or("Failed to open the key of a granted entry")?;
+        let json = open_shared_entry(&encrypted_entry, &title_index, &entry_key)?;
+        let item = serde_json::from_slice::<SharedItem>(&json).map_err(|e| format!("Failed to parse granted entry: {}", e))?;
+        entries.push(GrantedEntry { title_index, item });
+    }
+    entries.sort_by(|a, b| a.item.metadata.title.cmp(&b.item.metadata.title));
+    Ok(entries)
+}
+
+/// Brings the grants of scoped tokens in line with their scopes after entries
+/// were added or moved: entries that came into scope are sealed for the token,
+/// and those that left it are re-keyed. Writes nothing when all is in order.
+pub fn sync_token_grants(key: &Key) -> Result<(), String> {
+    let connection = establish_connection();
+    let tokens = api_tokens::table
+        .filter(api_tokens::scope.is_not_null())
+        .load::<ApiToken>(&connection)
+        .map_err(|e| format!("Failed to read API tokens: {}", e))?;
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let labels = EntryLabels::load(key)?;
+    let mut entries = Vec::new();
+    for entry in list_passwords() {
+        let metadata = decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, key)?;
+        entries.push((entry.title_index, metadata.title));
+    }
+    let grants = token_grants::table
+        .select((token_grants::token_id, token_grants::title_index))
+        .load::<(i32, String)>(&connection)
+        .map_err(|e| format!("Failed to read token grants: {}", e))?;
+    let granted: HashSet<(i32, &str)> = grants.iter().map(|(id, index)| (*id, index.as_str())).collect();
+
+    let mut added = Vec::new();
+    let mut wanted = HashSet::new();
+    for token in &tokens {
+        let scopes = token.scopes();
+        for (title_index, title) in &entries {
+            let folder = labels.folder(title_index);
+            if scopes.iter().any(|scope| scope.covers(title, folder, labels.tags(title_index))) {
+                wanted.insert((token.id, title_index.as_str()));
+                if !granted.contains(&(token.id, title_index.as_str())) {
+                    added.push((token, title_index.as_str()));
+                }
+            }
+        }
+    }
+    let removed: Vec<&(i32, &str)> = granted.iter().filter(|grant| !wanted.contains(*grant)).collect();
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    write_transaction(&connection, || {
+        for (token, title_index) in &added {
+            let entry_key = ensure_shared_copy(&connection, title_index, key)?;
+            diesel::insert_into(token_grants::table)
+                .values((
+                    token_grants::token_id.eq(token.id),
+                    token_grants::title_index.eq(*title_index),
+                    token_grants::sealed_key.eq(seal_for_token(&entry_key, &token.public_key)?),
+                ))
+                .execute(&connection)?;
+        }
+        for (token_id, title_index) in &removed {
+            diesel::delete(
+                token_grants::table
+                    .filter(token_grants::token_id.eq(token_id))
+                    .filter(token_grants::title_index.eq(title_index)),
+            )
+            .execute(&connection)?;
+            rekey_shared_entry(&connection, title_index, key)?;
+        }
+        Ok(())
+    })
+    .map_err(|e| format!("Failed to update the entries of scoped tokens: {}", e))
+}
+
+/// Whether any token was given the key of the entry, inside the caller's transaction.
+pub fn has_token_grants(connection: &SqliteConnection, title_index: &str) -> QueryResult<bool> {
+    token_grants::table
+        .filter(token_grants::title_index.eq(title_index))
+        .count()
+        .get_result::<i64>(connection)
+        .map(|count| count > 0)
+}
+
+/// Seals the new key of a re-keyed shared copy for the tokens that hold the
+/// entry, inside the caller's transaction.
+pub fn reseal_token_grants(connection: &SqliteConnection, title_index: &str, entry_key: &aead::Key) -> QueryResult<()> {
+    let holders = token_grants::table
+        .inner_join(api_tokens::table.on(api_tokens::id.eq(token_grants::token_id)))
+        .filter(token_grants::title_index.eq(title_index))
+        .select((token_grants::token_id, api_tokens::public_key))
+        .load::<(i32, String)>(connection)?;
+    for (token_id, public_key) in holders {
+        diesel::update(
+            token_grants::table
+                .filter(token_grants::token_id.eq(token_id))
+                .filter(token_grants::title_index.eq(title_index)),
+        )
+        .set(token_grants::sealed_key.eq(seal_for_token(entry_key, &public_key)?))
+        .execute(connection)?;
+    }
+    Ok(())
+}
+
+fn seal_for_token(entry_key: &aead::Key, public_key: &str) -> QueryResult<String> {
+    let public_key = base64::decode(public_key)
+        .ok()
+        .and_then(|bytes| box_::PublicKey::from_slice(&bytes))
+        .ok_or_else(|| diesel::result::Error::DeserializationError("Invalid public key of an API token".into()))?;
+    Ok(base64::encode(sealedbox::seal(entry_key.as_ref(), &public_key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::move_entries;
+    use crate::encryption::{generate_key, title_index};
+    use crate::labels::folder_label;
+    use crate::testing::{add_test_entry, test_vault};
+
+    fn titles(entries: Vec<GrantedEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.item.metadata.title).collect()
+    }
+
+    #[test]
+    fn test_create_authenticate_and_revoke_tokens() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        let read_only = TokenOptions {
+            read_only: true,
+            ..Default::default()
+        };
+
+        // Act
+        let secret = create_token("ci", &read_only, 1_700_000_000, &key).unwrap();
+        let duplicate = create_token("ci", &TokenOptions::default(), 1_700_000_000, &key);
+        let found = authenticate(&secret, 1_700_000_000);
+        let revoked = revoke_token("ci", &key).unwrap();
+
+        // Assert
+        assert!(secret.starts_with("pwv_"));
+        assert_eq!(duplicate, Err("A token named 'ci' already exists".to_string()));
+        assert_eq!(found.map(|token| (token.is_scoped(), token.name, token.read_only)), Some((false, "ci".to_string(), true)));
+        assert!(!list_tokens().unwrap().iter().any(|token| token.name == "ci"));
+        assert_eq!(revoked.map(|token| token.name), Some("ci".to_string()));
+        assert!(revoke_token("ci", &key).unwrap().is_none());
+        assert_eq!(authenticate(&secret, 1_700_000_000), None);
+        assert_eq!(authenticate("not a token", 1_700_000_000), None);
+    }
+
+    #[test]
+    fn test_scoped_tokens_only_open_entries_in_scope() {
+        // Arrange
+        let _vault = test_vault();
+        let key = generate_key();
+        add_test_entry("prod-db", "pw", Some("prod/db"), &key);
+        add_test_entry("prod-web", "pw", Some("prod"), &key);
+        let staging = add_test_entry("staging-db", "pw", Some("staging"), &key);
+        let options = TokenOptions {
+            scopes: vec![Scope::parse("folder:prod/*").unwrap()],
+            read_only: true,
+            expires_at: Some(1_700_086_400),
+        };
+
+        // Act
+        let secret = create_token("deploy", &options, 1_700_000_000, &key).unwrap();
+        let token = authenticate(&secret, 1_700_000_000).unwrap();
+        let before = titles(granted_entries(&token, &secret).unwrap());
+        move_entries(&[(staging, Some(folder_label("prod/staging", &key)))]).unwrap();
+        sync_token_grants(&key).unwrap();
+        let after_move = titles(granted_entries(&token, &secret).unwrap());
+        move_entries(&[(title_index("prod-db", &key), None)]).unwrap();
+        sync_token_grants(&key).unwrap();
+        let after_move_out = titles(granted_entries(&token, &secret).unwrap());
+        let other = create_token("other", &TokenOptions::default(), 1_700_000_000, &key).unwrap();
+
+        // Assert
+        assert_eq!(before, vec!["prod-db", "prod-web"]);
+        assert_eq!(after_move, vec!["prod-db", "prod-web", "staging-db"]);
+        assert_eq!(after_move_out, vec!["prod-web", "staging-db"]);
+        assert!(granted_entries(&token, &other).is_err());
+        assert_eq!(authenticate(&secret, 1_700_086_400), None);
+        assert_eq!(token.scopes(), vec![Scope::Folder { path: "prod".to_string(), subfolders: true }]);
+    }
+
+    #[test]
+    fn test_parse_scopes_and_lifetimes() {
+        // Act
+        let scope = Scope::parse("folder:prod").unwrap();
+
+        // Assert
+        assert!(scope.covers("x", Some("Prod"), &[]) && !scope.covers("x", Some("prod/db"), &[]));
+        assert!(Scope::parse("tag:db").unwrap().covers("x", None, &["DB".to_string()]));
+        assert!(Scope::parse("entry:GitHub").unwrap().covers(" github", None, &[]));
+        assert!(Scope::parse("folder:/").is_err() && Scope::parse("prod").is_err());
+        assert_eq!(parse_ttl("24h"), Ok(86_400));
+        assert_eq!(parse_ttl("2w"), Ok(1_209_600));
+        assert!(parse_ttl("0d").is_err() && parse_ttl("h").is_err() && parse_ttl("5y").is_err());
+    }
+}
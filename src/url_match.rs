@@ -0,0 +1,274 @@
+use publicsuffix::{List, Psl};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox::Key;
+use url::Url;
+use crate::database::list_passwords;
+use crate::encryption::{decrypt_metadata, EntryMetadata};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// The public suffix list (https://publicsuffix.org), which tells the base
+/// domain of a host: `example.co.uk` for `git.example.co.uk`, but
+/// `alice.github.io` for `alice.github.io`.
+const PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+/// How a URL of an entry is compared with the URL of a page.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    /// The same URL, query included.
+    Exact,
+    /// The same host and port, whatever the path.
+    #[default]
+    Host,
+    /// Any host under the same base domain.
+    Domain,
+    /// Any URL that starts with this one.
+    StartsWith,
+    /// Any URL the regular expression matches.
+    Regex,
+}
+
+impl MatchMode {
+    pub const ALL: [MatchMode; 5] = [MatchMode::Exact, MatchMode::Host, MatchMode::Domain, MatchMode::StartsWith, MatchMode::Regex];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Host => "host",
+            MatchMode::Domain => "domain",
+            MatchMode::StartsWith => "starts-with",
+            MatchMode::Regex => "regex",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == MatchMode::default()
+    }
+
+    /// How closely a match in this mode pins down the page. Modes that look
+    /// at the path come before those that only look at the host.
+    fn specificity(self) -> u8 {
+        match self {
+            MatchMode::Exact => 4,
+            MatchMode::StartsWith => 3,
+            MatchMode::Regex => 2,
+            MatchMode::Host => 1,
+            MatchMode::Domain => 0,
+        }
+    }
+}
+
+/// A URL of an entry and how it is matched.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UrlRule {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "MatchMode::is_default")]
+    pub mode: MatchMode,
+}
+
+impl UrlRule {
+    /// Parses `[mode:]url`, e.g. `github.com`, `domain:example.com` or
+    /// `regex:^https://git\.example\.com/`.
+    pub fn parse(spec: &str) -> Result<UrlRule, String> {
+        let (mode, url) = MatchMode::ALL
+            .iter()
+            .find_map(|mode| spec.strip_prefix(mode.name()).and_then(|rest| rest.strip_prefix(':')).map(|url| (*mode, url)))
+            .unwrap_or((MatchMode::default(), spec));
+        let rule = UrlRule {
+            url: url.trim().to_string(),
+            mode,
+        };
+        if rule.url.is_empty() {
+            return Err(format!("Invalid URL '{}': it is empty", spec));
+        }
+        match mode {
+            MatchMode::Regex => Regex::new(&rule.url).map(|_| rule).map_err(|e| format!("Invalid regex '{}': {}", url, e)),
+            _ => match web_url(&rule.url) {
+                Some(_) => Ok(rule),
+                None => Err(format!("Invalid URL '{}': only http and https URLs can be matched", url)),
+            },
+        }
+    }
+
+    /// Whether a page is matched by this rule. A rule for an https URL never
+    /// matches the same site over plain http.
+    pub fn matches(&self, page: &Url) -> bool {
+        if self.mode == MatchMode::Regex {
+            return compiled_regex(&self.url).is_some_and(|regex| regex.is_match(page.as_str()));
+        }
+        let url = match web_url(&self.url) {
+            Some(url) => url,
+            None => return false,
+        };
+        if url.scheme() == "https" && page.scheme() == "http" {
+            return false;
+        }
+        match self.mode {
+            MatchMode::Exact => without_fragment(&url) == without_fragment(page),
+            MatchMode::Host => url.host() == page.host() && url.port() == page.port(),
+            // IP addresses have no base domain, so they must be the same
+            MatchMode::Domain => match (url.domain(), page.domain()) {
+                (Some(domain), Some(page_domain)) => base_domain(domain) == base_domain(page_domain),
+                _ => url.host() == page.host(),
+            },
+            MatchMode::StartsWith => without_fragment(page).starts_with(without_fragment(&url)),
+            MatchMode::Regex => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for UrlRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.mode.name(), self.url)
+    }
+}
+
+/// The URLs of an entry with their match modes; `url` is matched by host.
+pub fn url_rules(metadata: &EntryMetadata) -> Vec<UrlRule> {
+    metadata
+        .url
+        .iter()
+        .map(|url| UrlRule {
+            url: url.clone(),
+            mode: MatchMode::default(),
+        })
+        .chain(metadata.urls.iter().cloned())
+        .collect()
+}
+
+/// Parses the URL of a page. Pages given without a scheme are taken to be https.
+pub fn page_url(url: &str) -> Result<Url, String> {
+    web_url(url.trim()).ok_or_else(|| format!("Invalid URL '{}': only http and https pages can be matched", url))
+}
+
+/// An entry with a URL matching a page, and the rule that matched best.
+pub struct UrlMatch {
+    pub title_index: String,
+    pub encrypted_password: String,
+    pub metadata: EntryMetadata,
+    pub rule: UrlRule,
+}
+
+/// The entries matching a page, most specific first: by the mode of their
+/// best rule, then by the length of its URL, then by title.
+pub fn match_entries(page: &Url, key: &Key) -> Result<Vec<UrlMatch>, String> {
+    let mut matches = Vec::new();
+    for entry in list_passwords() {
+        let metadata: EntryMetadata = decrypt_metadata(&entry.encrypted_metadata, &entry.title_index, key)?;
+        let best = url_rules(&metadata)
+            .into_iter()
+            .filter(|rule| rule.matches(page))
+            .max_by_key(|rule| (rule.mode.specificity(), rule.url.len()));
+        if let Some(rule) = best {
+            matches.push(UrlMatch {
+                title_index: entry.title_index,
+                encrypted_password: entry.encrypted_password,
+                metadata,
+                rule,
+            });
+        }
+    }
+    matches.sort_by(|a, b| {
+        (b.rule.mode.specificity(), b.rule.url.len())
+            .cmp(&(a.rule.mode.specificity(), a.rule.url.len()))
+            .then_with(|| a.metadata.title.cmp(&b.metadata.title))
+    });
+    Ok(matches)
+}
+
+/// Parses an http or https URL, taking URLs without a scheme to be https.
+fn web_url(url: &str) -> Option<Url> {
+    let url = if url.contains("://") { Url::parse(url) } else { Url::parse(&format!("https://{}", url)) };
+    url.ok().filter(|url| matches!(url.scheme(), "https" | "http") && url.host().is_some())
+}
+
+fn without_fragment(url: &Url) -> &str {
+    &url[..url::Position::AfterQuery]
+}
+
+/// The compiled form of a regex rule, or `None` if it is invalid. Each pattern
+/// is compiled once, not again for every page and entry it is matched against.
+fn compiled_regex(pattern: &str) -> Option<Regex> {
+    static REGEXES: OnceLock<Mutex<HashMap<String, Option<Regex>>>> = OnceLock::new();
+    let mut regexes = REGEXES.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    regexes
+        .entry(pattern.to_string())
+        .or_insert_with(|| Regex::new(pattern).ok())
+        .clone()
+}
+
+/// The registrable part of a domain under the public suffix list. Domains
+/// that are themselves public suffixes stand for themselves.
+fn base_domain(host: &str) -> String {
+    static LIST: OnceLock<List> = OnceLock::new();
+    let list = LIST.get_or_init(|| PUBLIC_SUFFIX_LIST.parse().expect("The bundled public suffix list is invalid"));
+    list.domain(host.as_bytes())
+        .and_then(|domain| std::str::from_utf8(domain.as_bytes()).ok().map(str::to_string))
+        .unwrap_or_else(|| host.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(spec: &str) -> UrlRule {
+        UrlRule::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn test_url_rules_match_by_mode() {
+        // Arrange
+        let page = page_url("https://git.internal.example.com/login?next=%2F#top").unwrap();
+
+        // Act
+        let matched = |spec: &str| rule(spec).matches(&page);
+
+        // Assert
+        assert!(matched("exact:https://git.internal.example.com/login?next=%2F"));
+        assert!(!matched("exact:https://git.internal.example.com/login"));
+        assert!(matched("git.internal.example.com"));
+        assert!(matched("host:http://git.internal.example.com/other"));
+        assert!(!matched("host:git.internal.example.com:8443"));
+        assert!(!matched("example.com"));
+        assert!(matched("domain:example.com"));
+        assert!(matched("domain:https://www.example.com"));
+        assert!(!matched("domain:example.co.uk"));
+        assert!(matched("starts-with:git.internal.example.com/log"));
+        assert!(!matched("starts-with:https://git.internal.example.com/logout"));
+        assert!(matched(r"regex:^https://[a-z]+\.internal\.example\.com/"));
+        assert!(!matched(r"regex:^https://example\.com/"));
+        // A stored rule with an invalid regex matches nothing, however often it is tried
+        let invalid = UrlRule { url: "(".to_string(), mode: MatchMode::Regex };
+        assert!(!invalid.matches(&page) && !invalid.matches(&page));
+        // An https rule is never matched over plain http
+        assert!(!rule("git.internal.example.com").matches(&page_url("http://git.internal.example.com").unwrap()));
+    }
+
+    #[test]
+    fn test_base_domains_follow_the_public_suffix_list() {
+        // Assert
+        assert_eq!(base_domain("git.example.co.uk"), "example.co.uk");
+        assert_eq!(base_domain("alice.github.io"), "alice.github.io");
+        assert_eq!(base_domain("localhost"), "localhost");
+        assert!(rule("domain:http://10.0.0.1").matches(&page_url("http://10.0.0.1:8080/admin").unwrap()));
+        assert!(!rule("domain:http://10.0.0.1").matches(&page_url("http://10.0.0.2").unwrap()));
+        assert!(!rule("domain:alice.github.io").matches(&page_url("https://bob.github.io").unwrap()));
+    }
+
+    #[test]
+    fn test_parse_url_rules() {
+        // Act
+        let parsed = rule("starts-with:https://example.com/app");
+
+        // Assert
+        assert_eq!(parsed, UrlRule { url: "https://example.com/app".to_string(), mode: MatchMode::StartsWith });
+        assert_eq!(parsed.to_string(), "starts-with:https://example.com/app");
+        assert_eq!(rule("https://example.com").mode, MatchMode::Host);
+        assert_eq!(UrlRule::parse("regex:(").unwrap_err().split(':').next(), Some("Invalid regex '('"));
+        assert!(UrlRule::parse("ftp://example.com").is_err());
+        assert!(UrlRule::parse("domain:").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A named vault known to the registry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VaultEntry {
+    pub path: String,
+}
+
+/// The registry of named vaults, stored as JSON in the data directory.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct VaultRegistry {
+    /// Name of the vault used when `--vault` is not given.
+    pub default: Option<String>,
+    pub vaults: BTreeMap<String, VaultEntry>,
+}
+
+/// Directory holding the registry and vaults created by name:
+/// `$XDG_DATA_HOME/pwvault` (usually `~/.local/share/pwvault`).
+pub fn data_dir() -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(dirs::data_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("pwvault")
+}
+
+/// Path of the vault used when nothing else is selected. It lives in the data
+/// directory, so it does not depend on the current working directory.
+pub fn default_vault_path() -> PathBuf {
+    data_dir().join("vaults").join("default.db")
+}
+
+fn registry_path() -> PathBuf {
+    data_dir().join("vaults.json")
+}
+
+/// Loads the registry, returning an empty one if it was never written.
+pub fn load_registry() -> Result<VaultRegistry, String> {
+    let path = registry_path();
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse vault registry {}: {}", path.display(), e)),
+        Err(_) => Ok(VaultRegistry::default()),
+    }
+}
+
+fn save_registry(registry: &VaultRegistry) -> Result<(), String> {
+    let path = registry_path();
+    fs::create_dir_all(data_dir()).map_err(|e| format!("Failed to create {}: {}", data_dir().display(), e))?;
+    let contents = serde_json::to_string_pretty(registry).expect("Failed to serialize vault registry");
+    fs::write(&path, contents).map_err(|e| format!("Failed to write vault registry {}: {}", path.display(), e))
+}
+
+/// Resolves which vault file to open. An explicit selector is looked up as a
+/// vault name first and otherwise treated as a path. Without one, `DATABASE_URL`
+/// wins, then the `default_vault` from the configuration file, then the registry
+/// default, then the default vault in the data directory.
+pub fn resolve_vault(selector: Option<&str>, configured: Option<&str>) -> Result<PathBuf, String> {
+    let registry = load_registry()?;
+
+    if let Some(selector) = selector {
+        return lookup_vault(&registry, selector);
+    }
+
+    if let Ok(url) = env::var("DATABASE_URL") {
+        return Ok(PathBuf::from(url));
+    }
+
+    if let Some(configured) = configured {
+        return lookup_vault(&registry, configured);
+    }
+
+    if let Some(name) = &registry.default {
+        return registry
+            .vaults
+            .get(name)
+            .map(|entry| PathBuf::from(&entry.path))
+            .ok_or_else(|| format!("The default vault '{}' is not registered", name));
+    }
+
+    Ok(default_vault_path())
+}
+
+fn lookup_vault(registry: &VaultRegistry, selector: &str) -> Result<PathBuf, String> {
+    if let Some(entry) = registry.vaults.get(selector) {
+        return Ok(PathBuf::from(&entry.path));
+    }
+    if looks_like_path(selector) {
+        return Ok(PathBuf::from(selector));
+    }
+    Err(format!("Unknown vault '{}'. See `vault list` for the known vaults.", selector))
+}
+
+fn looks_like_path(selector: &str) -> bool {
+    selector.contains(std::path::MAIN_SEPARATOR) || selector.contains('/') || selector.ends_with(".db") || Path::new(selector).exists()
+}
+
+/// Checks that a vault name is short and safe to use as a file name.
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid vault name '{}': use up to 64 letters, digits, '-' or '_'",
+            name
+        ))
+    }
+}
+
+/// Registers a vault under `name`. Without an explicit path the vault file is
+/// placed in the data directory. Returns the path of the vault file.
+pub fn register_vault(name: &str, path: Option<&str>) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    let mut registry = load_registry()?;
+    if registry.vaults.contains_key(name) {
+        return Err(format!("A vault named '{}' already exists", name));
+    }
+
+    let path = match path {
+        Some(path) => absolute_path(Path::new(path))?,
+        None => data_dir().join("vaults").join(format!("{}.db", name)),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    registry.vaults.insert(
+        name.to_string(),
+        VaultEntry {
+            path: path.to_string_lossy().into_owned(),
+        },
+    );
+    if registry.default.is_none() {
+        registry.default = Some(name.to_string());
+    }
+    save_registry(&registry)?;
+    Ok(path)
+}
+
+/// Removes a vault from the registry and returns its entry. The vault file
+/// itself is left alone.
+pub fn unregister_vault(name: &str) -> Result<VaultEntry, String> {
+    let mut registry = load_registry()?;
+    let entry = registry
+        .vaults
+        .remove(name)
+        .ok_or_else(|| format!("Unknown vault '{}'", name))?;
+    if registry.default.as_deref() == Some(name) {
+        registry.default = None;
+    }
+    save_registry(&registry)?;
+    Ok(entry)
+}
+
+/// Makes `name` the vault used when `--vault` is not given, and returns its entry.
+pub fn set_default_vault(name: &str) -> Result<VaultEntry, String> {
+    let mut registry = load_registry()?;
+    let entry = registry
+        .vaults
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("Unknown vault '{}'", name))?;
+    registry.default = Some(name.to_string());
+    save_registry(&registry)?;
+    Ok(entry)
+}
+
+fn absolute_path(path: &Path) -> Result<PathBuf, String> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        env::current_dir()
+            .map(|dir| dir.join(path))
+            .map_err(|e| format!("Failed to resolve {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points the data directory at a fresh temporary directory.
+    fn setup_test_registry(name: &str) {
+        let dir = env::temp_dir().join(format!("pwvault_registry_test_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        env::set_var("XDG_DATA_HOME", &dir);
+        env::remove_var("DATABASE_URL");
+    }
+
+    #[test]
+    fn test_register_and_resolve_vaults() {
+        // Setup
+        setup_test_registry("resolve");
+
+        // Act: Register two vaults; the first becomes the default
+        let work = register_vault("work", None).expect("Registering should succeed");
+        let home = register_vault("home", Some("/tmp/pwvault_home_test.db")).expect("Registering should succeed");
+
+        // Assert
+        assert_eq!(work, data_dir().join("vaults").join("work.db"));
+        assert_eq!(resolve_vault(Some("home"), None), Ok(home.clone()));
+        assert_eq!(resolve_vault(None, None), Ok(work));
+        assert_eq!(resolve_vault(None, Some("home")), Ok(home));
+        assert_eq!(resolve_vault(Some("other/path.db"), None), Ok(PathBuf::from("other/path.db")));
+        assert!(resolve_vault(Some("missing"), None).is_err());
+        assert!(register_vault("work", None).is_err());
+        assert!(register_vault("../escape", None).is_err());
+    }
+
+    #[test]
+    fn test_default_and_unregister() {
+        // Setup
+        setup_test_registry("default");
+        register_vault("first", None).unwrap();
+        register_vault("second", None).unwrap();
+
+        // Act & Assert: Switching the default
+        set_default_vault("second").expect("Setting the default should succeed");
+        assert_eq!(load_registry().unwrap().default, Some("second".to_string()));
+
+        // Act & Assert: Removing the default vault falls back to the data directory default
+        unregister_vault("second").expect("Removing should succeed");
+        assert_eq!(load_registry().unwrap().default, None);
+        assert_eq!(resolve_vault(None, None), Ok(default_vault_path()));
+        assert!(set_default_vault("second").is_err());
+    }
+}
@@ -1,9 +1,24 @@
 use std::process::Command;
 
+/// Runs the CLI against a vault in the scratch directory, never the user's
+/// default vault nor a file of the repository.
+fn cli() -> Command {
+    let mut command = Command::new("cargo");
+    command.env("DATABASE_URL", scratch("passwords.db")).env("XDG_STATE_HOME", scratch("state"));
+    command
+}
+
+/// A path in the scratch directory the CLI tests keep their vaults in.
+fn scratch(name: &str) -> String {
+    let dir = std::env::temp_dir().join("pwvault_cli_tests");
+    std::fs::create_dir_all(&dir).expect("Failed to create the scratch directory");
+    dir.join(name).to_string_lossy().into_owned()
+}
+
 #[test]
 fn test_cli_duplicate_password_handling() {
     // Step 1: Add a new password
-    let output1 = Command::new("cargo")
+    let output1 = cli()
         .args(&[
             "run",
             "--",
@@ -25,7 +40,7 @@ fn test_cli_duplicate_password_handling() {
     );
 
     // Step 2: Attempt to add the same password with the same title again
-    let output2 = Command::new("cargo")
+    let output2 = cli()
         .args(&[
             "run",
             "--",
@@ -52,7 +67,7 @@ fn test_cli_duplicate_password_handling() {
 #[test]
 fn test_cli_help() {
     // Act: Run the help command
-    let output = Command::new("cargo")
+    let output = cli()
         .args(&["run", "--", "--help"])
         .output()
         .expect("Failed to execute process");
@@ -68,7 +83,7 @@ fn test_cli_help() {
 #[test]
 fn test_cli_add_password() {
     // Act: Add a new password
-    let output = Command::new("cargo")
+    let output = cli()
         .args(&[
             "run", "--", "add", "--title", "test_title", "--password", "test_password",
         ])
@@ -83,8 +98,8 @@ fn test_cli_add_password() {
 
 #[test]
 fn test_cli_get_password() {
-    // Step 1: Add a password and capture the output
-    let add_output = Command::new("cargo")
+    // Step 1: Add a password
+    let add_output = cli()
         .args(&[
             "run", "--", "add", "--title", "test_get", "--password", "test_password",
         ])
@@ -93,24 +108,17 @@ fn test_cli_get_password() {
 
     let add_stdout = String::from_utf8(add_output.stdout).expect("Invalid UTF-8 in add output");
 
-    // Extract the nonce from the "add" command output
-    let nonce_line = add_stdout
-        .lines()
-        .find(|line| line.contains("Store this nonce securely:"))
-        .expect("Nonce not found in add command output");
-
-    let nonce = nonce_line
-        .split("Store this nonce securely: ")
-        .nth(1)
-        .expect("Failed to extract nonce")
-        .trim();
-
-    println!("Extracted Nonce: {}", nonce); // Debugging output
+    // The nonce is stored with the ciphertext and no longer handed out
+    assert!(
+        !add_stdout.contains("Store this nonce securely:"),
+        "Add command should not ask to store a nonce. Actual output: {}",
+        add_stdout
+    );
 
-    // Step 2: Retrieve the password using the captured nonce
-    let get_output = Command::new("cargo")
+    // Step 2: Retrieve the password by title alone
+    let get_output = cli()
         .args(&[
-            "run", "--", "get", "--title", "test_get", "--nonce", nonce,
+            "run", "--", "get", "--title", "test_get",
         ])
         .output()
         .expect("Failed to retrieve password");
@@ -128,7 +136,7 @@ fn test_cli_get_password() {
 #[test]
 fn test_cli_clean_database() {
     // Act: Run the clean command
-    let output = Command::new("cargo")
+    let output = cli()
         .args(&["run", "--", "clean"])
         .output()
         .expect("Failed to execute process");
@@ -142,7 +150,7 @@ fn test_cli_clean_database() {
 #[test]
 fn test_cli_modify_password() {
     // Step 1: Add a new password
-    let add_output = Command::new("cargo")
+    let add_output = cli()
         .args(&[
             "run", "--", "add", "--title", "modify_test", "--password", "old_password",
         ])
@@ -150,21 +158,14 @@ fn test_cli_modify_password() {
         .expect("Failed to add password");
 
     let add_stdout = String::from_utf8(add_output.stdout).expect("Invalid UTF-8 in add output");
+    assert!(
+        add_stdout.contains("Password added for 'modify_test'"),
+        "Add before modify failed. Output: {}",
+        add_stdout
+    );
 
-    // Extract the nonce from the "add" command output
-    let nonce_line = add_stdout
-        .lines()
-        .find(|line| line.contains("Store this nonce securely:"))
-        .expect("Nonce not found in add command output");
-
-    let old_nonce = nonce_line
-        .split("Store this nonce securely: ")
-        .nth(1)
-        .expect("Failed to extract nonce")
-        .trim();
-
-    // Step 2: Modify the password and capture the new nonce
-    let modify_output = Command::new("cargo")
+    // Step 2: Modify the password
+    let modify_output = cli()
         .args(&[
             "run",
             "--",
@@ -173,29 +174,22 @@ fn test_cli_modify_password() {
             "modify_test",
             "--new_password",
             "new_password",
-            "--nonce",
-            old_nonce,
         ])
         .output()
         .expect("Failed to modify password");
 
     let modify_stdout = String::from_utf8(modify_output.stdout).expect("Invalid UTF-8 in modify output");
 
-    let new_nonce_line = modify_stdout
-        .lines()
-        .find(|line| line.contains("Store this new nonce securely:"))
-        .expect("New nonce not found in modify command output");
-
-    let new_nonce = new_nonce_line
-        .split("Store this new nonce securely: ")
-        .nth(1)
-        .expect("Failed to extract new nonce")
-        .trim();
+    assert!(
+        modify_stdout.contains("Password updated for 'modify_test'"),
+        "Modify failed. Output: {}",
+        modify_stdout
+    );
 
-    // Step 3: Retrieve the updated password using the new nonce
-    let get_output = Command::new("cargo")
+    // Step 3: Retrieve the updated password
+    let get_output = cli()
         .args(&[
-            "run", "--", "get", "--title", "modify_test", "--nonce", new_nonce,
+            "run", "--", "get", "--title", "modify_test",
         ])
         .output()
         .expect("Failed to retrieve password");
@@ -213,7 +207,7 @@ fn test_cli_modify_password() {
 #[test]
 fn test_cli_delete_password() {
     // Step 1: Add a new password
-    let add_output = Command::new("cargo")
+    let add_output = cli()
         .args(&[
             "run", "--", "add", "--title", "delete_test", "--password", "delete_password",
         ])
@@ -221,23 +215,16 @@ fn test_cli_delete_password() {
         .expect("Failed to add password");
 
     let add_stdout = String::from_utf8(add_output.stdout).expect("Invalid UTF-8 in add output");
-
-    // Extract the nonce from the "add" command output
-    let nonce_line = add_stdout
-        .lines()
-        .find(|line| line.contains("Store this nonce securely:"))
-        .expect("Nonce not found in add command output");
-
-    let nonce = nonce_line
-        .split("Store this nonce securely: ")
-        .nth(1)
-        .expect("Failed to extract nonce")
-        .trim();
+    assert!(
+        add_stdout.contains("Password added for 'delete_test'"),
+        "Add before delete failed. Output: {}",
+        add_stdout
+    );
 
     // Step 2: Delete the password
-    let delete_output = Command::new("cargo")
+    let delete_output = cli()
         .args(&[
-            "run", "--", "delete", "--title", "delete_test", "--nonce", nonce,
+            "run", "--", "delete", "--title", "delete_test",
         ])
         .output()
         .expect("Failed to delete password");
@@ -250,9 +237,9 @@ fn test_cli_delete_password() {
     );
 
     // Step 3: Verify the password no longer exists
-    let get_output = Command::new("cargo")
+    let get_output = cli()
         .args(&[
-            "run", "--", "get", "--title", "delete_test", "--nonce", nonce,
+            "run", "--", "get", "--title", "delete_test",
         ])
         .output()
         .expect("Failed to retrieve password");
@@ -264,3 +251,769 @@ fn test_cli_delete_password() {
         "Password was not deleted successfully"
     );
 }
+
+#[test]
+fn test_cli_named_vaults() {
+    // Setup: Keep the registry and vaults in a temporary data directory
+    let data_home = std::env::temp_dir().join(format!("pwvault_cli_vaults_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_home);
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .env("XDG_DATA_HOME", &data_home)
+            .env_remove("DATABASE_URL")
+            .arg("run")
+            .arg("--")
+            .args(args)
+            .output()
+            .expect("Failed to execute vault command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+
+    // Act: Create two vaults and store a password in the second one
+    let create_output = run(&["vault", "create", "work"]);
+    run(&["vault", "create", "personal"]);
+    run(&["--vault", "personal", "add", "--title", "vault_cli_entry", "--password", "pw"]);
+
+    // Assert: The password is only visible in the selected vault
+    assert!(create_output.contains("Vault 'work' created"), "Vault creation failed");
+    assert!(run(&["vault", "list"]).contains("* work"), "First vault should be the default");
+    assert!(run(&["get", "--title", "vault_cli_entry", "--vault", "personal"]).contains("Decrypted password for 'vault_cli_entry': pw"));
+    assert!(run(&["get", "--title", "vault_cli_entry"]).contains("No password found"));
+
+    let _ = std::fs::remove_dir_all(&data_home);
+}
+
+#[test]
+fn test_cli_config_set_and_validation() {
+    // Setup: Use a configuration file in the temporary directory
+    let config_file = std::env::temp_dir().join(format!("pwvault_cli_config_{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&config_file);
+    let run = |args: &[&str]| {
+        let output = cli()
+            .args(&["run", "--"])
+            .args(args)
+            .arg("--config")
+            .arg(&config_file)
+            .output()
+            .expect("Failed to execute config command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+
+    // Act: Change a setting, then try an invalid one
+    let set_output = run(&["config", "set", "generator.length", "32"]);
+    let invalid_output = run(&["config", "set", "generator.length", "2"]);
+
+    // Assert: Only the valid setting is stored
+    assert!(set_output.contains("Set 'generator.length'"), "Setting a value failed");
+    assert!(invalid_output.contains("generator.length must be between 8 and 1024"));
+    assert!(run(&["config", "show"]).contains("length = 32"));
+
+    // Act & Assert: A broken configuration file stops other commands with a clear error
+    std::fs::write(&config_file, "clipbaord_timeout = 10\n").unwrap();
+    assert!(run(&["list"]).contains("Configuration error"));
+
+    let _ = std::fs::remove_file(&config_file);
+}
+
+#[test]
+fn test_cli_folders_and_tags() {
+    // Setup: Add two entries in different folders
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "folder_cli_db"]);
+    run(&["delete", "--title", "folder_cli_mail"]);
+    run(&["add", "--title", "folder_cli_db", "--password", "pw", "--folder", "prod/db", "--tag", "critical"]);
+    run(&["add", "--title", "folder_cli_mail", "--password", "pw", "--folder", "personal"]);
+
+    // Act & Assert: Filtering by folder includes subfolders, filtering by tag matches tagged entries only
+    let prod = run(&["list", "--folder", "prod"]);
+    assert!(prod.contains("folder_cli_db  [prod/db]  #critical"), "Unexpected list output: {}", prod);
+    assert!(!prod.contains("folder_cli_mail"));
+    assert!(!run(&["list", "--tag", "critical"]).contains("folder_cli_mail"));
+
+    // Act: Move the whole prod folder
+    let mv_output = run(&["mv", "--folder", "prod", "--to", "archive"]);
+
+    // Assert
+    assert!(mv_output.contains("Moved 1 password(s) from 'prod' to 'archive'"), "Unexpected mv output: {}", mv_output);
+    assert!(run(&["list", "--folder", "archive"]).contains("folder_cli_db  [archive/db]"));
+
+    run(&["delete", "--title", "folder_cli_db"]);
+    run(&["delete", "--title", "folder_cli_mail"]);
+}
+
+#[test]
+fn test_cli_search_and_suggestions() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "search_cli_github"]);
+    run(&["add", "--title", "search_cli_github", "--password", "pw", "--username", "search_cli_user"]);
+
+    // Act
+    let by_username = run(&["search", "search_cli_user"]);
+    let typo = run(&["get", "--title", "search_cli_gihtub"]);
+
+    // Assert
+    assert!(by_username.contains("search_cli_github  (username: search_cli_user)"), "Unexpected search output: {}", by_username);
+    assert!(typo.contains("No password found for 'search_cli_gihtub'. Did you mean 'search_cli_github'?"), "Unexpected get output: {}", typo);
+
+    run(&["delete", "--title", "search_cli_github"]);
+}
+
+#[test]
+fn test_cli_match_ranks_url_rules() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    for title in ["match_cli_domain", "match_cli_host", "match_cli_login_page", "match_cli_other"] {
+        run(&["delete", "--title", title]);
+    }
+    run(&["add", "--title", "match_cli_domain", "--password", "pw", "--url", "domain:match-cli.example.org"]);
+    run(&["add", "--title", "match_cli_host", "--password", "pw", "--url", "git.match-cli.example.org", "--username", "dev"]);
+    run(&["add", "--title", "match_cli_login_page", "--password", "pw", "--url", "starts-with:https://git.match-cli.example.org/login"]);
+    run(&["add", "--title", "match_cli_other", "--password", "pw", "--url", "wiki.match-cli.example.org"]);
+
+    // Act
+    let matched = run(&["match", "https://git.match-cli.example.org/login?next=/"]);
+    let invalid = run(&["add", "--title", "match_cli_invalid", "--password", "pw", "--url", "regex:("]);
+
+    // Assert
+    let lines: Vec<&str> = matched.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "match_cli_login_page  (starts-with:https://git.match-cli.example.org/login)",
+            "match_cli_host [dev]  (host:git.match-cli.example.org)",
+            "match_cli_domain  (domain:match-cli.example.org)",
+        ],
+        "Unexpected match output: {}",
+        matched
+    );
+    assert!(invalid.contains("Failed to add password: Invalid regex '('"), "Unexpected add output: {}", invalid);
+
+    for title in ["match_cli_domain", "match_cli_host", "match_cli_login_page", "match_cli_other"] {
+        run(&["delete", "--title", title]);
+    }
+}
+
+#[test]
+fn test_cli_json_output() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").arg("--output").arg("json").args(args).output().expect("Failed to execute command");
+        let stdout = String::from_utf8(output.stdout).expect("Invalid UTF-8 in output");
+        let report: serde_json::Value = serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("Invalid JSON report ({}): {}", e, stdout));
+        (output.status.success(), report)
+    };
+    run(&["delete", "--title", "json_cli_entry"]);
+
+    // Act
+    let (added, add_report) = run(&["add", "--title", "json_cli_entry", "--password", "json-secret", "--username", "ops", "--tag", "ci"]);
+    let (_, get_report) = run(&["get", "--title", "json_cli_entry"]);
+    let (_, list_report) = run(&["list", "--tag", "ci"]);
+    let (found, missing_report) = run(&["get", "--title", "json_cli_missing"]);
+    let (_, expiry_report) = run(&["expiry", "set", "--title", "json_cli_entry", "--every", "30d"]);
+    let (_, cleared_report) = run(&["expiry", "clear", "--title", "json_cli_entry"]);
+
+    // Assert
+    assert!(added, "Add failed: {}", add_report);
+    assert_eq!(add_report["schema_version"], 1);
+    assert_eq!(add_report["command"], "add");
+    assert_eq!(add_report["ok"], true);
+    assert_eq!(add_report["result"]["title"], "json_cli_entry");
+    assert_eq!(get_report["result"]["password"], "json-secret");
+    assert_eq!(get_report["result"]["username"], "ops");
+    let listed = list_report["result"]["entries"].as_array().expect("No entries listed");
+    assert!(listed.iter().any(|entry| entry["title"] == "json_cli_entry" && entry["tags"][0] == "ci"), "Unexpected list report: {}", list_report);
+    assert!(!found, "Getting a missing entry succeeded");
+    assert_eq!(missing_report["ok"], false);
+    assert!(missing_report["error"]["message"].as_str().is_some_and(|message| !message.is_empty()), "Unexpected report: {}", missing_report);
+    assert_eq!(expiry_report["result"], serde_json::json!({"title": "json_cli_entry", "rotate_every_days": 30}));
+    assert_eq!(cleared_report["result"], serde_json::json!({"title": "json_cli_entry"}));
+
+    run(&["delete", "--title", "json_cli_entry"]);
+}
+
+#[test]
+fn test_cli_batch_apply() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    let dir = std::env::temp_dir().join(format!("pwvault_cli_batch_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for title in ["batch_cli_old", "batch_cli_generated", "batch_cli_added"] {
+        run(&["delete", "--title", title]);
+    }
+    run(&["add", "--title", "batch_cli_old", "--password", "old-secret", "--username", "ops"]);
+    let manifest = dir.join("manifest.yaml");
+    std::fs::write(
+        &manifest,
+        concat!(
+            "operations:\n",
+            "  - {action: generate, title: batch_cli_generated, username: app, length: 20, tags: [batch_cli]}\n",
+            "  - {action: add, title: batch_cli_added, password: added-secret}\n",
+            "  - {action: modify, title: batch_cli_old, username: deploy}\n",
+        ),
+    )
+    .unwrap();
+    let invalid = dir.join("invalid.json");
+    std::fs::write(
+        &invalid,
+        r#"{"operations": [{"action": "delete", "title": "batch_cli_added"}, {"action": "modify", "title": "batch_cli_missing"}]}"#,
+    )
+    .unwrap();
+
+    // Act
+    let dry_run = run(&["batch", "apply", manifest.to_str().unwrap(), "--dry-run"]);
+    let listed_after_dry_run = run(&["list", "--tag", "batch_cli"]);
+    let applied = run(&["batch", "apply", manifest.to_str().unwrap()]);
+    let generated = run(&["get", "--title", "batch_cli_generated"]);
+    let rejected = run(&["batch", "apply", invalid.to_str().unwrap()]);
+    let added = run(&["get", "--title", "batch_cli_added"]);
+
+    // Assert
+    assert!(dry_run.contains("~ batch_cli_old\n    username: ops -> deploy"), "Unexpected dry run output: {}", dry_run);
+    assert!(dry_run.contains("nothing was written"), "Unexpected dry run output: {}", dry_run);
+    assert!(!dry_run.contains("added-secret"), "Secrets must not be shown: {}", dry_run);
+    assert!(!listed_after_dry_run.contains("batch_cli_generated"), "A dry run wrote entries: {}", listed_after_dry_run);
+    assert!(applied.contains("Applied 3 operation(s)"), "Unexpected apply output: {}", applied);
+    let password = generated.trim().rsplit(": ").next().unwrap_or_default();
+    assert_eq!(password.len(), 20, "Unexpected generated password: {}", generated);
+    assert!(rejected.contains("Operation 2 (modify 'batch_cli_missing'): No password found"), "Unexpected output: {}", rejected);
+    assert!(added.contains("added-secret"), "An invalid manifest changed the vault: {}", added);
+
+    for title in ["batch_cli_old", "batch_cli_generated", "batch_cli_added"] {
+        run(&["delete", "--title", title]);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cli_audit_reports_reused_passwords() {
+    // Setup: Two entries sharing a weak password
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "audit_cli_a"]);
+    run(&["delete", "--title", "audit_cli_b"]);
+    run(&["add", "--title", "audit_cli_a", "--password", "letmein"]);
+    run(&["add", "--title", "audit_cli_b", "--password", "letmein"]);
+
+    // Act
+    let table = run(&["audit"]);
+    let json = run(&["audit", "--format", "json"]);
+
+    // Assert
+    assert!(table.contains("reused with audit_cli_b"), "Unexpected audit output: {}", table);
+    assert!(json.contains("\"title\": \"audit_cli_a\""), "Unexpected audit output: {}", json);
+    assert!(json.contains("\"summary\""));
+
+    run(&["delete", "--title", "audit_cli_a"]);
+    run(&["delete", "--title", "audit_cli_b"]);
+}
+
+#[test]
+fn test_cli_rotation_policies() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "expiry_cli_db"]);
+    run(&["delete", "--title", "expiry_cli_mail"]);
+    run(&["add", "--title", "expiry_cli_db", "--password", "pw", "--folder", "expiry_cli/db"]);
+    run(&["add", "--title", "expiry_cli_mail", "--password", "pw", "--rotate-every", "1y"]);
+
+    // Act
+    let set_output = run(&["expiry", "set", "--folder", "expiry_cli", "--every", "30d"]);
+    let within_month = run(&["expiring", "--within", "60d"]);
+    let within_week = run(&["expiring", "--within", "7d"]);
+
+    // Assert
+    assert!(set_output.contains("Passwords in 'expiry_cli' must now be changed every 30 days."), "Unexpected expiry output: {}", set_output);
+    assert!(run(&["expiry", "list"]).contains("expiry_cli  every 30d"));
+    assert!(within_month.contains("expiry_cli_db  due in"), "Unexpected expiring output: {}", within_month);
+    assert!(within_month.contains("set on folder 'expiry_cli'"));
+    assert!(!within_month.contains("expiry_cli_mail"));
+    assert!(!within_week.contains("expiry_cli_db"));
+
+    run(&["expiry", "clear", "--folder", "expiry_cli"]);
+    run(&["delete", "--title", "expiry_cli_db"]);
+    run(&["delete", "--title", "expiry_cli_mail"]);
+}
+
+#[test]
+fn test_cli_audit_log_records_reads_and_writes() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "audit_log_cli"]);
+
+    // Act
+    run(&["add", "--title", "audit_log_cli", "--password", "pw"]);
+    run(&["get", "--title", "audit_log_cli"]);
+    run(&["delete", "--title", "audit_log_cli"]);
+    let log = run(&["audit-log", "--limit", "3"]);
+    let verify = run(&["audit-log", "verify"]);
+
+    // Assert
+    let actions: Vec<&str> = log
+        .lines()
+        .filter(|line| line.contains("audit_log_cli"))
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .collect();
+    assert_eq!(actions, vec!["add", "get", "delete"], "Unexpected audit log: {}", log);
+    assert!(verify.contains("Audit log verified:"), "Unexpected verify output: {}", verify);
+    assert!(!verify.contains("audit_log_cli"));
+}
+
+#[test]
+fn test_cli_typed_items() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(&["delete", "--title", "items_cli_token"]);
+
+    // Act
+    let invalid = run(&["add", "--title", "items_cli_token", "--type", "api-token", "--field", "expires=soon"]);
+    let added = run(&["add", "--title", "items_cli_token", "--type", "api-token", "--field", "token=abc123", "--field", "expires=2030-01-31"]);
+    let modified = run(&["modify", "--title", "items_cli_token", "--field", "token=def456"]);
+    let shown = run(&["get", "--title", "items_cli_token"]);
+
+    // Assert
+    assert!(invalid.contains("Failed to add item: api-token items need --field token=..."), "Unexpected add output: {}", invalid);
+    assert!(added.contains("API token added for 'items_cli_token'."), "Unexpected add output: {}", added);
+    assert!(modified.contains("API token updated for 'items_cli_token'."), "Unexpected modify output: {}", modified);
+    assert!(shown.contains("API token 'items_cli_token':\n  token: def456\n  expires: 2030-01-31\n"), "Unexpected get output: {}", shown);
+    assert!(run(&["list"]).contains("items_cli_token  (api-token)"));
+
+    run(&["delete", "--title", "items_cli_token"]);
+}
+
+#[test]
+fn test_cli_attachments() {
+    // Setup
+    let run = |args: &[&str]| {
+        let output = cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    let dir = std::env::temp_dir().join(format!("pwvault_cli_attachments_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("kubeconfig");
+    let output = dir.join("restored");
+    std::fs::write(&file, "apiVersion: v1\n").unwrap();
+    run(&["delete", "--title", "attach_cli"]);
+    run(&["add", "--title", "attach_cli", "--password", "pw"]);
+
+    // Act
+    let attached = run(&["attach", "--title", "attach_cli", "--file", file.to_str().unwrap()]);
+    let shown = run(&["get", "--title", "attach_cli"]);
+    let extracted = run(&["extract", "--title", "attach_cli", "--name", "kubeconfig", "--out", output.to_str().unwrap()]);
+    let detached = run(&["detach", "--title", "attach_cli", "--name", "kubeconfig"]);
+
+    // Assert
+    assert!(attached.contains("Attached 'kubeconfig' (15 B) to 'attach_cli'."), "Unexpected attach output: {}", attached);
+    assert!(shown.contains("Attachments: kubeconfig (15 B)"), "Unexpected get output: {}", shown);
+    assert!(extracted.contains("Extracted 'kubeconfig' (15 B)"), "Unexpected extract output: {}", extracted);
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "apiVersion: v1\n");
+    assert!(detached.contains("Removed 'kubeconfig' from 'attach_cli'."));
+
+    run(&["delete", "--title", "attach_cli"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cli_exec_injects_secrets() {
+    // Setup
+    let run = |args: &[&str]| cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+    run(&["delete", "--title", "exec_cli_db"]);
+    run(&["add", "--title", "exec_cli_db", "--password", "s3cret-value", "--username", "deploy", "--folder", "exec_cli"]);
+
+    // Act: The command checks its environment and exits with a status of its own
+    let script = "test \"$DB_PASS\" = s3cret-value && test \"$DB_USER\" = deploy && exit 7";
+    let output = run(&["exec", "--env", "DB_PASS=exec_cli/exec_cli_db", "--env", "DB_USER=exec_cli_db#username", "--", "sh", "-c", script]);
+    let missing = run(&["exec", "--env", "DB_PASS=exec_cli_missing", "--", "true"]);
+
+    // Assert
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(7), "Unexpected exec result: {:?}", output);
+    assert!(!stdout.contains("s3cret-value"), "The secret must never be printed");
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("Failed to read DB_PASS: No password found for 'exec_cli_missing'"));
+
+    run(&["delete", "--title", "exec_cli_db"]);
+}
+
+#[test]
+fn test_cli_inject_renders_templates() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Setup
+    let run = |args: &[&str]| cli().arg("run").arg("--").args(args).output().expect("Failed to execute command");
+    let dir = std::env::temp_dir().join(format!("pwvault_cli_inject_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let template = dir.join("app.conf.tpl");
+    let broken = dir.join("broken.tpl");
+    let output = dir.join("app.conf");
+    std::fs::write(&template, "user={{ vault \"inject_cli/inject_cli_db\" \"username\" }}\npass={{ vault \"inject_cli_db\" \"password\" }}\n").unwrap();
+    std::fs::write(&broken, "pass={{ vault \"inject_cli_db\" }}\nkey={{ vault \"inject_cli_missing\" \"password\" }}\n").unwrap();
+    run(&["delete", "--title", "inject_cli_db"]);
+    run(&["add", "--title", "inject_cli_db", "--password", "s3cret", "--username", "app", "--folder", "inject_cli"]);
+
+    // Act
+    let checked = run(&["inject", "--in", template.to_str().unwrap(), "--check"]);
+    let rendered = run(&["inject", "--in", template.to_str().unwrap(), "--out", output.to_str().unwrap()]);
+    let failed = run(&["inject", "--in", broken.to_str().unwrap(), "--out", output.to_str().unwrap()]);
+
+    // Assert
+    assert!(String::from_utf8_lossy(&checked.stdout).contains("All 2 reference(s)"), "Unexpected check output: {:?}", checked);
+    assert!(rendered.status.success(), "Unexpected inject output: {:?}", rendered);
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "user=app\npass=s3cret\n");
+    assert_eq!(std::fs::metadata(&output).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(failed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&failed.stdout).contains("broken.tpl:2: No password found for 'inject_cli_missing'"));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "user=app\npass=s3cret\n", "A failed render must leave the output alone");
+
+    run(&["delete", "--title", "inject_cli_db"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cli_share_between_vaults() {
+    // Setup: A second vault file for the recipient
+    let (vault, recipient_vault) = (scratch("passwords.db"), scratch("share_cli_recipient.db"));
+    let _ = std::fs::remove_file(&recipient_vault);
+    let run_in = |vault: &str, args: &[&str]| {
+        let output = cli().env("DATABASE_URL", vault).arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    let identity = run_in(&recipient_vault, &["identity"]);
+    let public_key = identity.lines().find_map(|line| line.strip_prefix("Public key: ")).expect("No public key printed").to_string();
+    run_in(&vault, &["delete", "--title", "share_cli_db"]);
+    run_in(&vault, &["add", "--title", "share_cli_db", "--password", "s3cret", "--username", "deploy"]);
+
+    // Act
+    let shared = run_in(&vault, &["share", "--title", "share_cli_db", "--with", &public_key]);
+    let listed = run_in(&vault, &["shared"]);
+    let received = run_in(&recipient_vault, &["shared", "--from", &vault, "--title", "share_cli_db"]);
+    let revoked = run_in(&vault, &["unshare", "--title", "share_cli_db", "--with", &public_key]);
+    let after_revoke = run_in(&recipient_vault, &["shared", "--from", &vault]);
+
+    // Assert
+    assert!(shared.contains(&format!("Shared 'share_cli_db' with {}.", public_key)), "Unexpected share output: {}", shared);
+    assert!(listed.contains(&format!("share_cli_db -> {}", public_key)), "Unexpected shared output: {}", listed);
+    assert!(received.contains("username: deploy") && received.contains("password: s3cret"), "Unexpected received output: {}", received);
+    assert!(revoked.contains("Stopped sharing 'share_cli_db'"), "Unexpected unshare output: {}", revoked);
+    assert!(!after_revoke.contains("share_cli_db"), "A revoked share must not be readable: {}", after_revoke);
+
+    run_in(&vault, &["delete", "--title", "share_cli_db"]);
+    let _ = std::fs::remove_file(&recipient_vault);
+}
+
+#[test]
+fn test_cli_team_vault_roles() {
+    // Setup: A team vault and one state directory, so one device key, per member
+    let vault = scratch("team_cli.db");
+    let _ = std::fs::remove_file(&vault);
+    let state = std::env::temp_dir().join("pwvault_team_cli");
+    let _ = std::fs::remove_dir_all(&state);
+    let run_as = |member: &str, args: &[&str]| {
+        let output = cli()
+            .env("DATABASE_URL", &vault)
+            .env("XDG_STATE_HOME", state.join(member))
+            .arg("run")
+            .arg("--")
+            .args(args)
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    let whoami = run_as("bob", &["team", "whoami"]);
+    let bob = whoami.lines().find_map(|line| line.strip_prefix("Member key: ")).expect("No member key printed").to_string();
+    run_as("alice", &["add", "--title", "team_cli_db", "--password", "s3cret"]);
+
+    // Act
+    let created = run_as("alice", &["team", "init"]);
+    let outsider = run_as("bob", &["get", "--title", "team_cli_db"]);
+    run_as("alice", &["team", "add", "--member", &bob, "--role", "reader"]);
+    let read = run_as("bob", &["get", "--title", "team_cli_db"]);
+    let denied = run_as("bob", &["add", "--title", "team_cli_other", "--password", "x"]);
+    let removed = run_as("alice", &["team", "remove", "--member", &bob]);
+    let after_removal = run_as("bob", &["get", "--title", "team_cli_db"]);
+    let owner_read = run_as("alice", &["get", "--title", "team_cli_db"]);
+
+    // Assert
+    assert!(created.contains("is now a team vault"), "Unexpected init output: {}", created);
+    assert!(outsider.contains("you are not a member"), "A non-member must not open the vault: {}", outsider);
+    assert!(read.contains("s3cret"), "Unexpected reader output: {}", read);
+    assert!(denied.contains("Permission denied: this needs the writer role"), "A reader must not add entries: {}", denied);
+    assert!(removed.contains("The team key was rotated (epoch 2)"), "Unexpected remove output: {}", removed);
+    assert!(after_removal.contains("you are not a member"), "A removed member must not open the vault: {}", after_removal);
+    assert!(owner_read.contains("s3cret"), "The owner must still read the vault: {}", owner_read);
+
+    let _ = std::fs::remove_file(&vault);
+    let _ = std::fs::remove_dir_all(&state);
+}
+
+#[test]
+fn test_cli_recovery_split_and_combine() {
+    // Setup: A team vault of alice, and a state directory, so a device key, per member
+    let vault = scratch("recovery_cli.db");
+    let _ = std::fs::remove_file(&vault);
+    let state = std::path::PathBuf::from(scratch("recovery_cli_state"));
+    let _ = std::fs::remove_dir_all(&state);
+    let run_as = |member: &str, args: &[&str]| {
+        let output = cli()
+            .env("DATABASE_URL", &vault)
+            .env("XDG_STATE_HOME", state.join(member))
+            .arg("run")
+            .arg("--")
+            .args(args)
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run_as("alice", &["add", "--title", "recovery_cli_db", "--password", "s3cret"]);
+
+    // Act
+    let plain = run_as("alice", &["recovery", "split", "--shares", "3", "--threshold", "2"]);
+    run_as("alice", &["team", "init"]);
+    let split = run_as("alice", &["recovery", "split", "--shares", "3", "--threshold", "2"]);
+    let shares: Vec<&str> = split.lines().filter(|line| line.split(' ').count() > 40).collect();
+    let too_few = run_as("bob", &["recovery", "combine", "--share", shares[1]]);
+    let combined = run_as("bob", &["recovery", "combine", "--share", shares[2], "--share", shares[0]]);
+    let read = run_as("bob", &["get", "--title", "recovery_cli_db"]);
+    let invalid = run_as("alice", &["recovery", "split", "--shares", "2", "--threshold", "3"]);
+
+    // Assert
+    assert!(plain.contains("Recovery shares are only for team vaults"), "Unexpected split output: {}", plain);
+    assert_eq!(shares.len(), 3, "Unexpected split output: {}", split);
+    assert!(too_few.contains("2 different shares are needed, only 1 given"), "Unexpected combine output: {}", too_few);
+    assert!(combined.contains("Team vault recovered; you are now its owner"), "Unexpected combine output: {}", combined);
+    assert!(read.contains("s3cret"), "The recovered owner must read the vault: {}", read);
+    assert!(invalid.contains("Cannot require 3 shares out of only 2"), "Unexpected split output: {}", invalid);
+
+    // Cleanup
+    let _ = std::fs::remove_file(&vault);
+    let _ = std::fs::remove_dir_all(&state);
+}
+
+#[test]
+fn test_cli_emergency_access() {
+    // Setup: The owner's vault, and a contact with a vault and device key of their own
+    let (owner_vault, contact_vault) = (scratch("emergency_cli_owner.db"), scratch("emergency_cli_contact.db"));
+    let (owner_vault, contact_vault) = (owner_vault.as_str(), contact_vault.as_str());
+    let state = std::env::temp_dir().join("pwvault_emergency_cli");
+    let cleanup = || {
+        let _ = std::fs::remove_file(owner_vault);
+        let _ = std::fs::remove_file(contact_vault);
+        let _ = std::fs::remove_dir_all(&state);
+    };
+    cleanup();
+    let run_as = |vault: &str, args: &[&str]| {
+        let output = cli()
+            .env("DATABASE_URL", vault)
+            .env("XDG_STATE_HOME", state.join(std::path::Path::new(vault).file_name().unwrap()))
+            .arg("run")
+            .arg("--")
+            .args(args)
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    let whoami = run_as(contact_vault, &["team", "whoami"]);
+    let contact = whoami.lines().find_map(|line| line.strip_prefix("Member key: ")).expect("No member key printed").to_string();
+    run_as(owner_vault, &["add", "--title", "emergency_cli_db", "--password", "s3cret"]);
+
+    // Act
+    run_as(owner_vault, &["emergency", "add", "--contact", &contact, "--wait-days", "3"]);
+    let requested = run_as(contact_vault, &["emergency", "request", "--from", owner_vault]);
+    let listed = run_as(owner_vault, &["emergency", "list"]);
+    let denied = run_as(owner_vault, &["emergency", "deny", "--contact", &contact]);
+    let refused = run_as(contact_vault, &["emergency", "access", "--from", owner_vault, "--title", "emergency_cli_db"]);
+    run_as(owner_vault, &["emergency", "add", "--contact", &contact, "--wait-days", "0"]);
+    run_as(contact_vault, &["emergency", "request", "--from", owner_vault]);
+    let released = run_as(contact_vault, &["emergency", "access", "--from", owner_vault, "--title", "emergency_cli_db"]);
+
+    // Assert
+    assert!(requested.contains("unless it is denied before then"), "Unexpected request output: {}", requested);
+    assert!(listed.contains(&format!("{}  wait 3 day(s)  requested", contact)), "Unexpected list output: {}", listed);
+    assert!(denied.contains("Denied the emergency access request"), "Unexpected deny output: {}", denied);
+    assert!(refused.contains("No emergency access: Your request was denied"), "A denied request must not be released: {}", refused);
+    assert!(released.contains("password: s3cret"), "Unexpected access output: {}", released);
+
+    cleanup();
+}
+
+#[test]
+fn test_cli_api_tokens() {
+    // Setup
+    let vault = scratch("tokens_cli.db");
+    let vault = vault.as_str();
+    let _ = std::fs::remove_file(vault);
+    let run = |args: &[&str]| {
+        let output = cli().env("DATABASE_URL", vault).arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+
+    // Act
+    let created = run(&["token", "create", "--name", "backup-job", "--read-only"]);
+    let duplicate = run(&["token", "create", "--name", "backup-job"]);
+    let listed = run(&["token", "list"]);
+    let revoked = run(&["token", "revoke", "--name", "backup-job"]);
+    let listed_after = run(&["token", "list"]);
+    let refused = run(&["serve", "--listen", "0.0.0.0:8787"]);
+
+    // Assert
+    assert!(created.trim().starts_with("pwv_"), "Unexpected create output: {}", created);
+    assert!(!listed.contains(created.trim()), "The secret must not be listed: {}", listed);
+    assert!(duplicate.contains("A token named 'backup-job' already exists"), "Unexpected create output: {}", duplicate);
+    assert!(listed.contains("backup-job  read-only, every entry"), "Unexpected list output: {}", listed);
+    assert!(revoked.contains("Revoked token 'backup-job'."), "Unexpected revoke output: {}", revoked);
+    assert!(listed_after.contains("This vault has no API tokens."), "Unexpected list output: {}", listed_after);
+    assert!(refused.contains("only listens on localhost"), "Unexpected serve output: {}", refused);
+    let _ = std::fs::remove_file(vault);
+}
+
+#[test]
+fn test_cli_scoped_token_reads() {
+    // Setup: One entry inside the token's scope and one outside it
+    let vault = scratch("scoped_tokens_cli.db");
+    let vault = vault.as_str();
+    let _ = std::fs::remove_file(vault);
+    let run = |token: Option<&str>, args: &[&str]| {
+        let mut command = cli();
+        command.env("DATABASE_URL", vault).env_remove("PWVAULT_TOKEN");
+        if let Some(token) = token {
+            command.env("PWVAULT_TOKEN", token);
+        }
+        let output = command.arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(None, &["add", "--title", "prod-db", "--password", "in-scope", "--folder", "prod/db"]);
+    run(None, &["add", "--title", "dev-db", "--password", "out-of-scope", "--folder", "dev"]);
+    let token = run(None, &["token", "create", "--name", "deploy", "--scope", "folder:prod/*", "--read-only", "--ttl", "24h"]);
+    let token = token.trim();
+
+    // Act
+    let in_scope = run(Some(token), &["get", "--title", "prod-db"]);
+    let out_of_scope = run(Some(token), &["get", "--title", "dev-db"]);
+    let listed = run(Some(token), &["list"]);
+    let write = run(Some(token), &["delete", "--title", "prod-db"]);
+    let tokens = run(None, &["token", "list"]);
+    run(None, &["mv", "--title", "dev-db", "--to", "prod/dev"]);
+    let moved_in = run(Some(token), &["get", "--title", "dev-db"]);
+    run(None, &["token", "revoke", "--name", "deploy"]);
+    let revoked = run(Some(token), &["get", "--title", "prod-db"]);
+
+    // Assert
+    assert!(in_scope.contains("Decrypted password for 'prod-db': in-scope"), "Unexpected get output: {}", in_scope);
+    assert!(
+        out_of_scope.contains("No password found for 'dev-db' in the scope of token 'deploy'.") && !out_of_scope.contains("out-of-scope"),
+        "Unexpected get output: {}",
+        out_of_scope
+    );
+    assert_eq!(listed.trim(), "prod-db", "Unexpected list output: {}", listed);
+    assert!(write.contains("only get and list can be used with PWVAULT_TOKEN"), "Unexpected delete output: {}", write);
+    assert!(tokens.contains("deploy  read-only, folder:prod/*") && tokens.contains("expires"), "Unexpected token list: {}", tokens);
+    assert!(moved_in.contains("Decrypted password for 'dev-db': out-of-scope"), "Unexpected get output: {}", moved_in);
+    assert!(revoked.contains("not a valid, unexpired token"), "Unexpected get output: {}", revoked);
+    let _ = std::fs::remove_file(vault);
+}
+
+#[test]
+fn test_cli_scoped_token_reads_are_audited() {
+    // Setup
+    let vault = scratch("scoped_tokens_audit_cli.db");
+    let vault = vault.as_str();
+    let _ = std::fs::remove_file(vault);
+    let run = |token: Option<&str>, args: &[&str]| {
+        let mut command = cli();
+        command.env("DATABASE_URL", vault).env_remove("PWVAULT_TOKEN");
+        if let Some(token) = token {
+            command.env("PWVAULT_TOKEN", token);
+        }
+        let output = command.arg("run").arg("--").args(args).output().expect("Failed to execute command");
+        String::from_utf8(output.stdout).expect("Invalid UTF-8 in output")
+    };
+    run(None, &["add", "--title", "ci-deploy-key", "--password", "pw"]);
+    let token = run(None, &["token", "create", "--name", "ci", "--scope", "entry:ci-deploy-key", "--read-only"]);
+
+    // Act
+    let read = run(Some(token.trim()), &["get", "--title", "ci-deploy-key"]);
+    let log = run(None, &["audit-log", "--limit", "1"]);
+
+    // Assert
+    assert!(read.contains("Decrypted password for 'ci-deploy-key': pw"), "Unexpected get output: {}", read);
+    assert!(log.contains("token-get:ci  ci-deploy-key"), "Unexpected audit log: {}", log);
+    assert!(log.contains("chain intact"), "Unexpected audit log: {}", log);
+    let _ = std::fs::remove_file(vault);
+}
+
+#[test]
+fn test_cli_native_host_over_stdin() {
+    // Setup
+    use std::io::Write;
+    let vault = scratch("native_host_cli.db");
+    let vault = vault.as_str();
+    let _ = std::fs::remove_file(vault);
+    cli()
+        .env("DATABASE_URL", vault)
+        .args(["run", "--", "add", "--title", "Forum", "--password", "forum-pw", "--username", "me", "--url", "https://forum.example.com/login"])
+        .output()
+        .expect("Failed to execute add");
+    let mut input = Vec::new();
+    for message in [
+        r#"{"id":1,"type":"status"}"#,
+        r#"{"id":2,"type":"candidates","origin":"https://forum.example.com"}"#,
+        r#"{"id":3,"type":"fill","origin":"https://evil.example.net","title":"Forum"}"#,
+        r#"{"id":4,"type":"fill","origin":"https://forum.example.com","title":"Forum"}"#,
+    ] {
+        input.extend((message.len() as u32).to_ne_bytes());
+        input.extend(message.as_bytes());
+    }
+
+    // Act
+    let mut child = cli()
+        .env("DATABASE_URL", vault)
+        .args(["run", "--", "native-host", "chrome-extension://abcdefghijklmnop/"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to start the native host");
+    child.stdin.take().unwrap().write_all(&input).expect("Failed to write messages");
+    let output = child.wait_with_output().expect("Failed to wait for the native host");
+    let mut responses = Vec::new();
+    let mut rest = output.stdout.as_slice();
+    while rest.len() >= 4 {
+        let length = u32::from_ne_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        responses.push(String::from_utf8(rest[4..4 + length].to_vec()).expect("Invalid UTF-8 in response"));
+        rest = &rest[4 + length..];
+    }
+
+    // Assert
+    assert!(output.status.success(), "The native host failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(responses.len(), 4, "Unexpected responses: {:?}", responses);
+    assert!(responses[0].contains(r#""unlocked":true"#), "Unexpected status: {}", responses[0]);
+    assert!(responses[1].contains(r#""title":"Forum""#) && !responses[1].contains("forum-pw"), "Unexpected candidates: {}", responses[1]);
+    assert!(responses[2].contains(r#""type":"error""#) && !responses[2].contains("forum-pw"), "Unexpected fill: {}", responses[2]);
+    assert!(responses[3].contains(r#""password":"forum-pw""#), "Unexpected fill: {}", responses[3]);
+    let _ = std::fs::remove_file(vault);
+}